@@ -0,0 +1,474 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Something the user can do in the viewer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Quit,
+    RawMode,
+    DirMode,
+    ToggleMode,
+    Down,
+    Up,
+    Select,
+    FocusNext,
+    FocusPrevious,
+    Follow,
+    NextInFile,
+    PreviousInFile,
+    Snapshot,
+    Older,
+    Newer,
+    CycleSort,
+    CycleColumns,
+    Filter,
+    Goto,
+    Calc,
+    OpenFile,
+    Zoom,
+    Browse,
+    Tree,
+    Parent,
+    Reload,
+    Stats,
+    Stat,
+    Check,
+    ToggleSizes,
+    ToggleBase,
+    ToggleMark,
+    NextMark,
+    ExportMarks,
+    ExportMarksJoined,
+    MarkStats,
+    Recent,
+    CycleDentrySort,
+    ToggleCase,
+    Diff,
+    Note,
+    CycleEncoding,
+    ToggleAnnotations,
+    ToggleWhitespace,
+    ToggleWrap,
+    ScrollLeft,
+    ScrollRight,
+    EditDentry,
+    Adopt,
+    CopyTo,
+    Screenshot,
+    Repeat,
+    Palette,
+    Dismiss,
+}
+
+impl Action {
+    /// Whether `.` can repeat the action. Moving around isn't repeatable, so moving to where
+    /// an action should run again doesn't replace it as the one to repeat
+    pub fn repeatable(self) -> bool {
+        !matches!(
+            self,
+            Action::Quit
+                | Action::Down
+                | Action::Up
+                | Action::Select
+                | Action::Parent
+                | Action::FocusNext
+                | Action::FocusPrevious
+                | Action::Follow
+                | Action::NextInFile
+                | Action::PreviousInFile
+                | Action::Older
+                | Action::Newer
+                | Action::NextMark
+                | Action::ScrollLeft
+                | Action::ScrollRight
+                | Action::Adopt
+                | Action::Calc
+                | Action::Repeat
+                | Action::Palette
+                | Action::Dismiss
+        )
+    }
+
+    /// How the help and the command palette describe the action
+    pub fn description(self) -> &'static str {
+        BINDINGS
+            .iter()
+            .find(|binding| binding.action == self)
+            .map_or("", |binding| binding.description)
+    }
+}
+
+/// An action, the keys that run it, and how the help and the command palette describe it
+pub struct Binding {
+    pub action: Action,
+    /// The keys that run the action, with the modifiers (other than shift) held down
+    pub keys: &'static [(KeyCode, KeyModifiers)],
+    /// The keys, as shown in the help
+    pub label: &'static str,
+    pub description: &'static str,
+}
+
+/// Bind `action` to keys pressed on their own
+macro_rules! bind {
+    ($action:ident, [$($key:expr),+], $label:expr, $description:expr) => {
+        Binding {
+            action: Action::$action,
+            keys: &[$(($key, KeyModifiers::NONE)),+],
+            label: $label,
+            description: $description,
+        }
+    };
+}
+
+/// Every action, in the order the help lists them. The key handling, the help and the command
+/// palette all go by this
+pub static BINDINGS: [Binding; 54] = [
+    bind!(Quit, [KeyCode::Char('q')], "q", "quit"),
+    bind!(RawMode, [KeyCode::Char('r')], "r", "view in raw mode"),
+    bind!(DirMode, [KeyCode::Char('d')], "d", "view in directory mode"),
+    bind!(ToggleMode, [KeyCode::Char('t')], "t", "toggle (raw/dir)"),
+    bind!(
+        Down,
+        [KeyCode::Char('j'), KeyCode::Down],
+        "j/↓",
+        "move down"
+    ),
+    bind!(Up, [KeyCode::Char('k'), KeyCode::Up], "k/↑", "move up"),
+    bind!(Select, [KeyCode::Enter], "enter", "open the selected entry"),
+    bind!(FocusNext, [KeyCode::Tab], "tab", "focus the next pane"),
+    bind!(
+        FocusPrevious,
+        [KeyCode::BackTab],
+        "S-tab",
+        "focus the previous pane"
+    ),
+    bind!(
+        Follow,
+        [KeyCode::Char('l'), KeyCode::Right],
+        "l/->",
+        "move to next block in file"
+    ),
+    bind!(
+        NextInFile,
+        [KeyCode::Char('n')],
+        "n",
+        "next block of the file"
+    ),
+    bind!(
+        PreviousInFile,
+        [KeyCode::Char('p')],
+        "p",
+        "previous block of the file"
+    ),
+    bind!(Snapshot, [KeyCode::Char('s')], "s", "snapshot the image"),
+    bind!(Older, [KeyCode::Char('[')], "[", "view older snapshot"),
+    bind!(Newer, [KeyCode::Char(']')], "]", "view newer snapshot"),
+    bind!(
+        CycleSort,
+        [KeyCode::Char('S')],
+        "S",
+        "cycle sort (block/next/owner/recent)"
+    ),
+    bind!(
+        CycleColumns,
+        [KeyCode::Char('K')],
+        "K",
+        "cycle FAT list columns (plain/chain)"
+    ),
+    bind!(Filter, [KeyCode::Char('f')], "f", "filter (esc to clear)"),
+    bind!(Goto, [KeyCode::Char('g')], "g", "go to block"),
+    bind!(
+        Calc,
+        [KeyCode::Char('=')],
+        "=",
+        "calculate an offset (e.g. slot(0x2a, 3))"
+    ),
+    bind!(OpenFile, [KeyCode::Char('o')], "o", "open file in $PAGER"),
+    bind!(
+        Zoom,
+        [KeyCode::Char('Z')],
+        "Z",
+        "hide/show the FAT list (zoom the block pane)"
+    ),
+    bind!(
+        Browse,
+        [KeyCode::Char('B')],
+        "B",
+        "browse files by name (B again for the FAT)"
+    ),
+    bind!(
+        Tree,
+        [KeyCode::Char('T')],
+        "T",
+        "show the directory tree (T again for the FAT)"
+    ),
+    bind!(
+        Parent,
+        [KeyCode::Backspace],
+        "backspace",
+        "go up a directory in the file browser or tree view"
+    ),
+    bind!(
+        Reload,
+        [KeyCode::Char('R'), KeyCode::F(5)],
+        "R/F5",
+        "reload the image now"
+    ),
+    bind!(Stats, [KeyCode::Char('z')], "z", "filesystem stats"),
+    bind!(
+        Stat,
+        [KeyCode::Char('I')],
+        "I",
+        "stat the selected file (its dentry, link target and chain)"
+    ),
+    bind!(Check, [KeyCode::Char('C')], "C", "consistency check"),
+    bind!(
+        ToggleSizes,
+        [KeyCode::Char('u')],
+        "u",
+        "dir sizes logical/physical"
+    ),
+    bind!(
+        ToggleBase,
+        [KeyCode::Char('x')],
+        "x",
+        "block numbers hex/decimal"
+    ),
+    bind!(
+        ToggleMark,
+        [KeyCode::Char(' ')],
+        "space",
+        "mark/unmark block"
+    ),
+    bind!(
+        NextMark,
+        [KeyCode::Char('\'')],
+        "'",
+        "jump to next marked block"
+    ),
+    bind!(
+        ExportMarks,
+        [KeyCode::Char('e')],
+        "e",
+        "export marked blocks to files"
+    ),
+    bind!(
+        ExportMarksJoined,
+        [KeyCode::Char('E')],
+        "E",
+        "export marked blocks as one file"
+    ),
+    bind!(MarkStats, [KeyCode::Char('m')], "m", "marked blocks stats"),
+    bind!(Recent, [KeyCode::Char('w')], "w", "recently modified files"),
+    bind!(
+        CycleDentrySort,
+        [KeyCode::Char('O')],
+        "O",
+        "cycle dentry order (slot/name/size/mtime)"
+    ),
+    bind!(
+        ToggleCase,
+        [KeyCode::Char('i')],
+        "i",
+        "sort names case-sensitively or not"
+    ),
+    bind!(
+        Diff,
+        [KeyCode::Char('c')],
+        "c",
+        "diff the block against its previous contents"
+    ),
+    bind!(
+        Note,
+        [KeyCode::Char('a')],
+        "a",
+        "add, edit or delete the note on the block"
+    ),
+    bind!(
+        CycleEncoding,
+        [KeyCode::Char('U')],
+        "U",
+        "cycle the raw view's encoding (utf-8/utf-16/binary/guess)"
+    ),
+    bind!(
+        ToggleAnnotations,
+        [KeyCode::Char('A')],
+        "A",
+        "color dentry fields in hex dumps of directory blocks"
+    ),
+    bind!(
+        ToggleWhitespace,
+        [KeyCode::Char('W')],
+        "W",
+        "show or hide tabs, line ends and control characters in text"
+    ),
+    bind!(
+        ToggleWrap,
+        [KeyCode::Char('N')],
+        "N",
+        "wrap long lines of text, or cut them off to scroll across"
+    ),
+    bind!(
+        ScrollLeft,
+        [KeyCode::Char('H')],
+        "H",
+        "scroll lines of text left, when they're not wrapped"
+    ),
+    bind!(
+        ScrollRight,
+        [KeyCode::Char('L')],
+        "L",
+        "scroll lines of text right, or list more of a directory in the tree view"
+    ),
+    bind!(
+        EditDentry,
+        [KeyCode::Char('M')],
+        "M",
+        "edit the dentry under the cursor (--writable)"
+    ),
+    bind!(
+        Adopt,
+        [KeyCode::Char('Y')],
+        "Y",
+        "adopt the orphan chain into a directory (--writable)"
+    ),
+    bind!(
+        CopyTo,
+        [KeyCode::Char('y')],
+        "y",
+        "copy the selected file into another image (image:/path)"
+    ),
+    bind!(
+        Screenshot,
+        [KeyCode::Char('P'), KeyCode::F(10)],
+        "P/F10",
+        "save the screen as text"
+    ),
+    bind!(
+        Repeat,
+        [KeyCode::Char('.')],
+        ".",
+        "repeat the last action, with the same input"
+    ),
+    Binding {
+        action: Action::Palette,
+        keys: &[(KeyCode::Char('p'), KeyModifiers::CONTROL)],
+        label: "^p",
+        description: "command palette",
+    },
+    bind!(Dismiss, [KeyCode::Esc], "esc", "dismiss error/clear marks"),
+];
+
+/// Get the action bound to a key, if any
+pub fn for_key(event: &KeyEvent) -> Option<Action> {
+    // shift is part of the character itself ('S' rather than 's')
+    let modifiers = event.modifiers - KeyModifiers::SHIFT;
+    BINDINGS
+        .iter()
+        .find(|binding| binding.keys.contains(&(event.code, modifiers)))
+        .map(|binding| binding.action)
+}
+
+/// Score how well `query` matches `text`: every character of the query has to appear in the
+/// text in order (ignoring case). Runs of consecutive characters and matches at the start of
+/// words score higher, so "vr" prefers "view in raw mode" over "move down a block"
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = position + text[position..].iter().position(|t| *t == c)?;
+        score += 1;
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        // gaps cost a little, so tighter matches win ties
+        score -= (found - position) as i32 / 4;
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+/// The actions matching `query` in the command palette, best first
+pub fn search(query: &str) -> Vec<&'static Binding> {
+    let mut matches: Vec<(i32, &Binding)> = BINDINGS
+        .iter()
+        .filter(|binding| binding.action != Action::Palette)
+        .filter_map(|binding| Some((fuzzy_score(query, binding.description)?, binding)))
+        .collect();
+    // a stable sort keeps the help's order among equally good matches
+    matches.sort_by_key(|(score, _)| -score);
+    matches.into_iter().map(|(_, binding)| binding).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_only_subsequences() {
+        assert!(fuzzy_score("raw", "view in raw mode").is_some());
+        assert!(fuzzy_score("RAW", "view in raw mode").is_some());
+        assert!(fuzzy_score("war", "view in raw mode").is_none());
+        assert_eq!(fuzzy_score("", "quit"), Some(0));
+    }
+
+    #[test]
+    fn prefers_runs_and_word_starts() {
+        // a run beats the same characters spread out
+        assert!(fuzzy_score("raw", "view in raw mode") > fuzzy_score("raw", "r a w"));
+        // the start of a word beats the middle of one
+        assert!(fuzzy_score("s", "snapshot the image") > fuzzy_score("s", "filesystem stats"));
+        // a tight match beats a loose one
+        assert!(fuzzy_score("vr", "view raw") > fuzzy_score("vr", "view in directory mode"));
+    }
+
+    #[test]
+    fn ranks_the_best_match_first() {
+        let first = |query| search(query)[0].action;
+        assert_eq!(first("vr"), Action::RawMode);
+        assert_eq!(first("view dir"), Action::DirMode);
+        assert_eq!(first("quit"), Action::Quit);
+        assert_eq!(first("consistency"), Action::Check);
+    }
+
+    #[test]
+    fn empty_query_lists_everything_in_help_order() {
+        let listed: Vec<Action> = search("").iter().map(|b| b.action).collect();
+        let help: Vec<Action> = BINDINGS
+            .iter()
+            .map(|b| b.action)
+            .filter(|action| *action != Action::Palette)
+            .collect();
+        assert_eq!(listed, help);
+    }
+
+    #[test]
+    fn no_key_runs_two_actions() {
+        let mut seen = Vec::new();
+        for binding in &BINDINGS {
+            assert!(!binding.description.is_empty());
+            for key in binding.keys {
+                assert!(!seen.contains(key), "{:?} is bound twice", key);
+                seen.push(*key);
+            }
+        }
+    }
+
+    #[test]
+    fn keys_run_their_binding() {
+        for binding in &BINDINGS {
+            for (code, modifiers) in binding.keys {
+                let event = KeyEvent::new(*code, *modifiers);
+                assert_eq!(for_key(&event), Some(binding.action));
+            }
+        }
+        // shift is part of the character
+        let event = KeyEvent::new(KeyCode::Char('C'), KeyModifiers::SHIFT);
+        assert_eq!(for_key(&event), Some(Action::Check));
+    }
+}
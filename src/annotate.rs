@@ -0,0 +1,66 @@
+use std::ops::Range;
+
+use tui::style::{Color, Style};
+
+use crate::pennfat::DentryLayout;
+
+/// A labelled range of a block's bytes, drawn in its own style in hex dumps so the structure
+/// the bytes make up can be seen without decoding it
+pub struct Annotation {
+    pub range: Range<usize>,
+    pub label: &'static str,
+    pub style: Style,
+}
+
+/// The colors the fields of a directory entry are drawn in, in the order of
+/// [`DentryLayout::fields`]
+const FIELD_COLORS: [Color; 7] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::LightBlue,
+    Color::LightRed,
+    Color::DarkGray,
+];
+
+/// Annotate the fields of every directory entry in a directory block of `len` bytes
+pub fn dentry_fields(layout: &DentryLayout, len: usize) -> Vec<Annotation> {
+    (0..len / layout.size)
+        .flat_map(|slot| {
+            let start = slot * layout.size;
+            layout
+                .fields()
+                .into_iter()
+                .zip(FIELD_COLORS)
+                .map(move |((label, field), color)| {
+                    let range = field.within(layout.size);
+                    Annotation {
+                        range: start + range.start..start + range.end,
+                        label,
+                        style: Style::default().fg(color),
+                    }
+                })
+        })
+        .filter(|annotation| !annotation.range.is_empty())
+        .collect()
+}
+
+/// Get the style of the annotation covering a byte, if any
+pub fn style_at(annotations: &[Annotation], offset: usize) -> Option<Style> {
+    annotations
+        .iter()
+        .find(|annotation| annotation.range.contains(&offset))
+        .map(|annotation| annotation.style)
+}
+
+/// Get each label of the annotations once, with its style, in the order they first appear
+pub fn legend(annotations: &[Annotation]) -> Vec<(&'static str, Style)> {
+    let mut legend: Vec<(&'static str, Style)> = Vec::new();
+    for annotation in annotations {
+        if !legend.iter().any(|(label, _)| *label == annotation.label) {
+            legend.push((annotation.label, annotation.style));
+        }
+    }
+    legend
+}
@@ -0,0 +1,2586 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tui::widgets::ListState;
+
+use crate::actions::{self, Action};
+use crate::browser::Browser;
+use crate::calc;
+use crate::check::{self, Problem};
+use crate::cli::{self, Options};
+use crate::commands;
+use crate::diff::BlockCache;
+use crate::du::{self, DirSize};
+use crate::edit::{self, DentryForm};
+use crate::encoding::{self, Controls, Encoding};
+use crate::filter::{self, Filter};
+use crate::hints::{Hints, Topic};
+use crate::history::{self, Blame};
+use crate::holders::{self, Writer};
+use crate::magic;
+use crate::marks::{self, MarkStats, Marks};
+use crate::ownership::Ownership;
+use crate::pane::{self, BlockPane, Pane};
+use crate::pennfat::{
+    self, BlockFormat, Chain, Dentry, Entry, FatTable, Location, Mode, PennFat, PfError, Profile,
+    Reload, ShowBlocks,
+};
+use crate::recent::RecentFiles;
+use crate::session::Session;
+use crate::snapshot::{self, Timeline};
+use crate::stats::Stats;
+use crate::tree::Tree;
+use crate::walk::{self, Lookup};
+
+/// How often to look for processes writing to the image. Going through every process's open
+/// files is too slow to do on every tick
+const WRITERS_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The start of the status message saying there's nothing to diff the selected block against
+const NO_PREVIOUS_COPY: &str = "no previous copy of block";
+
+/// How many columns H and L scroll lines of text across by
+const SCROLL_COLUMNS: isize = 8;
+
+/// How the FAT list is ordered
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortMode {
+    /// by block number
+    Block,
+    /// by the value of the FAT entry (the next block in the chain)
+    Next,
+    /// by the path of the file owning the block, then position in its chain
+    Owner,
+    /// most recently changed first
+    Recency,
+    /// most recently allocated first, with the blocks allocated before pfview started last
+    Age,
+}
+
+impl SortMode {
+    /// the mode that follows this one when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            SortMode::Block => SortMode::Next,
+            SortMode::Next => SortMode::Owner,
+            SortMode::Owner => SortMode::Recency,
+            SortMode::Recency => SortMode::Age,
+            SortMode::Age => SortMode::Block,
+        }
+    }
+
+    /// the title of the FAT list when sorted this way
+    pub fn title(self) -> &'static str {
+        match self {
+            SortMode::Block => "Fat Table",
+            SortMode::Next => "Fat by next",
+            SortMode::Owner => "Fat by owner",
+            SortMode::Recency => "Fat by recent",
+            SortMode::Age => "Fat by allocation age",
+        }
+    }
+}
+
+/// What the FAT list shows besides each block's entry
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FatColumns {
+    /// just the block and the entry
+    #[default]
+    Plain,
+    /// also, for the first block of a file, the length of its chain and its number of extents,
+    /// and for the other blocks, their index in the chain
+    Chain,
+}
+
+impl FatColumns {
+    /// the columns that follow these when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            FatColumns::Plain => FatColumns::Chain,
+            FatColumns::Chain => FatColumns::Plain,
+        }
+    }
+}
+
+/// How the dentries of a directory block are ordered
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DentrySort {
+    /// in the order of their slots in the block
+    Slot,
+    Name,
+    /// largest first
+    Size,
+    /// most recently modified first
+    Mtime,
+}
+
+impl DentrySort {
+    /// the order that follows this one when cycling through them
+    pub fn next(self) -> Self {
+        match self {
+            DentrySort::Slot => DentrySort::Name,
+            DentrySort::Name => DentrySort::Size,
+            DentrySort::Size => DentrySort::Mtime,
+            DentrySort::Mtime => DentrySort::Slot,
+        }
+    }
+
+    /// the order as shown in the block pane's title
+    pub fn describe(self) -> &'static str {
+        match self {
+            DentrySort::Slot => "by slot",
+            DentrySort::Name => "by name",
+            DentrySort::Size => "by size",
+            DentrySort::Mtime => "by mtime",
+        }
+    }
+
+    /// sort the dentries of a block. The sort is stable, and free and deleted slots always go
+    /// last, in slot order
+    pub fn sort(self, dentries: &mut [Dentry], case_sensitive: bool) {
+        dentries.sort_by(|a, b| self.compare(a, b, case_sensitive));
+    }
+
+    /// the slots of the dentries of a block, in the order [`DentrySort::sort`] puts them in
+    pub fn slots(self, dentries: &[Dentry], case_sensitive: bool) -> Vec<usize> {
+        let mut slots: Vec<usize> = (0..dentries.len()).collect();
+        slots.sort_by(|a, b| self.compare(&dentries[*a], &dentries[*b], case_sensitive));
+        slots
+    }
+
+    fn compare(self, a: &Dentry, b: &Dentry, case_sensitive: bool) -> Ordering {
+        let name = |dentry: &Dentry| match case_sensitive {
+            true => dentry.name(),
+            false => dentry.name().to_lowercase(),
+        };
+        let in_use = b.is_in_use().cmp(&a.is_in_use());
+        let by_key = match (self, a.is_in_use() && b.is_in_use()) {
+            (_, false) | (DentrySort::Slot, _) => Ordering::Equal,
+            (DentrySort::Name, true) => name(a).cmp(&name(b)),
+            (DentrySort::Size, true) => b.size.cmp(&a.size),
+            (DentrySort::Mtime, true) => b.mtime.cmp(&a.mtime),
+        };
+        in_use.then(by_key)
+    }
+}
+
+/// The FAT table as listed, and what's derived from it. This is only rebuilt when the image
+/// being viewed (or the list order or filter) changes, rather than on every tick
+pub struct FatView {
+    /// the allocated (block, next block) entries passing the filter, in list order
+    pub table: Vec<(u16, u16)>,
+    /// the position of each block in `table`
+    pub index: HashMap<u16, usize>,
+    /// the blocks whose entries changed since the previous snapshot, when time travelling
+    pub changed: HashSet<u16>,
+    /// what the entries that changed in the latest reload held before it, for the few ticks
+    /// after the reload that they're shown
+    pub previous: HashMap<u16, u16>,
+    /// the files the blocks belong to
+    pub ownership: Ownership,
+    /// the block pointing to each block, for finding where orphan chains start
+    pub predecessors: HashMap<u16, u16>,
+    /// what the special values of the entries mean
+    pub profile: Profile,
+    /// whether the image has bytes past the last data block, listed after the blocks
+    pub unaddressed: bool,
+    /// the reload generation each block was allocated in, when sorted by allocation age.
+    /// Blocks allocated before pfview started aren't in it
+    pub allocated_in: HashMap<u16, u32>,
+    /// the latest reload generation, to tell the blocks allocated in it
+    pub generation: u32,
+    /// how the image shows block numbers
+    pub blocks: BlockFormat,
+}
+
+impl FatView {
+    /// build the view of a filesystem's FAT from a scan of it, diffing against `previous` if
+    /// given
+    pub fn build(
+        fs: &PennFat,
+        fat_table: &FatTable,
+        previous: Option<&PennFat>,
+        sort: SortMode,
+        filter: Option<&Filter>,
+        blame: &Blame,
+        marks: &Marks,
+    ) -> Self {
+        let mut table = fat_table.entries.clone();
+        // marked blocks stay listed when they're freed, so the mark isn't lost from view
+        let freed: Vec<_> = marks
+            .blocks()
+            .iter()
+            .filter(|b| fs.fat_entry(**b) == Some(fs.profile().free))
+            .map(|b| (*b, fs.profile().free))
+            .collect();
+        if !freed.is_empty() {
+            table.extend(freed);
+            table.sort_unstable();
+        }
+        let ownership = Ownership::build(fs);
+        let predecessors = table
+            .iter()
+            .map(|(block_num, next)| (*next, *block_num))
+            .collect();
+        if let Some(filter) = filter {
+            table.retain(|(block_num, next)| {
+                filter.matches(*block_num, *next, fs.profile(), &ownership, blame)
+            });
+        }
+        match sort {
+            SortMode::Block => {}
+            SortMode::Next => table.sort_by_key(|(block_num, next)| (*next, *block_num)),
+            SortMode::Owner => table.sort_by_cached_key(|(block_num, _)| {
+                let owner = ownership.owner(*block_num);
+                // blocks without an owner go last
+                (
+                    owner.is_none(),
+                    owner.map(|o| (o.path.clone(), o.index)),
+                    *block_num,
+                )
+            }),
+            SortMode::Recency => table
+                .sort_by_key(|(block_num, _)| (Reverse(blame.changed_in(*block_num)), *block_num)),
+            SortMode::Age => table.sort_by_key(|(block_num, _)| {
+                (Reverse(blame.allocated_in(*block_num)), *block_num)
+            }),
+        }
+        // the ages are only shown sorted by them
+        let allocated_in = match sort {
+            SortMode::Age => table
+                .iter()
+                .filter_map(|(block_num, _)| Some((*block_num, blame.allocated_in(*block_num)?)))
+                .collect(),
+            _ => HashMap::new(),
+        };
+        let index = table
+            .iter()
+            .enumerate()
+            .map(|(i, (block_num, _))| (*block_num, i))
+            .collect();
+        let changed = previous
+            .map(|previous| snapshot::fat_diff(&table, &previous.get_fat_table()))
+            .unwrap_or_default();
+        FatView {
+            table,
+            index,
+            changed,
+            previous: HashMap::new(),
+            ownership,
+            predecessors,
+            profile: fs.profile(),
+            unaddressed: !fs.unaddressed().is_empty(),
+            allocated_in,
+            generation: blame.generation(),
+            blocks: fs.block_format(),
+        }
+    }
+
+    /// get the chain a block is part of, from its start, along with the path of the file
+    /// it belongs to (if any)
+    pub fn chain_of(&self, fs: &PennFat, block_num: u16) -> (Option<&str>, Chain) {
+        if let Some(owner) = self.ownership.owner(block_num) {
+            return (Some(&owner.path), fs.chain(owner.first_block));
+        }
+        // orphaned: walk back to the start of the chain, minding cycles
+        let mut first_block = block_num;
+        let mut visited = HashSet::from([block_num]);
+        while let Some(previous) = self.predecessors.get(&first_block) {
+            if !visited.insert(*previous) {
+                break;
+            }
+            first_block = *previous;
+        }
+        (None, fs.chain(first_block))
+    }
+}
+
+/// what a cached view of the image is for: the reload generation, and the snapshot being viewed
+pub type CacheKey = (u32, Option<String>);
+
+/// What a prompt in the status line is asking for
+#[derive(Clone, Copy)]
+pub enum PromptKind {
+    /// a filter for the FAT list
+    Filter,
+    /// a block to go to
+    Goto,
+    /// an expression to work out, such as the offset of a dentry
+    Calc,
+    /// the note on a block
+    Note(u16),
+    /// the path (and size) to adopt the orphan chain starting at a block as
+    Adopt(u16),
+    /// the image, and the path in it, to copy the selected file to
+    CopyTo,
+}
+
+/// A prompt in the status line, and what has been typed into it so far
+#[derive(Clone)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+}
+
+/// The last action that `.` can repeat, and for one that prompts, what was submitted to it
+#[derive(Clone)]
+pub struct LastAction {
+    pub action: Action,
+    pub prompt: Option<Prompt>,
+}
+
+/// The command palette: what has been typed into it so far, and which match is selected
+pub struct Palette {
+    pub input: String,
+    pub selected: usize,
+}
+
+/// The message shown in the status line
+#[derive(Default)]
+pub struct StatusLine {
+    /// the error currently shown, if any. Errors stay until the user acknowledges them
+    pub error: Option<String>,
+    /// the informational message currently shown, if any, when there's no error to show
+    pub info: Option<String>,
+    /// the last error the user acknowledged, so it isn't raised again while it persists
+    pub acknowledged: Option<String>,
+}
+
+impl StatusLine {
+    /// show an error, unless it is the one the user already acknowledged
+    pub fn error(&mut self, message: String) {
+        if self.acknowledged.as_ref() != Some(&message) {
+            if self.error.as_ref() != Some(&message) {
+                warn!("{}", message);
+            }
+            self.error = Some(message);
+        }
+    }
+
+    /// show an informational message, replacing the previous one
+    pub fn info(&mut self, message: String) {
+        self.info = Some(message);
+    }
+
+    /// acknowledge (and hide) the error currently shown
+    pub fn acknowledge(&mut self) {
+        if let Some(error) = self.error.take() {
+            self.acknowledged = Some(error);
+        }
+    }
+}
+
+/// run a scan of the image, logging how long it took
+pub fn timed<T>(name: &str, scan: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = scan();
+    debug!("{} took {:?}", name, start.elapsed());
+    result
+}
+
+/// A change to what's typed into a prompt, the palette or a field of the dentry form
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Edit {
+    Push(char),
+    Pop,
+    Clear,
+}
+
+impl Edit {
+    /// make the change to the text typed so far
+    pub fn apply(self, input: &mut String) {
+        match self {
+            Edit::Push(c) => input.push(c),
+            Edit::Pop => {
+                input.pop();
+            }
+            Edit::Clear => input.clear(),
+        }
+    }
+}
+
+/// What a key press comes to: an action, or what it does to the prompt, the palette or the
+/// dentry form that's open
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyAction {
+    /// run an action
+    Run(Action),
+    /// change what's typed in
+    Type(Edit),
+    /// move through the palette's matches or the form's fields, by this many
+    Move(isize),
+    /// submit the prompt, or the form's changes for confirmation
+    Submit,
+    /// close the prompt, the palette or the form
+    Cancel,
+    /// write the form's changes, as confirmed
+    Confirm,
+    /// go back from the confirmation to the form
+    Back,
+}
+
+/// What the main loop has to do for an action the app can't carry out by itself
+pub enum Request {
+    /// Restore the terminal and exit
+    Quit,
+    /// Suspend the viewer to show a file in an external program
+    OpenExternally { name: String, contents: Vec<u8> },
+    /// Save what the viewer shows to a text file
+    Screenshot,
+}
+
+/// The state of the viewer, and how it reacts to keys and to the image changing. This is
+/// everything but the terminal: the main loop feeds it events and draws what it holds
+pub struct App {
+    /// The live image
+    pub fs: PennFat,
+    pub options: Options,
+    /// How the image is being read, if that's worth a warning (e.g. a guessed root directory)
+    pub root_warning: Option<String>,
+    pub timeline: Timeline,
+    pub blame: Blame,
+    pub sort: SortMode,
+    pub filter: Option<Filter>,
+    pub prompt: Option<Prompt>,
+    pub palette: Option<Palette>,
+    /// the action `.` repeats
+    pub last_action: Option<LastAction>,
+    /// a block to select once the FAT view is up to date
+    pub goto: Option<u16>,
+    pub fat_view: FatView,
+    /// whether the image being viewed changed since fat_view was built
+    pub stale: bool,
+    /// whether it's been said that the image's modification times are kept to the second
+    coarse_mtimes_warned: bool,
+    /// the selected entry of the FAT list
+    pub list_state: ListState,
+    /// the first entry of the FAT list in view when it was last drawn. Only the entries in
+    /// view are built, so the list keeps its own scroll position rather than tui's
+    pub fat_offset: usize,
+    /// the view chosen with t/r/d, for the class of block (directory or not) it was chosen on.
+    /// Otherwise directory blocks are shown as dentries and everything else raw
+    pub view_override: Option<(bool, bool)>,
+    pub status: StatusLine,
+    pub show_stats: bool,
+    /// the path of the file the stat popup is about, while it's up
+    pub stat_path: Option<String>,
+    pub show_check: bool,
+    /// the consistency problems of the live image, to tell when new ones turn up
+    pub problems: Vec<Problem>,
+    /// the consistency check running in the background, if there's one
+    pub checking: Option<check::Pending>,
+    /// whether a consistency check has finished, so `problems` can be trusted
+    pub checked: bool,
+    /// the checksum of the live image's FAT when it was last checked, to tell whether a reload
+    /// changed it
+    fat_checksum: u32,
+    /// until when the overview flashes about new problems
+    pub alert_until: Option<Instant>,
+    /// the stats of the image being viewed, with the reload generation (and snapshot) they're for
+    pub stats_cache: Option<(CacheKey, Stats)>,
+    /// show directory sizes as blocks × block size rather than the sizes in the dentries
+    pub physical_sizes: bool,
+    pub dir_sizes_cache: Option<(CacheKey, HashMap<u16, DirSize>)>,
+    /// what the files listed in directory blocks look like, by their first block, for the
+    /// image being viewed. Only the files that were in view are sniffed
+    pub detected_cache: Option<(CacheKey, HashMap<u16, &'static str>)>,
+    /// the scan of the FAT of the image being viewed, so it's only scanned once per reload
+    /// generation (and snapshot), however often the list is rebuilt
+    fat_table_cache: Option<(CacheKey, FatTable)>,
+    /// the pane the navigation keys go to
+    pub focus: Pane,
+    pub block_pane: BlockPane,
+    /// the selected block, as read by the last [`App::prepare`]
+    pub selected_block: Option<Result<pennfat::Block, PfError>>,
+    /// the blocks marked for bulk actions
+    pub marks: Marks,
+    /// the files of the live image that changed most recently
+    pub recent: RecentFiles,
+    pub show_recent: bool,
+    /// whether the FAT list is hidden, giving the block pane the full width. Its selection
+    /// still moves, with the list focused
+    pub zoomed: bool,
+    /// the file browser, which stays where it was while the classic view is up
+    pub browser: Browser,
+    /// whether the file browser is up instead of the FAT list and the block pane
+    pub browsing: bool,
+    /// the tree view, which keeps what's expanded while the classic view is up
+    pub tree: Tree,
+    /// whether the tree view is up instead of the FAT list and the block pane
+    pub show_tree: bool,
+    /// the selected entry of the recently modified files
+    pub recent_state: ListState,
+    /// the other processes writing to the image, if that can be found out
+    pub writers: Option<Vec<Writer>>,
+    /// the look for writers running in the background, if there's one
+    pub finding_writers: Option<holders::Pending>,
+    /// when the writers were last looked for
+    pub writers_checked: Instant,
+    /// when the live image's contents last changed (or pfview started), for the idle counter
+    pub last_change: Instant,
+    /// how the dentries of directory blocks are ordered
+    pub dentry_sort: DentrySort,
+    /// sort dentries by name case-sensitively
+    pub case_sensitive: bool,
+    /// copies of the blocks viewed recently, to diff them against what they held before
+    pub block_cache: BlockCache,
+    /// show the selected block's changes since its previous contents
+    pub show_diff: bool,
+    /// color the fields of directory entries in hex dumps of directory blocks
+    pub annotate: bool,
+    /// what's remembered about the image between runs, such as notes on blocks
+    pub session: Session,
+    /// the encoding chosen for the raw view, instead of guessing it for each block
+    pub encoding: Option<Encoding>,
+    /// show tabs, line ends and other control characters in text, rather than as '.'
+    pub show_whitespace: bool,
+    /// the form editing a dentry of the live image, if open
+    pub dentry_form: Option<DentryForm>,
+    /// the hints shown on first visiting each view, only in the demo
+    pub hints: Option<Hints>,
+}
+
+impl App {
+    /// Start viewing a loaded image
+    pub fn new(fs: PennFat, options: Options, root_warning: Option<String>) -> Self {
+        let blame = Blame::new(&fs);
+        let sort = SortMode::Block;
+        let fat_table = timed("scanning the FAT", || fs.scan_fat());
+        let fat_view = timed("building the FAT view", || {
+            FatView::build(&fs, &fat_table, None, sort, None, &blame, &Marks::default())
+        });
+        let fat_table_cache = Some(((blame.generation(), None), fat_table));
+        // the first draw shouldn't wait on the check, however large the FAT
+        let checking = Some(check::Pending::start(&fs));
+        let fat_checksum = fat_checksum(&fs);
+        let recent = timed("scanning files", || RecentFiles::new(&fs));
+        // nor on the look for writers, which goes through every process
+        let finding_writers = Some(holders::Pending::start(fs.path()));
+        let mut list_state = ListState::default();
+        list_state.select(Some(0));
+        let mut recent_state = ListState::default();
+        recent_state.select(Some(0));
+        let block_cache = BlockCache::new(options.diff_cache);
+        let hints = options.demo.then(Hints::default);
+        let mut status = StatusLine::default();
+        let session = Session::load(&fs).unwrap_or_else(|e| {
+            status.error(format!("loading notes: {}", e));
+            Session::default()
+        });
+        App {
+            fs,
+            options,
+            root_warning,
+            timeline: Timeline::default(),
+            blame,
+            sort,
+            filter: None,
+            prompt: None,
+            palette: None,
+            last_action: None,
+            goto: None,
+            fat_view,
+            stale: false,
+            coarse_mtimes_warned: false,
+            list_state,
+            fat_offset: 0,
+            view_override: None,
+            status,
+            show_stats: false,
+            stat_path: None,
+            show_check: false,
+            problems: Vec::new(),
+            checking,
+            checked: false,
+            fat_checksum,
+            alert_until: None,
+            stats_cache: None,
+            physical_sizes: false,
+            dir_sizes_cache: None,
+            detected_cache: None,
+            fat_table_cache,
+            focus: Pane::default(),
+            block_pane: BlockPane::default(),
+            selected_block: None,
+            marks: Marks::default(),
+            recent,
+            show_recent: false,
+            zoomed: false,
+            browser: Browser::default(),
+            browsing: false,
+            tree: Tree::default(),
+            show_tree: false,
+            annotate: true,
+            recent_state,
+            writers: None,
+            finding_writers,
+            writers_checked: Instant::now(),
+            last_change: Instant::now(),
+            dentry_sort: DentrySort::Slot,
+            case_sensitive: false,
+            block_cache,
+            show_diff: false,
+            session,
+            encoding: None,
+            show_whitespace: true,
+            dentry_form: None,
+            hints,
+        }
+    }
+
+    /// The image being viewed: the snapshot when time travelling, otherwise the live image
+    pub fn view(&self) -> &PennFat {
+        self.timeline.current().unwrap_or(&self.fs)
+    }
+
+    /// The scan of the FAT of the image being viewed, scanning it if it changed since the last
+    fn fat_table(&mut self) -> &FatTable {
+        let key = (self.blame.generation(), self.timeline.describe());
+        if self
+            .fat_table_cache
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != key)
+        {
+            let fat_table = timed("scanning the FAT", || self.view().scan_fat());
+            self.fat_table_cache = Some((key, fat_table));
+        }
+        let (_, fat_table) = self
+            .fat_table_cache
+            .as_ref()
+            .expect("the FAT was just scanned");
+        fat_table
+    }
+
+    /// The position of the selection in the FAT list
+    pub fn selected(&self) -> usize {
+        self.list_state.selected().unwrap_or(0)
+    }
+
+    /// The number of items in the FAT list: the listed blocks, then the region past the last
+    /// data block if the image has one
+    pub fn fat_list_len(&self) -> usize {
+        self.fat_view.table.len() + self.fat_view.unaddressed as usize
+    }
+
+    /// Whether the region past the last data block is selected in the FAT list
+    pub fn unaddressed_selected(&self) -> bool {
+        self.fat_view.unaddressed && self.selected() == self.fat_view.table.len()
+    }
+
+    /// The FAT entries of the blocks up to two either side of `block_num` (None for free
+    /// blocks), as far as the data region goes, from the scan of the FAT
+    pub fn neighbors(&self, block_num: u16) -> Vec<(u16, Option<u16>)> {
+        let Some((_, fat_table)) = &self.fat_table_cache else {
+            return Vec::new();
+        };
+        let first = block_num.saturating_sub(2).max(1);
+        let last = block_num
+            .saturating_add(2)
+            .min(self.view().data_block_count());
+        (first..=last)
+            .map(|block| (block, fat_table.entry(block)))
+            .collect()
+    }
+
+    /// Whether the selected block belongs to a directory
+    pub fn is_dir_block(&self) -> bool {
+        self.fat_view
+            .table
+            .get(self.selected())
+            .is_some_and(|(block_num, _)| {
+                self.fat_view
+                    .ownership
+                    .owner(*block_num)
+                    .is_some_and(|owner| owner.is_dir)
+            })
+    }
+
+    /// Whether the selected block is shown raw, rather than as dentries
+    pub fn raw_mode(&self) -> bool {
+        self.view_override
+            .map_or(!self.is_dir_block(), |(_, raw)| raw)
+    }
+
+    /// Whether the overview is flashing about new problems
+    pub fn alert(&self) -> bool {
+        self.alert_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Reload the live image, and react to it having changed. A reload failure (e.g. the
+    /// writer truncated the file) shouldn't take the viewer down, so it's only reported.
+    /// Returns the first new consistency problem, once the check of a change finds the image
+    /// became less consistent
+    pub fn reload(&mut self) -> Option<Problem> {
+        match self.fs.reload() {
+            Ok(Reload::Changed) => {
+                self.on_reload();
+            }
+            // let it be known the writer is alive, without anything counting it as a change
+            Ok(Reload::Touched) => {
+                let modified: DateTime<Utc> = self.fs.last_update_time().into();
+                self.status.info(format!(
+                    "metadata touch at {} (no changes)",
+                    modified.format("%H:%M:%S")
+                ));
+            }
+            Ok(Reload::Unchanged) => {}
+            Err(e) => {
+                self.stale = true;
+                self.status.error(format!("reloading image: {}", e));
+            }
+        }
+        if self.fs.coarse_mtimes() && !self.coarse_mtimes_warned {
+            self.coarse_mtimes_warned = true;
+            if !self.options.poll_content {
+                self.status.info(
+                    "the image's mtimes are whole seconds, so quick writes can be missed \
+                     (try --poll-content)"
+                        .to_owned(),
+                );
+            }
+        }
+        if self.fs.writer_locked() {
+            self.status.error(commands::WRITER_LOCKED.to_owned());
+        } else if self.fs.degraded() {
+            self.status
+                .error("the image shrank under its map; reading from a copy".to_owned());
+        }
+        if let Some(writers) = self
+            .finding_writers
+            .as_ref()
+            .and_then(holders::Pending::poll)
+        {
+            self.writers = writers;
+            self.finding_writers = None;
+        }
+        // one look at a time, however long a look takes
+        if self.finding_writers.is_none() && self.writers_checked.elapsed() >= WRITERS_INTERVAL {
+            self.finding_writers = Some(holders::Pending::start(self.fs.path()));
+            self.writers_checked = Instant::now();
+        }
+        self.collect_check()
+    }
+
+    /// Reload the live image right away, even if it doesn't seem to have changed, and say
+    /// what changed
+    fn force_reload(&mut self) {
+        match self.fs.reload_force() {
+            Ok(Reload::Changed) => {
+                let (blocks, entries) = self.on_reload();
+                self.status.info(format!(
+                    "reloaded, {} blocks and {} FAT entries changed",
+                    blocks, entries
+                ));
+            }
+            Ok(Reload::Touched) | Ok(Reload::Unchanged) => {
+                self.status.info("reloaded, no changes".to_owned())
+            }
+            Err(e) => {
+                self.stale = true;
+                self.status.error(format!("reloading image: {}", e));
+            }
+        }
+    }
+
+    /// Catch up with a change to the live image, checking it again if its FAT changed.
+    /// Returns the number of blocks and of FAT entries that changed
+    fn on_reload(&mut self) -> (usize, usize) {
+        self.stale = true;
+        self.last_change = Instant::now();
+        let changed = timed("blame update", || self.blame.update(&self.fs));
+        timed("scanning files", || {
+            self.recent.update(&self.fs, self.blame.generation())
+        });
+        let fat_checksum = fat_checksum(&self.fs);
+        if fat_checksum != self.fat_checksum {
+            self.fat_checksum = fat_checksum;
+            // a check still running is of a FAT that's gone, so its result is dropped
+            self.checking = Some(check::Pending::start(&self.fs));
+        }
+        if self.options.snapshot_on_change > 0 {
+            let dir = self.options.snapshot_dir.as_deref();
+            if let Err(e) = snapshot::take(&self.fs, dir, true)
+                .and_then(|_| snapshot::prune(self.fs.path(), dir, self.options.snapshot_on_change))
+            {
+                self.status
+                    .error(format!("taking automatic snapshot: {}", e));
+            }
+        }
+        changed
+    }
+
+    /// Take the problems found by the background check, if it's done. Returns the first new
+    /// problem, if the image became less consistent since the last check. Problems the image
+    /// had when it was opened aren't new
+    fn collect_check(&mut self) -> Option<Problem> {
+        let found = self.checking.as_ref()?.poll()?;
+        self.checking = None;
+        let mut new_problem = None;
+        if self.checked && found.len() > self.problems.len() {
+            let new = found
+                .iter()
+                .find(|problem| !self.problems.contains(problem))
+                .unwrap_or(&found[0]);
+            self.status.error(format!(
+                "consistency: {} (C for details)",
+                new.show(self.fs.block_format())
+            ));
+            self.alert_until = Some(Instant::now() + Duration::from_secs(2));
+            new_problem = Some(new.clone());
+        }
+        self.problems = found;
+        self.checked = true;
+        new_problem
+    }
+
+    /// Bring everything derived from the image up to date before drawing, so the draw itself
+    /// can't fail
+    pub fn prepare(&mut self) {
+        if self.stale {
+            self.fat_table();
+        }
+        // when time travelling, show the snapshot instead of the live image,
+        // highlighting what changed since the snapshot before it
+        let view = self.timeline.current().unwrap_or(&self.fs);
+        if self.stale {
+            let selected_num = self
+                .list_state
+                .selected()
+                .and_then(|i| self.fat_view.table.get(i))
+                .map(|(block_num, _)| *block_num);
+            let (_, fat_table) = self
+                .fat_table_cache
+                .as_ref()
+                .expect("the FAT was just scanned");
+            self.fat_view = timed("building the FAT view", || {
+                FatView::build(
+                    view,
+                    fat_table,
+                    self.timeline.previous(),
+                    self.sort,
+                    self.filter.as_ref(),
+                    &self.blame,
+                    &self.marks,
+                )
+            });
+            // what changed entries held is only tracked for the live image
+            if self.timeline.current().is_none() {
+                if let Some(previous) = self.blame.recent_previous_entries() {
+                    self.fat_view.previous = previous.clone();
+                }
+            }
+            // keep the same block selected, wherever it ended up in the list
+            if let Some(i) = selected_num.and_then(|b| self.fat_view.index.get(&b)) {
+                self.list_state.select(Some(*i));
+            }
+            self.stale = false;
+        }
+        if self.blame.recent_previous_entries().is_none() {
+            self.fat_view.previous.clear();
+        }
+        if let Some(block_num) = self.goto.take() {
+            match self.fat_view.index.get(&block_num) {
+                Some(i) => self.list_state.select(Some(*i)),
+                None => self.status.error(format!(
+                    "block {} is not allocated",
+                    self.fs.format_block(block_num)
+                )),
+            }
+        }
+        // the table may have shrunk under the selection
+        let len = self.fat_list_len();
+        if self.list_state.selected().unwrap_or(0) >= len && len > 0 {
+            self.list_state.select(Some(len - 1));
+        }
+
+        // the override sticks until the selection moves to the other class of block
+        let is_dir_block = self.is_dir_block();
+        if self
+            .view_override
+            .is_some_and(|(dir_class, _)| dir_class != is_dir_block)
+        {
+            self.view_override = None;
+        }
+        let selected = self.fat_view.table.get(self.selected()).copied();
+        self.block_pane
+            .show(selected.map(|(block_num, _)| block_num));
+        let view = self.timeline.current().unwrap_or(&self.fs);
+        self.selected_block = selected.map(|(block_num, _)| {
+            let block = view.get_block(block_num);
+            if let Err(e) = &block {
+                self.status.error(format!(
+                    "reading block {}: {}",
+                    self.fs.format_block(block_num),
+                    e
+                ));
+            }
+            block
+        });
+        // only the live image's blocks change under the viewer; snapshots are diffed as a whole
+        if let (None, Some((block_num, _)), Some(Ok(block))) =
+            (self.timeline.current(), selected, &self.selected_block)
+        {
+            self.block_cache.observe(block_num, &block.data);
+        }
+        if self.show_diff {
+            match self.diff_missing() {
+                Some(block_num) => self.status.info(format!(
+                    "{} {}: it hasn't changed while viewed",
+                    NO_PREVIOUS_COPY,
+                    self.fs.format_block(block_num)
+                )),
+                // the block changed since, so the message is out of date
+                None if self
+                    .status
+                    .info
+                    .as_ref()
+                    .is_some_and(|info| info.starts_with(NO_PREVIOUS_COPY)) =>
+                {
+                    self.status.info = None
+                }
+                None => {}
+            }
+        }
+
+        let key = (self.blame.generation(), self.timeline.describe());
+        self.tree.refresh(&key);
+        if self.show_stats
+            && self
+                .stats_cache
+                .as_ref()
+                .is_none_or(|(cached, _)| *cached != key)
+        {
+            self.stats_cache = Some((key.clone(), timed("stats", || Stats::collect(view))));
+        }
+        if self
+            .dir_sizes_cache
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != key)
+        {
+            self.dir_sizes_cache = Some((
+                key.clone(),
+                timed("directory sizes", || du::dir_sizes(view)),
+            ));
+        }
+        if self
+            .detected_cache
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != key)
+        {
+            self.detected_cache = Some((key, HashMap::new()));
+        }
+        self.detect_visible_files();
+        if self.hints.is_some() {
+            let in_view = self.topics_in_view();
+            if let Some(hints) = &mut self.hints {
+                hints.visit(&in_view);
+            }
+        }
+    }
+
+    /// What's in view that the demo's hints explain, most specific first
+    fn topics_in_view(&self) -> Vec<Topic> {
+        let mut topics = Vec::new();
+        if self.show_check {
+            topics.push(Topic::Check);
+        }
+        if self.show_stats {
+            topics.push(Topic::Stats);
+        }
+        let selected = self.fat_view.table.get(self.selected());
+        if selected
+            .is_some_and(|(block_num, _)| self.fat_view.ownership.owner(*block_num).is_none())
+        {
+            topics.push(Topic::OrphanChain);
+        }
+        topics.push(Topic::FatList);
+        if selected.is_some() {
+            topics.push(match self.raw_mode() {
+                true => Topic::RawBlock,
+                false => Topic::DirectoryBlock,
+            });
+        }
+        if self.focus == Pane::Block {
+            topics.push(Topic::BlockPane);
+        }
+        topics
+    }
+
+    /// Sniff the files listed in the rows of the directory block in view, that haven't been
+    /// already
+    fn detect_visible_files(&mut self) {
+        let dentries = match &self.selected_block {
+            Some(Ok(block)) if !self.raw_mode() && self.is_dir_block() => self.dentries(block),
+            _ => return,
+        };
+        let rows = self.block_pane.visible_rows(self.focus == Pane::Block);
+        let view = self.timeline.current().unwrap_or(&self.fs);
+        let Some((_, detected)) = &mut self.detected_cache else {
+            return;
+        };
+        let files = dentries
+            .iter()
+            .skip(rows.start)
+            .take(rows.len())
+            .filter(|dentry| dentry.is_in_use() && !dentry.is_dir() && !dentry.is_symlink());
+        for dentry in files {
+            detected
+                .entry(dentry.first_block)
+                .or_insert_with(|| magic::detect_file(view, dentry));
+        }
+    }
+
+    /// Work out what a key press comes to. Open prompts, the palette and the dentry form take
+    /// the keys typed into them; otherwise the key runs the action bound to it. Nothing is
+    /// changed: [`App::apply_key`] carries out what this returns
+    pub fn handle_key(&self, event: &KeyEvent) -> Option<KeyAction> {
+        let ctrl_u =
+            event.code == KeyCode::Char('u') && event.modifiers.contains(KeyModifiers::CONTROL);
+        if let Some(form) = &self.dentry_form {
+            if form.confirm.is_some() {
+                return Some(match event.code {
+                    KeyCode::Char('y') | KeyCode::Enter => KeyAction::Confirm,
+                    // anything else goes back to the form
+                    _ => KeyAction::Back,
+                });
+            }
+            return match event.code {
+                KeyCode::Esc => Some(KeyAction::Cancel),
+                KeyCode::Enter => Some(KeyAction::Submit),
+                KeyCode::Down | KeyCode::Tab => Some(KeyAction::Move(1)),
+                KeyCode::Up | KeyCode::BackTab => Some(KeyAction::Move(-1)),
+                KeyCode::Backspace => Some(KeyAction::Type(Edit::Pop)),
+                // ctrl-u clears the field, as in a shell
+                _ if ctrl_u => Some(KeyAction::Type(Edit::Clear)),
+                KeyCode::Char(c) => Some(KeyAction::Type(Edit::Push(c))),
+                _ => None,
+            };
+        }
+        if self.prompt.is_some() {
+            return match event.code {
+                KeyCode::Enter => Some(KeyAction::Submit),
+                KeyCode::Esc => Some(KeyAction::Cancel),
+                KeyCode::Backspace => Some(KeyAction::Type(Edit::Pop)),
+                // like in the dentry form, ctrl-u clears what's been typed
+                _ if ctrl_u => Some(KeyAction::Type(Edit::Clear)),
+                KeyCode::Char(c) => Some(KeyAction::Type(Edit::Push(c))),
+                _ => None,
+            };
+        }
+        if let Some(palette) = &self.palette {
+            return match event.code {
+                KeyCode::Enter => Some(
+                    actions::search(&palette.input)
+                        .get(palette.selected)
+                        .map_or(KeyAction::Cancel, |binding| KeyAction::Run(binding.action)),
+                ),
+                KeyCode::Esc => Some(KeyAction::Cancel),
+                KeyCode::Up => Some(KeyAction::Move(-1)),
+                KeyCode::Down => Some(KeyAction::Move(1)),
+                KeyCode::Backspace => Some(KeyAction::Type(Edit::Pop)),
+                KeyCode::Char(c) => Some(KeyAction::Type(Edit::Push(c))),
+                _ => None,
+            };
+        }
+        actions::for_key(event).map(KeyAction::Run)
+    }
+
+    /// Carry out what a key press came to, as worked out by [`App::handle_key`]. Returns the
+    /// action to carry out next, if any
+    pub fn apply_key(&mut self, key_action: KeyAction) -> Option<Action> {
+        if let Some(form) = self.dentry_form.take() {
+            self.edit_form(form, key_action);
+            return None;
+        }
+        if let Some(mut active) = self.prompt.take() {
+            match key_action {
+                KeyAction::Submit => self.submit_prompt(active),
+                KeyAction::Cancel => {}
+                KeyAction::Type(edit) => {
+                    edit.apply(&mut active.input);
+                    self.prompt = Some(active);
+                }
+                _ => self.prompt = Some(active),
+            }
+            return None;
+        }
+        if let Some(mut active) = self.palette.take() {
+            match key_action {
+                // the palette closes to run the action
+                KeyAction::Run(action) => return Some(action),
+                KeyAction::Cancel => {}
+                KeyAction::Move(step) => {
+                    let matches = actions::search(&active.input).len();
+                    active.selected = active
+                        .selected
+                        .saturating_add_signed(step)
+                        .min(matches.saturating_sub(1));
+                    self.palette = Some(active);
+                }
+                KeyAction::Type(edit) => {
+                    edit.apply(&mut active.input);
+                    active.selected = 0;
+                    self.palette = Some(active);
+                }
+                _ => self.palette = Some(active),
+            }
+            return None;
+        }
+        match key_action {
+            KeyAction::Run(action) => Some(action),
+            _ => None,
+        }
+    }
+
+    /// Type into the dentry form, or answer its confirmation
+    fn edit_form(&mut self, mut form: DentryForm, key_action: KeyAction) {
+        let fields = form.inputs.len();
+        match key_action {
+            KeyAction::Confirm => return self.write_dentry(form),
+            KeyAction::Back => form.confirm = None,
+            KeyAction::Cancel => return,
+            KeyAction::Submit => match form.changes(&self.fs, self.options.allow_dangerous) {
+                Ok(changes) if changes.is_empty() => {
+                    self.status.info("nothing changed".to_owned());
+                    return;
+                }
+                Ok(changes) => form.confirm = Some(changes),
+                Err(e) => self.status.error(e),
+            },
+            KeyAction::Move(step) => {
+                form.selected = (form.selected as isize + step).rem_euclid(fields as isize) as usize
+            }
+            KeyAction::Type(edit) => edit.apply(&mut form.inputs[form.selected]),
+            KeyAction::Run(_) => {}
+        }
+        self.dentry_form = Some(form);
+    }
+
+    /// Write the confirmed changes of the dentry form to the image, record them in the audit
+    /// file and reload the image
+    fn write_dentry(&mut self, form: DentryForm) {
+        let changes = form.confirm.as_deref().unwrap_or_default();
+        if let Err(e) = form.write(&self.fs, changes) {
+            self.status
+                .error(format!("editing {}: {}", form.dentry.name(), e));
+            return;
+        }
+        let fields: Vec<&str> = changes.iter().map(|c| c.field.label()).collect();
+        self.status.info(format!(
+            "wrote {} of {}",
+            fields.join(", "),
+            form.dentry.name()
+        ));
+        if let Err(e) = edit::audit(&self.fs, &form, changes) {
+            self.status.error(format!(
+                "writing {}: {}",
+                edit::audit_path(self.fs.path()).display(),
+                e
+            ));
+        }
+        self.reload();
+    }
+
+    /// Act on what was typed into a prompt
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let action = match prompt.kind {
+            PromptKind::Filter => Action::Filter,
+            PromptKind::Goto => Action::Goto,
+            PromptKind::Calc => Action::Calc,
+            PromptKind::Note(_) => Action::Note,
+            PromptKind::Adopt(_) => Action::Adopt,
+            PromptKind::CopyTo => Action::CopyTo,
+        };
+        if action.repeatable() {
+            self.last_action = Some(LastAction {
+                action,
+                prompt: Some(prompt.clone()),
+            });
+        }
+        match prompt.kind {
+            PromptKind::Filter => match prompt.input.parse::<Filter>() {
+                Ok(new_filter) => {
+                    self.filter = Some(new_filter);
+                    self.stale = true;
+                }
+                Err(e) => self.status.error(e),
+            },
+            PromptKind::Goto if prompt.input.trim_start().starts_with('@') => {
+                let input = prompt.input.trim_start().trim_start_matches('@');
+                let location = filter::parse_offset(input).map(|offset| self.view().locate(offset));
+                match location {
+                    Some(Some(location)) => {
+                        self.status
+                            .info(location.show(self.view().block_format()).to_string());
+                        // a FAT entry goes to the block it describes
+                        match location {
+                            Location::Fat { entry } => self.goto = Some(entry),
+                            Location::Data { block, .. } => self.goto = Some(block),
+                            // the region is listed after the blocks
+                            Location::Unaddressed { .. } => {
+                                self.list_state.select(Some(self.fat_view.table.len()))
+                            }
+                        }
+                    }
+                    Some(None) => self.status.error(format!(
+                        "offset {} is past the end of the image",
+                        input.trim()
+                    )),
+                    None => self.status.error(format!("invalid offset '{}'", input)),
+                }
+            }
+            PromptKind::Goto => match filter::parse_block(&prompt.input) {
+                Some(block_num) => self.go_to(block_num),
+                None => self
+                    .status
+                    .error(format!("invalid block number '{}'", prompt.input)),
+            },
+            PromptKind::Calc => match calc::eval(&prompt.input, self.view()) {
+                Ok(value) => self.status.info(format!(
+                    "{} = {}",
+                    prompt.input.trim(),
+                    calc::format_value(value)
+                )),
+                Err(e) => self.status.error(e),
+            },
+            PromptKind::Note(block_num) => {
+                self.session.set_note(block_num, &prompt.input);
+                match self.session.save(&self.fs) {
+                    Ok(()) => self.status.info(format!(
+                        "note on block {} {}",
+                        self.fs.format_block(block_num),
+                        match self.session.note(block_num) {
+                            Some(_) => "saved",
+                            None => "deleted",
+                        }
+                    )),
+                    Err(e) => self.status.error(format!("saving notes: {}", e)),
+                }
+            }
+            PromptKind::Adopt(head) => self.adopt(head, &prompt.input),
+            PromptKind::CopyTo => self.copy_to(&prompt.input),
+        }
+    }
+
+    /// Carry out an action. Returns what's left for the main loop to do, if anything
+    pub fn update(&mut self, action: Action) -> Option<Request> {
+        if action.repeatable() {
+            self.last_action = Some(LastAction {
+                action,
+                prompt: None,
+            });
+        }
+        if self.browsing && self.browse(action) {
+            return None;
+        }
+        if self.show_tree && self.navigate_tree(action) {
+            return None;
+        }
+        let fat_table = &self.fat_view.table;
+        match action {
+            Action::Quit => return Some(Request::Quit),
+            Action::Down | Action::Up => match self.focus {
+                Pane::Fat => {
+                    let selected = pane::step(self.selected(), self.fat_list_len(), action);
+                    self.list_state.select(selected);
+                }
+                Pane::Block => {
+                    let last = self.block_pane.rows.saturating_sub(1);
+                    let past_end = match action {
+                        Action::Down => self.block_pane.cursor >= last,
+                        _ => self.block_pane.cursor == 0,
+                    };
+                    match self.block_pane.block {
+                        // scrolling off either end of a directory block carries on into the
+                        // next (or previous) block of the directory's chain
+                        Some(block_num) if past_end && !self.raw_mode() && self.is_dir_block() => {
+                            self.scroll_into_chain(block_num, action == Action::Down)
+                        }
+                        _ => {
+                            self.block_pane.cursor =
+                                pane::step(self.block_pane.cursor, self.block_pane.rows, action)
+                                    .unwrap_or(self.block_pane.cursor);
+                            // a name cut short to fit is shown whole when the cursor gets to it
+                            if let Some(Some(full)) =
+                                self.block_pane.truncated.get(self.block_pane.cursor)
+                            {
+                                self.status.info(full.clone());
+                            }
+                        }
+                    }
+                }
+                Pane::Recent => {
+                    let len = self.recent.changes().len();
+                    let selected = self.recent_state.selected().unwrap_or(0);
+                    self.recent_state.select(pane::step(selected, len, action));
+                }
+            },
+            Action::FocusNext => self.cycle_focus(Pane::next),
+            Action::FocusPrevious => self.cycle_focus(Pane::previous),
+            Action::Select => match self.focus {
+                Pane::Fat => self.focus = Pane::Block,
+                // go to the first block of the dentry under the cursor
+                Pane::Block => {
+                    let dentry = match &self.selected_block {
+                        Some(Ok(block)) if !self.raw_mode() => {
+                            self.dentries(block).into_iter().nth(self.block_pane.cursor)
+                        }
+                        _ => None,
+                    };
+                    match dentry {
+                        Some(dentry) if dentry.is_in_use() && dentry.first_block != 0 => {
+                            self.goto = Some(dentry.first_block)
+                        }
+                        Some(dentry) if dentry.is_in_use() => {
+                            self.status.info(format!("{} has no blocks", dentry.name()))
+                        }
+                        _ => self.status.info("no file under the cursor".to_owned()),
+                    }
+                }
+                Pane::Recent => {
+                    let selected = self.recent_state.selected().unwrap_or(0);
+                    if let Some(change) = self.recent.changes().get(selected) {
+                        match change.first_block {
+                            0 => self.status.info(format!("{} has no blocks", change.path)),
+                            block => self.goto = Some(block),
+                        }
+                    }
+                }
+            },
+            Action::Follow => {
+                // the last entry in the list may well point to another block
+                if let Some((_, next)) = fat_table.get(self.selected()) {
+                    if let Entry::Next(next) = self.view().profile().classify(*next) {
+                        if let Some(i) = self.fat_view.index.get(&next) {
+                            self.list_state.select(Some(*i));
+                        }
+                    }
+                }
+            }
+            Action::NextInFile | Action::PreviousInFile => {
+                if let Some((block_num, _)) = fat_table.get(self.selected()).copied() {
+                    self.step_in_file(block_num, action == Action::NextInFile);
+                }
+            }
+            Action::CycleSort => {
+                self.sort = self.sort.next();
+                self.stale = true;
+            }
+            Action::CycleColumns => {
+                self.session.fat_columns = self.session.fat_columns.next();
+                if let Err(e) = self.session.save(&self.fs) {
+                    self.status.error(format!("saving the session: {}", e));
+                }
+            }
+            Action::ToggleMode => {
+                self.view_override = Some((self.is_dir_block(), !self.raw_mode()));
+            }
+            Action::RawMode => {
+                self.view_override = Some((self.is_dir_block(), true));
+            }
+            Action::DirMode => {
+                self.view_override = Some((self.is_dir_block(), false));
+            }
+            Action::Snapshot => {
+                match snapshot::take(&self.fs, self.options.snapshot_dir.as_deref(), false) {
+                    Ok(path) => self
+                        .status
+                        .info(format!("snapshot saved to {}", path.display())),
+                    Err(e) => self.status.error(format!("taking snapshot: {}", e)),
+                }
+            }
+            Action::Older | Action::Newer => {
+                let dir = self.options.snapshot_dir.as_deref();
+                let moved = if action == Action::Older {
+                    self.timeline.older(&self.fs, dir)
+                } else {
+                    self.timeline.newer(&self.fs, dir)
+                };
+                match moved {
+                    Ok(true) => {
+                        self.stale = true;
+                        self.status.info(format!(
+                            "viewing {}",
+                            self.timeline
+                                .describe()
+                                .unwrap_or_else(|| "the live image".to_owned())
+                        ));
+                    }
+                    Ok(false) if action == Action::Older => {
+                        self.status.info("no older snapshot".to_owned())
+                    }
+                    Ok(false) => self
+                        .status
+                        .info("already viewing the live image".to_owned()),
+                    Err(e) => self.status.error(format!("loading snapshot: {}", e)),
+                }
+            }
+            Action::Filter => {
+                self.prompt = Some(Prompt {
+                    kind: PromptKind::Filter,
+                    input: String::new(),
+                });
+            }
+            Action::Goto => {
+                self.prompt = Some(Prompt {
+                    kind: PromptKind::Goto,
+                    input: String::new(),
+                });
+            }
+            Action::Calc => {
+                self.prompt = Some(Prompt {
+                    kind: PromptKind::Calc,
+                    input: String::new(),
+                });
+            }
+            Action::Note => {
+                // the prompt starts with the current note, to edit it
+                if let Some((block_num, _)) = fat_table.get(self.selected()) {
+                    self.prompt = Some(Prompt {
+                        kind: PromptKind::Note(*block_num),
+                        input: self.session.note(*block_num).unwrap_or_default().to_owned(),
+                    });
+                }
+            }
+            Action::OpenFile => match self.selected_file() {
+                Ok((name, contents)) => return Some(Request::OpenExternally { name, contents }),
+                Err(e) => self.status.error(format!("opening file: {}", e)),
+            },
+            Action::Stats => self.show_stats = !self.show_stats,
+            Action::Stat if self.stat_path.is_some() => self.stat_path = None,
+            Action::Stat => match self.selected_path() {
+                Some(path) => self.stat_path = Some(path),
+                None => self.status.info("no file selected".to_owned()),
+            },
+            Action::Check => self.show_check = !self.show_check,
+            Action::ToggleSizes => self.physical_sizes = !self.physical_sizes,
+            Action::ToggleBase => {
+                let decimal = !self.fs.decimal_blocks();
+                self.fs.set_decimal_blocks(decimal);
+                self.timeline.set_decimal_blocks(decimal);
+                self.stale = true;
+            }
+            Action::Dismiss => {
+                // close the hint or popup first, then dismiss the error, if there's one
+                if self.hints.as_mut().is_some_and(Hints::dismiss) {
+                    return None;
+                }
+                if self.show_stats || self.show_check || self.stat_path.is_some() {
+                    self.show_stats = false;
+                    self.show_check = false;
+                    self.stat_path = None;
+                } else if self.status.error.is_some() {
+                    self.status.acknowledge();
+                } else if !self.marks.is_empty() {
+                    self.marks.clear();
+                    // freed blocks are only listed while they're marked
+                    self.stale = true;
+                } else if self.filter.is_some() {
+                    self.filter = None;
+                    self.stale = true;
+                }
+            }
+            Action::ToggleMark => {
+                if let Some((block_num, next)) = fat_table.get(self.selected()) {
+                    self.marks.toggle(*block_num);
+                    if self.view().profile().classify(*next) == Entry::Free {
+                        self.stale = true;
+                    }
+                }
+            }
+            Action::NextMark => {
+                let selected = self.selected();
+                let mut positions: Vec<usize> = self
+                    .marks
+                    .blocks()
+                    .iter()
+                    .filter_map(|b| self.fat_view.index.get(b).copied())
+                    .collect();
+                positions.sort_unstable();
+                // the first mark below the selection, wrapping around to the top
+                match positions
+                    .iter()
+                    .find(|i| **i > selected)
+                    .or(positions.first())
+                {
+                    Some(i) => self.list_state.select(Some(*i)),
+                    None => self.status.info("no marked blocks in the list".to_owned()),
+                }
+            }
+            Action::ExportMarks | Action::ExportMarksJoined if self.marks.is_empty() => self
+                .status
+                .info("no marked blocks (space to mark)".to_owned()),
+            Action::ExportMarks => {
+                let dir = self.options.snapshot_dir.as_deref();
+                match marks::export_each(self.view(), &self.marks, dir) {
+                    Ok(dir) => self.status.info(format!(
+                        "{} blocks exported to {}",
+                        self.marks.len(),
+                        dir.display()
+                    )),
+                    Err(e) => self.status.error(format!("exporting blocks: {}", e)),
+                }
+            }
+            Action::ExportMarksJoined => {
+                let dir = self.options.snapshot_dir.as_deref();
+                match marks::export_concatenated(self.view(), &self.marks, dir) {
+                    Ok(path) => self.status.info(format!(
+                        "{} blocks exported to {}",
+                        self.marks.len(),
+                        path.display()
+                    )),
+                    Err(e) => self.status.error(format!("exporting blocks: {}", e)),
+                }
+            }
+            Action::MarkStats => {
+                let stats = MarkStats::collect(self.view(), &self.fat_view.ownership, &self.marks);
+                self.status.info(stats.to_string());
+            }
+            Action::CycleDentrySort => {
+                self.dentry_sort = self.dentry_sort.next();
+                self.block_pane.cursor = 0;
+            }
+            Action::ToggleCase => {
+                self.case_sensitive = !self.case_sensitive;
+                self.status.info(
+                    match self.case_sensitive {
+                        true => "sorting names case-sensitively",
+                        false => "sorting names ignoring case",
+                    }
+                    .to_owned(),
+                );
+            }
+            Action::Diff => {
+                self.show_diff = !self.show_diff;
+                if self.show_diff && self.diff_missing().is_none() {
+                    self.status
+                        .info("showing changes since the previous contents".to_owned());
+                }
+            }
+            Action::CycleEncoding => {
+                // after the last encoding, go back to guessing
+                self.encoding = match self.encoding {
+                    None => Some(Encoding::ALL[0]),
+                    Some(current) => Encoding::ALL
+                        .iter()
+                        .skip_while(|e| **e != current)
+                        .nth(1)
+                        .copied(),
+                };
+                self.status.info(match self.encoding {
+                    Some(encoding) => format!("showing raw blocks as {}", encoding.name()),
+                    None => "guessing the encoding of raw blocks".to_owned(),
+                });
+            }
+            Action::EditDentry => self.open_dentry_form(),
+            Action::Adopt => self.open_adopt_prompt(),
+            Action::CopyTo => self.open_copy_prompt(),
+            Action::Zoom => self.zoomed = !self.zoomed,
+            Action::Browse => self.toggle_browser(),
+            Action::Tree => self.toggle_tree(),
+            Action::Parent => self.status.info(
+                "backspace goes up a directory in the file browser (B) or the tree view (T)"
+                    .to_owned(),
+            ),
+            Action::ToggleAnnotations => self.annotate = !self.annotate,
+            Action::ToggleWhitespace => self.show_whitespace = !self.show_whitespace,
+            Action::ToggleWrap => {
+                self.block_pane.no_wrap = !self.block_pane.no_wrap;
+                self.block_pane.column = 0;
+                self.status.info(
+                    match self.block_pane.no_wrap {
+                        true => "cutting long lines off at the side; H and L scroll across",
+                        false => "wrapping long lines",
+                    }
+                    .to_owned(),
+                );
+            }
+            Action::ScrollLeft | Action::ScrollRight if !self.block_pane.no_wrap => self
+                .status
+                .info("long lines wrap; N cuts them off to scroll across".to_owned()),
+            Action::ScrollLeft => self.block_pane.scroll_across(-SCROLL_COLUMNS),
+            Action::ScrollRight => self.block_pane.scroll_across(SCROLL_COLUMNS),
+            Action::Recent => {
+                self.show_recent = !self.show_recent;
+                self.focus = match self.show_recent {
+                    true => Pane::Recent,
+                    false if self.focus == Pane::Recent => Pane::Fat,
+                    false => self.focus,
+                };
+            }
+            Action::Reload => self.force_reload(),
+            Action::Screenshot => return Some(Request::Screenshot),
+            Action::Repeat => return self.repeat(),
+            Action::Palette => {
+                self.palette = Some(Palette {
+                    input: String::new(),
+                    selected: 0,
+                });
+            }
+        }
+        None
+    }
+
+    /// Run the last repeatable action again, on whatever is selected now. An action that
+    /// prompted gets what was submitted to it last time, without prompting again
+    fn repeat(&mut self) -> Option<Request> {
+        let Some(last) = self.last_action.clone() else {
+            self.status.info("nothing to repeat yet".to_owned());
+            return None;
+        };
+        // what the action says about itself goes after what was repeated
+        self.status.info = None;
+        let request = match last.prompt.clone() {
+            Some(mut prompt) => {
+                // a note goes on the selected block, not the one it went on last time
+                if let PromptKind::Note(_) = prompt.kind {
+                    let Some((block_num, _)) = self.fat_view.table.get(self.selected()) else {
+                        self.status
+                            .info("no block selected to add the note to".to_owned());
+                        return None;
+                    };
+                    prompt.kind = PromptKind::Note(*block_num);
+                }
+                self.submit_prompt(prompt);
+                None
+            }
+            None => self.update(last.action),
+        };
+        let repeated = match &last.prompt {
+            Some(prompt) => format!(
+                "repeated '{}' with '{}'",
+                last.action.description(),
+                prompt.input
+            ),
+            None => format!("repeated '{}'", last.action.description()),
+        };
+        let message = match self.status.info.take() {
+            Some(said) => format!("{}: {}", repeated, said),
+            None => repeated,
+        };
+        self.status.info(message);
+        request
+    }
+
+    /// Carry out a navigation action in the file browser. Returns whether the action was one,
+    /// so the rest go on to do what they do in the classic view
+    fn browse(&mut self, action: Action) -> bool {
+        let fs = self.timeline.current().unwrap_or(&self.fs);
+        match action {
+            Action::Down | Action::Up => self.browser.step(fs, action),
+            Action::Select => {
+                if let Err(e) = self.browser.enter(fs) {
+                    self.status.info(e);
+                }
+            }
+            Action::Parent => {
+                if !self.browser.leave(fs) {
+                    self.status.info("already at the root directory".to_owned());
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Carry out a navigation action in the tree view. Returns whether the action was one, so
+    /// the rest go on to do what they do in the classic view
+    fn navigate_tree(&mut self, action: Action) -> bool {
+        let fs = self.timeline.current().unwrap_or(&self.fs);
+        match action {
+            Action::Down | Action::Up => self.tree.step(fs, action),
+            Action::Select => {
+                if let Err(e) = self.tree.toggle(fs) {
+                    self.status.info(e);
+                }
+            }
+            Action::Parent => {
+                if !self.tree.collapse_parent(fs) {
+                    self.status.info("already at the root directory".to_owned());
+                }
+            }
+            // there are no lines of text to scroll across in the tree
+            Action::ScrollRight => match self.tree.load_more(fs) {
+                Ok(listed) => self.status.info(format!("listing the first {}", listed)),
+                Err(e) => self.status.info(e),
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Switch between the file browser and the classic view. Back in the classic view, the
+    /// first block of the entry selected in the browser is selected in the FAT list
+    fn toggle_browser(&mut self) {
+        self.browsing = !self.browsing;
+        if self.browsing {
+            self.show_tree = false;
+            return;
+        }
+        let selected = self.browser.selected(self.view());
+        self.go_to_first_block(selected);
+    }
+
+    /// Switch between the tree view and the classic view, which gets the entry selected in
+    /// the tree as the file browser's does
+    fn toggle_tree(&mut self) {
+        self.show_tree = !self.show_tree;
+        if self.show_tree {
+            self.browsing = false;
+            return;
+        }
+        let selected = self.tree.selected(self.view());
+        self.go_to_first_block(selected);
+    }
+
+    /// Select the first block of an entry in the FAT list, focusing it
+    fn go_to_first_block(&mut self, dentry: Option<Dentry>) {
+        match dentry {
+            Some(dentry) if dentry.first_block != 0 => {
+                self.focus = Pane::Fat;
+                self.go_to(dentry.first_block);
+            }
+            Some(dentry) => self
+                .status
+                .info(format!("{} has no blocks", dentry.display_name())),
+            None => {}
+        }
+    }
+
+    /// Select a block once the FAT view is up to date. Going to a block hidden by the filter
+    /// clears the filter
+    fn go_to(&mut self, block_num: u16) {
+        let allocated = self.fat_table().is_allocated(block_num);
+        if self.filter.is_some() && allocated && !self.fat_view.index.contains_key(&block_num) {
+            self.filter = None;
+            self.stale = true;
+            self.status
+                .info("filter cleared to show the block".to_owned());
+        }
+        self.goto = Some(block_num);
+    }
+
+    /// Go to the next (or previous) block of the file a block belongs to, or of the orphan
+    /// chain it's part of, however the FAT list is ordered or filtered
+    fn step_in_file(&mut self, block_num: u16, forward: bool) {
+        if let Some(target) = self.neighbour_in_chain(block_num, forward) {
+            self.go_to(target);
+            return;
+        }
+        let (path, _) = self.fat_view.chain_of(self.view(), block_num);
+        let name = path.unwrap_or("the orphan chain").to_owned();
+        match forward {
+            true => self.status.info(format!("last block of {}", name)),
+            false => self.status.info(format!("first block of {}", name)),
+        }
+    }
+
+    /// The block after (or before) a block in its chain, if any
+    fn neighbour_in_chain(&self, block_num: u16, forward: bool) -> Option<u16> {
+        let (_, chain) = self.fat_view.chain_of(self.view(), block_num);
+        let i = chain.blocks.iter().position(|b| *b == block_num)?;
+        match forward {
+            true => chain.blocks.get(i + 1).copied(),
+            false => i.checked_sub(1).and_then(|i| chain.blocks.get(i).copied()),
+        }
+    }
+
+    /// Scroll the block pane off the end (or the start) of a directory block, into the next
+    /// (or previous) block of its chain, keeping the cursor at the edge it crossed
+    fn scroll_into_chain(&mut self, block_num: u16, forward: bool) {
+        if let Some(target) = self.neighbour_in_chain(block_num, forward) {
+            self.go_to(target);
+            // the pane keeps its cursor when it's already on the block; going up, it lands on
+            // the last row once the block is drawn
+            self.block_pane.block = Some(target);
+            self.block_pane.cursor = if forward { 0 } else { usize::MAX };
+            self.block_pane.column = 0;
+        }
+    }
+
+    /// What the selected block held before it last changed, if it changed while viewed. Only
+    /// blocks of the live image are kept
+    pub fn previous_contents(&self) -> Option<&[u8]> {
+        if self.timeline.current().is_some() {
+            return None;
+        }
+        let (block_num, _) = self.fat_view.table.get(self.selected())?;
+        self.block_cache.previous(*block_num)
+    }
+
+    /// The selected block, if there's no previous copy of it to diff against
+    fn diff_missing(&self) -> Option<u16> {
+        let (block_num, _) = self.fat_view.table.get(self.selected())?;
+        self.previous_contents().is_none().then_some(*block_num)
+    }
+
+    /// Where the file the selected block belongs to ends in it, if this is the file's last block
+    /// (or past it): the offset of the end within the block, and the size of the file
+    pub fn selected_eof(&self) -> Option<(usize, u32)> {
+        let (block_num, _) = self.fat_view.table.get(self.selected())?;
+        let owner = self
+            .fat_view
+            .ownership
+            .owner(*block_num)
+            .filter(|owner| !owner.is_dir)?;
+        let block_size = self.view().block_size() as usize;
+        // blocks wholly past the end are all slack
+        let eof = (owner.size as usize).saturating_sub(owner.index * block_size);
+        (eof < block_size).then_some((eof, owner.size))
+    }
+
+    /// The encoding a block is shown in raw: the one chosen with U, or else the one its file's
+    /// contents in it look like
+    pub fn encoding(&self, block: &pennfat::Block) -> Encoding {
+        let contents = match self.selected_eof() {
+            Some((eof, _)) => &block.data[..eof.min(block.data.len())],
+            None => &block.data,
+        };
+        self.encoding.unwrap_or_else(|| encoding::sniff(contents))
+    }
+
+    /// How control characters in text are drawn, as toggled with W, in ASCII if the theme is
+    pub fn controls(&self, ascii: bool) -> Controls {
+        match self.show_whitespace {
+            true => Controls::Visible { ascii },
+            false => Controls::Plain,
+        }
+    }
+
+    /// Open the form editing the dentry under the block pane's cursor. Only the live image can
+    /// be edited, and only if it was opened with --writable
+    fn open_dentry_form(&mut self) {
+        if self.timeline.current().is_some() {
+            self.status
+                .error("snapshots can't be edited; go back to the live image".to_owned());
+            return;
+        }
+        if self.fs.mode() != Mode::Writable {
+            self.status.error(PfError::ReadOnly.to_string());
+            return;
+        }
+        let slot = match (&self.selected_block, self.block_pane.block) {
+            (Some(Ok(block)), Some(block_num)) if !self.raw_mode() => {
+                let dentries = block.as_dentries(self.view().dentry_layout());
+                self.dentry_sort
+                    .slots(&dentries, self.case_sensitive)
+                    .get(self.block_pane.cursor)
+                    .filter(|slot| dentries[**slot].is_in_use())
+                    .map(|slot| (block_num, *slot))
+            }
+            _ => None,
+        };
+        let Some((block_num, slot)) = slot else {
+            self.status.info("no file under the cursor".to_owned());
+            return;
+        };
+        match DentryForm::new(&self.fs, block_num, slot) {
+            Ok(form) => self.dentry_form = form,
+            Err(e) => self.status.error(format!(
+                "reading block {}: {}",
+                self.fs.format_block(block_num),
+                e
+            )),
+        }
+    }
+
+    /// Prompt for where to adopt the orphan chain the selected block is part of, suggesting a
+    /// name in the root directory and a size covering the whole chain. Like the dentry form,
+    /// this only works on the live image opened with --writable
+    fn open_adopt_prompt(&mut self) {
+        if self.timeline.current().is_some() {
+            self.status
+                .error("snapshots can't be edited; go back to the live image".to_owned());
+            return;
+        }
+        if self.fs.mode() != Mode::Writable {
+            self.status.error(PfError::ReadOnly.to_string());
+            return;
+        }
+        let Some((block_num, _)) = self.fat_view.table.get(self.selected()).copied() else {
+            return;
+        };
+        let (owner, chain) = self.fat_view.chain_of(&self.fs, block_num);
+        if let Some(owner) = owner {
+            self.status.info(format!(
+                "block {} isn't an orphan, it belongs to {}",
+                self.fs.format_block(block_num),
+                owner
+            ));
+            return;
+        }
+        let head = chain.blocks.first().copied().unwrap_or(block_num);
+        self.prompt = Some(Prompt {
+            kind: PromptKind::Adopt(head),
+            input: format!(
+                "/recovered-{} {}",
+                self.fs.format_block(head),
+                edit::Adoption::estimated_size(&self.fs, head)
+            ),
+        });
+    }
+
+    /// Write a dentry for the orphan chain starting at `head`, where the prompt said, record
+    /// it in the audit file and reload the image
+    fn adopt(&mut self, head: u16, input: &str) {
+        let adoption =
+            match edit::Adoption::plan(&self.fs, head, input, self.options.allow_dangerous) {
+                Ok(adoption) => adoption,
+                Err(e) => {
+                    self.status.error(format!(
+                        "adopting block {}: {}",
+                        self.fs.format_block(head),
+                        e
+                    ));
+                    return;
+                }
+            };
+        if let Err(e) = adoption.write(&self.fs) {
+            self.status.error(format!(
+                "adopting block {}: {}",
+                self.fs.format_block(head),
+                e
+            ));
+            return;
+        }
+        self.status.info(format!(
+            "adopted the chain at {} as {} ({} bytes, {})",
+            self.fs.format_block(head),
+            adoption.path,
+            adoption.size,
+            adoption.describe_place(&self.fs)
+        ));
+        if let Err(e) = edit::audit_adoption(&self.fs, &adoption) {
+            self.status.error(format!(
+                "writing {}: {}",
+                edit::audit_path(self.fs.path()).display(),
+                e
+            ));
+        }
+        self.reload();
+    }
+
+    /// Prompt for the image, and the path in it, to copy the selected file to
+    fn open_copy_prompt(&mut self) {
+        match self.selected_path() {
+            Some(_) => {
+                self.prompt = Some(Prompt {
+                    kind: PromptKind::CopyTo,
+                    input: String::new(),
+                })
+            }
+            None => self.status.info("no file selected".to_owned()),
+        }
+    }
+
+    /// Copy the selected file to `<image>:<path>`, as `pfview cp` does: with its type,
+    /// permissions and modification time, worked out before anything is written, and recorded
+    /// in the destination's audit file. The image being viewed can only be the destination if
+    /// it was opened with --writable, and is reloaded after
+    fn copy_to(&mut self, input: &str) {
+        let Some(src_path) = self.selected_path() else {
+            self.status.info("no file selected".to_owned());
+            return;
+        };
+        let (image, dest_path) = match cli::parse_image_path(input.trim()) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.status.error(e);
+                return;
+            }
+        };
+        let viewed = same_file(Path::new(image), self.fs.path());
+        if viewed && self.fs.mode() != Mode::Writable {
+            self.status.error(PfError::ReadOnly.to_string());
+            return;
+        }
+        let copied = PennFat::load(image, Mode::Writable, self.options.image.window)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut dest| {
+                self.options.image.apply(&mut dest);
+                let (entry, copy) = commands::plan_copy(self.view(), &src_path, &dest, dest_path)?;
+                copy.write(&dest)?;
+                let source = format!("{}:{}", self.fs.path().display(), entry.path);
+                let audited = edit::audit_copy(&dest, &copy, &source).map_err(|e| {
+                    format!("writing {}: {}", edit::audit_path(dest.path()).display(), e)
+                });
+                Ok((entry, copy, dest, audited))
+            });
+        match copied {
+            Ok((entry, copy, dest, audited)) => {
+                self.status.info(format!(
+                    "copied {} to {}:{} ({} bytes in {} blocks, entry in {})",
+                    entry.path,
+                    image,
+                    copy.path,
+                    copy.size(),
+                    copy.blocks.len(),
+                    copy.describe_place(&dest)
+                ));
+                if let Err(e) = audited {
+                    self.status.error(e);
+                }
+            }
+            Err(e) => {
+                self.status.error(format!("copying {}: {:#}", src_path, e));
+                return;
+            }
+        }
+        if viewed {
+            self.reload();
+        }
+    }
+
+    /// The dentries of a directory block, in the order they're shown
+    pub fn dentries(&self, block: &pennfat::Block) -> Vec<Dentry> {
+        let mut dentries = block.as_dentries(self.view().dentry_layout());
+        self.dentry_sort.sort(&mut dentries, self.case_sensitive);
+        dentries
+    }
+
+    /// Move the focus with `step`, past the panes that aren't shown
+    fn cycle_focus(&mut self, step: fn(Pane) -> Pane) {
+        self.focus = step(self.focus);
+        if self.focus == Pane::Recent && !self.show_recent {
+            self.focus = step(self.focus);
+        }
+    }
+
+    /// The path of the file selected where the keys go: the entry selected in the tree view or
+    /// the file browser, the dentry under the block pane's cursor, or else the file the
+    /// selected block belongs to
+    fn selected_path(&self) -> Option<String> {
+        let view = self.view();
+        if self.show_tree {
+            return self.tree.selected_path(view);
+        }
+        if self.browsing {
+            let dentry = self.browser.selected(view)?;
+            let dir_path = self.browser.path();
+            return Some(format!(
+                "{}/{}",
+                dir_path.trim_end_matches('/'),
+                dentry.display_name()
+            ));
+        }
+        let (block_num, _) = self.fat_view.table.get(self.selected())?;
+        let owner = self.fat_view.ownership.owner(*block_num)?;
+        if let (Pane::Block, Some(Ok(block))) = (self.focus, &self.selected_block) {
+            if owner.is_dir && !self.raw_mode() {
+                let dentry = self
+                    .dentries(block)
+                    .into_iter()
+                    .nth(self.block_pane.cursor)
+                    .filter(Dentry::is_in_use)?;
+                return Some(format!(
+                    "{}/{}",
+                    owner.path.trim_end_matches('/'),
+                    dentry.display_name()
+                ));
+            }
+        }
+        Some(owner.path.clone())
+    }
+
+    /// Read the file the selected block belongs to, with its name
+    fn selected_file(&self) -> anyhow::Result<(String, Vec<u8>)> {
+        let view = self.view();
+        let owner = self
+            .fat_view
+            .table
+            .get(self.selected())
+            .and_then(|(block_num, _)| self.fat_view.ownership.owner(*block_num));
+        match owner {
+            None => Err(anyhow!("the selected block isn't part of a file")),
+            Some(owner) if owner.is_dir => Err(anyhow!("{} is a directory", owner.path)),
+            Some(owner) => match walk::lookup(view, &owner.path, false) {
+                Lookup::Found(entry) => Ok((entry.dentry.name(), view.read_file(&entry.dentry)?)),
+                _ => Err(anyhow!("{} can't be found", owner.path)),
+            },
+        }
+    }
+}
+
+/// Checksum the FAT of an image, to tell whether it changed
+fn fat_checksum(fs: &PennFat) -> u32 {
+    let fat_size = fs.fat_size() as usize;
+    history::checksum(&fs.bytes()[..fat_size.min(fs.bytes().len())])
+}
+
+/// Whether two paths name the same file, however they name it
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pfview::fuzz::ImageBuilder;
+    use pfview::testing;
+
+    /// Open an image in memory as the viewer would, drawn once
+    fn open(bytes: Vec<u8>) -> App {
+        let fs = PennFat::from_bytes(bytes).unwrap();
+        let options = Options::parse(&["test.img".to_owned()]).unwrap();
+        let mut app = App::new(fs, options, None);
+        app.prepare();
+        app
+    }
+
+    /// Press a key, carrying out whatever it comes to as the main loop does
+    fn press(app: &mut App, code: KeyCode) {
+        if let Some(action) = app
+            .handle_key(&KeyEvent::from(code))
+            .and_then(|key_action| app.apply_key(key_action))
+        {
+            app.update(action);
+        }
+        app.prepare();
+    }
+
+    /// The block selected in the FAT list
+    fn selected_block(app: &App) -> Option<u16> {
+        app.fat_view
+            .table
+            .get(app.selected())
+            .map(|(block_num, _)| *block_num)
+    }
+
+    fn select(app: &mut App, block_num: u16) {
+        app.goto = Some(block_num);
+        app.prepare();
+        assert_eq!(selected_block(app), Some(block_num));
+    }
+
+    #[test]
+    fn handle_key_changes_nothing() {
+        let mut app = open(testing::sample_image());
+        app.prompt = Some(Prompt {
+            kind: PromptKind::Goto,
+            input: "1".to_owned(),
+        });
+        let key = KeyEvent::from(KeyCode::Char('4'));
+        assert_eq!(app.handle_key(&key), Some(KeyAction::Type(Edit::Push('4'))));
+        assert_eq!(app.prompt.as_ref().unwrap().input, "1");
+        assert_eq!(app.apply_key(KeyAction::Type(Edit::Push('4'))), None);
+        assert_eq!(app.prompt.as_ref().unwrap().input, "14");
+    }
+
+    #[test]
+    fn keys_run_their_bindings_without_a_prompt() {
+        let app = open(testing::sample_image());
+        let key = KeyEvent::from(KeyCode::Char('l'));
+        assert_eq!(app.handle_key(&key), Some(KeyAction::Run(Action::Follow)));
+    }
+
+    #[test]
+    fn navigating_an_empty_fat() {
+        let mut bytes = testing::sample_image();
+        // free every block, the root directory's too
+        let fat_size = 256;
+        bytes[2..fat_size].fill(0);
+        let mut app = open(bytes);
+        assert_eq!(app.fat_list_len(), 0);
+        for code in [
+            KeyCode::Down,
+            KeyCode::Up,
+            KeyCode::Char('l'),
+            KeyCode::Char('n'),
+            KeyCode::Char('p'),
+            KeyCode::Enter,
+        ] {
+            press(&mut app, code);
+            assert_eq!(selected_block(&app), None);
+        }
+    }
+
+    #[test]
+    fn down_stops_at_the_last_entry() {
+        let mut app = open(testing::sample_image());
+        let last = app.fat_view.table.last().unwrap().0;
+        select(&mut app, last);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(selected_block(&app), Some(last));
+        // the last block ends its chain, so there's nothing to follow
+        press(&mut app, KeyCode::Char('l'));
+        assert_eq!(selected_block(&app), Some(last));
+    }
+
+    #[test]
+    fn up_stops_at_the_first_entry() {
+        let mut app = open(testing::sample_image());
+        press(&mut app, KeyCode::Up);
+        assert_eq!(app.selected(), 0);
+    }
+
+    #[test]
+    fn following_a_chain() {
+        let mut app = open(testing::sample_image());
+        // /notes.txt is scattered over 0x04, 0x0c, 0x05 and 0x14
+        select(&mut app, 0x04);
+        let mut visited = vec![0x04];
+        for _ in 0..4 {
+            press(&mut app, KeyCode::Char('l'));
+            visited.push(selected_block(&app).unwrap());
+        }
+        // following stays put at the end of the chain
+        assert_eq!(visited, [0x04, 0x0c, 0x05, 0x14, 0x14]);
+    }
+
+    #[test]
+    fn following_from_the_last_entry() {
+        // the last block in block order points back to an earlier one
+        let mut builder = ImageBuilder::new(0, 1);
+        builder.link(&[5, 2]);
+        let mut app = open(builder.bytes());
+        select(&mut app, 5);
+        assert_eq!(app.selected(), app.fat_list_len() - 1);
+        press(&mut app, KeyCode::Char('l'));
+        assert_eq!(selected_block(&app), Some(2));
+    }
+
+    #[test]
+    fn following_out_of_block_order() {
+        let mut app = open(testing::sample_image());
+        app.sort = SortMode::Next;
+        app.stale = true;
+        app.prepare();
+        select(&mut app, 0x04);
+        press(&mut app, KeyCode::Char('l'));
+        assert_eq!(selected_block(&app), Some(0x0c));
+        press(&mut app, KeyCode::Char('l'));
+        assert_eq!(selected_block(&app), Some(0x05));
+    }
+
+    #[test]
+    fn stepping_through_a_file_from_its_middle() {
+        let mut app = open(testing::sample_image());
+        select(&mut app, 0x05);
+        press(&mut app, KeyCode::Char('p'));
+        assert_eq!(selected_block(&app), Some(0x0c));
+        press(&mut app, KeyCode::Char('p'));
+        press(&mut app, KeyCode::Char('p'));
+        assert_eq!(selected_block(&app), Some(0x04));
+        assert_eq!(
+            app.status.info.as_deref(),
+            Some("first block of /notes.txt")
+        );
+    }
+
+    #[test]
+    fn tab_skips_the_recent_files_unless_shown() {
+        let mut app = open(testing::sample_image());
+        assert_eq!(app.focus, Pane::Fat);
+        press(&mut app, KeyCode::Tab);
+        assert_eq!(app.focus, Pane::Block);
+        press(&mut app, KeyCode::Tab);
+        assert_eq!(app.focus, Pane::Fat);
+        app.show_recent = true;
+        press(&mut app, KeyCode::BackTab);
+        assert_eq!(app.focus, Pane::Recent);
+    }
+
+    #[test]
+    fn navigation_keys_go_to_the_focused_pane() {
+        let mut app = open(testing::sample_image());
+        select(&mut app, 0x01);
+        // enter on the FAT list moves into the block pane
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.focus, Pane::Block);
+        // as drawing would have counted them: README, docs, notes.txt, latest
+        app.block_pane.rows = 4;
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.block_pane.cursor, 2);
+        assert_eq!(selected_block(&app), Some(0x01));
+        // enter on a dentry goes to its first block
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected_block(&app), Some(0x04));
+    }
+
+    #[test]
+    fn palette_enter_runs_the_selected_match() {
+        let mut app = open(testing::sample_image());
+        app.palette = Some(Palette {
+            input: "quit".to_owned(),
+            selected: 0,
+        });
+        let key_action = app.handle_key(&KeyEvent::from(KeyCode::Enter));
+        assert_eq!(key_action, Some(KeyAction::Run(Action::Quit)));
+        assert_eq!(app.apply_key(key_action.unwrap()), Some(Action::Quit));
+        assert!(app.palette.is_none());
+    }
+
+    #[test]
+    fn toggling_the_base_redraws_the_fat_list_in_decimal() {
+        let mut app = open(testing::sample_image());
+        assert!(!app.fat_view.blocks.decimal);
+        app.update(Action::ToggleBase);
+        app.prepare();
+        assert!(app.fs.decimal_blocks());
+        assert!(app.fat_view.blocks.decimal);
+        assert_eq!(app.fat_view.blocks.block(0x2a), "42");
+        app.update(Action::ToggleBase);
+        app.prepare();
+        assert!(!app.fat_view.blocks.decimal);
+    }
+
+    /// Type a block number into the goto prompt and submit it
+    fn go_to_typed(app: &mut App, input: &str) {
+        app.prompt = Some(Prompt {
+            kind: PromptKind::Goto,
+            input: input.to_owned(),
+        });
+        press(app, KeyCode::Enter);
+    }
+
+    #[test]
+    fn goto_takes_hex_or_decimal_whichever_is_shown() {
+        let mut app = open(testing::sample_image());
+        for decimal in [false, true] {
+            if app.fs.decimal_blocks() != decimal {
+                app.update(Action::ToggleBase);
+            }
+            go_to_typed(&mut app, "0x0a");
+            assert_eq!(selected_block(&app), Some(0x0a));
+            go_to_typed(&mut app, "13");
+            assert_eq!(selected_block(&app), Some(13));
+        }
+        go_to_typed(&mut app, "thirteen");
+        assert!(app.status.error.as_ref().unwrap().contains("thirteen"));
+    }
+
+    #[test]
+    fn unallocated_blocks_are_named_as_shown() {
+        let mut app = open(testing::sample_image());
+        go_to_typed(&mut app, "0x70");
+        assert_eq!(
+            app.status.error.take().unwrap(),
+            "block 0x70 is not allocated"
+        );
+        app.update(Action::ToggleBase);
+        go_to_typed(&mut app, "0x70");
+        assert_eq!(
+            app.status.error.take().unwrap(),
+            "block 112 is not allocated"
+        );
+    }
+
+    /// Submit what's typed into a prompt
+    fn submit(app: &mut App, kind: PromptKind, input: &str) {
+        app.prompt = Some(Prompt {
+            kind,
+            input: input.to_owned(),
+        });
+        press(app, KeyCode::Enter);
+    }
+
+    #[test]
+    fn nothing_to_repeat_at_first() {
+        let mut app = open(testing::sample_image());
+        press(&mut app, KeyCode::Char('.'));
+        assert_eq!(app.status.info.as_deref(), Some("nothing to repeat yet"));
+    }
+
+    #[test]
+    fn repeating_a_prompted_action_reuses_its_input() {
+        let mut app = open(testing::sample_image());
+        submit(&mut app, PromptKind::Filter, "dir");
+        assert!(matches!(app.filter, Some(Filter::Dir)));
+        app.filter = None;
+        app.stale = true;
+        press(&mut app, KeyCode::Char('.'));
+        assert!(matches!(app.filter, Some(Filter::Dir)));
+        assert!(app.prompt.is_none(), "repeating prompted again");
+        assert_eq!(
+            app.status.info.as_deref(),
+            Some(format!("repeated '{}' with 'dir'", Action::Filter.description()).as_str())
+        );
+    }
+
+    #[test]
+    fn a_repeated_note_goes_on_the_selected_block() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.img");
+        std::fs::write(&path, testing::sample_image()).unwrap();
+        let fs = PennFat::load(&path, Mode::ReadOnly, Default::default()).unwrap();
+        let options = Options::parse(&[path.display().to_string()]).unwrap();
+        let mut app = App::new(fs, options, None);
+        app.prepare();
+        select(&mut app, 0x02);
+        submit(&mut app, PromptKind::Note(0x02), "seen");
+        select(&mut app, 0x04);
+        press(&mut app, KeyCode::Char('.'));
+        assert_eq!(app.session.note(0x02), Some("seen"));
+        assert_eq!(app.session.note(0x04), Some("seen"));
+    }
+
+    #[test]
+    fn an_image_in_memory_keeps_its_session_to_itself() {
+        let mut app = open(testing::sample_image());
+        press(&mut app, KeyCode::Char('K'));
+        select(&mut app, 0x02);
+        submit(&mut app, PromptKind::Note(0x02), "seen");
+        assert_eq!(app.status.error, None);
+        assert_eq!(app.session.note(0x02), Some("seen"));
+        // nothing was written for the made-up path of the image
+        assert!(!Session::path(app.fs.path()).exists());
+    }
+
+    #[test]
+    fn moving_around_doesnt_replace_what_repeats() {
+        let mut app = open(testing::sample_image());
+        let physical = app.physical_sizes;
+        app.update(Action::ToggleSizes);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Char('.'));
+        assert_eq!(app.physical_sizes, physical);
+        assert!(app
+            .status
+            .info
+            .as_deref()
+            .is_some_and(|info| info.starts_with("repeated '")));
+    }
+
+    #[test]
+    fn w_switches_between_visible_and_plain_controls() {
+        let mut app = open(testing::sample_image());
+        assert_eq!(app.controls(false), Controls::Visible { ascii: false });
+        assert_eq!(app.controls(true), Controls::Visible { ascii: true });
+        press(&mut app, KeyCode::Char('W'));
+        assert_eq!(app.controls(false), Controls::Plain);
+        press(&mut app, KeyCode::Char('W'));
+        assert_eq!(app.controls(false), Controls::Visible { ascii: false });
+    }
+
+    #[test]
+    fn stat_is_about_whatever_is_selected_where_the_keys_go() {
+        let mut app = open(testing::sample_image());
+        // a block in the middle of /notes.txt
+        select(&mut app, 0x0c);
+        press(&mut app, KeyCode::Char('I'));
+        assert_eq!(app.stat_path.as_deref(), Some("/notes.txt"));
+        press(&mut app, KeyCode::Char('I'));
+        assert_eq!(app.stat_path, None);
+
+        // the dentry under the cursor of the root directory's block
+        select(&mut app, 0x01);
+        press(&mut app, KeyCode::Tab);
+        // as many rows as the block has dentry slots, as drawing it would count
+        app.block_pane.rows = 16;
+        for _ in 0..3 {
+            press(&mut app, KeyCode::Char('j'));
+        }
+        press(&mut app, KeyCode::Char('I'));
+        assert_eq!(app.stat_path.as_deref(), Some("/latest"));
+        let Lookup::Found(entry) = walk::lookup(app.view(), "/latest", false) else {
+            panic!("no /latest");
+        };
+        let stat = commands::file_stat(app.view(), &entry);
+        assert_eq!(stat[0], "path: /latest -> docs/guide.txt (file)");
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(app.stat_path, None);
+
+        // the entry selected in the tree view
+        press(&mut app, KeyCode::Char('T'));
+        press(&mut app, KeyCode::Char('j'));
+        press(&mut app, KeyCode::Char('I'));
+        assert_eq!(app.stat_path.as_deref(), Some("/docs"));
+    }
+
+    #[test]
+    fn y_copies_the_selected_file_into_another_image() {
+        let mut app = open(testing::sample_image());
+        // a block in the middle of /notes.txt
+        select(&mut app, 0x0c);
+        press(&mut app, KeyCode::Char('y'));
+        assert!(matches!(
+            app.prompt,
+            Some(Prompt {
+                kind: PromptKind::CopyTo,
+                ..
+            })
+        ));
+        let (path, _) = testing::writable_image(&testing::sample_image());
+        submit(
+            &mut app,
+            PromptKind::CopyTo,
+            &format!("{}:/docs", path.display()),
+        );
+        assert_eq!(app.status.error, None);
+        assert!(app
+            .status
+            .info
+            .as_deref()
+            .is_some_and(|info| info.starts_with("copied /notes.txt to ")));
+        let dest = PennFat::load(&path, Mode::ReadOnly, Default::default()).unwrap();
+        let Lookup::Found(copy) = walk::lookup(&dest, "/docs/notes.txt", false) else {
+            panic!("no copy");
+        };
+        let Lookup::Found(original) = walk::lookup(app.view(), "/notes.txt", false) else {
+            panic!("no /notes.txt");
+        };
+        assert_eq!(
+            dest.read_file(&copy.dentry).unwrap(),
+            app.view().read_file(&original.dentry).unwrap()
+        );
+        assert_eq!(copy.dentry.perm, original.dentry.perm);
+        assert_eq!(copy.dentry.mtime, original.dentry.mtime);
+
+        // an image without room for the file is left as it was
+        let mut full = ImageBuilder::new(0, 1);
+        full.allocate_lowest(124).unwrap();
+        let (path, _) = testing::writable_image(&full.bytes());
+        submit(
+            &mut app,
+            PromptKind::CopyTo,
+            &format!("{}:/notes.txt", path.display()),
+        );
+        assert!(app
+            .status
+            .error
+            .take()
+            .is_some_and(|e| e.contains("no space")));
+        assert_eq!(std::fs::read(&path).unwrap(), full.bytes());
+    }
+
+    #[test]
+    fn the_viewed_image_is_only_copied_into_when_writable() {
+        let (path, fs) = testing::writable_image(&testing::sample_image());
+        let readonly = PennFat::load(&path, Mode::ReadOnly, Default::default()).unwrap();
+        let options = Options::parse(&[path.display().to_string()]).unwrap();
+        let mut app = App::new(readonly, options, None);
+        app.prepare();
+        select(&mut app, 0x02);
+        let input = format!("{}:/README2", path.display());
+        submit(&mut app, PromptKind::CopyTo, &input);
+        assert_eq!(app.status.error.take(), Some(PfError::ReadOnly.to_string()));
+
+        let options = Options::parse(&[path.display().to_string()]).unwrap();
+        let mut app = App::new(fs, options, None);
+        app.prepare();
+        select(&mut app, 0x02);
+        submit(&mut app, PromptKind::CopyTo, &input);
+        assert_eq!(app.status.error, None);
+        // and the copy is seen straight away
+        assert!(matches!(
+            walk::lookup(app.view(), "/README2", false),
+            Lookup::Found(_)
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use tui::widgets::TableState;
+
+use crate::actions::Action;
+use crate::pane;
+use crate::pennfat::{Dentry, PennFat, PfError};
+use crate::walk::{self, Lookup};
+
+/// The most bytes of a file the preview reads
+const PREVIEW_BYTES: usize = 4096;
+
+/// The file browser: the directory tree walked a directory at a time, by name, with no blocks
+/// in sight. Where it's got to is kept while the classic view is up, to come back to
+pub struct Browser {
+    /// The directories descended into from the root, by name and first block
+    path: Vec<(String, u16)>,
+    /// The selected entry of the current directory
+    pub state: TableState,
+}
+
+/// What the preview pane shows of the selected entry
+pub enum Preview {
+    /// The entries of a directory
+    Listing(Vec<Result<Dentry, PfError>>),
+    /// The first bytes of a file, and its size
+    Head(Vec<u8>, u32),
+    /// Where a symbolic link points, and what it names there
+    Link(String, Lookup),
+    /// Nothing could be read
+    Error(PfError),
+}
+
+impl Default for Browser {
+    fn default() -> Self {
+        let mut state = TableState::default();
+        state.select(Some(0));
+        Browser {
+            path: Vec::new(),
+            state,
+        }
+    }
+}
+
+impl Browser {
+    /// Get the first block of the directory being browsed
+    pub fn directory(&self, fs: &PennFat) -> u16 {
+        self.path
+            .last()
+            .map_or(fs.root_block(), |(_, first_block)| *first_block)
+    }
+
+    /// Get the absolute path of the directory being browsed
+    pub fn path(&self) -> String {
+        match self.path.is_empty() {
+            true => "/".to_owned(),
+            false => self
+                .path
+                .iter()
+                .map(|(name, _)| format!("/{}", name))
+                .collect(),
+        }
+    }
+
+    /// Get the names of the directories descended into from the root, for the breadcrumb
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.path.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Read the entries of the directory being browsed. An unreadable block of the directory
+    /// is listed as its error
+    pub fn entries(&self, fs: &PennFat) -> Vec<Result<Dentry, PfError>> {
+        walk::children(fs, self.directory(fs)).collect()
+    }
+
+    /// Get the selected entry, if it could be read
+    pub fn selected(&self, fs: &PennFat) -> Option<Dentry> {
+        let selected = self.state.selected().unwrap_or(0);
+        self.entries(fs).into_iter().nth(selected)?.ok()
+    }
+
+    /// Move the selection by a navigation action
+    pub fn step(&mut self, fs: &PennFat, action: Action) {
+        let len = self.entries(fs).len();
+        let selected = self.state.selected().unwrap_or(0);
+        if let Some(selected) = pane::step(selected, len, action) {
+            self.state.select(Some(selected));
+        }
+    }
+
+    /// Descend into the selected directory, or the directory a selected symbolic link points
+    /// to. Anything else is an error to show
+    pub fn enter(&mut self, fs: &PennFat) -> Result<(), String> {
+        let dentry = self.selected(fs).ok_or("nothing to open")?;
+        let first_block = match dentry.is_symlink() {
+            true => match walk::resolve_link(fs, &self.path(), &dentry) {
+                (_, Lookup::Found(target)) if target.dentry.is_dir() => target.dentry.first_block,
+                (target, lookup) => {
+                    return Err(format!(
+                        "{} points to {} ({})",
+                        dentry.display_name(),
+                        target,
+                        lookup.describe()
+                    ))
+                }
+            },
+            false if dentry.is_dir() => dentry.first_block,
+            false => return Err(format!("{} isn't a directory", dentry.display_name())),
+        };
+        if first_block == 0 {
+            return Err(format!("{} has no blocks", dentry.display_name()));
+        }
+        self.path.push((dentry.name(), first_block));
+        self.state.select(Some(0));
+        Ok(())
+    }
+
+    /// Go up to the parent directory, selecting the directory just left. Returns whether there
+    /// was a parent to go to
+    pub fn leave(&mut self, fs: &PennFat) -> bool {
+        let Some((name, _)) = self.path.pop() else {
+            return false;
+        };
+        let selected = self
+            .entries(fs)
+            .iter()
+            .position(|dentry| dentry.as_ref().is_ok_and(|dentry| dentry.name() == name));
+        self.state.select(Some(selected.unwrap_or(0)));
+        true
+    }
+
+    /// Read what the preview pane shows of an entry of the directory being browsed
+    pub fn preview(&self, fs: &PennFat, dentry: &Dentry) -> Preview {
+        if dentry.is_dir() {
+            return Preview::Listing(walk::children(fs, dentry.first_block).collect());
+        }
+        if dentry.is_symlink() {
+            let (target, lookup) = walk::resolve_link(fs, &self.path(), dentry);
+            return Preview::Link(target, lookup);
+        }
+        // only as many blocks are read as the head needs, however large the file
+        let len = (dentry.size as usize).min(PREVIEW_BYTES);
+        let mut head = Vec::new();
+        for block in fs.chain(dentry.first_block).blocks {
+            if head.len() >= len {
+                break;
+            }
+            match fs.block_bytes(block) {
+                Ok(bytes) => head.extend_from_slice(bytes),
+                Err(e) => return Preview::Error(e),
+            }
+        }
+        head.truncate(len);
+        Preview::Head(head, dentry.size)
+    }
+}
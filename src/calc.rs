@@ -0,0 +1,276 @@
+use crate::filter::parse_offset;
+use crate::pennfat::PennFat;
+
+/// A piece of an expression
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    /// An operator, parenthesis or comma
+    Punct(char),
+}
+
+/// Split an expression into tokens. Numbers are decimal, or hex with a 0x prefix (and
+/// optionally grouped with underscores, as offsets are shown)
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &input[start..end];
+            tokens.push(match c.is_ascii_digit() {
+                true => parse_offset(word)
+                    .and_then(|n| i64::try_from(n).ok())
+                    .map(Token::Number)
+                    .ok_or_else(|| format!("invalid number {}", word))?,
+                false => Token::Name(word.to_owned()),
+            });
+        } else if "+-*/%(),".contains(c) {
+            tokens.push(Token::Punct(c));
+            chars.next();
+        } else {
+            return Err(format!("unexpected '{}'", c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// The most parentheses, calls and minus signs an expression can nest, well short of what
+/// would overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// A recursive-descent parser that evaluates an expression as it goes:
+///
+/// ```text
+/// expr    = term (("+" | "-") term)*
+/// term    = unary (("*" | "/" | "%") unary)*
+/// unary   = "-" unary | primary
+/// primary = number | name | name "(" expr ("," expr)* ")" | "(" expr ")"
+/// ```
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// How deeply nested the part of the expression being parsed is
+    depth: usize,
+    fs: &'a PennFat,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// Move past the next token if it's the punctuation `c`. Returns whether it was
+    fn eat(&mut self, c: char) -> bool {
+        let matches = self.peek() == Some(&Token::Punct(c));
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.eat(c) {
+            true => Ok(()),
+            false => Err(format!("expected '{}'", c)),
+        }
+    }
+
+    /// Parse a nested part of the expression with `parse`, unless it's nested too deeply
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("nested more than {} deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn expr(&mut self) -> Result<i64, String> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value = value.checked_add(self.term()?).ok_or("overflow")?;
+            } else if self.eat('-') {
+                value = value.checked_sub(self.term()?).ok_or("overflow")?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value = value.checked_mul(self.unary()?).ok_or("overflow")?;
+            } else if self.eat('/') {
+                value = value.checked_div(self.unary()?).ok_or("division by zero")?;
+            } else if self.eat('%') {
+                value = value.checked_rem(self.unary()?).ok_or("division by zero")?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.eat('-') {
+            true => self
+                .nested(Self::unary)?
+                .checked_neg()
+                .ok_or_else(|| "overflow".to_owned()),
+            false => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(n),
+            Token::Punct('(') => {
+                let value = self.nested(Self::expr)?;
+                self.expect(')')?;
+                Ok(value)
+            }
+            Token::Name(name) if self.eat('(') => {
+                let mut args = vec![self.nested(Self::expr)?];
+                while self.eat(',') {
+                    args.push(self.nested(Self::expr)?);
+                }
+                self.expect(')')?;
+                self.call(&name, &args)
+            }
+            Token::Name(name) => self.variable(&name),
+            Token::Punct(c) => Err(format!("unexpected '{}'", c)),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        let fs = self.fs;
+        Ok(match name {
+            "fat_size" => fs.fat_size() as i64,
+            "block_size" => fs.block_size() as i64,
+            "dentry_size" => fs.dentry_layout().size as i64,
+            "data_blocks" => fs.data_block_count() as i64,
+            _ => return Err(format!("unknown variable {}", name)),
+        })
+    }
+
+    fn call(&self, name: &str, args: &[i64]) -> Result<i64, String> {
+        match (name, args) {
+            ("block", [block]) => self.block(*block),
+            ("slot", [block, slot]) => {
+                let layout = self.fs.dentry_layout();
+                let slots = self.fs.block_size() as i64 / layout.size as i64;
+                if !(0..slots).contains(slot) {
+                    return Err(format!(
+                        "slot {} is out of range (0 to {})",
+                        slot,
+                        slots - 1
+                    ));
+                }
+                Ok(self.block(*block)? + slot * layout.size as i64)
+            }
+            ("block" | "slot", _) => Err(format!("wrong number of arguments for {}", name)),
+            _ => Err(format!("unknown function {}", name)),
+        }
+    }
+
+    /// The offset of a data block in the image
+    fn block(&self, block: i64) -> Result<i64, String> {
+        let last = self.fs.data_block_count();
+        match u16::try_from(block) {
+            Ok(block) if (1..=last).contains(&block) => self
+                .fs
+                .block_offset(block)
+                .map(|offset| offset as i64)
+                .map_err(|e| e.to_string()),
+            _ => Err(format!(
+                "block {} is out of range ({} to {})",
+                block,
+                self.fs.format_block(1),
+                self.fs.format_block(last)
+            )),
+        }
+    }
+}
+
+/// Evaluate an integer expression, with the image's geometry to hand: the variables `fat_size`,
+/// `block_size`, `dentry_size` and `data_blocks`, and the functions `block(n)` (the offset of
+/// block n in the image) and `slot(n, i)` (the offset of dentry slot i of block n)
+pub fn eval(input: &str, fs: &PennFat) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        depth: 0,
+        fs,
+    };
+    let value = parser.expr()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(Token::Punct(c)) => Err(format!("unexpected '{}'", c)),
+        Some(_) => Err("expected an operator".to_owned()),
+    }
+}
+
+/// Show a value in hex and in decimal
+pub fn format_value(value: i64) -> String {
+    match value < 0 {
+        true => format!("-{:#x} ({})", value.unsigned_abs(), value),
+        false => format!("{:#x} ({})", value, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pfview::testing::sample_image;
+
+    #[test]
+    fn offsets_come_from_the_geometry() {
+        let fs = PennFat::from_bytes(sample_image()).unwrap();
+        let slot = fs.block_offset(2).unwrap() as i64 + 3 * fs.dentry_layout().size as i64;
+        assert_eq!(eval("slot(0x2, 3)", &fs), Ok(slot));
+        assert_eq!(
+            eval("block(1) - fat_size", &fs),
+            Ok(fs.block_offset(1).unwrap() as i64 - fs.fat_size() as i64)
+        );
+        assert_eq!(eval("-(2 + 0x10) * 3 % 7", &fs), Ok(-54 % 7));
+        assert!(eval("block(0)", &fs).is_err());
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_stack_overflow() {
+        let fs = PennFat::from_bytes(sample_image()).unwrap();
+        let parens = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&parens(MAX_DEPTH), &fs), Ok(1));
+        let too_deep = Err(format!("nested more than {} deep", MAX_DEPTH));
+        assert_eq!(eval(&parens(MAX_DEPTH + 1), &fs), too_deep);
+        for input in [
+            parens(1_000_000),
+            "-".repeat(1_000_000) + "1",
+            "block(".repeat(1_000_000) + "1",
+        ] {
+            assert_eq!(eval(&input, &fs), too_deep);
+        }
+        // minus signs nest as parentheses do, so these two are as deep as each other
+        assert_eq!(eval(&("-".repeat(MAX_DEPTH) + "1"), &fs), Ok(1));
+        assert_eq!(eval(&("-".repeat(MAX_DEPTH + 1) + "1"), &fs), too_deep);
+    }
+}
@@ -0,0 +1,457 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+
+use log::warn;
+use serde::Serialize;
+
+use crate::pennfat::{BlockFormat, Entry, PennFat, ShowBlocks};
+use crate::walk;
+
+/// A consistency problem found in the FAT, or in a directory breaking a rule of the dentry
+/// layout
+#[derive(Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// A block's FAT entry points outside the data region
+    OutOfRange { block: u16, next: u16 },
+    /// More than one block's FAT entry points to the same block
+    CrossLink { block: u16, from: Vec<u16> },
+    /// Following the FAT from a block comes back around to it. `block` is the lowest block
+    /// in the cycle
+    Cycle { block: u16, length: usize },
+    /// FAT entry 0, which holds the configuration, changed since the image was loaded, so the
+    /// image is being read with a configuration it no longer has
+    ConfigChanged { entry: u16, loaded: u16 },
+    /// A FAT entry past the last data block is set, though no block can ever use it
+    Unreachable { entry: u32, value: u16 },
+    /// A directory that must be packed has an entry in use after a free slot, first at `slot`
+    /// of `block`
+    Unpacked {
+        dir: String,
+        block: u16,
+        slot: usize,
+    },
+    /// A directory that must be sorted by name has an entry that sorts before the one ahead of
+    /// it, first at `slot` of `block`
+    Unsorted {
+        dir: String,
+        block: u16,
+        slot: usize,
+    },
+}
+
+impl Problem {
+    /// The kind of problem, as counted in summaries
+    fn kind(&self) -> &'static str {
+        match self {
+            Problem::OutOfRange { .. } => "out of range",
+            Problem::CrossLink { .. } => "cross-linked",
+            Problem::Cycle { .. } => "cycle",
+            Problem::ConfigChanged { .. } => "config changed",
+            Problem::Unreachable { .. } => "unreachable",
+            Problem::Unpacked { .. } => "unpacked",
+            Problem::Unsorted { .. } => "unsorted",
+        }
+    }
+
+    /// The block the problem is at, if it's at one
+    pub fn block(&self) -> Option<u16> {
+        match self {
+            Problem::OutOfRange { block, .. }
+            | Problem::CrossLink { block, .. }
+            | Problem::Cycle { block, .. }
+            | Problem::Unpacked { block, .. }
+            | Problem::Unsorted { block, .. } => Some(*block),
+            Problem::ConfigChanged { .. } | Problem::Unreachable { .. } => None,
+        }
+    }
+
+    /// Whether the problem makes the image read wrongly, rather than just being untidy
+    pub fn severe(&self) -> bool {
+        !matches!(
+            self,
+            Problem::CrossLink { .. }
+                | Problem::Unreachable { .. }
+                | Problem::Unpacked { .. }
+                | Problem::Unsorted { .. }
+        )
+    }
+}
+
+impl ShowBlocks for Problem {
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result {
+        match self {
+            Problem::OutOfRange { block, next } => write!(
+                f,
+                "block {} points to {}, outside the data region",
+                blocks.block(*block),
+                blocks.block(*next)
+            ),
+            Problem::CrossLink { block, from } => {
+                let from: Vec<String> = from.iter().map(|b| blocks.block(*b)).collect();
+                write!(
+                    f,
+                    "block {} is linked from {}",
+                    blocks.block(*block),
+                    from.join(", ")
+                )
+            }
+            Problem::Cycle { block, length } => write!(
+                f,
+                "the chain through block {} loops after {} blocks",
+                blocks.block(*block),
+                length
+            ),
+            Problem::ConfigChanged { entry, loaded } => write!(
+                f,
+                "FAT entry 0 is {:#06x}, but the image was loaded as {:#06x} ({})",
+                entry,
+                loaded,
+                describe_config(*loaded)
+            ),
+            Problem::Unreachable { entry, value } => write!(
+                f,
+                "FAT entry {:#06x} set (to {}) but unreachable, past the last data block",
+                entry,
+                blocks.block(*value)
+            ),
+            Problem::Unpacked { dir, block, slot } => write!(
+                f,
+                "directory {} isn't packed: slot {} of block {} is in use after a free slot",
+                dir,
+                slot,
+                blocks.block(*block)
+            ),
+            Problem::Unsorted { dir, block, slot } => write!(
+                f,
+                "directory {} isn't sorted: slot {} of block {} sorts before the entry ahead of it",
+                dir,
+                slot,
+                blocks.block(*block)
+            ),
+        }
+    }
+}
+
+/// Summarize problems by kind, e.g. "3 issues: 1 cycle, 2 cross-linked", or "OK" if there
+/// are none
+pub fn summarize(problems: &[Problem]) -> String {
+    if problems.is_empty() {
+        return "OK".to_owned();
+    }
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for problem in problems {
+        match counts.iter_mut().find(|(kind, _)| *kind == problem.kind()) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((problem.kind(), 1)),
+        }
+    }
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(kind, count)| format!("{} {}", count, kind))
+        .collect();
+    format!(
+        "{} issue{}: {}",
+        problems.len(),
+        if problems.len() == 1 { "" } else { "s" },
+        counts.join(", ")
+    )
+}
+
+/// Describe the configuration held in FAT entry 0, e.g. "1 FAT block, 256-byte blocks"
+pub fn describe_config(entry: u16) -> String {
+    let [block_size_config, fat_blocks] = entry.to_le_bytes();
+    let block_size = match block_size_config {
+        0..=7 => format!("{}-byte blocks", 256 << block_size_config),
+        _ => format!("invalid block size config {}", block_size_config),
+    };
+    format!(
+        "{} FAT block{}, {}",
+        fat_blocks,
+        if fat_blocks == 1 { "" } else { "s" },
+        block_size
+    )
+}
+
+/// Run the cheap consistency checks on the FAT alone: a changed configuration, out-of-range
+/// entries, cross-links, cycles and entries set past the last data block. The problems come
+/// sorted by block
+pub fn check(fs: &PennFat) -> Vec<Problem> {
+    let table = fs.get_fat_table();
+    let mut problems = Vec::new();
+    // the links between data blocks, by the block they come from
+    let mut links: HashMap<u16, u16> = HashMap::new();
+    let mut predecessors: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+
+    if let Some(entry) = fs.fat_entry(0).filter(|entry| *entry != fs.config_entry()) {
+        problems.push(Problem::ConfigChanged {
+            entry,
+            loaded: fs.config_entry(),
+        });
+    }
+
+    for &(block, next) in &table {
+        let in_range = match fs.profile().classify(next) {
+            Entry::Eof | Entry::Reserved => continue,
+            Entry::Free => false,
+            Entry::Next(next) => next != 0 && next <= fs.data_block_count(),
+        };
+        if !in_range {
+            problems.push(Problem::OutOfRange { block, next });
+            continue;
+        }
+        links.insert(block, next);
+        predecessors.entry(next).or_default().push(block);
+    }
+
+    for (block, from) in predecessors {
+        if from.len() > 1 {
+            problems.push(Problem::CrossLink { block, from });
+        }
+    }
+
+    // every block has at most one next block, so each walk either ends or runs into a cycle.
+    // Blocks are marked with the walk that reached them, so each is only walked once
+    let mut walked: HashMap<u16, u16> = HashMap::new();
+    let mut starts: Vec<u16> = links.keys().copied().collect();
+    starts.sort_unstable();
+    for start in starts {
+        let mut block = start;
+        while !walked.contains_key(&block) {
+            walked.insert(block, start);
+            match links.get(&block) {
+                Some(next) => block = *next,
+                None => break,
+            }
+        }
+        // running into this walk's own trail means a new cycle
+        if walked[&block] == start && links.contains_key(&block) {
+            let mut cycle = vec![block];
+            let mut next = links[&block];
+            while next != block {
+                cycle.push(next);
+                next = links[&next];
+            }
+            problems.push(Problem::Cycle {
+                block: *cycle.iter().min().expect("a cycle has blocks"),
+                length: cycle.len(),
+            });
+        }
+    }
+
+    problems.extend(
+        fs.unreachable_entries()
+            .into_iter()
+            .map(|(entry, value)| Problem::Unreachable { entry, value }),
+    );
+
+    problems.sort_by_key(|problem| match problem {
+        Problem::Unreachable { entry, .. } => *entry,
+        _ => problem.block().map_or(0, u32::from),
+    });
+    problems
+}
+
+/// Check every directory against the rules of the dentry layout: packed, with no entry in use
+/// after a free slot, and sorted by name. A directory's slots count across the blocks of its
+/// chain, and each rule it breaks is reported once, at the first slot that breaks it
+pub fn check_directories(fs: &PennFat) -> Vec<Problem> {
+    let layout = fs.dentry_layout();
+    if !layout.packed_dirs && !layout.sorted_dirs {
+        return Vec::new();
+    }
+    let mut dirs = vec![("/".to_owned(), fs.root_block())];
+    dirs.extend(
+        walk::walk(fs)
+            .into_iter()
+            .filter(|entry| entry.dentry.is_dir())
+            .map(|entry| (entry.path, entry.dentry.first_block)),
+    );
+    // a directory linked in more than once is checked once
+    let mut checked = HashSet::new();
+    let mut problems = Vec::new();
+    for (dir, first_block) in dirs {
+        if !checked.insert(first_block) {
+            continue;
+        }
+        let mut after_free = false;
+        let mut previous: Option<String> = None;
+        let (mut unpacked, mut unsorted) = (None, None);
+        for block in fs.chain(first_block).blocks {
+            // unreadable blocks are skipped, as when listing the directory
+            let Ok(contents) = fs.get_block(block) else {
+                continue;
+            };
+            for (slot, dentry) in contents.as_dentries(layout).iter().enumerate() {
+                if dentry.is_free() {
+                    after_free = true;
+                }
+                if !dentry.is_in_use() {
+                    continue;
+                }
+                if after_free && unpacked.is_none() {
+                    unpacked = Some((block, slot));
+                }
+                let name = dentry.name();
+                if previous.as_ref().is_some_and(|previous| name < *previous) && unsorted.is_none()
+                {
+                    unsorted = Some((block, slot));
+                }
+                previous = Some(name);
+            }
+        }
+        if let Some((block, slot)) = unpacked.filter(|_| layout.packed_dirs) {
+            problems.push(Problem::Unpacked {
+                dir: dir.clone(),
+                block,
+                slot,
+            });
+        }
+        if let Some((block, slot)) = unsorted.filter(|_| layout.sorted_dirs) {
+            problems.push(Problem::Unsorted { dir, block, slot });
+        }
+    }
+    problems
+}
+
+/// Run every consistency check: the FAT's, and the directories' if the layout has rules for them
+pub fn check_all(fs: &PennFat) -> Vec<Problem> {
+    let mut problems = check(fs);
+    problems.extend(check_directories(fs));
+    problems
+}
+
+/// A consistency check running on a worker thread, so a large FAT can't hold up drawing
+pub struct Pending {
+    result: mpsc::Receiver<Vec<Problem>>,
+}
+
+impl Pending {
+    /// Start checking an image, on a view of it of its own
+    pub fn start(fs: &PennFat) -> Self {
+        let (tx, result) = mpsc::channel();
+        match fs.try_clone() {
+            Ok(fs) => {
+                thread::spawn(move || {
+                    // the receiver is gone if a newer check took over
+                    let _ = tx.send(check_all(&fs));
+                });
+            }
+            // without a view of its own, the check has to run here
+            Err(e) => {
+                warn!(
+                    "checking on the main thread, as the image couldn't be reopened: {}",
+                    e
+                );
+                let _ = tx.send(check_all(fs));
+            }
+        }
+        Pending { result }
+    }
+
+    /// Get the problems found, once the check is done
+    pub fn poll(&self) -> Option<Vec<Problem>> {
+        self.result.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::{DentrySpec, ImageBuilder};
+    use crate::pennfat::DentryLayout;
+    use crate::testing;
+
+    /// An image whose root holds a directory "d" and whose "d" holds files, each at the slot
+    /// given, loaded with both rules for directories on
+    fn image_with_dir(files: &[(usize, &str)]) -> PennFat {
+        let mut image = ImageBuilder::new(0, 1);
+        let dir = image.allocate_lowest(1).unwrap()[0];
+        let dentry = |name: &str, first_block, type_| DentrySpec {
+            name: name.as_bytes().to_vec(),
+            size: 0,
+            first_block,
+            type_,
+            perm: 6,
+            mtime: 0,
+        };
+        image.write_dentry(1, 0, &dentry("d", dir, 1));
+        for (slot, name) in files {
+            image.write_dentry(dir, *slot, &dentry(name, 0, 0));
+        }
+        let mut fs = PennFat::from_bytes(image.bytes()).unwrap();
+        fs.set_dentry_layout(DentryLayout {
+            packed_dirs: true,
+            sorted_dirs: true,
+            ..DentryLayout::PENNFAT_V1
+        });
+        fs
+    }
+
+    fn shown(fs: &PennFat, problems: &[Problem]) -> Vec<String> {
+        problems
+            .iter()
+            .map(|problem| problem.show(fs.block_format()).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn packed_and_sorted_directories_pass() {
+        let fs = image_with_dir(&[(0, "a"), (1, "b"), (2, "c")]);
+        assert!(check_all(&fs).is_empty());
+    }
+
+    #[test]
+    fn an_entry_after_a_free_slot_is_unpacked() {
+        let fs = image_with_dir(&[(0, "a"), (2, "b"), (3, "c")]);
+        assert_eq!(
+            shown(&fs, &check_directories(&fs)),
+            ["directory /d isn't packed: slot 2 of block 0x02 is in use after a free slot"]
+        );
+    }
+
+    #[test]
+    fn an_entry_sorting_before_the_one_ahead_is_unsorted() {
+        let fs = image_with_dir(&[(0, "a"), (1, "c"), (2, "b"), (3, "a")]);
+        assert_eq!(
+            shown(&fs, &check_directories(&fs)),
+            ["directory /d isn't sorted: slot 2 of block 0x02 sorts before the entry ahead of it"]
+        );
+    }
+
+    #[test]
+    fn directories_are_only_checked_when_asked() {
+        let mut fs = image_with_dir(&[(1, "b"), (2, "a")]);
+        assert_eq!(check_directories(&fs).len(), 2);
+        fs.set_dentry_layout(DentryLayout::PENNFAT_V1);
+        assert!(check_directories(&fs).is_empty());
+    }
+
+    #[test]
+    fn entry_zero_is_not_a_block() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        assert_eq!(fs.fat_entry(0), Some(fs.config_entry()));
+        assert!(fs.get_fat_table().iter().all(|(block, _)| *block != 0));
+        assert!(check(&fs).is_empty());
+    }
+
+    #[test]
+    fn entry_zero_disagreeing_with_the_header_is_flagged() {
+        let (_path, fs) = testing::writable_image(&testing::sample_image());
+        let loaded = fs.config_entry();
+        // 512-byte blocks instead of 256
+        fs.write_bytes(0, &(loaded + 1).to_le_bytes()).unwrap();
+        let problems = check(&fs);
+        assert!(matches!(
+            problems.as_slice(),
+            [Problem::ConfigChanged { entry, loaded: l }] if *entry == loaded + 1 && *l == loaded
+        ));
+        let shown = problems[0].show(fs.block_format()).to_string();
+        assert_eq!(
+            shown,
+            "FAT entry 0 is 0x0101, but the image was loaded as 0x0100 (1 FAT block, 256-byte blocks)"
+        );
+    }
+}
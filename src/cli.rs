@@ -0,0 +1,1031 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use log::LevelFilter;
+
+use crate::filter::{parse_block, parse_offset};
+use crate::pennfat::{DentryLayout, Mode, PennFat, Profile, Window};
+use crate::report::Format;
+use crate::walk;
+
+/// How to read an image: the options shared by the viewer and every subcommand
+pub struct ImageOptions {
+    /// Path to the PennFat image
+    pub path: String,
+    /// How directory entries are laid out
+    pub dentry_layout: DentryLayout,
+    /// Size of a directory entry in bytes, overriding the layout's
+    pub dentry_size: Option<usize>,
+    /// The block holding the root directory, instead of finding it
+    pub root_block: Option<u16>,
+    /// Show block numbers in decimal rather than hex
+    pub decimal_blocks: bool,
+    /// What the special values of FAT entries mean (pennfat's unless asked otherwise), or None
+    /// to guess from the image
+    pub profile: Option<Profile>,
+    /// Require directories to be packed, whatever the dentry layout says
+    pub packed_dirs: bool,
+    /// Require directories to be sorted by name, whatever the dentry layout says
+    pub sorted_dirs: bool,
+    /// Where in the file the image lies, for an image embedded in a larger one
+    pub window: Window,
+    /// The most blocks a chain is followed for, or None for as many as the image has
+    pub max_chain_len: Option<u16>,
+}
+
+impl ImageOptions {
+    /// Set up a loaded image to be read the way these options say. Returns a warning to show
+    /// if the root directory had to be guessed
+    pub fn apply(&self, fs: &mut PennFat) -> Option<String> {
+        fs.set_decimal_blocks(self.decimal_blocks);
+        let mut layout = match self.dentry_size {
+            Some(size) => self.dentry_layout.with_size(size),
+            None => self.dentry_layout,
+        };
+        layout.packed_dirs |= self.packed_dirs;
+        layout.sorted_dirs |= self.sorted_dirs;
+        fs.set_dentry_layout(layout);
+        if let Some(limit) = self.max_chain_len {
+            fs.set_max_chain_len(limit);
+        }
+        let warning = walk::find_root(fs, self.root_block);
+        // guessed knowing where the root is, as the guess hangs on nothing linking to it
+        fs.set_profile(self.profile.unwrap_or_else(|| Profile::detect(fs)));
+        warning
+    }
+}
+
+/// What a subcommand reads from the image: a file by its path, or the chain starting at a block
+pub enum Target {
+    Path(String),
+    Block(u16),
+}
+
+impl Target {
+    /// Parse a target: `@<block>` (in decimal or hex) names a block, and anything else is a path.
+    /// A file whose name starts with '@' can still be named as `./@name`
+    pub fn parse(arg: &str) -> Result<Self, String> {
+        match arg.strip_prefix('@') {
+            Some(block) => parse_block(block)
+                .map(Target::Block)
+                .ok_or_else(|| format!("invalid block {}", arg)),
+            None => Ok(Target::Path(arg.to_owned())),
+        }
+    }
+}
+
+/// The exit status of a command that failed to run, e.g. on an image that can't be read
+pub const EXIT_FAILURE: i32 = 1;
+/// The exit status of a command that ran, and found problems with the image
+pub const EXIT_PROBLEMS: i32 = 2;
+/// The exit status of a command line that doesn't parse (EX_USAGE)
+pub const EXIT_USAGE: i32 = 64;
+
+/// What pfview was asked to do
+pub enum Command {
+    /// Print the usage text
+    Help,
+    /// Browse the image in the viewer
+    View(Options),
+    /// List a directory in the image (or a single file, or a block's chain)
+    Ls {
+        image: ImageOptions,
+        target: Target,
+        /// Print every read made from the image
+        trace: bool,
+        /// Show permissions as the perm byte, rather than like `ls -l`
+        numeric: bool,
+    },
+    /// Print a file in the image (or a chain of blocks)
+    Cat {
+        image: ImageOptions,
+        target: Target,
+        /// Follow a symbolic link at the path, instead of printing its target
+        follow: bool,
+        /// Print every read made from the image
+        trace: bool,
+    },
+    /// Copy a file (or a chain of blocks) out of the image
+    Extract {
+        image: ImageOptions,
+        target: Target,
+        dest: PathBuf,
+        /// Follow a symbolic link at the path, instead of extracting its target
+        follow: bool,
+        /// Include the slack after the file's contents in its last block
+        slack: bool,
+        /// Print every read made from the image
+        trace: bool,
+    },
+    /// Copy every file of the image into a directory, with a manifest of their names
+    ExtractAll { image: ImageOptions, dest: PathBuf },
+    /// Print a file's dentry and chain, or what file and chain a block belongs to
+    Stat {
+        image: ImageOptions,
+        target: Target,
+        /// Follow a symbolic link at the path, instead of describing the link
+        follow: bool,
+        /// Print every read made from the image
+        trace: bool,
+    },
+    /// Print where a path leads: the file or directory it names and its first block
+    Lookup {
+        image: ImageOptions,
+        path: String,
+        /// Follow a symbolic link at the end of the path, instead of stopping at the link
+        follow: bool,
+        /// Print every read made from the image
+        trace: bool,
+    },
+    /// Print the image's chains (or one of them) as a Graphviz digraph
+    Graph {
+        image: ImageOptions,
+        /// The file or block whose chain to draw, or None for every chain
+        target: Option<Target>,
+        /// Follow a symbolic link at the path, instead of drawing the link's chain
+        follow: bool,
+    },
+    /// Print aggregate statistics of the image
+    Stats {
+        image: ImageOptions,
+        /// Print the statistics as JSON
+        json: bool,
+    },
+    /// Print what the viewer's overview shows about the image
+    Info {
+        image: ImageOptions,
+        /// Print the overview as JSON
+        json: bool,
+    },
+    /// Print a report of everything about the image
+    Report {
+        image: ImageOptions,
+        format: Format,
+        /// Include the large sections, such as the full FAT
+        full: bool,
+    },
+    /// Print where a byte offset of the image file lies in the filesystem
+    Locate { image: ImageOptions, offset: u64 },
+    /// Print the notes on the image's blocks
+    Notes { image: ImageOptions, format: Format },
+    /// Check everything that could make the viewer misbehave on the image
+    Doctor { image: ImageOptions },
+    /// Compare the image's files with a copy of them on the host
+    Verify {
+        image: ImageOptions,
+        dir: PathBuf,
+        /// Compare modification times too
+        check_mtime: bool,
+    },
+    /// Write randomly generated images, and a manifest of what's in them, to a directory
+    FuzzGen {
+        dir: PathBuf,
+        count: usize,
+        /// The seed the images are generated from, or None to pick one
+        seed: Option<u64>,
+        /// How likely each kind of fault is to be injected into an image
+        corrupt: f64,
+    },
+    /// Write the image's FAT entries to a JSON patch file
+    FatExport { image: ImageOptions, out: PathBuf },
+    /// Write the entries of a JSON patch file into the image's FAT
+    FatApply {
+        image: ImageOptions,
+        patch: PathBuf,
+        /// Only print what would change
+        dry_run: bool,
+    },
+    /// Copy a file from one image into another
+    Cp {
+        src: ImageOptions,
+        src_path: String,
+        dest: ImageOptions,
+        dest_path: String,
+        /// Only print where the copy would go
+        dry_run: bool,
+    },
+    /// Print how the image's contents would fit in every block size
+    Fit { image: ImageOptions },
+    /// Copy the image's directory tree into a fresh image
+    Repack {
+        image: ImageOptions,
+        dest: PathBuf,
+        /// The block size config of the new image, or None for the smallest image's
+        block_size_config: Option<u8>,
+        /// The number of FAT blocks of the new image, or None for as few as the contents need
+        fat_blocks: Option<u8>,
+    },
+    /// Work out how to fix the image's problems, and make the fixes if asked to
+    Repair {
+        image: ImageOptions,
+        /// Make the fixes, rather than only printing them
+        apply: bool,
+        /// Make every fix without asking first
+        yes: bool,
+    },
+    /// Describe an error code, or list them all
+    Explain { code: Option<String> },
+}
+
+impl Command {
+    /// Whether the command prints JSON, so a failure should be printed as JSON too
+    pub fn json(&self) -> bool {
+        match self {
+            Command::Stats { json, .. } | Command::Info { json, .. } => *json,
+            Command::Report { format, .. } | Command::Notes { format, .. } => {
+                matches!(format, Format::Json)
+            }
+            _ => false,
+        }
+    }
+
+    /// Parse the command line arguments (without the program name)
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        match args.first().map(String::as_str) {
+            Some("-h" | "--help" | "help") => Ok(Command::Help),
+            Some(
+                name @ ("ls" | "cat" | "extract" | "extract-all" | "stat" | "lookup" | "graph"
+                | "stats" | "info" | "report" | "locate" | "notes" | "verify" | "doctor"
+                | "fuzz-gen" | "fat-export" | "fat-apply" | "cp" | "fit" | "repack"
+                | "repair"),
+            ) => parse_subcommand(name, &args[1..]),
+            // the only subcommand without an image
+            Some("explain") => match &args[1..] {
+                [] => Ok(Command::Explain { code: None }),
+                [code] => Ok(Command::Explain {
+                    code: Some(code.clone()),
+                }),
+                _ => Err("wrong number of arguments for explain".to_owned()),
+            },
+            // the demo takes the viewer's options, and makes up the image
+            Some("demo") => {
+                let mut args = args[1..].to_vec();
+                args.push(demo_image_path().display().to_string());
+                let options = Options::parse(&args)?;
+                Ok(Command::View(Options {
+                    demo: true,
+                    ..options
+                }))
+            }
+            _ => Options::parse(args).map(Command::View),
+        }
+    }
+}
+
+/// Parse the arguments of a subcommand
+fn parse_subcommand(name: &str, args: &[String]) -> Result<Command, String> {
+    let mut positional = Vec::new();
+    let mut dentry_layout = DentryLayout::default();
+    let mut dentry_size = None;
+    let mut root_block = None;
+    let mut decimal_blocks = false;
+    let mut profile = Some(Profile::PENNFAT);
+    let mut follow = true;
+    let mut json = false;
+    let mut format = Format::Markdown;
+    let mut full = false;
+    let mut slack = false;
+    let mut check_mtime = true;
+    let mut count = 10;
+    let mut seed = None;
+    let mut corrupt = 0.0;
+    let mut block = None;
+    let mut trace = false;
+    let mut numeric = false;
+    let mut dry_run = false;
+    let mut apply = false;
+    let mut yes = false;
+    let mut packed_dirs = false;
+    let mut sorted_dirs = false;
+    let mut window = Window::default();
+    let mut max_chain_len = None;
+    let mut block_size_config = None;
+    let mut fat_blocks = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-follow" => follow = false,
+            "--decimal" => decimal_blocks = true,
+            "--json" => json = true,
+            "--full" => full = true,
+            "--with-slack" => slack = true,
+            "--trace" => trace = true,
+            "--numeric" => numeric = true,
+            "--dry-run" => dry_run = true,
+            "--apply" => apply = true,
+            "--yes" => yes = true,
+            "--packed-dirs" => packed_dirs = true,
+            "--sorted-dirs" => sorted_dirs = true,
+            "--ignore-mtime" => check_mtime = false,
+            "--format" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                format = name.parse()?;
+            }
+            "--dentry-size" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                dentry_size = Some(parse_dentry_size(n)?);
+            }
+            "--dentry-profile" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                dentry_layout = parse_dentry_profile(name)?;
+            }
+            "--root-block" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                root_block = Some(parse_root_block(n)?);
+            }
+            "--offset" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                window.offset = parse_window_bound(n, "offset")?;
+            }
+            "--length" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                window.length = Some(parse_window_bound(n, "length")?);
+            }
+            "--profile" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                profile = parse_profile(name)?;
+            }
+            "--max-chain-len" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                max_chain_len = Some(parse_max_chain_len(n)?);
+            }
+            "--block" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                block = Some(parse_block(n).ok_or_else(|| format!("invalid block {}", n))?);
+            }
+            "--block-size" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                block_size_config = Some(parse_block_size(n)?);
+            }
+            "--fat-blocks" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                fat_blocks = match n.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("invalid FAT block count {} (1 to 255)", n)),
+                };
+            }
+            "--count" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                count = n.parse().map_err(|_| format!("invalid count {}", n))?;
+            }
+            "--seed" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                seed = Some(n.parse().map_err(|_| format!("invalid seed {}", n))?);
+            }
+            "--corrupt" => {
+                let rate = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                corrupt = match rate.parse() {
+                    Ok(rate) if (0.0..=1.0).contains(&rate) => rate,
+                    _ => return Err(format!("invalid fault rate {} (0 to 1)", rate)),
+                };
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    // `--block <n>` stands in for the target after the image, as `@<n>` would
+    if let Some(block) = block {
+        positional.insert(1.min(positional.len()), format!("@{}", block));
+    }
+    let image = |path: &str| ImageOptions {
+        path: path.to_owned(),
+        dentry_layout,
+        dentry_size,
+        root_block,
+        decimal_blocks,
+        profile,
+        packed_dirs,
+        sorted_dirs,
+        window,
+        max_chain_len,
+    };
+    match (name, positional.as_slice()) {
+        ("ls", [path]) => Ok(Command::Ls {
+            image: image(path),
+            target: Target::Path("/".to_owned()),
+            trace,
+            numeric,
+        }),
+        ("ls", [path, dir]) => Ok(Command::Ls {
+            image: image(path),
+            target: Target::parse(dir)?,
+            trace,
+            numeric,
+        }),
+        ("cat", [path, file]) => Ok(Command::Cat {
+            image: image(path),
+            target: Target::parse(file)?,
+            follow,
+            trace,
+        }),
+        ("extract", [path, file, dest]) => Ok(Command::Extract {
+            image: image(path),
+            target: Target::parse(file)?,
+            dest: PathBuf::from(dest),
+            follow,
+            slack,
+            trace,
+        }),
+        ("extract-all", [path, dest]) => Ok(Command::ExtractAll {
+            image: image(path),
+            dest: PathBuf::from(dest),
+        }),
+        ("stat", [path, file]) => Ok(Command::Stat {
+            image: image(path),
+            target: Target::parse(file)?,
+            follow,
+            trace,
+        }),
+        ("lookup", [path, file]) => Ok(Command::Lookup {
+            image: image(path),
+            path: file.to_string(),
+            follow,
+            trace,
+        }),
+        ("graph", [path]) => Ok(Command::Graph {
+            image: image(path),
+            target: None,
+            follow,
+        }),
+        ("graph", [path, file]) => Ok(Command::Graph {
+            image: image(path),
+            target: Some(Target::parse(file)?),
+            follow,
+        }),
+        ("stats", [path]) => Ok(Command::Stats {
+            image: image(path),
+            json,
+        }),
+        ("info", [path]) => Ok(Command::Info {
+            image: image(path),
+            json,
+        }),
+        ("report", [path]) => Ok(Command::Report {
+            image: image(path),
+            format,
+            full,
+        }),
+        ("notes", [path]) => Ok(Command::Notes {
+            image: image(path),
+            format,
+        }),
+        ("doctor", [path]) => Ok(Command::Doctor { image: image(path) }),
+        ("verify", [path, dir]) => Ok(Command::Verify {
+            image: image(path),
+            dir: PathBuf::from(dir),
+            check_mtime,
+        }),
+        ("locate", [path, offset]) => Ok(Command::Locate {
+            image: image(path),
+            offset: parse_offset(offset).ok_or_else(|| format!("invalid offset {}", offset))?,
+        }),
+        ("fuzz-gen", [dir]) => Ok(Command::FuzzGen {
+            dir: PathBuf::from(dir),
+            count,
+            seed,
+            corrupt,
+        }),
+        ("fat-export", [path, out]) => Ok(Command::FatExport {
+            image: image(path),
+            out: PathBuf::from(out),
+        }),
+        ("fat-apply", [path, patch]) => Ok(Command::FatApply {
+            image: image(path),
+            patch: PathBuf::from(patch),
+            dry_run,
+        }),
+        ("cp", [src, dest]) => {
+            let (src, src_path) = parse_image_path(src)?;
+            let (dest, dest_path) = parse_image_path(dest)?;
+            Ok(Command::Cp {
+                src: image(src),
+                src_path: src_path.to_owned(),
+                dest: image(dest),
+                dest_path: dest_path.to_owned(),
+                dry_run,
+            })
+        }
+        ("fit", [path]) => Ok(Command::Fit { image: image(path) }),
+        ("repack", [path, dest]) => Ok(Command::Repack {
+            image: image(path),
+            dest: PathBuf::from(dest),
+            block_size_config,
+            fat_blocks,
+        }),
+        ("repair", _) if dry_run && apply => {
+            Err("--dry-run and --apply don't go together".to_owned())
+        }
+        ("repair", [path]) => Ok(Command::Repair {
+            image: image(path),
+            apply,
+            yes,
+        }),
+        _ => Err(format!("wrong number of arguments for {}", name)),
+    }
+}
+
+/// Split `<image>:<path>` into the image and the absolute path in it. The path starts at the
+/// last ":/", so the image's own path can have colons in it
+pub fn parse_image_path(arg: &str) -> Result<(&str, &str), String> {
+    match arg.rfind(":/") {
+        Some(i) if i > 0 => Ok((&arg[..i], &arg[i + 1..])),
+        _ => Err(format!("expected <image>:<path>, got {}", arg)),
+    }
+}
+
+/// Parse a dentry size, which can't be 0
+fn parse_dentry_size(n: &str) -> Result<usize, String> {
+    match n.parse() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!("invalid dentry size {}", n)),
+    }
+}
+
+/// Parse the name of a built-in dentry layout, or the path of a TOML file describing one
+fn parse_dentry_profile(name: &str) -> Result<DentryLayout, String> {
+    if let Some(layout) = DentryLayout::by_name(name) {
+        return Ok(layout);
+    }
+    if !Path::new(name).is_file() {
+        let names: Vec<_> = DentryLayout::ALL.iter().map(|(name, _)| *name).collect();
+        return Err(format!(
+            "unknown dentry profile {} ({}, or a TOML file)",
+            name,
+            names.join(", ")
+        ));
+    }
+    let text = fs::read_to_string(name).map_err(|e| format!("reading {}: {}", name, e))?;
+    let layout: DentryLayout = toml::from_str(&text).map_err(|e| format!("{}: {}", name, e))?;
+    layout.validate().map_err(|e| format!("{}: {}", name, e))?;
+    Ok(layout)
+}
+
+/// Parse the block of the root directory, in decimal or hex
+fn parse_root_block(n: &str) -> Result<u16, String> {
+    match parse_block(n) {
+        Some(block) if block > 0 => Ok(block),
+        _ => Err(format!("invalid root block {}", n)),
+    }
+}
+
+/// Parse the most blocks a chain is followed for, at least 1
+fn parse_max_chain_len(n: &str) -> Result<u16, String> {
+    match parse_block(n) {
+        Some(limit) if limit > 0 => Ok(limit),
+        _ => Err(format!("invalid chain length limit {}", n)),
+    }
+}
+
+/// Parse a block size in bytes, a power of two from 256 to 32768, into its block size config
+fn parse_block_size(n: &str) -> Result<u8, String> {
+    (0..=7)
+        .find(|config| n.parse() == Ok(256u32 << config))
+        .ok_or_else(|| format!("invalid block size {} (256, 512, ... 32768)", n))
+}
+
+/// Parse the offset or length of an embedded image's window, in bytes, in decimal or hex
+fn parse_window_bound(n: &str, what: &str) -> Result<u64, String> {
+    parse_offset(n).ok_or_else(|| format!("invalid {} {}", what, n))
+}
+
+/// Parse how many seconds the image can go without changing before the idle counter warns
+fn parse_idle_secs(secs: &str) -> Result<Duration, String> {
+    secs.parse()
+        .map(Duration::from_secs)
+        .map_err(|_| format!("invalid idle time {}", secs))
+}
+
+/// Parse the name of a FAT profile, or "auto" to guess it from the image
+fn parse_profile(name: &str) -> Result<Option<Profile>, String> {
+    match name {
+        "auto" => Ok(None),
+        _ => Profile::by_name(name).map(Some).ok_or_else(|| {
+            let names: Vec<_> = Profile::ALL.iter().map(|profile| profile.name).collect();
+            format!("unknown profile {} ({} or auto)", name, names.join(", "))
+        }),
+    }
+}
+
+/// Command line options for the viewer
+pub struct Options {
+    /// The image to view, and how to read it
+    pub image: ImageOptions,
+    /// Keep retrying to load the image when it is invalid, instead of exiting
+    pub watch_invalid: bool,
+    /// Directory to write snapshots to, instead of next to the image
+    pub snapshot_dir: Option<PathBuf>,
+    /// Number of automatic snapshots to keep, taken whenever the image changes (0 = off)
+    pub snapshot_on_change: usize,
+    /// Bytes of viewed blocks to keep copies of, to diff them when they change
+    pub diff_cache: usize,
+    /// How often to reload and redraw while the terminal doesn't have the focus, or None to
+    /// carry on as usual
+    pub unfocused_tick: Option<Duration>,
+    /// Poll the FAT for changes on every tick, for writes the modification time misses
+    pub poll_content: bool,
+    /// How long the image can go without changing before the idle counter turns yellow
+    pub idle_warn: Duration,
+    /// How long the image can go without changing before the idle counter turns red
+    pub idle_alert: Duration,
+    /// Ring the terminal bell when the image becomes less consistent
+    pub bell: bool,
+    /// Exit with an error as soon as the image becomes less consistent
+    pub fail_fast: bool,
+    /// Whether the image may be written to
+    pub mode: Mode,
+    /// Write edits that make the image inconsistent, such as a first block out of range
+    pub allow_dangerous: bool,
+    /// Draw with ASCII characters only (or not), instead of guessing from the environment
+    pub ascii: Option<bool>,
+    /// Whether to draw in color
+    pub color: ColorChoice,
+    /// File to write a debugging log to
+    pub log_file: Option<PathBuf>,
+    /// The most verbose level to log at
+    pub log_level: LevelFilter,
+    /// Keep the colors of screenshots, as ANSI escape codes
+    pub ansi_screenshots: bool,
+    /// Viewing the generated sample image, with hints on each view the first time it's visited
+    pub demo: bool,
+}
+
+/// Whether to draw in color
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// In color unless the terminal seems not to have enough colors
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "invalid color choice {} (always, auto or never)",
+                s
+            )),
+        }
+    }
+}
+
+impl Options {
+    /// Parse the command line arguments (without the program name)
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut path = None;
+        let mut watch_invalid = false;
+        let mut snapshot_dir = None;
+        let mut snapshot_on_change = 0;
+        let mut diff_cache = 4 << 20;
+        let mut unfocused_tick = Some(Duration::from_secs(5));
+        let mut poll_content = false;
+        let mut idle_warn = Duration::from_secs(30);
+        let mut idle_alert = Duration::from_secs(120);
+        let mut dentry_layout = DentryLayout::default();
+        let mut dentry_size = None;
+        let mut root_block = None;
+        let mut decimal_blocks = false;
+        let mut profile = Some(Profile::PENNFAT);
+        let mut packed_dirs = false;
+        let mut sorted_dirs = false;
+        let mut window = Window::default();
+        let mut max_chain_len = None;
+        let mut ansi_screenshots = false;
+        let mut bell = false;
+        let mut fail_fast = false;
+        let mut mode = Mode::ReadOnly;
+        let mut allow_dangerous = false;
+        let mut ascii = None;
+        let mut color = ColorChoice::Auto;
+        let mut log_file = None;
+        let mut log_level = LevelFilter::Info;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--watch-invalid" => watch_invalid = true,
+                "--poll-content" => poll_content = true,
+                "--packed-dirs" => packed_dirs = true,
+                "--sorted-dirs" => sorted_dirs = true,
+                "--ansi-screenshots" => ansi_screenshots = true,
+                "--decimal" => decimal_blocks = true,
+                "--bell" => bell = true,
+                "--fail-fast" => fail_fast = true,
+                "--writable" => mode = Mode::Writable,
+                "--allow-dangerous" => allow_dangerous = true,
+                "--ascii" => ascii = Some(true),
+                "--unicode" => ascii = Some(false),
+                "--color" => color = value()?.parse()?,
+                flag if flag.starts_with("--color=") => color = flag["--color=".len()..].parse()?,
+                "--snapshot-dir" => snapshot_dir = Some(PathBuf::from(value()?)),
+                "--snapshot-on-change" => {
+                    let n = value()?;
+                    snapshot_on_change = n
+                        .parse()
+                        .map_err(|_| format!("invalid snapshot count {}", n))?;
+                }
+                "--diff-cache" => {
+                    let mb = value()?;
+                    diff_cache = mb
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid diff cache size {}", mb))?
+                        << 20;
+                }
+                "--unfocused-tick-ms" => {
+                    let ms = value()?;
+                    let ms = ms
+                        .parse()
+                        .map_err(|_| format!("invalid tick interval {}", ms))?;
+                    unfocused_tick = (ms > 0).then(|| Duration::from_millis(ms));
+                }
+                "--idle-warn-secs" => idle_warn = parse_idle_secs(value()?)?,
+                "--idle-alert-secs" => idle_alert = parse_idle_secs(value()?)?,
+                "--dentry-size" => dentry_size = Some(parse_dentry_size(value()?)?),
+                "--dentry-profile" => dentry_layout = parse_dentry_profile(value()?)?,
+                "--root-block" => root_block = Some(parse_root_block(value()?)?),
+                "--offset" => window.offset = parse_window_bound(value()?, "offset")?,
+                "--length" => window.length = Some(parse_window_bound(value()?, "length")?),
+                "--profile" => profile = parse_profile(value()?)?,
+                "--max-chain-len" => max_chain_len = Some(parse_max_chain_len(value()?)?),
+                "--log-file" => log_file = Some(PathBuf::from(value()?)),
+                "--log-level" => {
+                    let level = value()?;
+                    log_level = level
+                        .parse()
+                        .map_err(|_| format!("invalid log level {}", level))?;
+                }
+                flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+                _ if path.is_some() => return Err(format!("unexpected argument {}", arg)),
+                _ => path = Some(arg.clone()),
+            }
+        }
+
+        if idle_alert < idle_warn {
+            return Err("--idle-alert-secs can't be less than --idle-warn-secs".to_owned());
+        }
+
+        Ok(Options {
+            image: ImageOptions {
+                path: path.ok_or("missing image path")?,
+                dentry_layout,
+                dentry_size,
+                root_block,
+                decimal_blocks,
+                profile,
+                packed_dirs,
+                sorted_dirs,
+                window,
+                max_chain_len,
+            },
+            watch_invalid,
+            snapshot_dir,
+            snapshot_on_change,
+            diff_cache,
+            unfocused_tick,
+            poll_content,
+            idle_warn,
+            idle_alert,
+            bell,
+            fail_fast,
+            mode,
+            allow_dangerous,
+            ascii,
+            color,
+            log_file,
+            log_level,
+            ansi_screenshots,
+            demo: false,
+        })
+    }
+}
+
+/// Where the demo writes its sample image, for as long as it runs
+fn demo_image_path() -> PathBuf {
+    std::env::temp_dir().join(format!("pfview-demo-{}.img", std::process::id()))
+}
+
+/// The usage text, for the given program name
+pub fn usage(program: &str) -> String {
+    format!(
+        "Usage: {0} [options] <filename>\n       \
+         {0} demo [options]\n       \
+         {0} ls [--numeric] [--trace] <filename> [path|@block]\n       \
+         {0} cat [--no-follow] [--trace] <filename> <path|@block>\n       \
+         {0} extract [--no-follow] [--with-slack] [--trace] <filename> <path|@block> <dest>\n       \
+         {0} extract-all <filename> <dir>\n       \
+         {0} stat [--no-follow] [--trace] <filename> <path|@block>\n       \
+         {0} lookup [--no-follow] [--trace] <filename> <path>\n       \
+         {0} graph [--no-follow] <filename> [path|@block]\n       \
+         {0} stats [--json] <filename>\n       \
+         {0} info [--json] <filename>\n       \
+         {0} report [--format markdown|html|json] [--full] <filename>\n       \
+         {0} locate <filename> <byte offset>\n       \
+         {0} notes [--format markdown|html|json] <filename>\n       \
+         {0} verify [--ignore-mtime] <filename> <dir>\n       \
+         {0} doctor [--packed-dirs] [--sorted-dirs] <filename>\n       \
+         {0} fuzz-gen [--count <n>] [--seed <n>] [--corrupt <rate>] <dir>\n       \
+         {0} fat-export <filename> <out.json>\n       \
+         {0} fat-apply [--dry-run] <filename> <patch.json>\n       \
+         {0} cp [--dry-run] <src.img>:<path> <dest.img>:<path>\n       \
+         {0} fit <filename>\n       \
+         {0} repack [--block-size <bytes>] [--fat-blocks <n>] <filename> <dest>\n       \
+         {0} repair [--dry-run | --apply [--yes]] <filename>\n       \
+         {0} explain [<code>]\n       \
+         {0} --help\n\n\
+         Options:\n  \
+         --watch-invalid            if the image fails to load, keep retrying instead of exiting\n  \
+         --snapshot-dir <dir>       write snapshots to <dir> instead of next to the image\n  \
+         --snapshot-on-change <n>   snapshot the image whenever it changes, keeping the last <n>\n  \
+         --diff-cache <mb>          keep up to <mb> MB of viewed blocks to diff them (default 4)\n  \
+         --unfocused-tick-ms <ms>   reload every <ms> while the terminal is unfocused (default 5000, 0: off)\n  \
+         --poll-content             also reload when the FAT changes, for mtimes kept to the second\n  \
+         --idle-warn-secs <n>       turn the idle counter yellow after <n> seconds without a change (default 30)\n  \
+         --idle-alert-secs <n>      turn the idle counter red after <n> seconds without a change (default 120)\n  \
+         --dentry-size <n>          read directory entries as <n> bytes each (default 64)\n  \
+         --dentry-profile <name>    lay out directory entries as pennfat-v1, pennfat-wide or a TOML file\n  \
+         --root-block <n>           read the root directory from block <n> instead of finding it\n  \
+         --offset <n>               read the image embedded at byte <n> of the file (decimal or 0x hex)\n  \
+         --length <n>               read only <n> bytes of the file as the image, from --offset\n  \
+         --profile <name>           read FAT entries as pennfat (the default) or reserved (1 is reserved), or auto to guess\n  \
+         --max-chain-len <n>        follow chains for at most <n> blocks (default: the data blocks)\n  \
+         --decimal                  show block numbers in decimal rather than hex\n  \
+         --packed-dirs              require directories to be packed, with no entry after a free slot\n  \
+         --sorted-dirs              require the entries of directories to be sorted by name\n  \
+         --bell                     ring the bell when the image gets new consistency problems\n  \
+         --fail-fast                exit with an error when the image gets new consistency problems\n  \
+         --writable                 open the image for writing (it is only ever read otherwise)\n  \
+         --allow-dangerous          let edits make the image inconsistent (e.g. a bad first block)\n  \
+         --ascii                    draw with ASCII characters only, for terminals without Unicode\n  \
+         --unicode                  draw with Unicode characters, even if the locale isn't UTF-8\n  \
+         --color <when>             draw in color always, never or auto (the default)\n  \
+         --ansi-screenshots         keep the colors of screenshots (P) as ANSI escape codes\n  \
+         --log-file <path>          log what pfview does to <path> (or $PFVIEW_LOG), for debugging\n  \
+         --log-level <level>        log at error, warn, info, debug or trace level (default info)\n  \
+         --no-follow                cat/extract/stat/lookup/graph a symbolic link itself, not what it points to\n  \
+         --with-slack               extract the rest of the file's last block too\n  \
+         --block <n>                ls/cat/extract/stat/graph block <n>'s chain, like @<n>\n  \
+         --trace                    print every FAT entry, block and dentry ls/cat/extract/stat/lookup read\n  \
+         --numeric                  ls permissions as the perm byte, rather than like rw-\n  \
+         --json                     print stats as JSON\n  \
+         --full                     include the full FAT in the report\n  \
+         --count <n>                generate <n> images (default 10)\n  \
+         --seed <n>                 generate the images from seed <n>, to get the same ones again\n  \
+         --corrupt <rate>           inject each kind of fault into an image with probability <rate>\n  \
+         --dry-run                  print what fat-apply, cp or repair would change, without writing it\n  \
+         --apply                    make repair's fixes, asking before each\n  \
+         --yes                      make every fix without asking\n  \
+         --block-size <bytes>       repack into blocks of <bytes> (default: whichever makes the smallest image)\n  \
+         --fat-blocks <n>           repack with a FAT of <n> blocks (default: as few as the contents need)\n\n\
+         Output goes to stdout, and errors and warnings to stderr. Exit status:\n  \
+         0                          success\n  \
+         1                          an error, such as an image that can't be read\n  \
+         2                          problems found: by doctor, verify, fat-apply, repack and repair, or with --fail-fast\n  \
+         64                         a usage error",
+        program
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::ImageBuilder;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn block_targets_parse_either_way() {
+        for args in [
+            &["stat", "a.img", "@0x2a"][..],
+            &["stat", "a.img", "@42"],
+            &["stat", "--block", "42", "a.img"],
+            &["stat", "a.img", "--block", "0x2a"],
+        ] {
+            assert!(
+                matches!(
+                    parse(args),
+                    Ok(Command::Stat {
+                        target: Target::Block(0x2a),
+                        ..
+                    })
+                ),
+                "{:?}",
+                args
+            );
+        }
+        assert!(matches!(
+            parse(&["lookup", "--trace", "--no-follow", "a.img", "/docs/a"]),
+            Ok(Command::Lookup { path, follow: false, trace: true, .. }) if path == "/docs/a"
+        ));
+        assert!(matches!(
+            parse(&["graph", "a.img", "--block", "7"]),
+            Ok(Command::Graph {
+                target: Some(Target::Block(7)),
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse(&["graph", "a.img"]),
+            Ok(Command::Graph { target: None, .. })
+        ));
+    }
+
+    #[test]
+    fn dot_slash_names_a_file_starting_with_at() {
+        assert!(matches!(
+            parse(&["stat", "a.img", "./@0x2a"]),
+            Ok(Command::Stat { target: Target::Path(path), .. }) if path == "./@0x2a"
+        ));
+    }
+
+    #[test]
+    fn invalid_blocks_are_usage_errors() {
+        assert_eq!(
+            parse(&["stat", "a.img", "@0x10000"]).err().as_deref(),
+            Some("invalid block @0x10000")
+        );
+        assert!(parse(&["extract", "a.img", "--block", "x", "out"]).is_err());
+        assert!(parse(&["stat", "a.img"]).is_err());
+    }
+
+    /// The image options of `stats` with the given flags
+    fn image_options(flags: &[&str]) -> ImageOptions {
+        let mut args = vec!["stats"];
+        args.extend(flags);
+        args.push("a.img");
+        match parse(&args) {
+            Ok(Command::Stats { image, .. }) => image,
+            _ => panic!("{:?} didn't parse", args),
+        }
+    }
+
+    #[test]
+    fn images_are_read_as_pennfat_unless_asked_to_guess() {
+        let mut image = ImageBuilder::new(0, 1);
+        let chain = image.allocate_lowest(2).unwrap();
+        // under pennfat, a cross-link into the root; under reserved, an end
+        image.link(&[chain[1], 1]);
+        let bytes = image.bytes();
+        let profile = |flags: &[&str]| {
+            let mut fs = PennFat::from_bytes(bytes.clone()).unwrap();
+            image_options(flags).apply(&mut fs);
+            fs.profile().name
+        };
+        assert_eq!(profile(&[]), "pennfat");
+        assert_eq!(profile(&["--profile", "reserved"]), "reserved");
+        assert_eq!(profile(&["--profile", "auto"]), "reserved");
+        // with the root elsewhere, 1 is an ordinary block to link to
+        assert_eq!(
+            profile(&["--profile", "auto", "--root-block", "2"]),
+            "pennfat"
+        );
+        assert!(parse(&["stats", "--profile", "fat32", "a.img"])
+            .err()
+            .is_some_and(|e| e.starts_with("unknown profile fat32")));
+    }
+}
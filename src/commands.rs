@@ -0,0 +1,1182 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::check;
+use crate::cli::{ImageOptions, Target};
+use crate::doctor;
+use crate::edit::{self, FileCopy};
+use crate::explain;
+use crate::extract::{self, Extracted};
+use crate::fuzz;
+use crate::ownership::Ownership;
+use crate::patch::FatPatch;
+use crate::pennfat::{
+    format_offset, Chain, ChainEnd, Dentry, Entry as FatEntry, Mode, PennFat, PermTable, PfError,
+    ShowBlocks, Window,
+};
+use crate::repack::{self, Contents, Fit};
+use crate::repair;
+use crate::report::{self, Format, Report};
+use crate::session::Session;
+use crate::stats::{Overview, Stats};
+use crate::verify;
+use crate::walk::{self, Entry, Lookup};
+
+/// The warning shown when another process holds an exclusive lock on the image
+pub const WRITER_LOCKED: &str = "another process has the image locked, it may be mid-write";
+
+/// The error of a command that ran, and found problems with the image, as opposed to one that
+/// couldn't run. It exits with its own status, so scripts can tell the two apart
+#[derive(thiserror::Error, Debug)]
+#[error("{0}")]
+pub struct ProblemsFound(pub String);
+
+/// Load an image for a subcommand
+fn open(image: &ImageOptions) -> Result<PennFat> {
+    open_in(image, Mode::ReadOnly)
+}
+
+/// Load an image for a subcommand, opened for reading only or for writing too
+fn open_in(image: &ImageOptions, mode: Mode) -> Result<PennFat> {
+    let mut fs = PennFat::load(&image.path, mode, image.window)?;
+    if fs.writer_locked() {
+        eprintln!("warning: {}", WRITER_LOCKED);
+    }
+    if let Some(warning) = image.apply(&mut fs) {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(fs)
+}
+
+/// Look up a path that is expected to exist
+fn find(fs: &PennFat, path: &str, follow: bool) -> Result<Entry> {
+    match walk::lookup(fs, path, follow) {
+        Lookup::Found(entry) => Ok(entry),
+        Lookup::Missing => bail!("{}: no such file or directory", path),
+        Lookup::Loop => bail!("{}: too many levels of symbolic links", path),
+    }
+}
+
+/// What a target names in the image
+enum Resolved {
+    /// A file, found by its path
+    File(Entry),
+    /// An allocated data block
+    Block(u16),
+}
+
+/// Resolve a target: look up a path, or check that a block is an allocated data block
+fn resolve(fs: &PennFat, target: &Target, follow: bool) -> Result<Resolved> {
+    let block = match target {
+        Target::Path(path) => return find(fs, path, follow).map(Resolved::File),
+        Target::Block(block) => *block,
+    };
+    if block == 0 || block > fs.data_block_count() {
+        bail!(
+            "block {} is out of range (data blocks are {} to {})",
+            fs.format_block(block),
+            fs.format_block(1),
+            fs.format_block(fs.data_block_count())
+        );
+    }
+    match fs
+        .fat_entry(block)
+        .map(|entry| fs.profile().classify(entry))
+    {
+        Some(FatEntry::Free) => bail!("block {} is free", fs.format_block(block)),
+        None => bail!(
+            "block {}: FAT entry missing from the image",
+            fs.format_block(block)
+        ),
+        Some(_) => Ok(Resolved::Block(block)),
+    }
+}
+
+/// Run `f`, tracing the reads it makes from the image if asked and printing them to stderr
+/// afterwards, whether or not it succeeded
+fn traced<T>(fs: &PennFat, trace: bool, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if trace {
+        fs.start_trace();
+    }
+    let result = f();
+    if let Some(trace) = fs.finish_trace() {
+        eprint!("{}", trace.show(fs.block_format()));
+    }
+    result
+}
+
+/// Format a chain of blocks as `0x0002 -> 0x0003 -> EOF`
+fn format_chain(chain: &Chain) -> String {
+    let end = match chain.end {
+        ChainEnd::Eof => "EOF".to_owned(),
+        ChainEnd::Free => "FREE (broken chain)".to_owned(),
+        ChainEnd::Reserved => "RESERVED".to_owned(),
+        ChainEnd::OutOfRange(block) => format!("{} (out of range)", chain.format.block(block)),
+        ChainEnd::Cycle(block) => format!("{} (cycle)", chain.format.block(block)),
+        ChainEnd::TooLong(limit) => format!("... (longer than {} blocks)", limit),
+    };
+    let mut parts: Vec<String> = chain
+        .blocks
+        .iter()
+        .map(|b| chain.format.block(*b))
+        .collect();
+    parts.push(end);
+    parts.join(" -> ")
+}
+
+/// Print what a block is part of: the file it belongs to, where it is in the file's chain and
+/// the chain itself. An orphan block's chain is followed from the block on
+fn describe_block(fs: &PennFat, block: u16) {
+    let ownership = Ownership::build(fs);
+    match ownership.owner(block) {
+        Some(owner) => {
+            let kind = match owner.is_dir {
+                true => "directory",
+                false => "file",
+            };
+            let shape = match ownership.chain_shape(block) {
+                Some(shape) if !shape.broken => {
+                    format!(" of {} ({} extents)", shape.length, shape.extents)
+                }
+                _ => String::new(),
+            };
+            println!(
+                "block {}: block {}{} of {} {}",
+                fs.format_block(block),
+                owner.index + 1,
+                shape,
+                kind,
+                owner.path
+            );
+            println!("{}", format_chain(&fs.chain(owner.first_block)));
+        }
+        None => {
+            println!(
+                "block {}: orphan, not in any file's chain",
+                fs.format_block(block)
+            );
+            println!("{}", format_chain(&fs.chain(block)));
+        }
+    }
+}
+
+/// Read the whole blocks of the chain starting at a block. A chain longer than the limit is an
+/// error, rather than read in part
+fn read_chain(fs: &PennFat, block: u16) -> Result<Vec<u8>> {
+    let chain = fs.chain(block);
+    chain.check_len()?;
+    let mut contents = Vec::new();
+    for block in chain.blocks {
+        contents.extend_from_slice(fs.block_bytes(block)?);
+    }
+    Ok(contents)
+}
+
+/// Format the permissions of a dentry like `ls -l` does, or as `???` if `perms` doesn't
+/// define them
+fn format_perm(perm: u8, perms: PermTable) -> String {
+    if !perms.is_legal(perm) {
+        return "???".to_owned();
+    }
+    [(4, 'r'), (2, 'w'), (1, 'x')]
+        .iter()
+        .map(|(bit, c)| if perm & bit != 0 { *c } else { '-' })
+        .collect()
+}
+
+/// Format the type and permissions of a dentry like `ls -l` does, e.g. `drw-`. With
+/// `numeric`, the perm byte is shown as it is, e.g. `d  6`
+pub fn format_mode(dentry: &Dentry, perms: PermTable, numeric: bool) -> String {
+    let kind = match dentry.type_ {
+        1 => 'd',
+        2 => 'l',
+        _ => '-',
+    };
+    match numeric {
+        true => format!("{}{:>3}", kind, dentry.perm),
+        false => format!("{}{}", kind, format_perm(dentry.perm, perms)),
+    }
+}
+
+/// Format a dentry of the directory at `dir_path` as a line of `ls` output, with its perm
+/// as a number if `numeric`. A directory says how many entries it has
+pub fn format_entry(fs: &PennFat, dir_path: &str, dentry: &Dentry, numeric: bool) -> String {
+    let mut name = dentry.display_name();
+    if dentry.is_symlink() {
+        let (target, lookup) = walk::resolve_link(fs, dir_path, dentry);
+        name = format!("{} -> {} ({})", name, target, lookup.describe());
+    } else if dentry.is_dir() {
+        let entries = walk::count_children(fs, dentry.first_block);
+        name = format!(
+            "{} ({} entr{})",
+            name,
+            entries,
+            if entries == 1 { "y" } else { "ies" }
+        );
+    }
+    format!(
+        "{} {:>10} {:>6} {}",
+        format_mode(dentry, fs.dentry_layout().perms, numeric),
+        dentry.size,
+        fs.format_block(dentry.first_block),
+        name
+    )
+}
+
+/// List the directory at a path, or the file at it if it isn't one. A block is described
+/// instead, by the file and chain it belongs to. With `trace`, the reads made finding the
+/// path are printed. Permissions are shown like `ls -l` does, or as numbers with `numeric`
+pub fn ls(image: &ImageOptions, target: &Target, trace: bool, numeric: bool) -> Result<()> {
+    let fs = open(image)?;
+    // a symbolic link lists as itself, unless it points to a directory
+    let mut entry = match traced(&fs, trace, || resolve(&fs, target, false))? {
+        Resolved::File(entry) => entry,
+        Resolved::Block(block) => {
+            describe_block(&fs, block);
+            return Ok(());
+        }
+    };
+    if entry.dentry.is_symlink() {
+        if let Lookup::Found(target) = walk::lookup(&fs, &entry.path, true) {
+            if target.dentry.is_dir() {
+                entry = target;
+            }
+        }
+    }
+    if !entry.dentry.is_dir() {
+        let dir_path = entry.path.rsplit_once('/').map_or("/", |(dir, _)| dir);
+        println!("{}", format_entry(&fs, dir_path, &entry.dentry, numeric));
+        return Ok(());
+    }
+
+    let mut count = 0;
+    for dentry in walk::children(&fs, entry.dentry.first_block) {
+        println!("{}", format_entry(&fs, &entry.path, &dentry?, numeric));
+        count += 1;
+    }
+    println!("{} entr{}", count, if count == 1 { "y" } else { "ies" });
+    Ok(())
+}
+
+/// Read the file at a path. Without `follow`, a symbolic link reads as its target path. With
+/// `slack`, the rest of the file's last block comes along. A block reads as the whole blocks
+/// of the chain starting at it
+fn contents(fs: &PennFat, target: &Target, follow: bool, slack: bool) -> Result<Vec<u8>> {
+    let entry = match resolve(fs, target, follow)? {
+        Resolved::File(entry) => entry,
+        Resolved::Block(block) => return read_chain(fs, block),
+    };
+    if entry.dentry.is_dir() {
+        bail!("{}: is a directory", entry.path);
+    }
+    if entry.dentry.is_symlink() {
+        return Ok(walk::read_link(fs, &entry.dentry)?.into_bytes());
+    }
+    match slack {
+        true => Ok(fs.read_allocated(&entry.dentry)?),
+        false => Ok(fs.read_file(&entry.dentry)?),
+    }
+}
+
+/// Print the file (or chain of blocks) a target names, and with `trace`, the reads made
+/// finding and reading it
+pub fn cat(image: &ImageOptions, target: &Target, follow: bool, trace: bool) -> Result<()> {
+    let fs = open(image)?;
+    let contents = traced(&fs, trace, || contents(&fs, target, follow, false))?;
+    io::stdout().write_all(&contents)?;
+    Ok(())
+}
+
+/// Copy the file (or chain of blocks) a target names out of the image to `dest`, along with
+/// its slack if asked, and with `trace`, print the reads made finding and reading it
+pub fn extract(
+    image: &ImageOptions,
+    target: &Target,
+    dest: &Path,
+    follow: bool,
+    slack: bool,
+    trace: bool,
+) -> Result<()> {
+    let fs = open(image)?;
+    let contents = traced(&fs, trace, || contents(&fs, target, follow, slack))?;
+    fs::write(dest, contents)?;
+    Ok(())
+}
+
+/// Copy every file of the image into `dest`, printing what couldn't be copied. Fails if any
+/// file couldn't be, once the rest have been
+pub fn extract_all(image: &ImageOptions, dest: &Path) -> Result<()> {
+    let fs = open(image)?;
+    let extracted = extract::extract_all(&fs, dest)
+        .with_context(|| format!("extracting into {}", dest.display()))?;
+    let failed: Vec<&Extracted> = extracted.iter().filter(|e| e.error.is_some()).collect();
+    for file in &failed {
+        eprintln!(
+            "{}: {}",
+            file.path,
+            file.error.as_deref().unwrap_or_default()
+        );
+    }
+    println!(
+        "copied {} files to {}, see {}",
+        extracted.len() - failed.len(),
+        dest.display(),
+        dest.join(extract::MANIFEST).display()
+    );
+    if !failed.is_empty() {
+        bail!("{} files couldn't be copied", failed.len());
+    }
+    Ok(())
+}
+
+/// Describe a file, a line at a time: its dentry's fields, then the chain of blocks it's
+/// stored in and how many extents (runs of consecutive blocks) the chain makes
+pub fn file_stat(fs: &PennFat, entry: &Entry) -> Vec<String> {
+    let dentry = &entry.dentry;
+    let kind = match dentry.type_ {
+        1 => "directory",
+        2 => "symlink",
+        _ => "file",
+    };
+    // a symbolic link shows where it points, and what's there
+    let path = match dentry.is_symlink() {
+        true => {
+            let dir_path = entry.path.rsplit_once('/').map_or("/", |(dir, _)| dir);
+            let (target, lookup) = walk::resolve_link(fs, dir_path, dentry);
+            format!("{} -> {} ({})", entry.path, target, lookup.describe())
+        }
+        false => entry.path.clone(),
+    };
+    let mut lines = vec![
+        format!("path: {}", path),
+        format!("type: {} ({})", kind, dentry.type_),
+        format!("size: {}", dentry.size),
+        format!("perm: {}", fs.dentry_layout().perms.describe(dentry.perm)),
+        format!("mtime: {}", dentry.format_mtime()),
+        format!("first block: {}", fs.format_block(dentry.first_block)),
+    ];
+    // an empty file has no chain
+    if dentry.first_block == 0 {
+        lines.push("blocks: none".to_owned());
+        return lines;
+    }
+    let chain = fs.chain(dentry.first_block);
+    let extents = 1 + chain
+        .blocks
+        .windows(2)
+        .filter(|pair| pair[1] != pair[0].wrapping_add(1))
+        .count();
+    lines.push(format!(
+        "blocks: {} ({} extent{})",
+        chain.blocks.len(),
+        extents,
+        if extents == 1 { "" } else { "s" }
+    ));
+    lines.push(format!("chain: {}", format_chain(&chain)));
+    lines
+}
+
+/// Print what a target is: the dentry of a file and the chain it's stored in, or for a block,
+/// the file and chain it belongs to. With `trace`, the reads made finding it are printed too
+pub fn stat(image: &ImageOptions, target: &Target, follow: bool, trace: bool) -> Result<()> {
+    let fs = open(image)?;
+    match traced(&fs, trace, || resolve(&fs, target, follow))? {
+        Resolved::File(entry) => {
+            for line in file_stat(&fs, &entry) {
+                println!("{}", line);
+            }
+        }
+        Resolved::Block(block) => describe_block(&fs, block),
+    }
+    Ok(())
+}
+
+/// Where a lookup of a path ends up: the path it resolves to, what's there and its first block
+fn describe_lookup(fs: &PennFat, entry: &Entry) -> String {
+    let kind = match entry.dentry.type_ {
+        1 => "directory",
+        2 => "symlink",
+        _ => "file",
+    };
+    format!(
+        "{} ({}, first block {})",
+        entry.path,
+        kind,
+        fs.format_block(entry.dentry.first_block)
+    )
+}
+
+/// Look up a path, printing where it leads, and with `trace`, each directory searched and
+/// dentry compared on the way
+pub fn lookup(image: &ImageOptions, path: &str, follow: bool, trace: bool) -> Result<()> {
+    let fs = open(image)?;
+    let entry = traced(&fs, trace, || find(&fs, path, follow))?;
+    println!("{}", describe_lookup(&fs, &entry));
+    Ok(())
+}
+
+/// Escape text to go between the quotes of a Graphviz id
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Draw the chains of the FAT as a Graphviz digraph: a node per allocated block, with the
+/// path of the file a chain starts on its first block, and an edge per link. With `only`,
+/// just the blocks of that chain are drawn
+pub fn graph_dot(fs: &PennFat, only: Option<&Chain>) -> String {
+    let ownership = Ownership::build(fs);
+    let blocks: Vec<(u16, u16)> = match only {
+        Some(chain) => chain
+            .blocks
+            .iter()
+            .filter_map(|block| Some((*block, fs.fat_entry(*block)?)))
+            .collect(),
+        None => fs.get_fat_table(),
+    };
+    let linked: HashSet<u16> = blocks.iter().map(|(_, next)| *next).collect();
+    let mut dot = String::from("digraph fat {\n  node [shape=box];\n");
+    for (block, next) in &blocks {
+        let name = fs.format_block(*block);
+        let mut label = vec![name.clone()];
+        match ownership.owner(*block) {
+            Some(owner) if owner.index == 0 => label.push(owner.path.clone()),
+            None if !linked.contains(block) => label.push("(orphan)".to_owned()),
+            _ => {}
+        }
+        let label: Vec<String> = label.iter().map(|line| dot_escape(line)).collect();
+        dot += &format!("  \"{}\" [label=\"{}\"];\n", name, label.join("\\n"));
+        let end = match fs.profile().classify(*next) {
+            FatEntry::Next(next) => fs.format_block(next),
+            FatEntry::Eof => continue,
+            FatEntry::Free => "FREE".to_owned(),
+            FatEntry::Reserved => "RESERVED".to_owned(),
+        };
+        dot += &format!("  \"{}\" -> \"{}\";\n", name, end);
+    }
+    dot += "}\n";
+    dot
+}
+
+/// Print the chains of the image's FAT as a Graphviz digraph, or just the chain of a file or
+/// of the chain a block is part of
+pub fn graph(image: &ImageOptions, target: Option<&Target>, follow: bool) -> Result<()> {
+    let fs = open(image)?;
+    let chain = match target
+        .map(|target| resolve(&fs, target, follow))
+        .transpose()?
+    {
+        None => None,
+        Some(Resolved::File(entry)) if entry.dentry.first_block == 0 => {
+            bail!("{}: is empty, so has no chain", entry.path)
+        }
+        Some(Resolved::File(entry)) => Some(fs.chain(entry.dentry.first_block)),
+        Some(Resolved::Block(block)) => {
+            let owners = Ownership::build(&fs);
+            let first_block = owners.owner(block).map_or(block, |owner| owner.first_block);
+            Some(fs.chain(first_block))
+        }
+    };
+    print!("{}", graph_dot(&fs, chain.as_ref()));
+    Ok(())
+}
+
+/// Print aggregate statistics of the image, as a table or as JSON
+pub fn stats(image: &ImageOptions, json: bool) -> Result<()> {
+    let fs = open(image)?;
+    let stats = Stats::collect(&fs);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    for (key, value) in stats.rows() {
+        println!("{:<22}{}", key, value);
+    }
+    Ok(())
+}
+
+/// Print what the viewer's overview shows about the image, from its FAT alone
+pub fn info(image: &ImageOptions, json: bool) -> Result<()> {
+    let fs = open(image)?;
+    let overview = Overview::collect(&fs);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&overview)?);
+        return Ok(());
+    }
+    for (key, value) in overview.rows() {
+        println!("{:<15}{}", key, value);
+    }
+    Ok(())
+}
+
+/// Print a report of everything about the image. The large sections are only included with
+/// `full`
+pub fn report(image: &ImageOptions, format: Format, full: bool) -> Result<()> {
+    let fs = open(image)?;
+    print!("{}", Report::collect(&fs, full).render(format));
+    Ok(())
+}
+
+/// Print the notes on the blocks of an image, kept in its session file
+pub fn notes(image: &ImageOptions, format: Format) -> Result<()> {
+    let fs = open(image)?;
+    let session = Session::load(&fs)
+        .with_context(|| format!("reading {}", Session::path(fs.path()).display()))?;
+    print!(
+        "{}",
+        report::render_notes(&image.path, &session.notes, fs.block_format(), format)
+    );
+    Ok(())
+}
+
+/// Run every check on the image and print what each found, failing if any check failed. Unlike
+/// the other commands, an image that doesn't load is reported on rather than an error, though a
+/// file that can't be opened at all is still an error
+pub fn doctor(image: &ImageOptions) -> Result<()> {
+    fs::File::open(&image.path).with_context(|| format!("opening {}", image.path))?;
+    let diagnosis = doctor::diagnose(image);
+    println!("{}", diagnosis);
+    if diagnosis.failed() {
+        bail!(ProblemsFound(format!("{} has problems", image.path)));
+    }
+    Ok(())
+}
+
+/// Generate `count` random images into a directory, with a manifest of what's in them and
+/// what faults were injected
+pub fn fuzz_gen(dir: &Path, count: usize, seed: Option<u64>, corrupt: f64) -> Result<()> {
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    });
+    let (manifest, corpus) = fuzz::generate_corpus(dir, count, seed, corrupt)
+        .with_context(|| format!("generating images in {}", dir.display()))?;
+    let faults: usize = corpus.iter().map(|image| image.faults.len()).sum();
+    println!(
+        "wrote {} images to {} (seed {}), {} faults injected, see {}",
+        corpus.len(),
+        dir.display(),
+        seed,
+        faults,
+        manifest.display()
+    );
+    Ok(())
+}
+
+/// Write every FAT entry of the image's data blocks to a JSON patch file
+pub fn fat_export(image: &ImageOptions, out: &Path) -> Result<()> {
+    let fs = open(image)?;
+    let patch = FatPatch::export(&fs);
+    let json = serde_json::to_string_pretty(&patch)?;
+    fs::write(out, json + "\n").with_context(|| format!("writing {}", out.display()))?;
+    println!(
+        "wrote {} FAT entries to {}",
+        patch.entries.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+/// Write the entries of a JSON patch file into the image's FAT, printing each entry that
+/// changes. The whole patch is checked against the image before anything is written, and the
+/// image is checked again afterwards, failing if it's worse off, as a repair judges it.
+/// A dry run only prints what would change
+pub fn fat_apply(image: &ImageOptions, patch_path: &Path, dry_run: bool) -> Result<()> {
+    let json = fs::read_to_string(patch_path)
+        .with_context(|| format!("reading {}", patch_path.display()))?;
+    let patch: FatPatch =
+        serde_json::from_str(&json).with_context(|| format!("parsing {}", patch_path.display()))?;
+    let mode = match dry_run {
+        true => Mode::ReadOnly,
+        false => Mode::Writable,
+    };
+    let fs = open_in(image, mode)?;
+    let changes = match patch.changes(&fs) {
+        Ok(changes) => changes,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("error: {}", error);
+            }
+            bail!("the patch doesn't fit {}, nothing was written", image.path);
+        }
+    };
+    for change in &changes {
+        println!("{}", change.show(fs.block_format()));
+    }
+    let unchanged = patch.entries.len() - changes.len();
+    if dry_run {
+        println!(
+            "{} entr{} would change, {} already match",
+            changes.len(),
+            if changes.len() == 1 { "y" } else { "ies" },
+            unchanged
+        );
+        return Ok(());
+    }
+
+    let before = check::check(&fs);
+    for change in &changes {
+        fs.set_fat_entry(change.block, change.new)
+            .with_context(|| format!("writing the entry of block {}", change.block))?;
+    }
+    drop(fs);
+    let fs = open(image)?;
+    let after = check::check(&fs);
+    println!(
+        "changed {} entr{}, {} already matched",
+        changes.len(),
+        if changes.len() == 1 { "y" } else { "ies" },
+        unchanged
+    );
+    println!("before: {}", check::summarize(&before));
+    println!("after: {}", check::summarize(&after));
+    for problem in after.iter().filter(|problem| !before.contains(problem)) {
+        println!("new problem: {}", problem.show(fs.block_format()));
+    }
+    if repair::worse(&before, &after) {
+        bail!(ProblemsFound(format!(
+            "the patch made {} worse",
+            image.path
+        )));
+    }
+    Ok(())
+}
+
+/// Compare the image's files with a copy of them in a host directory, printing every difference
+/// and failing if there are any
+pub fn verify(image: &ImageOptions, dir: &Path, check_mtime: bool) -> Result<()> {
+    let fs = open(image)?;
+    if !dir.is_dir() {
+        bail!("{}: not a directory", dir.display());
+    }
+    let verification = verify::verify(&fs, dir, check_mtime);
+    for finding in &verification.findings {
+        println!("{}", finding);
+    }
+    println!("{}", verification);
+    if !verification.findings.is_empty() {
+        bail!(ProblemsFound(format!(
+            "{} differs from {}",
+            image.path,
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Print where a byte offset of the image file lies in the filesystem
+pub fn locate(image: &ImageOptions, offset: u64) -> Result<()> {
+    let fs = open(image)?;
+    match fs.locate(offset) {
+        Some(location) => println!(
+            "{}: {}",
+            format_offset(offset),
+            location.show(fs.block_format())
+        ),
+        None => bail!("{}: past the end of the image", format_offset(offset)),
+    }
+    Ok(())
+}
+
+/// Print, for each block size, the fewest FAT blocks the image's contents fit in and how big
+/// an image that makes, marking the smallest
+pub fn fit(image: &ImageOptions) -> Result<()> {
+    let fs = open(image)?;
+    let fits = Contents::collect(&fs).fits();
+    // with each block size, the fewest FAT blocks that fit, or the most if none do
+    let rows: Vec<&Fit> = (0..=7)
+        .filter_map(|config| {
+            let mut with_config = fits.iter().filter(|fit| fit.block_size_config == config);
+            with_config
+                .clone()
+                .find(|fit| fit.fits())
+                .or_else(|| with_config.next_back())
+        })
+        .collect();
+    let smallest = rows
+        .iter()
+        .filter(|fit| fit.fits())
+        .min_by_key(|fit| fit.image_size);
+    println!(
+        "  {:>10}  {:>13}  {:>10}  {:>12}",
+        "block size", "blocks needed", "FAT blocks", "image size"
+    );
+    for fit in &rows {
+        let marker = match smallest {
+            Some(smallest) if std::ptr::eq(*smallest, *fit) => "*",
+            _ => " ",
+        };
+        let (fat_blocks, image_size) = match fit.fits() {
+            true => (fit.fat_blocks.to_string(), fit.image_size.to_string()),
+            false => ("-".to_owned(), "doesn't fit".to_owned()),
+        };
+        println!(
+            "{} {:>10}  {:>13}  {:>10}  {:>12}",
+            marker,
+            fit.block_size(),
+            fit.blocks_needed,
+            fat_blocks,
+            image_size
+        );
+    }
+    println!(
+        "currently: {}, {} bytes",
+        check::describe_config(fs.config_entry()),
+        fs.bytes().len()
+    );
+    match smallest {
+        Some(fit) => println!(
+            "smallest: {}, {} bytes",
+            check::describe_config(fit.block_size_config as u16 | (fit.fat_blocks as u16) << 8),
+            fit.image_size
+        ),
+        None => println!("the contents fit in no geometry"),
+    }
+    Ok(())
+}
+
+/// Work out how to copy the file at `src_path` in `source` to `dest_path` in `dest`, with its
+/// type, permissions and modification time. Symbolic links on the way to the source are
+/// followed, and a destination that's a directory gets the file under its own name. Nothing is
+/// written, so a destination without room for the file is found out before anything is.
+/// Returns the file copied, and how
+pub fn plan_copy(
+    source: &PennFat,
+    src_path: &str,
+    dest: &PennFat,
+    dest_path: &str,
+) -> Result<(Entry, FileCopy)> {
+    let entry = find(source, src_path, true)?;
+    if entry.dentry.is_dir() {
+        bail!("{}: is a directory", entry.path);
+    }
+    let contents = source
+        .read_file(&entry.dentry)
+        .with_context(|| format!("reading {}", entry.path))?;
+    let dest_path = match walk::lookup(dest, dest_path, true) {
+        Lookup::Found(dir) if dir.dentry.is_dir() => {
+            format!("{}/{}", dir.path.trim_end_matches('/'), entry.dentry.name())
+        }
+        _ => dest_path.to_owned(),
+    };
+    let copy = FileCopy::plan(dest, &entry.dentry, contents, &dest_path, false)
+        .map_err(|e| anyhow!("{}: {}", dest.path().display(), e))?;
+    Ok((entry, copy))
+}
+
+/// Copy a file from one image into another, as [`plan_copy`] works out, recording it in the
+/// destination's audit file. A dry run only prints where it would go
+pub fn cp(
+    src: &ImageOptions,
+    src_path: &str,
+    dest: &ImageOptions,
+    dest_path: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let source = open(src)?;
+    let mode = match dry_run {
+        true => Mode::ReadOnly,
+        false => Mode::Writable,
+    };
+    let fs = open_in(dest, mode)?;
+    let (entry, copy) = plan_copy(&source, src_path, &fs, dest_path)?;
+    let len = copy.size();
+    let action = match dry_run {
+        true => "would copy",
+        false => "copying",
+    };
+    println!(
+        "{} {}:{} to {}:{} ({} bytes in {} blocks, entry in {})",
+        action,
+        src.path,
+        entry.path,
+        dest.path,
+        copy.path,
+        len,
+        copy.blocks.len(),
+        copy.describe_place(&fs)
+    );
+    if dry_run {
+        return Ok(());
+    }
+    copy.write(&fs)
+        .with_context(|| format!("writing {}", copy.path))?;
+    let source = format!("{}:{}", src.path, entry.path);
+    if let Err(e) = edit::audit_copy(&fs, &copy, &source) {
+        eprintln!(
+            "warning: writing {}: {}",
+            edit::audit_path(fs.path()).display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+/// Copy the image's directory tree into a fresh image at `dest`, which mustn't exist yet. The
+/// geometry is the smallest the contents fit in, out of those with the block size and number of
+/// FAT blocks given. The copy is compared with the image afterwards, failing if they differ
+pub fn repack(
+    image: &ImageOptions,
+    dest: &Path,
+    block_size_config: Option<u8>,
+    fat_blocks: Option<u8>,
+) -> Result<()> {
+    let fs = open(image)?;
+    let fits = Contents::collect(&fs).fits();
+    let fit = fits
+        .iter()
+        .filter(|fit| block_size_config.is_none_or(|config| fit.block_size_config == config))
+        .filter(|fit| fat_blocks.is_none_or(|n| fit.fat_blocks == n))
+        .filter(|fit| fit.fits())
+        .min_by_key(|fit| fit.image_size)
+        .ok_or_else(|| anyhow!("the contents of {} don't fit that geometry", image.path))?;
+    let config = fit.block_size_config as u16 | (fit.fat_blocks as u16) << 8;
+    let bytes = repack::repack(&fs, fit.block_size_config, fit.fat_blocks)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dest)
+        .with_context(|| format!("creating {}", dest.display()))?;
+    file.write_all(&bytes)
+        .with_context(|| format!("writing {}", dest.display()))?;
+    drop(file);
+
+    let repacked = PennFat::load(dest, Mode::ReadOnly, Window::default())?;
+    let (differences, compared) = repack::compare(&fs, &repacked);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    println!(
+        "repacked {} into {}: {}, {} bytes (was {})",
+        image.path,
+        dest.display(),
+        check::describe_config(config),
+        bytes.len(),
+        fs.bytes().len()
+    );
+    if !differences.is_empty() {
+        bail!(ProblemsFound(format!(
+            "{} doesn't match {}",
+            dest.display(),
+            image.path
+        )));
+    }
+    println!(
+        "verified: {} entr{} match",
+        compared,
+        if compared == 1 { "y" } else { "ies" }
+    );
+    Ok(())
+}
+
+/// Work out how to fix the image's problems, and print the plan: a step per fix, each with what
+/// it risks. With `apply`, each step is made in turn, asking first unless `yes`. The image is
+/// checked after each step, and a step that leaves it worse off is rolled back, by writing back
+/// the bytes it overwrote
+pub fn repair(image: &ImageOptions, apply: bool, yes: bool) -> Result<()> {
+    let mode = match apply {
+        true => Mode::Writable,
+        false => Mode::ReadOnly,
+    };
+    let fs = open_in(image, mode)?;
+    let plan = repair::plan(&fs);
+    for (i, step) in plan.steps.iter().enumerate() {
+        println!("{}. {}", i + 1, step.action);
+        println!("   risk: {}", step.risk);
+    }
+    for (problem, why) in &plan.unfixed {
+        println!("not fixed: {} ({})", problem.show(fs.block_format()), why);
+    }
+    if plan.steps.is_empty() {
+        println!("nothing to repair");
+        return Ok(());
+    }
+    if !apply {
+        println!(
+            "{} step{} would run (--apply to run them)",
+            plan.steps.len(),
+            if plan.steps.len() == 1 { "" } else { "s" }
+        );
+        return Ok(());
+    }
+
+    let mut problems = check::check_all(&open(image)?);
+    let (mut applied, mut rolled_back) = (0, 0);
+    for (i, step) in plan.steps.iter().enumerate() {
+        if !yes {
+            match ask(&format!("run step {}? [y/N/q] ", i + 1))?.as_str() {
+                "y" | "yes" => {}
+                "q" | "quit" => break,
+                _ => continue,
+            }
+        }
+        let undo = step
+            .apply(&fs)
+            .with_context(|| format!("step {}: {}", i + 1, step.action))?;
+        // checked on a load of its own, which maps the image as it is now
+        let after = check::check_all(&open(image)?);
+        if repair::worse(&problems, &after) {
+            undo.restore(&fs)
+                .with_context(|| format!("rolling back step {}", i + 1))?;
+            println!(
+                "step {} rolled back: it left {} (was {})",
+                i + 1,
+                check::summarize(&after),
+                check::summarize(&problems)
+            );
+            rolled_back += 1;
+            continue;
+        }
+        if let Err(e) = edit::audit_repair(&fs, &step.action) {
+            eprintln!(
+                "warning: writing {}: {}",
+                edit::audit_path(fs.path()).display(),
+                e
+            );
+        }
+        println!("step {} done: {}", i + 1, check::summarize(&after));
+        problems = after;
+        applied += 1;
+    }
+    println!(
+        "ran {} of {} steps, {} rolled back; {}",
+        applied,
+        plan.steps.len(),
+        rolled_back,
+        check::summarize(&problems)
+    );
+    if rolled_back > 0 {
+        bail!(ProblemsFound(format!(
+            "{} step{} would have made {} worse",
+            rolled_back,
+            if rolled_back == 1 { "" } else { "s" },
+            image.path
+        )));
+    }
+    Ok(())
+}
+
+/// Ask a question on stderr and read the answer from stdin, trimmed and lowercased. The end of
+/// the input answers "q"
+fn ask(question: &str) -> Result<String> {
+    eprint!("{}", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer)? {
+        0 => Ok("q".to_owned()),
+        _ => Ok(answer.trim().to_lowercase()),
+    }
+}
+
+/// Print what an error code means and what usually causes it, or every code with what it
+/// means in a line
+pub fn explain(code: Option<&str>) -> Result<()> {
+    let Some(code) = code else {
+        for explanation in &explain::EXPLANATIONS {
+            println!(
+                "{}  {:<12} {}",
+                explanation.code, explanation.category, explanation.summary
+            );
+        }
+        return Ok(());
+    };
+    let explanation = explain::explain(code)
+        .ok_or_else(|| anyhow!("unknown error code {} (`explain` lists them)", code))?;
+    println!(
+        "{} ({}): {}\n\n{}",
+        explanation.code, explanation.category, explanation.summary, explanation.causes
+    );
+    Ok(())
+}
+
+/// Print an error as JSON on stdout, for a command that prints JSON: the message with its
+/// context, and the code and category of the error reading the image behind it, if any
+pub fn print_json_error(e: &anyhow::Error) {
+    let pf = e.chain().find_map(|e| e.downcast_ref::<PfError>());
+    // the context, down to the error reading the image, whose message covers its own source
+    let mut message = Vec::new();
+    for e in e.chain() {
+        message.push(e.to_string());
+        if e.is::<PfError>() {
+            break;
+        }
+    }
+    let error = serde_json::json!({
+        "error": {
+            "code": pf.map(PfError::code),
+            "category": pf.map(PfError::category),
+            "message": message.join(": "),
+        }
+    });
+    println!("{:#}", error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pfview::fuzz::{DentrySpec, ImageBuilder};
+    use pfview::testing;
+
+    fn sample() -> PennFat {
+        PennFat::from_bytes(testing::sample_image()).unwrap()
+    }
+
+    /// An image whose root holds a file named like a block, "@0x2a", stored in block 0x03
+    fn image_with_block_name() -> PennFat {
+        let mut image = ImageBuilder::new(0, 1);
+        let file = image.allocate_lowest(2).unwrap();
+        image.write(&file[1..], b"not block 0x2a\n");
+        image.write_dentry(
+            1,
+            0,
+            &DentrySpec {
+                name: b"@0x2a".to_vec(),
+                size: 15,
+                first_block: file[1],
+                type_: 0,
+                perm: 6,
+                mtime: 0,
+            },
+        );
+        PennFat::from_bytes(image.bytes()).unwrap()
+    }
+
+    fn target(arg: &str) -> Target {
+        Target::parse(arg).unwrap()
+    }
+
+    #[test]
+    fn blocks_and_paths_resolve_to_the_same_file() {
+        let fs = sample();
+        let by_path = contents(&fs, &target("/notes.txt"), true, true).unwrap();
+        let by_block = contents(&fs, &target("@0x04"), true, true).unwrap();
+        let by_decimal = contents(&fs, &target("@4"), true, true).unwrap();
+        assert_eq!(by_path, by_block);
+        assert_eq!(by_block, by_decimal);
+        assert_eq!(by_block.len(), 4 * 256);
+    }
+
+    #[test]
+    fn free_and_out_of_range_blocks_are_errors() {
+        let fs = sample();
+        let error = |arg: &str| match resolve(&fs, &target(arg), true) {
+            Ok(_) => panic!("{} resolved", arg),
+            Err(e) => e.to_string(),
+        };
+        assert_eq!(error("@0x70"), "block 0x70 is free");
+        assert_eq!(
+            error("@0"),
+            "block 0x00 is out of range (data blocks are 0x01 to 0x7f)"
+        );
+        assert_eq!(
+            error("@0x80"),
+            "block 0x80 is out of range (data blocks are 0x01 to 0x7f)"
+        );
+        assert!(Target::parse("@zz").is_err());
+    }
+
+    #[test]
+    fn a_file_named_like_a_block_is_reached_through_dot_slash() {
+        let fs = image_with_block_name();
+        assert!(matches!(target("@0x2a"), Target::Block(0x2a)));
+        assert!(
+            resolve(&fs, &target("@0x2a"), true).is_err(),
+            "0x2a is free"
+        );
+        let file = contents(&fs, &target("./@0x2a"), true, false).unwrap();
+        assert_eq!(file, b"not block 0x2a\n");
+    }
+
+    #[test]
+    fn stat_describes_a_file_and_its_chain() {
+        let fs = sample();
+        let Lookup::Found(entry) = walk::lookup(&fs, "/notes.txt", true) else {
+            panic!("no /notes.txt");
+        };
+        let lines = file_stat(&fs, &entry);
+        assert_eq!(lines[0], "path: /notes.txt");
+        assert!(lines.contains(&"size: 940".to_owned()));
+        assert!(lines.contains(&"first block: 0x04".to_owned()));
+        assert!(lines.contains(&"blocks: 4 (4 extents)".to_owned()));
+        assert_eq!(
+            lines.last().unwrap(),
+            "chain: 0x04 -> 0x0c -> 0x05 -> 0x14 -> EOF"
+        );
+    }
+
+    #[test]
+    fn ls_shows_perms_as_letters_or_the_byte() {
+        let dentry = |type_, perm| {
+            let mut dentry = find(&sample(), "/README", false).unwrap().dentry;
+            dentry.type_ = type_;
+            dentry.perm = perm;
+            dentry
+        };
+        for (perm, letters) in [
+            (0, "----"),
+            (2, "--w-"),
+            (4, "-r--"),
+            (5, "-r-x"),
+            (6, "-rw-"),
+            (7, "-rwx"),
+            (3, "-???"),
+        ] {
+            assert_eq!(
+                format_mode(&dentry(0, perm), PermTable::Spec, false),
+                letters
+            );
+        }
+        assert_eq!(format_mode(&dentry(1, 7), PermTable::Spec, true), "d  7");
+        assert_eq!(format_mode(&dentry(2, 3), PermTable::Spec, true), "l  3");
+        assert_eq!(format_mode(&dentry(0, 3), PermTable::Bits, false), "--wx");
+    }
+
+    #[test]
+    fn lookups_say_where_a_link_leads() {
+        let fs = sample();
+        assert_eq!(
+            describe_lookup(&fs, &find(&fs, "/latest", true).unwrap()),
+            "/docs/guide.txt (file, first block 0x0a)"
+        );
+        assert_eq!(
+            describe_lookup(&fs, &find(&fs, "/latest", false).unwrap()),
+            "/latest (symlink, first block 0x06)"
+        );
+        assert_eq!(
+            find(&fs, "/docs/missing", true).err().unwrap().to_string(),
+            "/docs/missing: no such file or directory"
+        );
+    }
+
+    #[test]
+    fn graphing_a_chain_draws_only_its_blocks() {
+        let fs = sample();
+        let dot = graph_dot(&fs, Some(&fs.chain(0x04)));
+        assert_eq!(
+            dot,
+            "digraph fat {\n  node [shape=box];\n\
+             \x20 \"0x04\" [label=\"0x04\\n/notes.txt\"];\n  \"0x04\" -> \"0x0c\";\n\
+             \x20 \"0x0c\" [label=\"0x0c\"];\n  \"0x0c\" -> \"0x05\";\n\
+             \x20 \"0x05\" [label=\"0x05\"];\n  \"0x05\" -> \"0x14\";\n\
+             \x20 \"0x14\" [label=\"0x14\"];\n}\n"
+        );
+    }
+
+    #[test]
+    fn graphing_everything_marks_the_orphans() {
+        let fs = sample();
+        let dot = graph_dot(&fs, None);
+        assert!(dot.contains("\"0x1e\" [label=\"0x1e\\n(orphan)\"];"));
+        // only the head of the orphan chain is one
+        assert!(dot.contains("\"0x1f\" [label=\"0x1f\"];"));
+        assert!(dot.contains("\"0x0d\" [label=\"0x0d\\n/docs/src/main.c\"];"));
+        assert_eq!(dot.matches(" [label=").count(), fs.get_fat_table().len());
+    }
+}
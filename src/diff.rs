@@ -0,0 +1,94 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+};
+
+/// The contents of a block as last seen, and as seen before they last changed
+struct Versions {
+    current: Vec<u8>,
+    previous: Option<Vec<u8>>,
+}
+
+impl Versions {
+    fn size(&self) -> usize {
+        self.current.len() + self.previous.as_ref().map_or(0, Vec::len)
+    }
+}
+
+/// Copies of the blocks viewed recently, so a block that changes while it's being watched can
+/// be diffed against what it held before. The least recently viewed blocks are dropped to keep
+/// the copies within a budget of bytes
+pub struct BlockCache {
+    blocks: HashMap<u16, Versions>,
+    /// The cached blocks, least recently viewed first
+    order: VecDeque<u16>,
+    /// The bytes held by the copies
+    size: usize,
+    budget: usize,
+}
+
+impl BlockCache {
+    pub fn new(budget: usize) -> Self {
+        BlockCache {
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+            size: 0,
+            budget,
+        }
+    }
+
+    /// Record a block's contents as just viewed. If they differ from the copy last seen, that
+    /// copy becomes the previous one
+    pub fn observe(&mut self, block: u16, data: &[u8]) {
+        match self.blocks.get_mut(&block) {
+            Some(versions) => {
+                if versions.current != data {
+                    self.size -= versions.size();
+                    versions.previous =
+                        Some(std::mem::replace(&mut versions.current, data.to_vec()));
+                    self.size += versions.size();
+                }
+                self.order.retain(|b| *b != block);
+            }
+            None => {
+                self.size += data.len();
+                self.blocks.insert(
+                    block,
+                    Versions {
+                        current: data.to_vec(),
+                        previous: None,
+                    },
+                );
+            }
+        }
+        self.order.push_back(block);
+        // the block just viewed stays, even if it alone is over budget
+        while self.size > self.budget && self.order.len() > 1 {
+            if let Some(evicted) = self.order.pop_front() {
+                if let Some(versions) = self.blocks.remove(&evicted) {
+                    self.size -= versions.size();
+                }
+            }
+        }
+    }
+
+    /// The contents of a block before it last changed, if it changed while cached
+    pub fn previous(&self, block: u16) -> Option<&[u8]> {
+        self.blocks.get(&block)?.previous.as_deref()
+    }
+}
+
+/// The ranges of bytes that differ between two versions of a block
+pub fn changed_ranges(old: &[u8], new: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for i in 0..old.len().max(new.len()) {
+        if old.get(i) == new.get(i) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.end == i => last.end = i + 1,
+            _ => ranges.push(i..i + 1),
+        }
+    }
+    ranges
+}
@@ -0,0 +1,528 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+
+use crate::check::{self, Problem};
+use crate::cli::ImageOptions;
+use crate::pennfat::{Dentry, Entry, Mode, PennFat, ShowBlocks, Window};
+use crate::walk;
+
+/// How long the directory walk may take before it gives up, so a pathological image can't hang
+/// the doctor
+const WALK_DEADLINE: Duration = Duration::from_secs(5);
+
+/// How far in the future a modification time may be before it's suspicious, allowing for clock
+/// skew between the machine that wrote the image and this one
+const FUTURE_SLACK_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// How a check went
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Something the viewer copes with, but that's likely a bug in whatever wrote the image
+    Warn,
+    /// Something that makes the viewer show nonsense, or fail to show the image at all
+    Fail,
+    /// The check couldn't run, because the image didn't load
+    Skip,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+            Status::Skip => "skip",
+        })
+    }
+}
+
+/// The outcome of one of the doctor's checks, and how long it took
+pub struct Finding {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:<12}{:>9.1} ms  {}",
+            self.status,
+            self.name,
+            self.elapsed.as_secs_f64() * 1000.0,
+            self.detail
+        )
+    }
+}
+
+/// Everything the doctor found. Every check runs, whatever the ones before it found
+#[derive(Default)]
+pub struct Diagnosis {
+    pub findings: Vec<Finding>,
+}
+
+impl Diagnosis {
+    /// Run a check, timing it
+    fn run(&mut self, name: &'static str, check: impl FnOnce() -> (Status, String)) {
+        let start = Instant::now();
+        let (status, detail) = check();
+        self.findings.push(Finding {
+            name,
+            status,
+            detail,
+            elapsed: start.elapsed(),
+        });
+    }
+
+    fn skip(&mut self, name: &'static str) {
+        self.run(name, || (Status::Skip, "the image didn't load".to_owned()));
+    }
+
+    fn count(&self, status: Status) -> usize {
+        self.findings.iter().filter(|f| f.status == status).count()
+    }
+
+    /// Whether any check failed
+    pub fn failed(&self) -> bool {
+        self.count(Status::Fail) > 0
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        write!(
+            f,
+            "{} passed, {} warnings, {} failed, {} skipped",
+            self.count(Status::Pass),
+            self.count(Status::Warn),
+            self.count(Status::Fail),
+            self.count(Status::Skip)
+        )
+    }
+}
+
+/// Run every check on an image, reading it as `image` says, and report what each found. Nothing
+/// here trusts the image: the configuration and size are checked from the raw file before it's
+/// loaded, and the walk is bounded in time
+pub fn diagnose(image: &ImageOptions) -> Diagnosis {
+    let mut diagnosis = Diagnosis::default();
+    diagnosis.run("config", || check_config(&image.path, image.window));
+
+    let start = Instant::now();
+    let loaded = PennFat::load(&image.path, Mode::ReadOnly, image.window).map(|mut fs| {
+        let warning = image.apply(&mut fs);
+        (fs, warning)
+    });
+    let elapsed = start.elapsed();
+    let (fs, root_warning) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            diagnosis.findings.push(Finding {
+                name: "load",
+                status: Status::Fail,
+                detail: e.to_string(),
+                elapsed,
+            });
+            for name in [
+                "fat",
+                "walk",
+                "dentries",
+                "links",
+                "directories",
+                "timestamps",
+            ] {
+                diagnosis.skip(name);
+            }
+            return diagnosis;
+        }
+    };
+    diagnosis.findings.push(Finding {
+        name: "load",
+        status: match root_warning {
+            Some(_) => Status::Warn,
+            None => Status::Pass,
+        },
+        detail: root_warning.unwrap_or_else(|| {
+            format!(
+                "{} bytes, {} data blocks, root at {}",
+                fs.bytes().len(),
+                fs.data_block_count(),
+                fs.format_block(fs.root_block())
+            )
+        }),
+        elapsed,
+    });
+
+    diagnosis.run("fat", || check_fat(&fs));
+    let mut dentries = Vec::new();
+    diagnosis.run("walk", || {
+        let (status, detail, found) = walk_tree(&fs);
+        dentries = found;
+        (status, detail)
+    });
+    diagnosis.run("dentries", || check_dentries(&fs, &dentries));
+    diagnosis.run("links", || check_links(&fs, &dentries));
+    diagnosis.run("directories", || check_directories(&fs));
+    diagnosis.run("timestamps", || check_timestamps(&dentries));
+    diagnosis
+}
+
+/// Check the configuration bytes and the file size straight from the file
+fn check_config(path: &str, window: Window) -> (Status, String) {
+    let mut header = [0; 2];
+    let size = match File::open(path).and_then(|mut file| {
+        file.seek(SeekFrom::Start(window.offset))?;
+        file.read_exact(&mut header)?;
+        file.metadata()
+    }) {
+        Ok(metadata) => window
+            .length
+            .unwrap_or(metadata.len().saturating_sub(window.offset)),
+        Err(e) => return (Status::Fail, format!("reading the first two bytes: {}", e)),
+    };
+    let [block_size_config, fat_blocks] = header;
+    if block_size_config > 7 {
+        return (
+            Status::Fail,
+            format!(
+                "block size config {} is out of range (0-7)",
+                block_size_config
+            ),
+        );
+    }
+    if fat_blocks == 0 {
+        return (Status::Fail, "the FAT spans no blocks".to_owned());
+    }
+    let block_size = 256u64 << block_size_config;
+    let fat_size = block_size * fat_blocks as u64;
+    let data_blocks = (fat_size / 2 - 1).min(0xFFFE);
+    let expected = fat_size + data_blocks * block_size;
+    let config = check::describe_config(u16::from_le_bytes(header));
+    // a FAT with more entries than block numbers can address may come with the blocks past
+    // the last one anyway, which the image is read without
+    let capped = fat_size / 2 - 1 > data_blocks;
+    match size == expected {
+        true => (Status::Pass, config),
+        false if capped && size > expected => (
+            Status::Warn,
+            format!(
+                "{}, {} trailing bytes unaddressable by FAT",
+                config,
+                size - expected
+            ),
+        ),
+        false => (
+            Status::Fail,
+            format!(
+                "{}, so {} bytes, but the image is {}",
+                config, expected, size
+            ),
+        ),
+    }
+}
+
+/// Run the consistency checks on the FAT, counting the problems of each kind
+fn check_fat(fs: &PennFat) -> (Status, String) {
+    let problems = check::check(fs);
+    let Some(first) = problems.first() else {
+        return (
+            Status::Pass,
+            format!("{} blocks allocated", fs.get_fat_table().len()),
+        );
+    };
+    let count = |kind: fn(&Problem) -> bool| problems.iter().filter(|p| kind(p)).count();
+    // entries nothing can reach don't change how the image reads
+    let unreachable = count(|p| matches!(p, Problem::Unreachable { .. }));
+    (
+        match unreachable == problems.len() {
+            true => Status::Warn,
+            false => Status::Fail,
+        },
+        format!(
+            "{} out of range, {} cross-linked, {} cycles, {} config changes, {} unreachable; \
+             first: {}",
+            count(|p| matches!(p, Problem::OutOfRange { .. })),
+            count(|p| matches!(p, Problem::CrossLink { .. })),
+            count(|p| matches!(p, Problem::Cycle { .. })),
+            count(|p| matches!(p, Problem::ConfigChanged { .. })),
+            unreachable,
+            first.show(fs.block_format())
+        ),
+    )
+}
+
+/// Walk the directory tree from the root, noting directories linked in more than once and
+/// unreadable blocks, and stopping if it takes too long. Returns the dentries found
+fn walk_tree(fs: &PennFat) -> (Status, String, Vec<Dentry>) {
+    let deadline = Instant::now() + WALK_DEADLINE;
+    let mut dentries = Vec::new();
+    let (mut loops, mut unreadable, mut directories_walked) = (0, 0, 0);
+    let mut visited = HashSet::from([fs.root_block()]);
+    let mut directories = VecDeque::from([fs.root_block()]);
+    let mut timed_out = false;
+
+    while let Some(first_block) = directories.pop_front() {
+        if Instant::now() > deadline {
+            timed_out = true;
+            break;
+        }
+        directories_walked += 1;
+        for dentry in walk::children(fs, first_block) {
+            let Ok(dentry) = dentry else {
+                unreadable += 1;
+                continue;
+            };
+            if dentry.is_dir() && dentry.first_block != 0 {
+                match visited.insert(dentry.first_block) {
+                    true => directories.push_back(dentry.first_block),
+                    false => loops += 1,
+                }
+            }
+            dentries.push(dentry);
+        }
+    }
+
+    let mut detail = format!(
+        "{} entries in {} directories",
+        dentries.len(),
+        directories_walked
+    );
+    let mut status = Status::Pass;
+    if loops > 0 {
+        detail += &format!(", {} directories linked in more than once", loops);
+        status = Status::Warn;
+    }
+    if unreadable > 0 {
+        detail += &format!(", {} unreadable blocks", unreadable);
+        status = Status::Fail;
+    }
+    if timed_out {
+        detail += &format!(
+            ", gave up after {}s with {} directories left",
+            WALK_DEADLINE.as_secs(),
+            directories.len()
+        );
+        status = Status::Fail;
+    }
+    (status, detail, dentries)
+}
+
+/// Count the dentries whose fields make no sense
+fn check_dentries(fs: &PennFat, dentries: &[Dentry]) -> (Status, String) {
+    let block_size = fs.block_size() as u64;
+    let profile = fs.profile();
+    let perms = fs.dentry_layout().perms;
+    let counts = [
+        (
+            "of unknown type",
+            dentries.iter().filter(|d| d.type_ > 2).count(),
+        ),
+        (
+            "with a perm the table doesn't define",
+            dentries.iter().filter(|d| !perms.is_legal(d.perm)).count(),
+        ),
+        (
+            "with a first block out of range",
+            dentries
+                .iter()
+                .filter(|d| d.first_block > fs.data_block_count())
+                .count(),
+        ),
+        (
+            "with contents but no blocks",
+            dentries
+                .iter()
+                .filter(|d| d.first_block == 0 && d.size > 0)
+                .count(),
+        ),
+        (
+            "starting at a free block",
+            dentries
+                .iter()
+                .filter(|d| d.first_block != 0)
+                .filter(|d| {
+                    fs.fat_entry(d.first_block)
+                        .is_some_and(|e| profile.classify(e) == Entry::Free)
+                })
+                .count(),
+        ),
+        (
+            "larger than their chains",
+            dentries
+                .iter()
+                .filter(|d| !d.is_dir() && d.first_block != 0)
+                .filter(|d| {
+                    let chain = fs.chain(d.first_block);
+                    chain.check_len().is_ok()
+                        && d.size as u64 > chain.blocks.len() as u64 * block_size
+                })
+                .count(),
+        ),
+        (
+            "with chains longer than the limit",
+            dentries
+                .iter()
+                .filter(|d| d.first_block != 0)
+                .filter(|d| fs.chain(d.first_block).check_len().is_err())
+                .count(),
+        ),
+        (
+            "with a '/' in their names",
+            dentries.iter().filter(|d| d.name().contains('/')).count(),
+        ),
+        (
+            "named . or ..",
+            dentries
+                .iter()
+                .filter(|d| matches!(d.name().as_str(), "." | ".."))
+                .count(),
+        ),
+        (
+            "with names that aren't UTF-8",
+            dentries
+                .iter()
+                .filter(|d| d.name().contains(char::REPLACEMENT_CHARACTER))
+                .count(),
+        ),
+    ];
+    summarize(dentries.len(), &counts)
+}
+
+/// Check the files that share blocks. Files sharing a whole chain are hard links where the
+/// dentry profile allows them, and corruption where it doesn't. A file starting partway into
+/// another file's chain shares only its tail, which is always corruption
+fn check_links(fs: &PennFat, dentries: &[Dentry]) -> (Status, String) {
+    let mut links: BTreeMap<u16, usize> = BTreeMap::new();
+    for dentry in dentries
+        .iter()
+        .filter(|d| !d.is_dir() && d.first_block != 0)
+    {
+        *links.entry(dentry.first_block).or_default() += 1;
+    }
+    let shared: Vec<usize> = links.values().copied().filter(|n| *n > 1).collect();
+    // a chain running into another file's first block takes that whole file in as its tail
+    let starts: HashSet<u16> = dentries
+        .iter()
+        .map(|d| d.first_block)
+        .chain([fs.root_block()])
+        .collect();
+    let overlapping = starts
+        .iter()
+        .filter(|block| **block != 0)
+        .filter(|block| {
+            fs.chain(**block)
+                .blocks
+                .iter()
+                .skip(1)
+                .any(|block| starts.contains(block))
+        })
+        .count();
+
+    let allowed = fs.dentry_layout().allow_shared_chains;
+    let mut detail = match shared.len() {
+        0 => "no files share a chain".to_owned(),
+        n => format!(
+            "{} chains shared by {} files{}",
+            n,
+            shared.iter().sum::<usize>(),
+            match allowed {
+                true => " (allowed by the dentry profile)",
+                false => " (allow_shared_chains is off)",
+            }
+        ),
+    };
+    if overlapping > 0 {
+        detail += &format!(
+            ", {} chains run into another file's first block",
+            overlapping
+        );
+    }
+    let status = match (shared.is_empty() || allowed) && overlapping == 0 {
+        true => Status::Pass,
+        false => Status::Fail,
+    };
+    (status, detail)
+}
+
+/// Check the directories against the rules the dentry layout (or `--packed-dirs` and
+/// `--sorted-dirs`) sets for them. Breaking a rule the spec sets fails
+fn check_directories(fs: &PennFat) -> (Status, String) {
+    let layout = fs.dentry_layout();
+    let rules: Vec<&str> = [
+        (layout.packed_dirs, "packed"),
+        (layout.sorted_dirs, "sorted"),
+    ]
+    .into_iter()
+    .filter_map(|(required, rule)| required.then_some(rule))
+    .collect();
+    if rules.is_empty() {
+        return (
+            Status::Pass,
+            "neither packed nor sorted required (--packed-dirs, --sorted-dirs)".to_owned(),
+        );
+    }
+    let problems = check::check_directories(fs);
+    match problems.as_slice() {
+        [] => (
+            Status::Pass,
+            format!("every directory is {}", rules.join(" and ")),
+        ),
+        [problem] => (Status::Fail, problem.show(fs.block_format()).to_string()),
+        [first, rest @ ..] => (
+            Status::Fail,
+            format!("{}, and {} more", first.show(fs.block_format()), rest.len()),
+        ),
+    }
+}
+
+/// Count the dentries with modification times that can't be right
+fn check_timestamps(dentries: &[Dentry]) -> (Status, String) {
+    let now = Utc::now().timestamp_millis();
+    let millis = |d: &Dentry| i64::try_from(d.mtime).unwrap_or(i64::MAX);
+    let counts = [
+        ("at 0", dentries.iter().filter(|d| d.mtime == 0).count()),
+        (
+            "in the future",
+            dentries
+                .iter()
+                .filter(|d| millis(d) > now + FUTURE_SLACK_MILLIS)
+                .count(),
+        ),
+        (
+            // a time in seconds reads as a date in January 1970
+            "that look like seconds, not milliseconds",
+            dentries
+                .iter()
+                .filter(|d| d.mtime != 0 && millis(d) < now / 1000 * 2)
+                .count(),
+        ),
+    ];
+    summarize(dentries.len(), &counts)
+}
+
+/// Summarize counts of suspicious dentries: a pass if there are none, otherwise a warning
+/// naming each kind found
+fn summarize(total: usize, counts: &[(&str, usize)]) -> (Status, String) {
+    let found: Vec<String> = counts
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|(what, n)| format!("{} {}", n, what))
+        .collect();
+    match found.is_empty() {
+        true => (Status::Pass, format!("{} entries look fine", total)),
+        false => (Status::Warn, format!("of {}: {}", total, found.join(", "))),
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::pennfat::PennFat;
+use crate::walk;
+
+/// The total size of everything beneath a directory
+#[derive(Clone, Copy, Default)]
+pub struct DirSize {
+    /// Sum of the sizes in the dentries beneath the directory
+    pub bytes: u64,
+    /// Blocks consumed beneath the directory, including the directory blocks themselves
+    pub blocks: usize,
+}
+
+impl DirSize {
+    /// Describe the size, as the sizes in the dentries (logical) or the space of the blocks
+    /// consumed (physical)
+    pub fn describe(&self, block_size: u16, physical: bool) -> String {
+        let bytes = match physical {
+            true => self.blocks as u64 * block_size as u64,
+            false => self.bytes,
+        };
+        format!("{} bytes in {} blocks", bytes, self.blocks)
+    }
+}
+
+/// Compute the total size of every directory in the tree, by the first block of its chain.
+/// Each directory is only counted once, under the first directory it's found in, so
+/// directories linked into the tree more than once (or into themselves) can't loop
+pub fn dir_sizes(fs: &PennFat) -> HashMap<u16, DirSize> {
+    let mut sizes: HashMap<u16, DirSize> = HashMap::new();
+    let mut visited = HashSet::from([fs.root_block()]);
+    // directories in breadth-first order, with their parent
+    let mut order = vec![(fs.root_block(), None)];
+
+    let mut i = 0;
+    while let Some(&(first_block, _)) = order.get(i) {
+        i += 1;
+        let mut size = DirSize {
+            bytes: 0,
+            blocks: fs.chain(first_block).blocks.len(),
+        };
+        for dentry in walk::children(fs, first_block).flatten() {
+            size.bytes += dentry.size as u64;
+            if !dentry.is_dir() {
+                size.blocks += fs.chain(dentry.first_block).blocks.len();
+            } else if visited.insert(dentry.first_block) {
+                order.push((dentry.first_block, Some(first_block)));
+            }
+        }
+        sizes.insert(first_block, size);
+    }
+
+    // children come after their parents, so going backwards totals them up bottom-up
+    for (first_block, parent) in order.into_iter().rev() {
+        let Some(parent) = parent else {
+            continue;
+        };
+        let size = sizes[&first_block];
+        let total = sizes.entry(parent).or_default();
+        total.bytes += size.bytes;
+        total.blocks += size.blocks;
+    }
+    sizes
+}
@@ -0,0 +1,749 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+use crate::filter;
+use crate::ownership::Ownership;
+use crate::pennfat::{
+    self, format_offset, BlockFormat, ChainEnd, Dentry, Field, PennFat, PermTable, ShowBlocks,
+};
+use crate::snapshot;
+use crate::walk::{self, Lookup};
+
+/// A field of a directory entry that can be edited
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Name,
+    Perm,
+    Type,
+    FirstBlock,
+    Mtime,
+}
+
+impl FieldKind {
+    /// Every field, in the order the form shows them
+    pub const ALL: [FieldKind; 5] = [
+        FieldKind::Name,
+        FieldKind::Perm,
+        FieldKind::Type,
+        FieldKind::FirstBlock,
+        FieldKind::Mtime,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FieldKind::Name => "name",
+            FieldKind::Perm => "perm",
+            FieldKind::Type => "type",
+            FieldKind::FirstBlock => "first_block",
+            FieldKind::Mtime => "mtime",
+        }
+    }
+
+    /// What the form says can be typed into the field. Only the perm values `perms` defines
+    /// are offered
+    pub fn hint(self, perms: PermTable) -> String {
+        match self {
+            FieldKind::Name => "no '/'".to_owned(),
+            FieldKind::Perm => {
+                let legal: Vec<String> = perms.legal().iter().map(|p| p.to_string()).collect();
+                format!("{}, or like rw-", legal.join(" "))
+            }
+            FieldKind::Type => "0-2, or file, dir, symlink".to_owned(),
+            FieldKind::FirstBlock => "0 for none".to_owned(),
+            FieldKind::Mtime => "YYYY-MM-DD HH:MM:SS (UTC), ms, or now".to_owned(),
+        }
+    }
+
+    /// Where the field lives in a directory entry
+    fn field(self, fs: &PennFat) -> Field {
+        let layout = fs.dentry_layout();
+        match self {
+            FieldKind::Name => layout.name,
+            FieldKind::Perm => layout.perm,
+            FieldKind::Type => layout.type_,
+            FieldKind::FirstBlock => layout.first_block,
+            FieldKind::Mtime => layout.mtime,
+        }
+    }
+
+    /// How the field of a dentry is shown in the form
+    fn show(self, dentry: &Dentry, fs: &PennFat) -> String {
+        match self {
+            FieldKind::Name => dentry.name(),
+            FieldKind::Perm => dentry.perm.to_string(),
+            FieldKind::Type => dentry.type_.to_string(),
+            FieldKind::FirstBlock => fs.format_block(dentry.first_block),
+            FieldKind::Mtime => dentry.format_mtime(),
+        }
+    }
+}
+
+/// A change to a field of a directory entry, and the bytes that make it
+pub struct Change {
+    pub field: FieldKind,
+    pub old: String,
+    pub new: String,
+    /// Where the field starts in the image file
+    pub offset: u64,
+    bytes: Vec<u8>,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field.label(), self.old, self.new)
+    }
+}
+
+/// The form editing a directory entry in place: what's been typed into each field, and once
+/// it's submitted, the changes waiting to be confirmed
+pub struct DentryForm {
+    /// The directory block holding the entry
+    pub block: u16,
+    /// The slot of the entry in the block
+    pub slot: usize,
+    pub dentry: Dentry,
+    /// The entry's bytes when the form was opened, to tell whether it changed under the form
+    entry: Vec<u8>,
+    pub inputs: Vec<String>,
+    /// The field being typed into
+    pub selected: usize,
+    pub confirm: Option<Vec<Change>>,
+}
+
+impl DentryForm {
+    /// Open the form on the entry in a slot of a directory block, with its fields filled in
+    pub fn new(fs: &PennFat, block: u16, slot: usize) -> pennfat::Result<Option<Self>> {
+        let size = fs.dentry_layout().size;
+        let entry = match fs.block_bytes(block)?.get(slot * size..(slot + 1) * size) {
+            Some(entry) => entry.to_vec(),
+            None => return Ok(None),
+        };
+        let Some(dentry) = Dentry::parse(&entry, fs.dentry_layout()) else {
+            return Ok(None);
+        };
+        Ok(Some(DentryForm {
+            block,
+            slot,
+            inputs: FieldKind::ALL.iter().map(|f| f.show(&dentry, fs)).collect(),
+            dentry,
+            entry,
+            selected: 0,
+            confirm: None,
+        }))
+    }
+
+    /// Where the entry starts in the image file
+    fn entry_offset(&self, fs: &PennFat) -> pennfat::Result<u64> {
+        Ok(fs.block_offset(self.block)? as u64 + (self.slot * fs.dentry_layout().size) as u64)
+    }
+
+    /// Work out the changes to the fields that were edited, checking each new value the way
+    /// the consistency checks would. Values that would make the image inconsistent (such as a
+    /// first block outside the data region) are refused unless `allow_dangerous`
+    pub fn changes(&self, fs: &PennFat, allow_dangerous: bool) -> Result<Vec<Change>, String> {
+        let entry_offset = self.entry_offset(fs).map_err(|e| e.to_string())?;
+        let size = fs.dentry_layout().size;
+        let mut changes = Vec::new();
+        for (kind, input) in FieldKind::ALL.iter().zip(&self.inputs) {
+            let old = kind.show(&self.dentry, fs);
+            if *input == old {
+                continue;
+            }
+            let range = kind.field(fs).within(size);
+            if range.is_empty() {
+                return Err(format!("the entries have no room for {}", kind.label()));
+            }
+            let (new, bytes) = parse(*kind, input, range.len(), fs, allow_dangerous)?;
+            changes.push(Change {
+                field: *kind,
+                old,
+                new,
+                offset: entry_offset + range.start as u64,
+                bytes,
+            });
+        }
+        Ok(changes)
+    }
+
+    /// Write the changes to the image, unless the entry changed since the form was opened
+    pub fn write(&self, fs: &PennFat, changes: &[Change]) -> anyhow::Result<()> {
+        let offset = self.entry_offset(fs)? as usize;
+        if fs.bytes().get(offset..offset + self.entry.len()) != Some(&self.entry[..]) {
+            anyhow::bail!("the entry changed since it was opened for editing");
+        }
+        for change in changes {
+            fs.write_bytes(change.offset, &change.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where a new directory entry goes
+enum Place {
+    /// A free slot of one of the directory's blocks
+    Slot { block: u16, slot: usize },
+    /// The first slot of a free block, linked onto the end of the directory's chain
+    Append { last: u16, block: u16 },
+}
+
+/// A directory entry to add for an orphan chain, so the file it holds is back in the tree,
+/// and where it goes. Everything is worked out before anything is written
+pub struct Adoption {
+    /// The first block of the orphan chain
+    pub head: u16,
+    /// The path the file gets
+    pub path: String,
+    pub size: u32,
+    /// The new entry's bytes
+    entry: Vec<u8>,
+    place: Place,
+}
+
+impl Adoption {
+    /// Get the size an orphan chain's file is guessed to have: all of its blocks
+    pub fn estimated_size(fs: &PennFat, head: u16) -> u32 {
+        (fs.chain(head).blocks.len() * fs.block_size() as usize) as u32
+    }
+
+    /// Work out how to adopt the orphan chain starting at `head`, from what was typed into the
+    /// prompt: the path to give the file, then optionally its size. The entry goes in the first
+    /// free slot of the directory, or a free block appended to it when it has none
+    pub fn plan(
+        fs: &PennFat,
+        head: u16,
+        input: &str,
+        allow_dangerous: bool,
+    ) -> Result<Self, String> {
+        let input = input.trim();
+        let (path, size) = match input.rsplit_once(' ') {
+            Some((path, size)) if size.parse::<u32>().is_ok() => {
+                (path.trim(), size.parse::<u32>().ok())
+            }
+            _ => (input, None),
+        };
+        let chain_size = Self::estimated_size(fs, head);
+        let size = size.unwrap_or(chain_size);
+        if size > chain_size && !allow_dangerous {
+            return Err(format!(
+                "{} bytes don't fit in the chain's {} bytes (--allow-dangerous to write it anyway)",
+                size, chain_size
+            ));
+        }
+        if let Some(owner) = Ownership::build(fs).owner(head) {
+            return Err(format!(
+                "block {} already belongs to {}",
+                fs.format_block(head),
+                owner.path
+            ));
+        }
+
+        let (dir_path, dir, name) = destination(fs, path)?;
+        let fields = [
+            (FieldKind::Name, name.to_owned()),
+            (FieldKind::Perm, "6".to_owned()),
+            (FieldKind::Type, "0".to_owned()),
+            (FieldKind::FirstBlock, fs.format_block(head)),
+            (FieldKind::Mtime, "now".to_owned()),
+        ];
+        let entry = new_entry(fs, fields, size, allow_dangerous)?;
+        let place = Place::find(fs, dir)?;
+        Ok(Adoption {
+            head,
+            path: format!("{}/{}", dir_path.trim_end_matches('/'), name),
+            size,
+            entry,
+            place,
+        })
+    }
+
+    /// Describe where the entry goes
+    pub fn describe_place(&self, fs: &PennFat) -> String {
+        self.place.show(fs.block_format()).to_string()
+    }
+
+    /// Write the entry
+    pub fn write(&self, fs: &PennFat) -> anyhow::Result<()> {
+        self.place.write(fs, &self.entry)
+    }
+
+    /// Get the stretches of the image writing the entry overwrites, as offsets and lengths
+    pub fn footprint(&self, fs: &PennFat) -> pennfat::Result<Vec<(u64, usize)>> {
+        self.place.footprint(fs, self.entry.len())
+    }
+}
+
+/// Find the directory a new entry at `path` goes in, checking nothing is at the path already.
+/// Returns the directory's path and first block, and the name the entry gets
+fn destination<'a>(fs: &PennFat, path: &'a str) -> Result<(&'a str, u16, &'a str), String> {
+    let (dir_path, name) = match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((dir, name)) => (dir, name),
+        None => ("/", path),
+    };
+    let dir = match walk::lookup(fs, dir_path, true) {
+        Lookup::Found(dir) if dir.dentry.is_dir() => dir,
+        Lookup::Found(_) => return Err(format!("{}: not a directory", dir_path)),
+        _ => return Err(format!("{}: no such directory", dir_path)),
+    };
+    let exists = walk::children(fs, dir.dentry.first_block)
+        .filter_map(|dentry| dentry.ok())
+        .any(|dentry| dentry.name() == name);
+    if exists {
+        return Err(format!("{} already exists", path));
+    }
+    Ok((dir_path, dir.dentry.first_block, name))
+}
+
+/// Lay out the bytes of a new entry. Every field but the size goes through the form's checks
+fn new_entry(
+    fs: &PennFat,
+    fields: [(FieldKind, String); 5],
+    size: u32,
+    allow_dangerous: bool,
+) -> Result<Vec<u8>, String> {
+    let layout = fs.dentry_layout();
+    let mut entry = vec![0; layout.size];
+    for (kind, input) in fields {
+        let range = kind.field(fs).within(layout.size);
+        if range.is_empty() {
+            continue;
+        }
+        let (_, bytes) = parse(kind, &input, range.len(), fs, allow_dangerous)?;
+        entry[range].copy_from_slice(&bytes);
+    }
+    let range = layout.file_size.within(layout.size);
+    let bytes: Vec<u8> = (size as u64).to_le_bytes()[..range.len().min(8)].to_vec();
+    if range.len() < 4 && size >> (range.len() * 8) != 0 {
+        return Err(format!("{} doesn't fit in the size field", size));
+    }
+    entry[range.start..range.start + bytes.len()].copy_from_slice(&bytes);
+    Ok(entry)
+}
+
+impl Place {
+    /// Find where a new entry goes in the directory starting at `first_block`: the first free
+    /// slot of the directory, or a free block appended to it when it has none
+    fn find(fs: &PennFat, first_block: u16) -> Result<Place, String> {
+        let layout = fs.dentry_layout();
+        let chain = fs.chain(first_block);
+        for block in &chain.blocks {
+            let data = fs.block_bytes(*block).map_err(|e| e.to_string())?;
+            let free = data
+                .chunks_exact(layout.size)
+                .position(|entry| Dentry::parse(entry, layout).is_some_and(|d| !d.is_in_use()));
+            if let Some(slot) = free {
+                return Ok(Place::Slot {
+                    block: *block,
+                    slot,
+                });
+            }
+        }
+        let (Some(last), ChainEnd::Eof) = (chain.blocks.last(), &chain.end) else {
+            return Err(
+                "the directory is full, and its chain is broken so it can't grow".to_owned(),
+            );
+        };
+        let free = fs.profile().free;
+        let block = (1..=fs.data_block_count())
+            .find(|block| fs.fat_entry(*block) == Some(free) && fs.block_bytes(*block).is_ok())
+            .ok_or("the directory is full, and there's no free block to grow it")?;
+        Ok(Place::Append { last: *last, block })
+    }
+
+    /// Write an entry here. A new directory block is filled in before it's linked onto the
+    /// directory, so the directory never has a half-written block
+    fn write(&self, fs: &PennFat, entry: &[u8]) -> anyhow::Result<()> {
+        match *self {
+            Place::Slot { block, slot } => {
+                let offset = fs.block_offset(block)? + slot * entry.len();
+                fs.write_bytes(offset as u64, entry)?;
+            }
+            Place::Append { last, block } => {
+                let mut data = vec![0; fs.block_size() as usize];
+                data[..entry.len()].copy_from_slice(entry);
+                fs.write_block(block, &data)?;
+                fs.set_fat_entry(block, fs.profile().eof)?;
+                fs.set_fat_entry(last, block)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the stretches of the image writing an entry of `len` bytes here overwrites, as
+    /// offsets and lengths
+    fn footprint(&self, fs: &PennFat, len: usize) -> pennfat::Result<Vec<(u64, usize)>> {
+        Ok(match *self {
+            Place::Slot { block, slot } => {
+                vec![((fs.block_offset(block)? + slot * len) as u64, len)]
+            }
+            Place::Append { last, block } => vec![
+                (fs.block_offset(block)? as u64, fs.block_size() as usize),
+                (block as u64 * 2, 2),
+                (last as u64 * 2, 2),
+            ],
+        })
+    }
+
+    /// Describe the FAT entries appending a block to the directory changes, if it does
+    fn describe_links(&self, fs: &PennFat) -> Option<String> {
+        let Place::Append { last, block } = *self else {
+            return None;
+        };
+        Some(format!(
+            "fat entry {}: {} -> {}, fat entry {}: {} -> {}",
+            fs.format_block(block),
+            fs.format_block(fs.profile().free),
+            fs.format_block(fs.profile().eof),
+            fs.format_block(last),
+            fs.format_block(fs.profile().eof),
+            fs.format_block(block)
+        ))
+    }
+}
+
+impl ShowBlocks for Place {
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result {
+        match self {
+            Place::Slot { block, slot } => {
+                write!(f, "block {} slot {}", blocks.block(*block), slot)
+            }
+            Place::Append { block, .. } => write!(f, "new block {} slot 0", blocks.block(*block)),
+        }
+    }
+}
+
+/// A file copied into an image from another: its contents, written to free blocks, and a new
+/// directory entry for it with the original's type, permissions and modification time.
+/// Everything is worked out, and the free blocks counted, before anything is written
+pub struct FileCopy {
+    /// The path the copy gets
+    pub path: String,
+    contents: Vec<u8>,
+    /// The blocks the contents go in, in order
+    pub blocks: Vec<u16>,
+    /// The new entry's bytes
+    entry: Vec<u8>,
+    place: Place,
+}
+
+impl FileCopy {
+    /// Work out how to copy a file, with the entry `source` and its `contents`, to `path` in
+    /// the image. The image's blocks can be any size, as only the contents are copied. The
+    /// entry goes where an adopted file's would, and the contents in the lowest free blocks
+    pub fn plan(
+        fs: &PennFat,
+        source: &Dentry,
+        contents: Vec<u8>,
+        path: &str,
+        allow_dangerous: bool,
+    ) -> Result<Self, String> {
+        let (dir_path, dir, name) = destination(fs, path)?;
+        let place = Place::find(fs, dir)?;
+        let taken = match place {
+            Place::Append { block, .. } => Some(block),
+            Place::Slot { .. } => None,
+        };
+        let needed = contents.len().div_ceil(fs.block_size() as usize);
+        let free = fs.profile().free;
+        let blocks: Vec<u16> = (1..=fs.data_block_count())
+            .filter(|block| Some(*block) != taken)
+            .filter(|block| fs.fat_entry(*block) == Some(free) && fs.block_bytes(*block).is_ok())
+            .take(needed)
+            .collect();
+        if blocks.len() < needed {
+            return Err(format!(
+                "no space: {} needs {} blocks, and only {} are free",
+                path,
+                needed + taken.is_some() as usize,
+                blocks.len() + taken.is_some() as usize
+            ));
+        }
+        let size = u32::try_from(contents.len()).map_err(|_| format!("{} is too large", path))?;
+        let fields = [
+            (FieldKind::Name, name.to_owned()),
+            (FieldKind::Perm, source.perm.to_string()),
+            (FieldKind::Type, source.type_.to_string()),
+            (
+                FieldKind::FirstBlock,
+                fs.format_block(blocks.first().copied().unwrap_or(0)),
+            ),
+            (FieldKind::Mtime, source.mtime.to_string()),
+        ];
+        let entry = new_entry(fs, fields, size, allow_dangerous)?;
+        Ok(FileCopy {
+            path: format!("{}/{}", dir_path.trim_end_matches('/'), name),
+            contents,
+            blocks,
+            entry,
+            place,
+        })
+    }
+
+    /// How many bytes the copy holds
+    pub fn size(&self) -> usize {
+        self.contents.len()
+    }
+
+    /// Describe where the entry goes
+    pub fn describe_place(&self, fs: &PennFat) -> String {
+        self.place.show(fs.block_format()).to_string()
+    }
+
+    /// Write the contents and link their blocks into a chain, then write the entry. Until the
+    /// entry is written, the blocks are just an orphan chain, so a copy cut short never leaves
+    /// a file with half its contents
+    pub fn write(&self, fs: &PennFat) -> anyhow::Result<()> {
+        let block_size = fs.block_size() as usize;
+        for (block, data) in self.blocks.iter().zip(self.contents.chunks(block_size)) {
+            let mut data = data.to_vec();
+            data.resize(block_size, 0);
+            fs.write_block(*block, &data)?;
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            let next = self.blocks.get(i + 1).copied().unwrap_or(fs.profile().eof);
+            fs.set_fat_entry(*block, next)?;
+        }
+        self.place.write(fs, &self.entry)
+    }
+}
+
+/// Parse what was typed into a field into the value to show and the `len` bytes to write
+fn parse(
+    kind: FieldKind,
+    input: &str,
+    len: usize,
+    fs: &PennFat,
+    allow_dangerous: bool,
+) -> Result<(String, Vec<u8>), String> {
+    let dangerous = |what: String| match allow_dangerous {
+        true => Ok(()),
+        false => Err(format!("{} (--allow-dangerous to write it anyway)", what)),
+    };
+    let value = match kind {
+        FieldKind::Name => {
+            // with room for the NUL that ends it
+            let name = input.as_bytes();
+            if name.is_empty() || name.len() >= len {
+                return Err(format!("a name must be 1 to {} bytes long", len - 1));
+            }
+            if name.contains(&b'/') || name.contains(&0) {
+                return Err("a name can't contain '/' or NUL".to_owned());
+            }
+            if input == "." || input == ".." {
+                return Err(format!("a file can't be named '{}'", input));
+            }
+            // the first byte also says whether the slot is free or deleted
+            if name[0] <= 2 {
+                return Err("a name can't start with a byte below 3".to_owned());
+            }
+            let mut bytes = name.to_vec();
+            bytes.resize(len, 0);
+            return Ok((input.to_owned(), bytes));
+        }
+        FieldKind::Perm => {
+            let perm = parse_perm(input).ok_or_else(|| format!("invalid perm '{}'", input))?;
+            let perms = fs.dentry_layout().perms;
+            if !perms.is_legal(perm) {
+                let legal: Vec<String> = perms.legal().iter().map(|p| p.to_string()).collect();
+                dangerous(format!("perm {} isn't one of {}", perm, legal.join(", ")))?;
+            }
+            perm as u64
+        }
+        FieldKind::Type => {
+            let type_ = match input.trim() {
+                "file" => 0,
+                "dir" | "directory" => 1,
+                "symlink" | "link" => 2,
+                n => n
+                    .parse::<u8>()
+                    .map_err(|_| format!("invalid type '{}'", input))?,
+            };
+            if type_ > 2 {
+                dangerous(format!(
+                    "type {} is neither a file, a directory nor a link",
+                    type_
+                ))?;
+            }
+            type_ as u64
+        }
+        FieldKind::FirstBlock => {
+            let block = filter::parse_block(input)
+                .ok_or_else(|| format!("invalid block number '{}'", input))?;
+            if block > fs.data_block_count() {
+                dangerous(format!(
+                    "block {} is outside the data region",
+                    fs.format_block(block)
+                ))?;
+            }
+            block as u64
+        }
+        FieldKind::Mtime => {
+            let millis = parse_mtime(input).ok_or_else(|| format!("invalid time '{}'", input))?;
+            // stored in whatever unit the layout keeps mtimes in
+            millis / fs.dentry_layout().mtime_unit.millis()
+        }
+    };
+    // integers are little-endian, cut short to fit the field
+    let bytes: Vec<u8> = value.to_le_bytes().into_iter().take(len).collect();
+    if bytes.len() < 8 && value >> (bytes.len() * 8) != 0 {
+        return Err(format!("{} doesn't fit in {}", input, kind.label()));
+    }
+    let new = match kind {
+        FieldKind::FirstBlock => fs.format_block(value as u16),
+        FieldKind::Perm => fs.dentry_layout().perms.describe(value as u8),
+        FieldKind::Mtime => {
+            pennfat::format_mtime(value.saturating_mul(fs.dentry_layout().mtime_unit.millis()))
+        }
+        _ => value.to_string(),
+    };
+    Ok((new, bytes))
+}
+
+/// Parse permissions as a number, or as letters like "rw-" (or just "rw")
+fn parse_perm(input: &str) -> Option<u8> {
+    let input = input.trim();
+    if let Ok(perm) = input.parse() {
+        return Some(perm);
+    }
+    input.chars().try_fold(0, |perm, c| match c {
+        'r' => Some(perm | 4),
+        'w' => Some(perm | 2),
+        'x' => Some(perm | 1),
+        '-' => Some(perm),
+        _ => None,
+    })
+}
+
+/// Parse a modification time, in milliseconds since the epoch: a date and time in UTC as
+/// shown in dentries (or just a date), "now", or the milliseconds themselves
+fn parse_mtime(input: &str) -> Option<u64> {
+    let input = input.trim();
+    if input == "now" {
+        return u64::try_from(Utc::now().timestamp_millis()).ok();
+    }
+    if let Ok(millis) = input.parse() {
+        return Some(millis);
+    }
+    let time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })?;
+    u64::try_from(Utc.from_utc_datetime(&time).timestamp_millis()).ok()
+}
+
+/// Get the path of the file recording the edits made to an image, next to it
+pub fn audit_path(image: &Path) -> PathBuf {
+    let stem = image
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image".to_owned());
+    snapshot::snapshot_dir(image, None).join(format!("{}.pfview-audit.log", stem))
+}
+
+/// Append lines to the image's audit file, each stamped with the time
+fn append_audit(fs: &PennFat, lines: &[String]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path(fs.path()))?;
+    let now = Utc::now().format("%Y-%m-%d %H:%M:%S");
+    for line in lines {
+        writeln!(file, "{} {}", now, line)?;
+    }
+    Ok(())
+}
+
+/// Append the changes made to an entry to the image's audit file, a line per change
+pub fn audit(fs: &PennFat, form: &DentryForm, changes: &[Change]) -> io::Result<()> {
+    let lines: Vec<String> = changes
+        .iter()
+        .map(|change| {
+            format!(
+                "block {} slot {} ({}): {} at {}",
+                fs.format_block(form.block),
+                form.slot,
+                form.dentry.name(),
+                change,
+                format_offset(change.offset)
+            )
+        })
+        .collect();
+    append_audit(fs, &lines)
+}
+
+/// Record an adopted orphan chain in the image's audit file
+pub fn audit_adoption(fs: &PennFat, adoption: &Adoption) -> io::Result<()> {
+    let mut lines = vec![format!(
+        "{}: new entry {} ({} bytes, first_block {})",
+        adoption.describe_place(fs),
+        adoption.path,
+        adoption.size,
+        fs.format_block(adoption.head)
+    )];
+    lines.extend(adoption.place.describe_links(fs));
+    append_audit(fs, &lines)
+}
+
+/// Record a file copied in from another image in the image's audit file
+pub fn audit_copy(fs: &PennFat, copy: &FileCopy, source: &str) -> io::Result<()> {
+    let mut lines = vec![format!(
+        "{}: new entry {} ({} bytes, first_block {}), copied from {}",
+        copy.describe_place(fs),
+        copy.path,
+        copy.contents.len(),
+        fs.format_block(copy.blocks.first().copied().unwrap_or(0)),
+        source
+    )];
+    if let (Some(first), Some(last)) = (copy.blocks.first(), copy.blocks.last()) {
+        lines.push(format!(
+            "fat entries of {} blocks from {} to {}: linked into a chain",
+            copy.blocks.len(),
+            fs.format_block(*first),
+            fs.format_block(*last)
+        ));
+    }
+    lines.extend(copy.place.describe_links(fs));
+    append_audit(fs, &lines)
+}
+
+/// Record a step of a repair in the image's audit file
+pub fn audit_repair(fs: &PennFat, action: &str) -> io::Result<()> {
+    append_audit(fs, &[format!("repair: {}", action)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn only_the_specs_perms_are_written_unless_dangerous() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        let perm = |input, allow_dangerous| {
+            parse(FieldKind::Perm, input, 1, &fs, allow_dangerous).map(|(_, bytes)| bytes)
+        };
+        for legal in ["0", "2", "4", "5", "6", "7", "rw-", "r-x"] {
+            assert!(perm(legal, false).is_ok(), "{}", legal);
+        }
+        assert_eq!(perm("rw", false), Ok(vec![6]));
+        assert_eq!(
+            perm("3", false),
+            Err(
+                "perm 3 isn't one of 0, 2, 4, 5, 6, 7 (--allow-dangerous to write it anyway)"
+                    .to_owned()
+            )
+        );
+        assert_eq!(perm("-wx", true), Ok(vec![3]));
+        assert_eq!(perm("rwz", true), Err("invalid perm 'rwz'".to_owned()));
+    }
+}
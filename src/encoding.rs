@@ -0,0 +1,415 @@
+use std::ops::Range;
+
+use unicode_width::UnicodeWidthStr;
+
+/// How many columns apart tab stops are, when tabs are shown
+const TAB_STOP: usize = 8;
+
+/// How the bytes of a block are shown in the raw view: as text in one of the encodings test
+/// generators tend to write, or as a hex dump
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Binary,
+}
+
+impl Encoding {
+    /// Every encoding, in the order the override cycles through them
+    pub const ALL: [Encoding; 4] = [
+        Encoding::Utf8,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Binary,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Binary => "binary",
+        }
+    }
+
+    /// How many bytes the smallest character takes
+    pub fn unit(self) -> usize {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+            Encoding::Utf8 | Encoding::Binary => 1,
+        }
+    }
+}
+
+/// How the control characters of decoded text are drawn
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Controls {
+    /// Each as '.', so every row holds the same number of bytes
+    Plain,
+    /// Each as what it is: a tab out to the next tab stop, a line feed ending its row, and the
+    /// rest as control pictures ('␍', '␀', ...), or as escapes with `ascii`
+    Visible { ascii: bool },
+}
+
+/// Guess the encoding of some bytes of a file: a byte order mark settles it, then UTF-16 is
+/// recognised by the NUL in every other byte that Latin text has, then valid UTF-8 without
+/// control characters is text. Anything else is binary. The bytes may start or end in the
+/// middle of a character, as a block of a file does
+pub fn sniff(bytes: &[u8]) -> Encoding {
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => return Encoding::Utf8,
+        [0xFF, 0xFE, ..] => return Encoding::Utf16Le,
+        [0xFE, 0xFF, ..] => return Encoding::Utf16Be,
+        [] => return Encoding::Binary,
+        _ => {}
+    }
+
+    let pairs = bytes.len() / 2;
+    let nuls_at = |parity: usize| {
+        bytes
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .take(pairs)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    let (even, odd) = (nuls_at(0), nuls_at(1));
+    if pairs >= 2 && odd * 10 >= pairs * 9 && even * 10 <= pairs {
+        return Encoding::Utf16Le;
+    }
+    if pairs >= 2 && even * 10 >= pairs * 9 && odd * 10 <= pairs {
+        return Encoding::Utf16Be;
+    }
+
+    // a character cut off at either end belongs to the neighbouring block
+    let start = bytes
+        .iter()
+        .take(3)
+        .take_while(|b| is_continuation(**b))
+        .count();
+    let text = match std::str::from_utf8(&bytes[start..]) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => {
+            std::str::from_utf8(&bytes[start..start + e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return Encoding::Binary,
+    };
+    match text
+        .chars()
+        .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+    {
+        true => Encoding::Utf8,
+        false => Encoding::Binary,
+    }
+}
+
+/// Whether a byte continues a UTF-8 character, rather than starting one
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// Get a range of a block as rows of `row_len` bytes in an encoding, each paired with the offset
+/// of its first byte, like [`crate::pennfat::Block::as_raw_rows`]. Text is decoded, with each
+/// character on the row its first byte is in and anything undecodable as '.'. Control
+/// characters are drawn as `controls` says; shown, they break rows at line feeds and where
+/// the row's columns run out, so rows no longer hold `row_len` bytes each. Binary is a hex dump
+/// followed by the printable bytes
+pub fn rows(
+    data: &[u8],
+    range: Range<usize>,
+    row_len: usize,
+    encoding: Encoding,
+    controls: Controls,
+) -> Vec<(usize, String)> {
+    let start = range.start.min(data.len());
+    let end = range.end.clamp(start, data.len());
+    let row_len = row_len.max(1);
+    let offsets = (start..end).step_by(row_len);
+    if encoding == Encoding::Binary {
+        return offsets
+            .map(|offset| {
+                let chunk = &data[offset..(offset + row_len).min(end)];
+                let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                let text: String = chunk
+                    .iter()
+                    .map(|b| match b {
+                        0x20..=0x7E => *b as char,
+                        _ => '.',
+                    })
+                    .collect();
+                // short rows are padded, so the text lines up
+                let row = format!(
+                    "{:<width$} {}",
+                    hex.join(" "),
+                    text,
+                    width = row_len * 3 - 1
+                );
+                (offset, row)
+            })
+            .collect();
+    }
+
+    let chars = decode(data, start, end, encoding);
+    match controls {
+        Controls::Plain => {
+            let mut rows: Vec<(usize, String)> =
+                offsets.map(|offset| (offset, String::new())).collect();
+            for (offset, c) in chars {
+                let c = if c.is_control() { '.' } else { c };
+                rows[(offset - start) / row_len].1.push(c);
+            }
+            rows
+        }
+        Controls::Visible { ascii } => flow(chars, row_len / encoding.unit(), ascii),
+    }
+}
+
+/// Decode the bytes from `start` to `end` in a text encoding, into each character and the
+/// offset of its first byte. Bytes that don't decode are '.'
+fn decode(data: &[u8], start: usize, end: usize, encoding: Encoding) -> Vec<(usize, char)> {
+    let mut chars = Vec::new();
+    let mut push = |offset: usize, c: char| chars.push((offset, c));
+    let mut i = start;
+    if encoding == Encoding::Utf8 {
+        // the tail of a character started in the previous block
+        while i < end && i < start + 3 && is_continuation(data[i]) {
+            push(i, '.');
+            i += 1;
+        }
+        while i < end {
+            let len = match data[i] {
+                0xF0..=0xF7 => 4,
+                0xE0..=0xEF => 3,
+                0xC0..=0xDF => 2,
+                _ => 1,
+            };
+            match std::str::from_utf8(&data[i..(i + len).min(data.len())]) {
+                Ok(s) => {
+                    push(i, s.chars().next().unwrap_or('.'));
+                    i += len;
+                }
+                Err(_) => {
+                    push(i, '.');
+                    i += 1;
+                }
+            }
+        }
+        return chars;
+    }
+
+    let unit = |i: usize| {
+        let pair = [data[i], data[i + 1]];
+        match encoding {
+            Encoding::Utf16Be => u16::from_be_bytes(pair),
+            _ => u16::from_le_bytes(pair),
+        }
+    };
+    while i + 1 < end {
+        let mut units = vec![unit(i)];
+        // a high surrogate pairs with the next unit
+        if (0xD800..0xDC00).contains(&units[0]) && i + 3 < data.len() {
+            units.push(unit(i + 2));
+        }
+        match char::decode_utf16(units).next() {
+            Some(Ok(c)) => {
+                push(i, c);
+                i += c.len_utf16() * 2;
+            }
+            _ => {
+                push(i, '.');
+                i += 2;
+            }
+        }
+    }
+    // an odd byte at the end
+    if i < end {
+        push(i, '.');
+    }
+    chars
+}
+
+/// Lay decoded characters out in rows `columns` wide, with control characters shown, each
+/// row paired with the offset of its first character. A line feed ends its row
+fn flow(chars: Vec<(usize, char)>, columns: usize, ascii: bool) -> Vec<(usize, String)> {
+    let columns = columns.max(1);
+    let mut rows: Vec<(usize, String)> = Vec::new();
+    let mut width = 0;
+    let mut line_ended = true;
+    for (offset, c) in chars {
+        let mut shown = glyph(c, width, columns, ascii);
+        if line_ended || (width > 0 && width + shown.width() > columns) {
+            // a tab at the start of a row reaches the first tab stop
+            shown = glyph(c, 0, columns, ascii);
+            rows.push((offset, String::new()));
+            width = 0;
+        }
+        width += shown.width();
+        line_ended = c == '\n';
+        if let Some((_, row)) = rows.last_mut() {
+            row.push_str(&shown);
+        }
+    }
+    rows
+}
+
+/// Show a character of text, by its class, `column` columns into a row `columns` wide
+fn glyph(c: char, column: usize, columns: usize, ascii: bool) -> String {
+    let escape = |escape: &str, picture: &str| match ascii {
+        true => escape.to_owned(),
+        false => picture.to_owned(),
+    };
+    match c {
+        '\t' => {
+            let stop = (TAB_STOP - column % TAB_STOP).min(columns.saturating_sub(column));
+            format!("{:<stop$}", escape("\\t", "→"))
+        }
+        '\r' => escape("\\r", "␍"),
+        '\n' => escape("\\n", "␊"),
+        '\0' => escape("\\0", "␀"),
+        // the rest of C0 have pictures at the same offset, and caret notation in ASCII
+        '\u{1}'..='\u{1f}' => match ascii {
+            true => format!("^{}", (c as u8 + 0x40) as char),
+            false => char::from_u32(0x2400 + c as u32).unwrap_or('.').to_string(),
+        },
+        '\u{7f}' => escape("^?", "␡"),
+        c if c.is_control() => ".".to_owned(),
+        c => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| match big_endian {
+                true => unit.to_be_bytes(),
+                false => unit.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sniffs_utf8() {
+        assert_eq!(sniff(b"hello, world\n\tindented\r\n"), Encoding::Utf8);
+        assert_eq!(
+            sniff("caf\u{e9} \u{65e5}\u{672c}".as_bytes()),
+            Encoding::Utf8
+        );
+        assert_eq!(
+            sniff(b"\xEF\xBB\xBF\x00\x01"),
+            Encoding::Utf8,
+            "the BOM settles it"
+        );
+        // a block starting and ending mid-character
+        let text = "\u{65e5}\u{672c}\u{8a9e}".as_bytes();
+        assert_eq!(sniff(&text[1..text.len() - 1]), Encoding::Utf8);
+    }
+
+    #[test]
+    fn sniffs_utf16() {
+        assert_eq!(sniff(&utf16("hello, world", false)), Encoding::Utf16Le);
+        assert_eq!(sniff(&utf16("hello, world", true)), Encoding::Utf16Be);
+        assert_eq!(sniff(b"\xFF\xFE"), Encoding::Utf16Le);
+        assert_eq!(sniff(b"\xFE\xFF"), Encoding::Utf16Be);
+    }
+
+    #[test]
+    fn sniffs_binary() {
+        assert_eq!(sniff(b""), Encoding::Binary);
+        assert_eq!(sniff(&[0; 64]), Encoding::Binary);
+        assert_eq!(sniff(b"\x7fELF\x02\x01\x01\x00"), Encoding::Binary);
+        assert_eq!(sniff(b"ok\xC3\x28 not utf-8"), Encoding::Binary);
+    }
+
+    #[test]
+    fn decodes_each_encoding() {
+        let plain = |data: &[u8], encoding| {
+            rows(data, 0..data.len(), 64, encoding, Controls::Plain)
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            plain("h\u{e9}llo".as_bytes(), Encoding::Utf8),
+            ["h\u{e9}llo"]
+        );
+        assert_eq!(
+            plain(&utf16("h\u{e9}llo", false), Encoding::Utf16Le),
+            ["h\u{e9}llo"]
+        );
+        assert_eq!(
+            plain(&utf16("h\u{e9}llo", true), Encoding::Utf16Be),
+            ["h\u{e9}llo"]
+        );
+        // a surrogate pair decodes as one character
+        assert_eq!(
+            plain(&utf16("\u{1f600}!", false), Encoding::Utf16Le),
+            ["\u{1f600}!"]
+        );
+        let dump = rows(b"AB\x00", 0..3, 4, Encoding::Binary, Controls::Plain);
+        assert_eq!(dump, [(0, "41 42 00    AB.".to_owned())]);
+    }
+
+    #[test]
+    fn reading_utf16_in_the_wrong_order_never_panics() {
+        let data = utf16("\u{1f600}\u{1f600}x", false);
+        for encoding in Encoding::ALL {
+            for start in 0..data.len() {
+                let rows = rows(&data, start..data.len(), 4, encoding, Controls::Plain);
+                assert!(!rows.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn every_control_character_is_shown() {
+        let data: Vec<u8> = (0..0x20).chain([0x7f]).collect();
+        let shown = |controls| rows(&data, 0..data.len(), 16, Encoding::Utf8, controls);
+        let rows = |rows: &[(usize, &str)]| -> Vec<(usize, String)> {
+            rows.iter()
+                .map(|(offset, row)| (*offset, row.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            shown(Controls::Plain),
+            rows(&[(0, "................"), (16, "................"), (32, ".")])
+        );
+        // the tab runs to the end of the row, and the line feed ends its own
+        assert_eq!(
+            shown(Controls::Visible { ascii: false }),
+            rows(&[
+                (0, "␀␁␂␃␄␅␆␇␈→      "),
+                (10, "␊"),
+                (11, "␋␌␍␎␏␐␑␒␓␔␕␖␗␘␙␚"),
+                (27, "␛␜␝␞␟␡"),
+            ])
+        );
+        assert_eq!(
+            shown(Controls::Visible { ascii: true }),
+            rows(&[
+                (0, "\\0^A^B^C^D^E^F^G"),
+                (8, "^H\\t    \\n"),
+                (11, "^K^L\\r^N^O^P^Q^R"),
+                (19, "^S^T^U^V^W^X^Y^Z"),
+                (27, "^[^\\^]^^^_^?"),
+            ])
+        );
+    }
+
+    #[test]
+    fn crlf_and_lf_line_endings_look_different() {
+        let shown = |data: &[u8]| {
+            let visible = Controls::Visible { ascii: false };
+            rows(data, 0..data.len(), 16, Encoding::Utf8, visible)
+                .into_iter()
+                .map(|(_, row)| row)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(shown(b"a\r\nb\r\n"), ["a␍␊", "b␍␊"]);
+        assert_eq!(shown(b"a\nb\n"), ["a␊", "b␊"]);
+    }
+}
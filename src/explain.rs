@@ -0,0 +1,155 @@
+/// What an error code means, as `pfview explain` prints it
+pub struct Explanation {
+    pub code: &'static str,
+    /// The category of the errors with the code, as [`crate::pennfat::PfError::category`] names it
+    pub category: &'static str,
+    /// What went wrong, in a line
+    pub summary: &'static str,
+    /// What usually causes it, and what to try
+    pub causes: &'static str,
+}
+
+/// Every error code, in order
+pub static EXPLANATIONS: [Explanation; 14] = [
+    Explanation {
+        code: "PF001",
+        category: "io",
+        summary: "The image file couldn't be read or written.",
+        causes: "The path is wrong, the file isn't readable (or writable, with --writable), or \
+                 the disk failed. The message after \"IO error\" is the operating system's.",
+    },
+    Explanation {
+        code: "PF002",
+        category: "format",
+        summary: "The image is a different size from what its FAT configuration says.",
+        causes: "The image was truncated, or grew, after being made; its first two bytes were \
+                 overwritten; or it's embedded in a larger file, which --offset and --length \
+                 read it out of.",
+    },
+    Explanation {
+        code: "PF003",
+        category: "format",
+        summary: "The file is too small to hold even the FAT configuration.",
+        causes: "The file is empty or nearly so: the image was being written when it was \
+                 opened, or isn't a PennFat image at all.",
+    },
+    Explanation {
+        code: "PF004",
+        category: "format",
+        summary: "The FAT configuration in entry 0 makes no sense.",
+        causes: "The block size config is over 7, or the FAT spans no blocks. The first two \
+                 bytes of the image were overwritten, or the file isn't a PennFat image; with \
+                 an embedded image, --offset may be wrong.",
+    },
+    Explanation {
+        code: "PF005",
+        category: "format",
+        summary: "The offset the image was said to start at is past the end of the file.",
+        causes: "--offset is wrong, or in the wrong unit: it's in bytes, decimal or 0x hex.",
+    },
+    Explanation {
+        code: "PF006",
+        category: "format",
+        summary: "The image would run past the end of the file.",
+        causes: "--length is longer than what's left of the file after --offset, or the file \
+                 was truncated.",
+    },
+    Explanation {
+        code: "PF007",
+        category: "integrity",
+        summary: "A block number is outside the data region.",
+        causes: "A FAT entry or a directory entry's first block points past the last data \
+                 block, or at block 0. Usually a FAT entry was written with the wrong value, \
+                 or a directory entry was corrupted. `pfview doctor` finds them, and \
+                 `pfview repair` fixes what it can.",
+    },
+    Explanation {
+        code: "PF008",
+        category: "integrity",
+        summary: "A block lies past the end of the image file.",
+        causes: "The image was truncated after being made, or is still being written. \
+                 `pfview doctor` says how large it should be.",
+    },
+    Explanation {
+        code: "PF009",
+        category: "integrity",
+        summary: "A block lies past the length the embedded image was given.",
+        causes: "--length is shorter than the image, or the image's FAT configuration is \
+                 wrong.",
+    },
+    Explanation {
+        code: "PF010",
+        category: "integrity",
+        summary: "A chain is longer than chains are followed for.",
+        causes: "The chain loops, or --max-chain-len is lower than the file needs. Raise the \
+                 limit, or look for the cycle with `pfview doctor`.",
+    },
+    Explanation {
+        code: "PF011",
+        category: "integrity",
+        summary: "A write would go past the end of the image.",
+        causes: "An edit targeted a block or directory entry outside the image, usually one \
+                 found through a corrupted FAT entry or directory entry.",
+    },
+    Explanation {
+        code: "PF012",
+        category: "unsupported",
+        summary: "A block's offset is too large to address on this platform.",
+        causes: "The image is larger than this platform's address space; use a 64-bit build.",
+    },
+    Explanation {
+        code: "PF013",
+        category: "unsupported",
+        summary: "The image is too large to map into memory on this platform.",
+        causes: "The image is larger than this platform's address space; use a 64-bit build, \
+                 or read part of it with --offset and --length.",
+    },
+    Explanation {
+        code: "PF014",
+        category: "read-only",
+        summary: "The image was opened read-only, and something tried to write to it.",
+        causes: "Edits need the image opened with --writable.",
+    },
+];
+
+/// Look up what an error code means, ignoring case
+///
+/// Every error has an explanation, under a code no other error has:
+///
+/// ```
+/// use pfview::explain::{explain, EXPLANATIONS};
+/// use pfview::pennfat::{BlockFormat, FormatError, IntegrityError, PfError, UnsupportedError};
+/// use std::collections::HashSet;
+///
+/// let blocks = BlockFormat::default();
+/// let errors: Vec<PfError> = vec![
+///     PfError::Io(std::io::Error::other("disk on fire")),
+///     FormatError::FileSize { expected: 2, actual: 1 }.into(),
+///     FormatError::TooSmall { len: 1 }.into(),
+///     FormatError::InvalidConfig { reason: String::new() }.into(),
+///     FormatError::OffsetPastEnd { offset: 2, len: 1 }.into(),
+///     FormatError::WindowPastEnd { end: 2, len: 1 }.into(),
+///     IntegrityError::InvalidBlockNumber { block: 0, max: 1, blocks }.into(),
+///     IntegrityError::Truncated { block: 1, blocks }.into(),
+///     IntegrityError::PastWindow { block: 1, blocks }.into(),
+///     IntegrityError::ChainTooLong { start: 1, limit: 1, blocks }.into(),
+///     IntegrityError::WriteOutOfBounds { offset: 2, len: 1 }.into(),
+///     UnsupportedError::OffsetOverflow { block: 1, blocks }.into(),
+///     UnsupportedError::WindowTooLarge { len: 1 }.into(),
+///     PfError::ReadOnly,
+/// ];
+/// let codes: HashSet<&str> = errors.iter().map(PfError::code).collect();
+/// assert_eq!(codes.len(), errors.len());
+/// assert_eq!(EXPLANATIONS.len(), errors.len());
+/// for error in &errors {
+///     let explanation = explain(error.code()).unwrap();
+///     assert_eq!(explanation.category, error.category());
+///     assert!(error.to_string().starts_with(error.code()));
+/// }
+/// assert_eq!(explain("pf007").unwrap().code, "PF007");
+/// ```
+pub fn explain(code: &str) -> Option<&'static Explanation> {
+    EXPLANATIONS
+        .iter()
+        .find(|explanation| explanation.code.eq_ignore_ascii_case(code))
+}
@@ -0,0 +1,300 @@
+//! Copying the whole directory tree of an image out to the host. Every file is named by
+//! [`walk::host_names`], so whatever the names in the image, nothing is written outside the
+//! destination, and a manifest records what each host file was called in the image
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+
+use crate::pennfat::{Dentry, PennFat};
+use crate::walk;
+
+/// The name of the manifest written at the top of the destination. No file of the image's
+/// root gets this name on the host; one named like it is renamed as any clash is
+pub const MANIFEST: &str = "manifest.json";
+
+/// A file of the image and where it was copied to, as recorded in the manifest
+#[derive(Serialize)]
+pub struct Extracted {
+    /// The file's path in the image, as listings show it
+    pub path: String,
+    /// The file's name in the image, up to its first NUL
+    pub name: String,
+    /// Where the file was written, relative to the destination
+    pub host: PathBuf,
+    /// Why the file couldn't be copied, if it couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Copy a file, directory or symbolic link of the image to `host`, which mustn't exist yet.
+/// Symbolic links are written as host links where the host has them, and as a file holding
+/// the target elsewhere
+fn copy(fs: &PennFat, dentry: &Dentry, host: &Path) -> io::Result<()> {
+    if dentry.is_dir() {
+        return fs::create_dir(host);
+    }
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    if dentry.is_symlink() {
+        let target = walk::read_link(fs, dentry).map_err(invalid)?;
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(target, host);
+        #[cfg(not(unix))]
+        return copy_contents(host, target.as_bytes(), dentry.mtime);
+    }
+    let contents = fs.read_file(dentry).map_err(invalid)?;
+    copy_contents(host, &contents, dentry.mtime)
+}
+
+/// Write a new host file, with the mtime (in milliseconds) of the file it's a copy of.
+/// A file already there is never written over, nor a link followed
+fn copy_contents(host: &Path, contents: &[u8], mtime: u64) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(host)?;
+    file.write_all(contents)?;
+    file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_millis(mtime))
+}
+
+/// Copy every file of the image into `dest`, which is made if it doesn't exist and must be
+/// empty if it does, with [`MANIFEST`] saying where each went. Files that can't be read or
+/// written are recorded with the error and skipped. Returns what was copied, as in the
+/// manifest
+pub fn extract_all(fs: &PennFat, dest: &Path) -> io::Result<Vec<Extracted>> {
+    if fs::read_dir(dest).is_ok_and(|mut listing| listing.next().is_some()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", dest.display()),
+        ));
+    }
+    fs::create_dir_all(dest)?;
+
+    let mut extracted = Vec::new();
+    let mut visited = HashSet::from([fs.root_block()]);
+    // the root's path is empty so that its children get paths like "/name"
+    let mut directories = VecDeque::from([(String::new(), PathBuf::new(), fs.root_block())]);
+    while let Some((dir_path, host_dir, first_block)) = directories.pop_front() {
+        let mut dentries = Vec::new();
+        for dentry in walk::children(fs, first_block) {
+            match dentry {
+                Ok(dentry) => dentries.push(dentry),
+                Err(e) => extracted.push(Extracted {
+                    path: format!("{}/", dir_path),
+                    name: String::new(),
+                    host: host_dir.clone(),
+                    error: Some(e.to_string()),
+                }),
+            }
+        }
+        let taken: &[&str] = match dir_path.is_empty() {
+            true => &[MANIFEST],
+            false => &[],
+        };
+        let names: Vec<String> = dentries.iter().map(Dentry::name).collect();
+        let hosts = walk::host_names(names.iter().map(String::as_str), taken);
+        for ((dentry, name), host) in dentries.iter().zip(names).zip(hosts) {
+            let path = format!("{}/{}", dir_path, dentry.display_name());
+            let host = host_dir.join(host);
+            let error = copy(fs, dentry, &dest.join(&host)).err();
+            if error.is_none() && dentry.is_dir() && visited.insert(dentry.first_block) {
+                directories.push_back((path.clone(), host.clone(), dentry.first_block));
+            }
+            extracted.push(Extracted {
+                path,
+                name,
+                host,
+                error: error.map(|e| e.to_string()),
+            });
+        }
+    }
+
+    let manifest = serde_json::to_string_pretty(&extracted)? + "\n";
+    fs::write(dest.join(MANIFEST), manifest)?;
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::{DentrySpec, ImageBuilder};
+    use crate::testing::sample_image;
+
+    /// An image whose root holds a file under each name, holding its index, and a directory
+    /// "sub" holding the same. The blocks hold 16 dentries each
+    fn image_with_names(names: &[&[u8]]) -> PennFat {
+        let mut image = ImageBuilder::new(2, 1);
+        let sub = image.allocate_lowest(1).unwrap()[0];
+        for dir in [1, sub] {
+            for (slot, name) in names.iter().enumerate() {
+                let block = image.allocate_lowest(1).unwrap()[0];
+                image.write(&[block], &[slot as u8]);
+                let spec = DentrySpec {
+                    name: name.to_vec(),
+                    size: 1,
+                    first_block: block,
+                    type_: 0,
+                    perm: 6,
+                    mtime: 0,
+                };
+                image.write_dentry(dir, slot, &spec);
+            }
+        }
+        let spec = DentrySpec {
+            name: b"sub".to_vec(),
+            size: 0,
+            first_block: sub,
+            type_: 1,
+            perm: 7,
+            mtime: 0,
+        };
+        image.write_dentry(1, names.len(), &spec);
+        PennFat::from_bytes(image.bytes()).unwrap()
+    }
+
+    /// Every path under `dir`, relative to it
+    fn files_under(dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_owned()];
+        while let Some(next) = dirs.pop() {
+            for entry in fs::read_dir(next).unwrap().flatten() {
+                if entry.file_type().unwrap().is_dir() {
+                    dirs.push(entry.path());
+                }
+                files.push(entry.path().strip_prefix(dir).unwrap().to_owned());
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn adversarial_names_are_all_written_inside_the_destination() {
+        let names: [&[u8]; 9] = [
+            b"..",
+            b"../escape",
+            b"/etc/passwd",
+            b"a\\..\\b",
+            b"nul\0mid",
+            b"nul\0end",
+            &[0xff; 31],
+            b"README",
+            b"readme",
+        ];
+        let fs = image_with_names(&names);
+        let parent = tempfile::tempdir().unwrap();
+        let dest = parent.path().join("out");
+        let extracted = extract_all(&fs, &dest).unwrap();
+
+        // nothing went next to the destination, let alone further out
+        assert_eq!(files_under(parent.path())[0], Path::new("out"));
+        assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 1);
+        let hosts = [
+            "%2E%2E",
+            "..%2Fescape",
+            "%2Fetc%2Fpasswd",
+            "a%5C..%5Cb",
+            "nul",
+            "nul~2",
+            &"\u{fffd}".repeat(31),
+            "README",
+            "readme~2",
+        ];
+        let mut expected: Vec<PathBuf> = hosts.iter().map(PathBuf::from).collect();
+        expected.extend(hosts.iter().map(|host| Path::new("sub").join(host)));
+        expected.push(PathBuf::from("sub"));
+        expected.push(PathBuf::from(MANIFEST));
+        expected.sort();
+        assert_eq!(files_under(&dest), expected);
+
+        // and each host file holds what its dentry's file does
+        assert!(extracted.iter().all(|file| file.error.is_none()));
+        for (index, host) in hosts.iter().enumerate() {
+            assert_eq!(
+                fs::read(dest.join(host)).unwrap(),
+                [index as u8],
+                "{}",
+                host
+            );
+            assert_eq!(
+                fs::read(dest.join("sub").join(host)).unwrap(),
+                [index as u8]
+            );
+        }
+    }
+
+    #[test]
+    fn the_manifest_keeps_the_names_in_the_image() {
+        let fs = image_with_names(&[b"../escape", b"manifest.json", b"Manifest.JSON"]);
+        let dest = tempfile::tempdir().unwrap();
+        extract_all(&fs, dest.path()).unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(dest.path().join(MANIFEST)).unwrap()).unwrap();
+        let rows: Vec<(&str, &str, &str)> = manifest
+            .as_array()
+            .unwrap()
+            .iter()
+            .take(3)
+            .map(|row| {
+                (
+                    row["path"].as_str().unwrap(),
+                    row["name"].as_str().unwrap(),
+                    row["host"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        // a file named like the manifest gets out of its way
+        assert_eq!(
+            rows,
+            [
+                ("/..\\x2fescape", "../escape", "..%2Fescape"),
+                ("/manifest.json", "manifest.json", "manifest.json~2"),
+                ("/Manifest.JSON", "Manifest.JSON", "Manifest.JSON~3"),
+            ]
+        );
+        assert_eq!(fs::read(dest.path().join("manifest.json~2")).unwrap(), [1]);
+    }
+
+    #[test]
+    fn the_sample_comes_out_whole() {
+        let fs = PennFat::from_bytes(sample_image()).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        let extracted = extract_all(&fs, dest.path()).unwrap();
+        assert_eq!(extracted.len(), walk::walk(&fs).len());
+        for entry in walk::walk(&fs) {
+            let host = dest.path().join(&entry.path[1..]);
+            match (entry.dentry.is_dir(), entry.dentry.is_symlink()) {
+                (true, _) => assert!(host.is_dir(), "{}", entry.path),
+                #[cfg(unix)]
+                (_, true) => assert_eq!(
+                    fs::read_link(&host).unwrap(),
+                    Path::new(&walk::read_link(&fs, &entry.dentry).unwrap())
+                ),
+                _ => assert_eq!(
+                    fs::read(&host).unwrap(),
+                    fs.read_file(&entry.dentry).unwrap(),
+                    "{}",
+                    entry.path
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn a_destination_with_files_in_it_is_refused() {
+        let fs = PennFat::from_bytes(sample_image()).unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        fs::write(dest.path().join("README"), "mine").unwrap();
+        assert_eq!(
+            extract_all(&fs, dest.path()).err().unwrap().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(fs::read(dest.path().join("README")).unwrap(), b"mine");
+    }
+}
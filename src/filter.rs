@@ -0,0 +1,202 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    history::Blame,
+    ownership::Ownership,
+    pennfat::{BlockFormat, Entry, Profile, ShowBlocks},
+};
+
+/// A predicate selecting which FAT entries to list
+pub enum Filter {
+    /// entries marking the end of a chain
+    Eof,
+    /// blocks belonging to directories
+    Dir,
+    /// allocated blocks that don't belong to any file
+    Orphan,
+    /// blocks belonging to files whose path starts with the given prefix
+    File(String),
+    /// blocks whose contents changed in the last reload
+    Changed,
+    /// blocks in the given (inclusive) range
+    Range(u16, u16),
+}
+
+/// Parse a block number, in hex (with a 0x prefix) or decimal
+pub fn parse_block(s: &str) -> Option<u16> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Parse a byte offset in the image file, in hex (with a 0x prefix, and optionally grouped
+/// with underscores like 0x0001_2400) or decimal
+pub fn parse_offset(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Parse a filter: one of `eof`, `dir`, `orphan`, `changed`, `file:<path prefix>`,
+    /// or `range:<first>-<last>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.split_once(':') {
+            None => match s {
+                "eof" => Ok(Filter::Eof),
+                "dir" => Ok(Filter::Dir),
+                "orphan" => Ok(Filter::Orphan),
+                "changed" => Ok(Filter::Changed),
+                _ => Err(format!(
+                    "unknown filter '{}' (try eof, dir, orphan, changed, file:<path>, range:<a>-<b>)",
+                    s
+                )),
+            },
+            Some(("file", prefix)) if prefix.starts_with('/') => {
+                Ok(Filter::File(prefix.to_owned()))
+            }
+            Some(("file", _)) => Err("file: filters take an absolute path, like file:/dir".into()),
+            Some(("range", range)) => {
+                let (first, last) = range
+                    .split_once('-')
+                    .ok_or_else(|| format!("invalid range '{}', expected <first>-<last>", range))?;
+                match (parse_block(first), parse_block(last)) {
+                    (Some(first), Some(last)) if first <= last => Ok(Filter::Range(first, last)),
+                    (Some(_), Some(_)) => Err(format!("empty range '{}'", range)),
+                    _ => Err(format!("invalid block number in range '{}'", range)),
+                }
+            }
+            Some((kind, _)) => Err(format!("unknown filter '{}:'", kind)),
+        }
+    }
+}
+
+impl ShowBlocks for Filter {
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result {
+        match self {
+            Filter::Eof => write!(f, "eof"),
+            Filter::Dir => write!(f, "dir"),
+            Filter::Orphan => write!(f, "orphan"),
+            Filter::File(prefix) => write!(f, "file:{}", prefix),
+            Filter::Changed => write!(f, "changed"),
+            Filter::Range(first, last) => {
+                write!(f, "range:{}-{}", blocks.block(*first), blocks.block(*last))
+            }
+        }
+    }
+}
+
+impl Filter {
+    /// Whether the FAT entry of `block` (pointing to `next`) passes the filter
+    pub fn matches(
+        &self,
+        block: u16,
+        next: u16,
+        profile: Profile,
+        ownership: &Ownership,
+        blame: &Blame,
+    ) -> bool {
+        let owner = ownership.owner(block);
+        match self {
+            Filter::Eof => profile.classify(next) == Entry::Eof,
+            Filter::Dir => owner.is_some_and(|o| o.is_dir),
+            Filter::Orphan => owner.is_none(),
+            Filter::File(prefix) => owner.is_some_and(|o| o.path.starts_with(prefix.as_str())),
+            Filter::Changed => {
+                blame.generation() > 0 && blame.changed_in(block) == blame.generation()
+            }
+            Filter::Range(first, last) => (*first..=*last).contains(&block),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pennfat::PennFat;
+    use crate::testing;
+
+    fn parse(s: &str) -> Result<Filter, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_each_form() {
+        assert!(matches!(parse("eof"), Ok(Filter::Eof)));
+        assert!(matches!(parse(" dir "), Ok(Filter::Dir)));
+        assert!(matches!(parse("orphan"), Ok(Filter::Orphan)));
+        assert!(matches!(parse("changed"), Ok(Filter::Changed)));
+        assert!(matches!(parse("file:/docs"), Ok(Filter::File(p)) if p == "/docs"));
+        assert!(matches!(
+            parse("range:0x10-0x40"),
+            Ok(Filter::Range(0x10, 0x40))
+        ));
+        assert!(matches!(parse("range:16-16"), Ok(Filter::Range(16, 16))));
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        for invalid in [
+            "",
+            "eofs",
+            "file:docs",
+            "range:0x40-0x10",
+            "range:0x10",
+            "range:0x10-zz",
+            "range:0x10000-0x10001",
+            "size:10",
+        ] {
+            assert!(parse(invalid).is_err(), "{} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        let blocks = BlockFormat::default();
+        for filter in ["eof", "dir", "orphan", "changed", "file:/docs/src"] {
+            assert_eq!(parse(filter).unwrap().show(blocks).to_string(), filter);
+        }
+        let range = parse("range:16-0x40").unwrap();
+        assert_eq!(range.show(blocks).to_string(), "range:0x0010-0x0040");
+    }
+
+    #[test]
+    fn parses_blocks_and_offsets() {
+        assert_eq!(parse_block("0x1f"), Some(0x1f));
+        assert_eq!(parse_block("31"), Some(31));
+        assert_eq!(parse_block("0x10000"), None);
+        assert_eq!(parse_offset("0x0001_2400"), Some(0x12400));
+        assert_eq!(parse_offset("-1"), None);
+    }
+
+    #[test]
+    fn matches_the_sample_image() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        let ownership = Ownership::build(&fs);
+        let blame = Blame::new(&fs);
+        let listed = |filter: &str| -> Vec<u16> {
+            let filter = parse(filter).unwrap();
+            fs.get_fat_table()
+                .into_iter()
+                .filter(|(block, next)| {
+                    filter.matches(*block, *next, fs.profile(), &ownership, &blame)
+                })
+                .map(|(block, _)| block)
+                .collect()
+        };
+        assert_eq!(listed("dir"), [1, 3, 8, 9]);
+        assert_eq!(listed("orphan"), [30, 31, 32]);
+        assert_eq!(listed("file:/notes"), [4, 5, 12, 20]);
+        assert_eq!(listed("range:4-6"), [4, 5, 6]);
+        assert!(listed("eof").contains(&20));
+        // nothing has changed before the first reload
+        assert!(listed("changed").is_empty());
+    }
+}
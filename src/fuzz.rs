@@ -0,0 +1,468 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::pennfat::{DentryLayout, Field};
+
+/// The layout the generated images' directory entries are written in
+const LAYOUT: DentryLayout = DentryLayout::PENNFAT_V1;
+
+/// How deep the generated directory trees go, below the root
+const MAX_DEPTH: usize = 3;
+
+/// A small pseudo-random number generator (SplitMix64). The same seed gives the same numbers on
+/// every platform and with every version of the crate's dependencies, so a seed names a corpus
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to (but not including) `n`, which mustn't be 0
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    /// Whether something that happens with probability `p` happens this time
+    pub fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// A PennFAT image being put together in memory, with its directory entries laid out as
+/// pennfat-v1 and the root directory at block 1
+pub struct ImageBuilder {
+    block_size_config: u8,
+    /// Every FAT entry, entry 0 included
+    fat: Vec<u16>,
+    /// The data region, block 1 first
+    data: Vec<u8>,
+}
+
+impl ImageBuilder {
+    pub fn new(block_size_config: u8, fat_blocks: u8) -> Self {
+        let block_size = 256usize << block_size_config;
+        let entries = block_size * fat_blocks as usize / 2;
+        let data_blocks = (entries - 1).min(0xfffe);
+        let mut fat = vec![0; entries];
+        fat[0] = block_size_config as u16 | (fat_blocks as u16) << 8;
+        // the root directory, a block to begin with
+        fat[1] = 0xffff;
+        ImageBuilder {
+            block_size_config,
+            fat,
+            data: vec![0; data_blocks * block_size],
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        256 << self.block_size_config
+    }
+
+    pub fn data_blocks(&self) -> usize {
+        self.data.len() / self.block_size()
+    }
+
+    /// Allocate a chain of `n` free blocks, picked at random, linking them in the FAT. Returns
+    /// None if there aren't enough free blocks
+    pub fn allocate(&mut self, rng: &mut Rng, n: usize) -> Option<Vec<u16>> {
+        let mut free: Vec<u16> = (1..=self.data_blocks() as u16)
+            .filter(|block| self.fat[*block as usize] == 0)
+            .collect();
+        if free.len() < n {
+            return None;
+        }
+        let mut chain = Vec::with_capacity(n);
+        for _ in 0..n {
+            // files are mostly contiguous, as allocators tend to make them
+            let i = match chain.last() {
+                Some(last) if rng.chance(0.7) => free
+                    .iter()
+                    .position(|block| *block == last + 1)
+                    .unwrap_or_else(|| rng.below(free.len() as u64) as usize),
+                _ => rng.below(free.len() as u64) as usize,
+            };
+            chain.push(free.swap_remove(i));
+        }
+        self.link(&chain);
+        Some(chain)
+    }
+
+    /// Allocate a chain of the `n` lowest free blocks, linking them in the FAT. Returns None if
+    /// there aren't enough free blocks
+    pub fn allocate_lowest(&mut self, n: usize) -> Option<Vec<u16>> {
+        let chain: Vec<u16> = (1..=self.data_blocks() as u16)
+            .filter(|block| self.fat[*block as usize] == 0)
+            .take(n)
+            .collect();
+        if chain.len() < n {
+            return None;
+        }
+        self.link(&chain);
+        Some(chain)
+    }
+
+    /// Link the given blocks into a chain in the FAT, in order, ending it at the last
+    pub fn link(&mut self, chain: &[u16]) {
+        for pair in chain.windows(2) {
+            self.fat[pair[0] as usize] = pair[1];
+        }
+        if let Some(last) = chain.last() {
+            self.fat[*last as usize] = 0xffff;
+        }
+    }
+
+    /// Get the offset of a data block in the data region
+    fn block_offset(&self, block: u16) -> usize {
+        (block as usize - 1) * self.block_size()
+    }
+
+    /// Write `contents` over the blocks of a chain, in order
+    pub fn write(&mut self, chain: &[u16], contents: &[u8]) {
+        for (block, chunk) in chain.iter().zip(contents.chunks(self.block_size())) {
+            let offset = self.block_offset(*block);
+            self.data[offset..offset + chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    /// Write a directory entry into a slot of a directory block
+    pub fn write_dentry(&mut self, block: u16, slot: usize, dentry: &DentrySpec) {
+        self.write_dentry_as(block, slot, dentry, &LAYOUT);
+    }
+
+    /// Write a directory entry into a slot of a directory block, laid out as `layout` says.
+    /// The mtime is written as is, in whatever unit the layout keeps it in
+    pub fn write_dentry_as(
+        &mut self,
+        block: u16,
+        slot: usize,
+        dentry: &DentrySpec,
+        layout: &DentryLayout,
+    ) {
+        let offset = self.block_offset(block) + slot * layout.size;
+        let entry = &mut self.data[offset..offset + layout.size];
+        let mut name = dentry.name.clone();
+        name.resize(layout.name.len, 0);
+        put(entry, &layout.name, &name);
+        put(entry, &layout.file_size, &dentry.size.to_le_bytes());
+        put(
+            entry,
+            &layout.first_block,
+            &dentry.first_block.to_le_bytes(),
+        );
+        put(entry, &layout.type_, &[dentry.type_]);
+        put(entry, &layout.perm, &[dentry.perm]);
+        put(entry, &layout.mtime, &dentry.mtime.to_le_bytes());
+    }
+
+    /// Get the image as it goes in a file: the FAT, then the data region
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.fat.iter().flat_map(|e| e.to_le_bytes()).collect();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// Write a value into a field of a directory entry, cut short to fit
+fn put(entry: &mut [u8], field: &Field, value: &[u8]) {
+    let range = field.within(entry.len());
+    let len = range.len().min(value.len());
+    entry[range.start..range.start + len].copy_from_slice(&value[..len]);
+}
+
+/// The fields of a directory entry to write
+pub struct DentrySpec {
+    pub name: Vec<u8>,
+    pub size: u32,
+    pub first_block: u16,
+    pub type_: u8,
+    pub perm: u8,
+    pub mtime: u64,
+}
+
+/// A fault injected into a generated image
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    /// A block's FAT entry was overwritten
+    FatEntry { block: u16, old: u16, new: u16 },
+    /// The size, first block, type and permissions of a directory entry were overwritten
+    Dentry { block: u16, slot: usize },
+    /// The image file was cut short
+    Truncated { from: u64, to: u64 },
+}
+
+/// What went into a generated image, as recorded in the manifest
+#[derive(Serialize)]
+pub struct Generated {
+    pub file: String,
+    /// The seed the image was generated from
+    pub seed: u64,
+    pub block_size: usize,
+    pub fat_blocks: u8,
+    pub files: usize,
+    pub directories: usize,
+    pub faults: Vec<Fault>,
+}
+
+/// Where a directory entry was written, to pick one to corrupt
+struct Slot {
+    block: u16,
+    slot: usize,
+}
+
+/// A random image under construction, and what's in it
+struct Generator {
+    rng: Rng,
+    builder: ImageBuilder,
+    slots: Vec<Slot>,
+    files: usize,
+    directories: usize,
+}
+
+impl Generator {
+    /// Fill the directory block `block` with random entries, recursing into the directories
+    fn fill_directory(&mut self, block: u16, depth: usize) {
+        let slots = self.builder.block_size() / LAYOUT.size;
+        let entries = self.rng.below(slots as u64 + 1) as usize;
+        for slot in 0..entries {
+            let is_dir = depth < MAX_DEPTH && self.rng.chance(0.2);
+            let name = format!("{}{}", random_name(&mut self.rng), slot);
+            let (size, chain) = match is_dir {
+                true => (0, self.builder.allocate(&mut self.rng, 1)),
+                false => {
+                    let size = self.rng.below(4 * self.builder.block_size() as u64 + 1) as usize;
+                    let blocks = size.div_ceil(self.builder.block_size());
+                    (size, self.builder.allocate(&mut self.rng, blocks))
+                }
+            };
+            // a full image just ends the directory
+            let Some(chain) = chain else {
+                break;
+            };
+            if !is_dir {
+                let contents = random_contents(&mut self.rng, size);
+                self.builder.write(&chain, &contents);
+            }
+            let dentry = DentrySpec {
+                name: name.into_bytes(),
+                size: size as u32,
+                first_block: chain.first().copied().unwrap_or(0),
+                type_: is_dir as u8,
+                perm: [4, 6, 7][self.rng.below(3) as usize],
+                // some time in 2020-2025, in milliseconds
+                mtime: 1_577_836_800_000 + self.rng.below(6 * 365 * 24 * 3600 * 1000),
+            };
+            self.builder.write_dentry(block, slot, &dentry);
+            self.slots.push(Slot { block, slot });
+            match is_dir {
+                true => {
+                    self.directories += 1;
+                    self.fill_directory(chain[0], depth + 1);
+                }
+                false => self.files += 1,
+            }
+        }
+    }
+
+    /// Inject each kind of fault with probability `rate`, returning the image's bytes and the
+    /// faults injected
+    fn corrupt(&mut self, rate: f64) -> (Vec<u8>, Vec<Fault>) {
+        let mut faults = Vec::new();
+        let used: Vec<u16> = (1..=self.builder.data_blocks() as u16)
+            .filter(|block| self.builder.fat[*block as usize] != 0)
+            .collect();
+        if !used.is_empty() && self.rng.chance(rate) {
+            let block = used[self.rng.below(used.len() as u64) as usize];
+            let old = self.builder.fat[block as usize];
+            let new = match self.rng.below(3) {
+                0 => 0,
+                1 => self.rng.below(self.builder.data_blocks() as u64 + 1) as u16,
+                _ => self.rng.next_u64() as u16,
+            };
+            if new != old {
+                self.builder.fat[block as usize] = new;
+                faults.push(Fault::FatEntry { block, old, new });
+            }
+        }
+        if !self.slots.is_empty() && self.rng.chance(rate) {
+            let Slot { block, slot } =
+                &self.slots[self.rng.below(self.slots.len() as u64) as usize];
+            let offset = self.builder.block_offset(*block) + slot * LAYOUT.size;
+            // everything between the name and the mtime
+            for byte in
+                &mut self.builder.data[offset + LAYOUT.name.len..offset + LAYOUT.mtime.offset]
+            {
+                *byte = self.rng.next_u64() as u8;
+            }
+            faults.push(Fault::Dentry {
+                block: *block,
+                slot: *slot,
+            });
+        }
+        let mut bytes = self.builder.bytes();
+        if self.rng.chance(rate) {
+            let from = bytes.len() as u64;
+            let to = self.rng.below(from);
+            bytes.truncate(to as usize);
+            faults.push(Fault::Truncated { from, to });
+        }
+        (bytes, faults)
+    }
+}
+
+/// Make up a file name of a few lowercase letters
+fn random_name(rng: &mut Rng) -> String {
+    let len = 1 + rng.below(12) as usize;
+    (0..len)
+        .map(|_| (b'a' + rng.below(26) as u8) as char)
+        .collect()
+}
+
+/// Make up the contents of a file: lines of text, or random bytes
+fn random_contents(rng: &mut Rng, size: usize) -> Vec<u8> {
+    match rng.chance(0.5) {
+        true => b"the quick brown fox jumps over the lazy dog\n"
+            .iter()
+            .copied()
+            .cycle()
+            .take(size)
+            .collect(),
+        false => (0..size).map(|_| rng.next_u64() as u8).collect(),
+    }
+}
+
+/// Generate an image from a seed, with a random geometry and directory tree, injecting faults
+/// at `corrupt_rate`
+pub fn generate(seed: u64, corrupt_rate: f64) -> (Vec<u8>, Generated) {
+    let mut rng = Rng::new(seed);
+    // small geometries, so a corpus of many images stays small
+    let block_size_config = rng.below(3) as u8;
+    let fat_blocks = 1 + rng.below(2) as u8;
+    let mut generator = Generator {
+        builder: ImageBuilder::new(block_size_config, fat_blocks),
+        rng,
+        slots: Vec::new(),
+        files: 0,
+        directories: 0,
+    };
+    generator.fill_directory(1, 0);
+    let (bytes, faults) = generator.corrupt(corrupt_rate);
+    let generated = Generated {
+        file: String::new(),
+        seed,
+        block_size: generator.builder.block_size(),
+        fat_blocks,
+        files: generator.files,
+        directories: generator.directories,
+        faults,
+    };
+    (bytes, generated)
+}
+
+/// Build the sample image the demo opens: a small tree with nested directories, a fragmented
+/// file, a symbolic link, a deleted entry and an orphan chain, the same every time
+pub fn sample() -> Vec<u8> {
+    // Sat, 1 Jun 2024 12:00:00 UTC, in milliseconds
+    const MTIME: u64 = 1_717_243_200_000;
+    let mut builder = ImageBuilder::new(0, 1);
+    let mut add = |dir: u16, slot: usize, name: &str, type_: u8, chain: &[u16], contents: &[u8]| {
+        builder.link(chain);
+        builder.write(chain, contents);
+        let dentry = DentrySpec {
+            name: name.as_bytes().to_vec(),
+            size: contents.len() as u32,
+            first_block: chain[0],
+            type_,
+            perm: if type_ == 1 { 7 } else { 6 },
+            mtime: MTIME,
+        };
+        builder.write_dentry(dir, slot, &dentry);
+    };
+    let notes: Vec<u8> = (1..=20)
+        .flat_map(|n| {
+            format!("note {:02}: the blocks of this file are scattered\n", n).into_bytes()
+        })
+        .collect();
+    let guide: Vec<u8> = b"follow a file's chain with l, block by block\n"
+        .iter()
+        .copied()
+        .cycle()
+        .take(400)
+        .collect();
+
+    // the root directory spills into a second block
+    add(
+        1,
+        0,
+        "README",
+        0,
+        &[2],
+        b"a sample PennFAT image, made by pfview demo\n",
+    );
+    add(1, 1, "docs", 1, &[3], b"");
+    add(1, 2, "notes.txt", 0, &[4, 12, 5, 20], &notes);
+    add(1, 3, "latest", 2, &[6], b"docs/guide.txt");
+    add(3, 0, "guide.txt", 0, &[10, 11], &guide);
+    add(3, 1, "src", 1, &[8], b"");
+    add(8, 0, "main.c", 0, &[13], b"int main(void) { return 0; }\n");
+    builder.link(&[1, 9]);
+
+    // a deleted file: its dentry and contents are left behind, but its blocks are free
+    let old = b"this file was deleted, but its contents are still here\n";
+    builder.write(&[7], old);
+    let dentry = DentrySpec {
+        name: b"\x01old.log".to_vec(),
+        size: old.len() as u32,
+        first_block: 7,
+        type_: 0,
+        perm: 6,
+        mtime: MTIME,
+    };
+    builder.write_dentry(9, 0, &dentry);
+
+    // and an orphan chain: allocated in the FAT, but in no directory
+    builder.link(&[30, 31, 32]);
+    let lost: Vec<u8> = b"no directory entry leads here\n"
+        .iter()
+        .copied()
+        .cycle()
+        .take(600)
+        .collect();
+    builder.write(&[30, 31, 32], &lost);
+    builder.bytes()
+}
+
+/// Generate `count` images into `dir`, as `fuzz-<n>.img`, along with `manifest.json` saying
+/// what went into each. Returns the path of the manifest and what was generated
+pub fn generate_corpus(
+    dir: &Path,
+    count: usize,
+    seed: u64,
+    corrupt_rate: f64,
+) -> io::Result<(PathBuf, Vec<Generated>)> {
+    fs::create_dir_all(dir)?;
+    let mut seeds = Rng::new(seed);
+    let mut corpus = Vec::with_capacity(count);
+    for i in 0..count {
+        let (bytes, mut generated) = generate(seeds.next_u64(), corrupt_rate);
+        generated.file = format!("fuzz-{:04}.img", i);
+        fs::write(dir.join(&generated.file), bytes)?;
+        corpus.push(generated);
+    }
+    let manifest = dir.join("manifest.json");
+    fs::write(&manifest, serde_json::to_string_pretty(&corpus)? + "\n")?;
+    Ok((manifest, corpus))
+}
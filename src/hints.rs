@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+
+/// A part of the viewer worth explaining the first time it's visited
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Topic {
+    FatList,
+    DirectoryBlock,
+    RawBlock,
+    /// A block no file owns
+    OrphanChain,
+    /// The block pane, once it has the focus
+    BlockPane,
+    Stats,
+    Check,
+}
+
+impl Topic {
+    /// What the hint on the topic says
+    pub fn text(self) -> &'static str {
+        match self {
+            Topic::FatList => {
+                "The FAT list, on the left, has a row for each allocated block, with the block its \
+                 entry points to. Move with up/down, and press l to follow the chain to the \
+                 file's next block"
+            }
+            Topic::DirectoryBlock => {
+                "Directory blocks are shown as their entries: name, size, first block and \
+                 so on. t shows the raw bytes instead, and tab moves the focus here"
+            }
+            Topic::RawBlock => {
+                "The blocks of files are shown raw, in hex and as text. Bytes past the end \
+                 of the file are slack. d reads the block as directory entries instead"
+            }
+            Topic::OrphanChain => {
+                "No file owns this block: it's allocated in the FAT, but no directory entry \
+                 leads to its chain. C runs the consistency check, which reports it"
+            }
+            Topic::BlockPane => {
+                "The block pane has the focus: up/down move the cursor over its rows. Tab \
+                 moves the focus on, back to the FAT list"
+            }
+            Topic::Stats => {
+                "The statistics of the whole image: how full it is, and how fragmented its \
+                 files are. z or esc closes them"
+            }
+            Topic::Check => {
+                "Every consistency problem found in the image, such as orphan chains and \
+                 files sharing blocks. C or esc closes the list"
+            }
+        }
+    }
+}
+
+/// Short hints shown the first time each part of the viewer is visited, one at a time. A hint
+/// stays up until it's dismissed or its topic goes out of view, and is never shown again
+#[derive(Default)]
+pub struct Hints {
+    shown: HashSet<Topic>,
+    current: Option<Topic>,
+}
+
+impl Hints {
+    /// Note what's in view, most specific first, bringing up the hint on the first topic that
+    /// hasn't had one yet
+    pub fn visit(&mut self, in_view: &[Topic]) {
+        if self.current.is_some_and(|topic| !in_view.contains(&topic)) {
+            self.current = None;
+        }
+        if self.current.is_none() {
+            self.current = in_view
+                .iter()
+                .copied()
+                .find(|topic| self.shown.insert(*topic));
+        }
+    }
+
+    /// The hint up at the moment, if any
+    pub fn current(&self) -> Option<Topic> {
+        self.current
+    }
+
+    /// Dismiss the hint that's up. Returns whether there was one
+    pub fn dismiss(&mut self) -> bool {
+        self.current.take().is_some()
+    }
+}
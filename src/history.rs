@@ -0,0 +1,367 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::debug;
+
+use crate::pennfat::{BlockFormat, Entry, PennFat, Profile};
+
+/// Number of reload generations whose times are remembered
+const GENERATION_RING_SIZE: usize = 1024;
+
+/// How long the FAT list shows what changed entries held before, after a reload
+const PREVIOUS_ENTRIES_SHOWN_FOR: Duration = Duration::from_secs(3);
+
+/// Checksum a block's (or a file's) contents (32-bit FNV-1a)
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// Tracks, for every data block, the reload generation in which its contents last changed.
+/// Generation 0 is the image as first loaded, and every reload that picked up changes to the
+/// file starts a new generation
+pub struct Blame {
+    /// The current generation
+    generation: u32,
+    /// Checksum of each block's contents as of the current generation (index = block - 1)
+    checksums: Vec<u32>,
+    /// Generation in which each block's checksum last changed (index = block - 1)
+    changed_in: Vec<u32>,
+    /// When each of the most recent generations started, oldest first
+    times: VecDeque<(u32, SystemTime)>,
+    /// Every FAT entry as of the current generation (index = block)
+    entries: Vec<u16>,
+    /// What the FAT entries that changed in the current generation held before it, by block.
+    /// Only the current generation's are kept
+    previous_entries: HashMap<u16, u16>,
+    /// When the current generation's changes were picked up
+    updated_at: Instant,
+    /// Generation in which each block went from free to allocated (index = block). 0 if it's
+    /// free, or was allocated before pfview started, so its age is unknown
+    allocated_in: Vec<u32>,
+    /// Where the blocks allocated since pfview started were placed
+    placement: Placement,
+}
+
+/// Where the blocks allocated across reloads were placed, to tell whether the writer allocates
+/// first-fit, taking the lowest-numbered free blocks
+#[derive(Default)]
+pub struct Placement {
+    /// Blocks seen going from free to allocated
+    pub allocated: usize,
+    /// Of those, the ones that were among the lowest free blocks when they were allocated
+    pub lowest_free: usize,
+    /// Reloads that allocated blocks
+    pub reloads: usize,
+    /// Of those, the ones that allocated only the lowest free blocks
+    pub first_fit_reloads: usize,
+    /// The latest allocation that wasn't first-fit
+    pub last_miss: Option<Miss>,
+}
+
+/// A block allocated while a lower-numbered block was free
+pub struct Miss {
+    pub generation: u32,
+    pub block: u16,
+    /// The lowest free block, which first-fit would have taken instead
+    pub lowest_free: u16,
+}
+
+/// A FAT entry that changed in a reload
+pub struct EntryChange {
+    pub block: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+impl EntryChange {
+    /// Describe the change, e.g. "0x002a: 0x0000 -> 0xffff (was free, now EOF)"
+    pub fn describe(&self, profile: Profile, blocks: BlockFormat) -> String {
+        let meaning = |entry| match profile.classify(entry) {
+            Entry::Free => "free",
+            Entry::Eof => "EOF",
+            Entry::Reserved => "reserved",
+            Entry::Next(_) => "a link",
+        };
+        format!(
+            "{}: {} -> {} (was {}, now {})",
+            blocks.block(self.block),
+            blocks.block(self.old),
+            blocks.block(self.new),
+            meaning(self.old),
+            meaning(self.new)
+        )
+    }
+}
+
+/// Read every usable FAT entry of a filesystem (index = block). Entries a truncated file
+/// doesn't hold read as 0
+fn read_entries(fs: &PennFat) -> Vec<u16> {
+    (0..fs.usable_entries())
+        .map(|block| fs.fat_entry(block as u16).unwrap_or(0))
+        .collect()
+}
+
+impl Blame {
+    /// Start tracking changes to the blocks of a filesystem, as currently loaded
+    pub fn new(fs: &PennFat) -> Self {
+        let mut blame = Blame {
+            generation: 0,
+            checksums: Vec::new(),
+            changed_in: Vec::new(),
+            times: VecDeque::from([(0, fs.last_update_time())]),
+            entries: read_entries(fs),
+            previous_entries: HashMap::new(),
+            updated_at: Instant::now(),
+            allocated_in: Vec::new(),
+            placement: Placement::default(),
+        };
+        blame.allocated_in = vec![0; blame.entries.len()];
+        blame.checksums = blame.checksum_blocks(fs);
+        blame.changed_in = vec![0; blame.checksums.len()];
+        blame
+    }
+
+    /// Checksum every data block of the filesystem. Blocks that can't be read (in a truncated
+    /// file) get a checksum of 0
+    fn checksum_blocks(&self, fs: &PennFat) -> Vec<u32> {
+        (1..=fs.data_block_count())
+            .map(|block| fs.block_bytes(block).map(checksum).unwrap_or(0))
+            .collect()
+    }
+
+    /// Start a new generation after a reload picked up changes to the file, recording which
+    /// blocks changed in it. Returns the number of blocks and of FAT entries that changed
+    pub fn update(&mut self, fs: &PennFat) -> (usize, usize) {
+        self.generation += 1;
+        if self.times.len() == GENERATION_RING_SIZE {
+            self.times.pop_front();
+        }
+        self.times
+            .push_back((self.generation, fs.last_update_time()));
+
+        let checksums = self.checksum_blocks(fs);
+        // blocks that didn't exist before (if the geometry changed) count as changed
+        self.changed_in.resize(checksums.len(), self.generation);
+        let mut changed = 0;
+        for (i, checksum) in checksums.iter().enumerate() {
+            if self.checksums.get(i) != Some(checksum) {
+                self.changed_in[i] = self.generation;
+                changed += 1;
+            }
+        }
+        self.checksums = checksums;
+
+        // entry 0 holds the configuration rather than a link, so it's left out
+        let entries = read_entries(fs);
+        self.place_allocations(fs.profile(), &entries);
+        self.previous_entries = entries
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter_map(|(block, entry)| {
+                let old = self.entries.get(block).copied().unwrap_or(0);
+                (old != *entry).then_some((block as u16, old))
+            })
+            .collect();
+        self.entries = entries;
+        self.updated_at = Instant::now();
+        debug!(
+            "generation {}: {} blocks and {} FAT entries changed",
+            self.generation,
+            changed,
+            self.previous_entries.len()
+        );
+        (changed, self.previous_entries.len())
+    }
+
+    /// Note the blocks that went from free to allocated in the current generation, and whether
+    /// they were the lowest free ones. Blocks that were freed lose their age
+    fn place_allocations(&mut self, profile: Profile, entries: &[u16]) {
+        let free = |entry: u16| profile.classify(entry) == Entry::Free;
+        let was_free = |block: usize| self.entries.get(block).is_none_or(|entry| free(*entry));
+        let allocated: Vec<u16> = (1..entries.len())
+            .filter(|block| !free(entries[*block]) && was_free(*block))
+            .map(|block| block as u16)
+            .collect();
+        self.allocated_in.resize(entries.len(), 0);
+        for (block, entry) in entries.iter().enumerate() {
+            if free(*entry) {
+                self.allocated_in[block] = 0;
+            }
+        }
+        for block in &allocated {
+            self.allocated_in[*block as usize] = self.generation;
+        }
+        if allocated.is_empty() {
+            return;
+        }
+
+        let lowest: Vec<u16> = (1..self.entries.len())
+            .filter(|block| free(self.entries[*block]))
+            .map(|block| block as u16)
+            .take(allocated.len())
+            .collect();
+        let placed_lowest = allocated.iter().filter(|b| lowest.contains(b)).count();
+        let placement = &mut self.placement;
+        placement.allocated += allocated.len();
+        placement.lowest_free += placed_lowest;
+        placement.reloads += 1;
+        if placed_lowest == allocated.len() {
+            placement.first_fit_reloads += 1;
+        } else if let (Some(block), Some(lowest_free)) = (
+            allocated.iter().find(|b| !lowest.contains(b)),
+            lowest.iter().find(|b| !allocated.contains(b)),
+        ) {
+            placement.last_miss = Some(Miss {
+                generation: self.generation,
+                block: *block,
+                lowest_free: *lowest_free,
+            });
+        }
+    }
+
+    /// Get the generation in which a block went from free to allocated, or None if its age is
+    /// unknown: it was allocated before pfview started (or is free)
+    pub fn allocated_in(&self, block: u16) -> Option<u32> {
+        self.allocated_in
+            .get(block as usize)
+            .copied()
+            .filter(|generation| *generation > 0)
+    }
+
+    /// Describe where the allocations seen so far were placed, as rows of a key/value table
+    pub fn placement_rows(
+        &self,
+        profile: Profile,
+        blocks: BlockFormat,
+    ) -> Vec<(&'static str, String)> {
+        let placement = &self.placement;
+        let unknown_age = (1..self.entries.len())
+            .filter(|block| {
+                profile.classify(self.entries[*block]) != Entry::Free
+                    && self.allocated_in[*block] == 0
+            })
+            .count();
+        let mut rows = vec![
+            (
+                "allocations seen",
+                format!(
+                    "{} blocks in {} reloads",
+                    placement.allocated, placement.reloads
+                ),
+            ),
+            (
+                "first-fit",
+                match placement.allocated {
+                    0 => "-".to_owned(),
+                    n => format!(
+                        "{}/{} blocks lowest free, {}/{} reloads",
+                        placement.lowest_free, n, placement.first_fit_reloads, placement.reloads
+                    ),
+                },
+            ),
+            (
+                "unknown age",
+                format!("{} blocks (allocated before pfview started)", unknown_age),
+            ),
+        ];
+        if let Some(miss) = &placement.last_miss {
+            rows.push((
+                "last non-first-fit",
+                format!(
+                    "{} in reload {}, {} was free",
+                    blocks.block(miss.block),
+                    miss.generation,
+                    blocks.block(miss.lowest_free)
+                ),
+            ));
+        }
+        rows
+    }
+
+    /// Get what a FAT entry held before the current generation, if it changed in it
+    pub fn previous_entry(&self, block: u16) -> Option<u16> {
+        self.previous_entries.get(&block).copied()
+    }
+
+    /// Get what the FAT entries that changed in the current generation held before it, while
+    /// they're recent enough to show in the FAT list
+    pub fn recent_previous_entries(&self) -> Option<&HashMap<u16, u16>> {
+        (self.updated_at.elapsed() < PREVIOUS_ENTRIES_SHOWN_FOR
+            && !self.previous_entries.is_empty())
+        .then_some(&self.previous_entries)
+    }
+
+    /// Get the FAT entries of the chain from `first_block` that changed in the current
+    /// generation. The chain is followed through the entries as they are now, or where an
+    /// entry no longer links anywhere, as it was, so a chain that was freed is still found
+    pub fn chain_changes(&self, fs: &PennFat, first_block: u16) -> Vec<EntryChange> {
+        let profile = fs.profile();
+        let mut changes = Vec::new();
+        let mut visited = HashSet::new();
+        let mut block = first_block;
+        while block != 0 && visited.insert(block) {
+            let Some(new) = fs.fat_entry(block) else {
+                break;
+            };
+            let old = self.previous_entry(block);
+            if let Some(old) = old {
+                changes.push(EntryChange { block, old, new });
+            }
+            block = match (profile.classify(new), old.map(|old| profile.classify(old))) {
+                (Entry::Next(next), _) | (_, Some(Entry::Next(next))) => next,
+                _ => break,
+            };
+        }
+        changes
+    }
+
+    /// Get the current generation
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Get the generation in which a block last changed (0 if it hasn't changed since the
+    /// image was first loaded)
+    pub fn changed_in(&self, block: u16) -> u32 {
+        match block {
+            0 => 0,
+            _ => self
+                .changed_in
+                .get(block as usize - 1)
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Describe when a block last changed, e.g. "last changed 14:05:32, 3 reloads ago"
+    pub fn describe(&self, block: u16) -> String {
+        let generation = self.changed_in(block);
+        if generation == 0 {
+            return "unchanged since pfview started".to_owned();
+        }
+        let reloads_ago = match self.generation - generation {
+            0 => "in the last reload".to_owned(),
+            1 => "1 reload ago".to_owned(),
+            n => format!("{} reloads ago", n),
+        };
+        let format_time = |time: SystemTime| {
+            chrono::DateTime::<chrono::Utc>::from(time)
+                .format("%H:%M:%S")
+                .to_string()
+        };
+        match self.times.iter().find(|(g, _)| *g == generation) {
+            Some((_, time)) => format!("last changed {}, {}", format_time(*time), reloads_ago),
+            // the generation fell out of the ring, so all we know is it's older than the oldest
+            None => format!(
+                "last changed before {}, {}",
+                format_time(self.times[0].1),
+                reloads_ago
+            ),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use crate::app::timed;
+
+/// A process with a file open for writing
+pub struct Writer {
+    pub pid: u32,
+    /// The name of the process's program
+    pub name: String,
+}
+
+impl std::fmt::Display for Writer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pid {} ({})", self.pid, self.name)
+    }
+}
+
+/// Find the other processes that have a file open for writing, by going through their open
+/// files in /proc. Only Linux has the information, so elsewhere this is None
+#[cfg(target_os = "linux")]
+pub fn writers(path: &Path) -> Option<Vec<Writer>> {
+    use std::{fs, os::unix::fs::MetadataExt, process};
+
+    let target = fs::metadata(path).ok()?;
+    let mut writers = Vec::new();
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) else {
+            continue;
+        };
+        if pid == process::id() {
+            continue;
+        }
+        // other users' processes can't be looked into, which is fine
+        let Ok(fds) = fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let writing = fds.flatten().any(|fd| {
+            let same_file = fs::metadata(fd.path())
+                .is_ok_and(|m| m.dev() == target.dev() && m.ino() == target.ino());
+            same_file && is_open_for_writing(&entry.path(), &fd.file_name().to_string_lossy())
+        });
+        if writing {
+            let name = fs::read_to_string(entry.path().join("comm"))
+                .map(|name| name.trim_end().to_owned())
+                .unwrap_or_else(|_| "?".to_owned());
+            writers.push(Writer { pid, name });
+        }
+    }
+    writers.sort_by_key(|writer| writer.pid);
+    Some(writers)
+}
+
+/// Whether a process's file descriptor was opened for writing, going by the access mode in its
+/// flags (in octal, in /proc/<pid>/fdinfo/<fd>)
+#[cfg(target_os = "linux")]
+fn is_open_for_writing(process: &Path, fd: &str) -> bool {
+    let Ok(info) = std::fs::read_to_string(process.join("fdinfo").join(fd)) else {
+        return false;
+    };
+    info.lines()
+        .find_map(|line| line.strip_prefix("flags:"))
+        .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+        // O_WRONLY or O_RDWR
+        .is_some_and(|flags| flags & 0o3 != 0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn writers(_path: &Path) -> Option<Vec<Writer>> {
+    None
+}
+
+/// A look for writers running on a worker thread, as going through every process's open
+/// files can take long enough to hold up drawing and input
+pub struct Pending {
+    result: mpsc::Receiver<Option<Vec<Writer>>>,
+}
+
+impl Pending {
+    /// Start looking for the processes writing to the file at `path`
+    pub fn start(path: &Path) -> Self {
+        let (tx, result) = mpsc::channel();
+        let path = PathBuf::from(path);
+        thread::spawn(move || {
+            // the receiver is gone if pfview is quitting
+            let _ = tx.send(timed("looking for writers", || writers(&path)));
+        });
+        Pending { result }
+    }
+
+    /// Get the writers found, once the look is done
+    pub fn poll(&self) -> Option<Option<Vec<Writer>>> {
+        self.result.try_recv().ok()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{
+        process::{Command, Stdio},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    #[test]
+    fn writers_are_found_off_the_calling_thread() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image");
+        std::fs::write(&path, b"").unwrap();
+        // a shell holding the image open for appending until its stdin closes
+        let mut writer = Command::new("sh")
+            .arg("-c")
+            .arg("exec 3>>\"$0\"; echo; read _")
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut ready = [0];
+        std::io::Read::read_exact(writer.stdout.as_mut().unwrap(), &mut ready).unwrap();
+
+        let pending = Pending::start(&path);
+        let start = Instant::now();
+        let found = loop {
+            if let Some(found) = pending.poll() {
+                break found;
+            }
+            assert!(start.elapsed() < Duration::from_secs(30));
+            thread::sleep(Duration::from_millis(10));
+        };
+        drop(writer.stdin.take());
+        writer.wait().unwrap();
+        let found = found.unwrap();
+        assert!(
+            found.iter().any(|found| found.pid == writer.id()),
+            "{:?}",
+            found.iter().map(Writer::to_string).collect::<Vec<_>>()
+        );
+    }
+}
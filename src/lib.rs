@@ -0,0 +1,31 @@
+//! Reading PennFAT images: the filesystem itself, walking its directory tree, following who
+//! owns each block, and checking it for consistency. Then changing them: editing and adding
+//! directory entries, and planning and carrying out repairs. The `pfview` viewer and its
+//! subcommands are built on these.
+//!
+//! ```
+//! use pfview::pennfat::PennFat;
+//! use pfview::walk;
+//!
+//! let fs = PennFat::from_bytes(pfview::testing::sample_image()).unwrap();
+//! let names: Vec<String> = walk::walk(&fs).into_iter().map(|entry| entry.path).collect();
+//! assert!(names.contains(&"/docs/src/main.c".to_owned()));
+//! ```
+
+pub mod check;
+pub mod edit;
+pub mod encoding;
+pub mod explain;
+pub mod extract;
+pub mod filter;
+pub mod fuzz;
+pub mod history;
+pub mod ownership;
+pub mod pennfat;
+pub mod repair;
+mod sigbus;
+pub mod snapshot;
+pub mod stats;
+pub mod testing;
+pub mod trace;
+pub mod walk;
@@ -0,0 +1,23 @@
+use std::path::Path;
+
+use chrono::Utc;
+use log::LevelFilter;
+
+/// Log to a file, for debugging pfview itself while the viewer owns the screen. Nothing is
+/// logged unless this is called
+pub fn init(path: &Path, level: LevelFilter) -> Result<(), fern::InitError> {
+    fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{} {:<5} {}: {}",
+                Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .level(level)
+        .chain(fern::log_file(path)?)
+        .apply()?;
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::encoding::{self, Encoding};
+use crate::pennfat::{Dentry, PennFat};
+
+/// The signatures of the kinds of file worth telling apart: what they're called, where their
+/// magic number is and what it is
+const SIGNATURES: [(&str, usize, &[u8]); 17] = [
+    ("ELF", 0, b"\x7fELF"),
+    ("PNG", 0, b"\x89PNG\r\n\x1a\n"),
+    ("JPEG", 0, b"\xff\xd8\xff"),
+    ("GIF", 0, b"GIF87a"),
+    ("GIF", 0, b"GIF89a"),
+    ("PDF", 0, b"%PDF-"),
+    ("gzip", 0, b"\x1f\x8b"),
+    ("bzip2", 0, b"BZh"),
+    ("xz", 0, b"\xfd7zXZ\x00"),
+    ("zstd", 0, b"\x28\xb5\x2f\xfd"),
+    ("zip", 0, b"PK\x03\x04"),
+    ("7z", 0, b"7z\xbc\xaf\x27\x1c"),
+    ("tar", 257, b"ustar"),
+    ("SQLite", 0, b"SQLite format 3\x00"),
+    ("Mach-O", 0, b"\xcf\xfa\xed\xfe"),
+    ("PE", 0, b"MZ"),
+    ("script", 0, b"#!"),
+];
+
+/// Tell what some bytes from the start of a file look like: a kind of file known by its magic
+/// number, text, all zeros or just data
+pub fn detect(bytes: &[u8]) -> &'static str {
+    if bytes.is_empty() {
+        return "empty";
+    }
+    let signature = SIGNATURES
+        .iter()
+        .find(|(_, offset, magic)| bytes.get(*offset..offset + magic.len()) == Some(magic));
+    if let Some((name, _, _)) = signature {
+        return name;
+    }
+    if bytes.iter().all(|b| *b == 0) {
+        return "zeros";
+    }
+    match encoding::sniff(bytes) {
+        Encoding::Utf8 => "UTF-8 text",
+        Encoding::Utf16Le | Encoding::Utf16Be => "UTF-16 text",
+        Encoding::Binary => "data",
+    }
+}
+
+/// Tell what a file looks like from the first block of its chain, up to its size
+pub fn detect_file(fs: &PennFat, dentry: &Dentry) -> &'static str {
+    if dentry.size == 0 || dentry.first_block == 0 {
+        return "empty";
+    }
+    match fs.block_bytes(dentry.first_block) {
+        Ok(bytes) => detect(&bytes[..bytes.len().min(dentry.size as usize)]),
+        Err(_) => "unreadable",
+    }
+}
@@ -1,26 +1,101 @@
-mod pennfat;
+mod actions;
+mod annotate;
+mod app;
+mod browser;
+mod calc;
+mod cli;
+mod commands;
+mod diff;
+mod doctor;
+mod du;
+mod hints;
+mod holders;
+mod logging;
+mod magic;
+mod marks;
+mod pane;
+mod patch;
+mod recent;
+mod repack;
+mod report;
+mod screenshot;
+mod session;
+mod theme;
+mod title;
+mod tree;
+mod verify;
 
-use std::{cmp::Ordering, io, process::exit, sync::mpsc, thread};
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    io::{IsTerminal, Write},
+    ops::Range,
+    panic,
+    path::PathBuf,
+    process,
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
 
+use actions::Binding;
+use annotate::Annotation;
+use app::{
+    App, DentrySort, FatColumns, FatView, Prompt, PromptKind, Request, SortMode, StatusLine,
+};
+use browser::{Browser, Preview};
+use check::Problem;
 use chrono::prelude::*;
 use colored::Colorize;
+use commands::ProblemsFound;
 use crossterm::{
-    event::{self, Event as CEvent, KeyCode},
+    cursor,
+    event::{self, DisableFocusChange, EnableFocusChange, Event as CEvent, KeyCode, KeyEvent},
+    execute,
     terminal::{disable_raw_mode, enable_raw_mode},
 };
-use pennfat::PennFat;
+use du::DirSize;
+use edit::{DentryForm, FieldKind};
+use encoding::{Controls, Encoding};
+use hints::Topic;
+use history::Blame;
+use holders::Writer;
+use log::{debug, info, trace};
+use marks::Marks;
+use ownership::{Owner, Ownership};
+use pane::{Minimap, Pane};
+use pennfat::{
+    format_offset, BlockFormat, Chain, ChainEnd, Dentry, DentryUsage, Entry, Mode, PennFat,
+    PermTable, PfError, ShowBlocks,
+};
+use pfview::{
+    check, edit, encoding, explain, extract, filter, fuzz, history, ownership, pennfat, repair,
+    snapshot, stats, walk,
+};
+use recent::RecentFiles;
+use session::Session;
 use std::time::{Duration, Instant};
+use theme::Theme;
+use title::Title;
+use tree::RowKind;
 use tui::{
-    backend::CrosstermBackend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    backend::{Backend, CrosstermBackend, TestBackend},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier},
     text::{Span, Spans},
-    widgets::{Block, BorderType, Borders, List, ListState, Paragraph, Wrap},
+    widgets::{
+        Block, BorderType, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table,
+        Wrap,
+    },
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use tui::style::Style;
-use tui::Terminal;
+use tui::{Frame, Terminal};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 /// Events that can be sent to the main loop
 enum Event<I> {
@@ -28,56 +103,353 @@ enum Event<I> {
     Input(I),
     /// Tick event, for updating the screen
     Tick,
+    /// The terminal gained (true) or lost (false) the focus, on terminals that report it
+    Focus(bool),
+    /// The terminal was resized, to this many columns and rows
+    Resize(u16, u16),
+    /// The input thread stopped reading the terminal, as asked
+    Paused,
+    /// The terminal can't be read any more (e.g. the SSH session dropped), so the input thread
+    /// gave up
+    InputError(io::Error),
+}
+
+/// Read the terminal's events and send them to the main loop, with a tick whenever `tick`
+/// milliseconds pass without one, until the terminal is lost or the main loop stops listening.
+/// While `paused` is set the terminal is left alone, for another program to read.
+/// `poll` and `read` are crossterm's, but for tests
+fn read_input(
+    tx: &mpsc::Sender<Event<KeyEvent>>,
+    tick: &AtomicU64,
+    paused: &AtomicBool,
+    mut poll: impl FnMut(Duration) -> io::Result<bool>,
+    mut read: impl FnMut() -> io::Result<CEvent>,
+) {
+    let mut last_tick = Instant::now();
+    let mut acknowledged = false;
+    loop {
+        let tick_rate = Duration::from_millis(tick.load(Ordering::SeqCst));
+        if paused.load(Ordering::SeqCst) {
+            if !acknowledged {
+                if tx.send(Event::Paused).is_err() {
+                    return;
+                }
+                acknowledged = true;
+            }
+            thread::sleep(Duration::from_millis(50));
+            continue;
+        }
+        acknowledged = false;
+
+        // poll in short steps, to notice being paused quickly
+        let timeout = tick_rate
+            .checked_sub(last_tick.elapsed())
+            .unwrap_or_else(|| Duration::from_secs(0))
+            .min(Duration::from_millis(100));
+
+        // a signal interrupting the poll or the read isn't a problem, just try again. Any
+        // other error means the terminal is gone. Once the main loop stops listening,
+        // there's nothing left to do either
+        let ready = match poll(timeout) {
+            Ok(ready) => ready,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => false,
+            Err(e) => {
+                let _ = tx.send(Event::InputError(e));
+                return;
+            }
+        };
+        if ready {
+            match read() {
+                Ok(CEvent::Key(key)) => {
+                    if tx.send(Event::Input(key)).is_err() {
+                        return;
+                    }
+                }
+                Ok(CEvent::FocusGained) => {
+                    if tx.send(Event::Focus(true)).is_err() {
+                        return;
+                    }
+                }
+                Ok(CEvent::FocusLost) => {
+                    if tx.send(Event::Focus(false)).is_err() {
+                        return;
+                    }
+                }
+                // redrawn straight away, rather than left torn until the next tick
+                Ok(CEvent::Resize(width, height)) => {
+                    if tx.send(Event::Resize(width, height)).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = tx.send(Event::InputError(e));
+                    return;
+                }
+            }
+        }
+
+        if last_tick.elapsed() >= tick_rate {
+            if tx.send(Event::Tick).is_err() {
+                return;
+            }
+            last_tick = Instant::now();
+        }
+    }
 }
 
-/// make a paragraph with the overview of the filesystem
-fn make_overview(fs: &PennFat) -> Paragraph {
+/// make a paragraph with the overview of the filesystem, flashing it if `alert` is set.
+/// `warning` is about how the image is being read (e.g. a guessed root directory), and
+/// `point_in_time` describes the
+/// snapshot being viewed, if it isn't the live image. `idle_counter` says how long the live
+/// image has gone without changing. The title is fit to `width`, the width of the pane
+#[allow(clippy::too_many_arguments)]
+fn make_overview<'a>(
+    fs: &'a PennFat,
+    theme: &Theme,
+    point_in_time: Option<String>,
+    warning: Option<&str>,
+    alert: bool,
+    writers: Option<&[Writer]>,
+    problems: Option<&[Problem]>,
+    idle_counter: Option<Span<'a>>,
+    width: u16,
+) -> Paragraph<'a> {
     let last_update_time: DateTime<Utc> = fs.last_update_time().into();
     let overview_string = format!(
-        "fat size = {} ({} entries max), block size: {}, # data blocks = {}, last updated: {}",
+        "fat size = {} ({} entries max), block size: {}, # data blocks = {}, ",
         fs.fat_size(),
         fs.num_fat_entries(),
         fs.block_size(),
         fs.data_block_count(),
-        last_update_time.format("%Y-%m-%d %H:%M:%S")
     );
-    Paragraph::new(overview_string)
+    // a FAT larger than the data region can use has entries nothing can reach
+    let overview_string = match fs.wasted_fat_bytes() {
+        0 => overview_string,
+        wasted => format!("{}{} bytes of FAT unusable, ", overview_string, wasted),
+    };
+    // nobody writing to an image that hasn't changed in a while suggests watching the wrong file
+    let idle = Utc::now().signed_duration_since(last_update_time) > chrono::Duration::minutes(5);
+    let last_updated_style = match writers {
+        Some([]) if idle => Style::default().fg(Color::DarkGray),
+        _ => Style::default(),
+    };
+    let mut spans = vec![
+        Span::raw(overview_string),
+        Span::styled(
+            format!(
+                "last updated: {}",
+                last_update_time.format("%Y-%m-%d %H:%M:%S")
+            ),
+            last_updated_style,
+        ),
+    ];
+    // up front, so a narrow terminal cuts the line short after it
+    if let Some(idle_counter) = idle_counter {
+        spans.splice(0..0, [idle_counter, Span::raw(" | ")]);
+    }
+    // make it hard to miss that this isn't the live image; what's being read matters more than
+    // who else is reading it, so the writers are dropped first in a narrow terminal
+    let (viewing, border_color) = match point_in_time {
+        Some(point_in_time) => (format!("viewing {}", point_in_time), Color::Yellow),
+        None => (String::new(), Color::White),
+    };
+    let embedded = match fs.window().offset {
+        0 => String::new(),
+        offset => format!("embedded at offset {:#x}", offset),
+    };
+    let mode = match fs.mode() {
+        Mode::ReadOnly => format!("{}read-only", theme.lock),
+        Mode::Writable => "writable".to_owned(),
+    };
+    let writers = match writers {
+        Some([]) => "no writer".to_owned(),
+        Some(writers) => format!(
+            "writer: {}",
+            writers
+                .iter()
+                .map(Writer::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        None => String::new(),
+    };
+    let border_color = match warning {
+        Some(_) => Color::Yellow,
+        None => border_color,
+    };
+    let title = Title::new("PennFat Overview")
+        .part(5, viewing)
+        .part(3, embedded)
+        .part(2, mode)
+        .part(1, writers)
+        .part(4, warning.unwrap_or_default())
+        .fit(theme, width.saturating_sub(2) as usize);
+    // flash when the image just became less consistent
+    let border_color = if alert { Color::Red } else { border_color };
+    // entry 0 holds the configuration, which the image keeps being read with
+    let mut config = vec![Span::raw(format!(
+        "FAT entry 0 = {:#06x} ({})",
+        fs.config_entry(),
+        check::describe_config(fs.config_entry())
+    ))];
+    if let Some(entry) = fs.fat_entry(0).filter(|entry| *entry != fs.config_entry()) {
+        config.push(Span::styled(
+            format!(", now {:#06x} ({})", entry, check::describe_config(entry)),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ));
+    }
+    // bytes past the last block go unread, so the image may not be what its writer thinks
+    if !fs.unaddressed().is_empty() {
+        config.push(Span::styled(
+            format!(
+                ", {} trailing bytes unaddressable by FAT",
+                fs.unaddressed().len()
+            ),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if let Some(reserved) = fs.profile().reserved {
+        config.push(Span::styled(
+            format!(", {:#06x} is reserved", reserved),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    // how healthy the image is, by the latest consistency check
+    let (health, health_color) = match problems {
+        None => (format!("checking{}", theme.ellipsis), Color::DarkGray),
+        Some([]) => ("OK".to_owned(), Color::Green),
+        Some(problems) => (
+            format!("{} (C for details)", check::summarize(problems)),
+            match problems.iter().any(Problem::severe) {
+                true => Color::Red,
+                false => Color::Yellow,
+            },
+        ),
+    };
+    config.push(Span::raw(" | "));
+    config.push(Span::styled(health, Style::default().fg(health_color)));
+    Paragraph::new(vec![Spans::from(spans), Spans::from(config)])
         .style(Style::default().fg(Color::LightCyan))
         .alignment(Alignment::Center)
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .style(Style::default().fg(Color::White))
-                .title(format!("PennFat Overview"))
+                .style(Style::default().fg(border_color))
+                .title(title)
                 .border_type(BorderType::Plain),
         )
 }
 
-/// set of instructions to display in the help box
-static INSTRUCTIONS: [[&str; 2]; 7] = [
-    ["q", "quit"],
-    ["r", "view in raw mode"],
-    ["d", "view in directory mode"],
-    ["t", "toggle (raw/dir)"],
-    ["j/↓", "move down a block"],
-    ["k/↑", "move up a block"],
-    ["l/->", "move to next block in file"],
-];
+/// make the counter of how long the live image has gone without its contents changing, e.g.
+/// "idle 00:37", yellow once it's been idle for `warn` and red once it's been idle for `alert`
+fn make_idle_counter(idle: Duration, warn: Duration, alert: Duration) -> Span<'static> {
+    let secs = idle.as_secs();
+    let counter = match secs / 3600 {
+        0 => format!("idle {:02}:{:02}", secs / 60, secs % 60),
+        hours => format!("idle {}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    };
+    let style = Style::default().add_modifier(Modifier::BOLD);
+    let style = match idle {
+        idle if idle >= alert => style.fg(Color::Red),
+        idle if idle >= warn => style.fg(Color::Yellow),
+        _ => style,
+    };
+    Span::styled(counter, style)
+}
+
+/// make a paragraph with the status line
+fn make_status_line<'a>(
+    status: &'a StatusLine,
+    prompt: Option<&'a Prompt>,
+    blocks: BlockFormat,
+) -> Paragraph<'a> {
+    // an open prompt takes over the status line
+    if let Some(prompt) = prompt {
+        let label = match prompt.kind {
+            PromptKind::Filter => {
+                "filter (eof, dir, orphan, changed, file:<path>, range:<a>-<b>)".to_owned()
+            }
+            PromptKind::Goto => format!(
+                "go to block {} to {} (or @offset)",
+                blocks.block(1),
+                blocks.block(blocks.last)
+            ),
+            PromptKind::Calc => {
+                "calculate (fat_size, block_size, block(n), slot(n, i), + - * / %)".to_owned()
+            }
+            PromptKind::Note(block_num) => {
+                format!(
+                    "note on block {} (empty to delete)",
+                    blocks.block(block_num)
+                )
+            }
+            PromptKind::Adopt(head) => {
+                format!("adopt the chain at {} as (path [size])", blocks.block(head))
+            }
+            PromptKind::CopyTo => "copy the selected file to (image:/path)".to_owned(),
+        };
+        return Paragraph::new(Spans::from(vec![
+            Span::styled(format!(" {}: ", label), Style::default().fg(Color::Green)),
+            Span::raw(prompt.input.as_str()),
+            // a block cursor
+            Span::styled(" ", Style::default().add_modifier(Modifier::REVERSED)),
+        ]));
+    }
 
+    let spans = match (&status.error, &status.info) {
+        (Some(error), _) => Spans::from(vec![
+            Span::styled(
+                format!(" error: {}", error),
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(" (esc to dismiss)", Style::default().fg(Color::DarkGray)),
+        ]),
+        (None, Some(info)) => Spans::from(Span::styled(
+            format!(" {}", info),
+            Style::default().fg(Color::LightCyan),
+        )),
+        (None, None) => Spans::default(),
+    };
+    Paragraph::new(spans)
+}
+
+/// make the block pane shown in place of the block contents when the block can't be read:
+/// a red-bordered pane with the error centered in it
+fn make_block_error(error: &str, pane_height: u16) -> Paragraph<'_> {
+    // pad the top so the message sits in the middle of the pane (minus the borders)
+    let mut lines = vec![Spans::default(); (pane_height.saturating_sub(2) / 2) as usize];
+    lines.push(Spans::from(Span::styled(
+        error,
+        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+    )));
+    Paragraph::new(lines)
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::Red))
+                .title("block (error)")
+                .border_type(BorderType::Plain),
+        )
+}
+
+/// set of instructions to display in the help box
 /// make a paragraph with the instructions
-fn make_instructions() -> Paragraph<'static> {
-    let spans = INSTRUCTIONS
+fn make_instructions(theme: &Theme, title: String) -> Paragraph<'static> {
+    let spans = actions::BINDINGS
         .iter()
-        .map(|x| {
+        .map(|binding| {
             let key = Span::styled(
-                x[0],
+                theme.key_label(binding.label),
                 Style::default()
                     .fg(Color::Green)
                     .add_modifier(Modifier::BOLD),
             );
-            let desc = Span::styled(x[1], Style::default().fg(Color::White));
-            let line = vec![key, Span::raw(": "), desc];
-            line
+            let desc = Span::styled(binding.description, Style::default().fg(Color::White));
+            vec![key, Span::raw(": "), desc]
         })
         .collect::<Vec<Vec<Span>>>()
         .join(&Span::raw(" | "));
@@ -91,33 +463,325 @@ fn make_instructions() -> Paragraph<'static> {
             Block::default()
                 .borders(Borders::ALL)
                 .style(Style::default().fg(Color::White))
-                .title("Help")
+                .title(title)
+                .border_type(BorderType::Plain),
+        )
+}
+
+/// get a rect of (at most) the given size, centered in `area`
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// make the popup with the aggregate statistics of the filesystem, as a key/value table
+fn make_stats_popup(rows: Vec<(&'static str, String)>) -> Table<'static> {
+    let rows = rows.into_iter().map(|(key, value)| {
+        Row::new(vec![
+            Cell::from(key).style(Style::default().fg(Color::Green)),
+            Cell::from(value),
+        ])
+    });
+    Table::new(rows)
+        .style(Style::default().fg(Color::LightCyan))
+        .widths(&[Constraint::Length(22), Constraint::Length(45)])
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title("Filesystem stats (z to close)")
+                .border_type(BorderType::Plain),
+        )
+}
+
+/// make the popup describing a file, as `stat` does
+fn make_stat_popup(lines: Vec<String>) -> Paragraph<'static> {
+    let lines: Vec<Spans> = lines
+        .into_iter()
+        .map(|line| match line.split_once(": ") {
+            Some((key, value)) => Spans::from(vec![
+                Span::styled(format!("{}: ", key), Style::default().fg(Color::Green)),
+                Span::raw(value.to_owned()),
+            ]),
+            None => Spans::from(line),
+        })
+        .collect();
+    Paragraph::new(lines)
+        .style(Style::default().fg(Color::LightCyan))
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title("stat (I to close)")
+                .border_type(BorderType::Plain),
+        )
+}
+
+/// make the popup listing the consistency problems of the image
+fn make_check_popup(problems: &[Problem], blocks: BlockFormat) -> List<'static> {
+    let items: Vec<ListItem> = match problems {
+        [] => vec![ListItem::new("no problems found")],
+        _ => problems
+            .iter()
+            .map(|problem| ListItem::new(problem.show(blocks).to_string()))
+            .collect(),
+    };
+    List::new(items)
+        .style(Style::default().fg(Color::LightCyan))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(format!(
+                    "Consistency check - {} problems (C to close)",
+                    problems.len()
+                ))
+                .border_type(BorderType::Plain),
+        )
+}
+
+/// make the popup editing a dentry: a line per field, with the selected one showing what it takes,
+/// or once submitted, the changes to confirm. The perm field offers the values `perms` defines
+fn make_dentry_form(form: &DentryForm, perms: PermTable, blocks: BlockFormat) -> Paragraph<'_> {
+    let green = Style::default().fg(Color::Green);
+    let mut lines: Vec<Spans> = match &form.confirm {
+        Some(changes) => changes
+            .iter()
+            .map(|change| Spans::from(change.to_string()))
+            .collect(),
+        None => FieldKind::ALL
+            .iter()
+            .zip(&form.inputs)
+            .enumerate()
+            .map(|(i, (field, input))| {
+                let mut spans = vec![
+                    Span::styled(format!("{:<12}", field.label()), green),
+                    Span::raw(input.as_str()),
+                ];
+                if i == form.selected {
+                    spans.push(Span::styled(
+                        " ",
+                        Style::default().add_modifier(Modifier::REVERSED),
+                    ));
+                    spans.push(Span::styled(
+                        format!("  {}", field.hint(perms)),
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                }
+                Spans::from(spans)
+            })
+            .collect(),
+    };
+    lines.push(Spans::default());
+    lines.push(Spans::from(Span::styled(
+        match form.confirm {
+            Some(_) => "y/enter to write, any other key to go back",
+            None => "tab/arrows: move | ^u: clear | enter: review changes | esc: cancel",
+        },
+        Style::default().fg(Color::DarkGray),
+    )));
+    let title = match form.confirm {
+        Some(_) => format!("Write to {}?", form.dentry.name()),
+        None => format!(
+            "Edit {} (block {}, slot {})",
+            form.dentry.name(),
+            blocks.block(form.block),
+            form.slot
+        ),
+    };
+    Paragraph::new(lines)
+        .style(Style::default().fg(Color::LightCyan))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(title)
+                .border_type(BorderType::Plain),
+        )
+}
+
+/// make the command palette popup, listing the actions in `matches` under what has been typed
+fn make_palette<'a>(theme: &Theme, input: &str, matches: &[&'a Binding]) -> List<'a> {
+    let items: Vec<ListItem> = matches
+        .iter()
+        .map(|binding| {
+            ListItem::new(Spans::from(vec![
+                Span::styled(
+                    format!("{:<6}", theme.key_label(binding.label)),
+                    Style::default().fg(Color::Green),
+                ),
+                Span::raw(binding.description),
+            ]))
+        })
+        .collect();
+    List::new(items)
+        .style(Style::default().fg(Color::LightCyan))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::White))
+                .title(format!("> {}", input))
                 .border_type(BorderType::Plain),
         )
 }
 
-/// make a list view of the FAT table
-fn make_fat_table_view<'a>(fat_table: &'a Vec<(u16, u16)>) -> List<'a> {
+/// make the frame of a pane, with its border highlighted and its title bolded when it has the
+/// focus
+fn make_pane_frame(title: String, focused: bool) -> Block<'static> {
+    let (border_style, title_style) = match focused {
+        true => (
+            Style::default().fg(Color::Yellow),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        false => (Style::default(), Style::default()),
+    };
+    Block::default()
+        .borders(Borders::ALL)
+        .style(Style::default().fg(Color::White))
+        .border_style(border_style)
+        .title(Span::styled(title, title_style))
+        .border_type(BorderType::Plain)
+}
+
+/// make the scrollbar of the FAT list, with the part of the list in `window` filled in and the
+/// marked blocks and the blocks with problems at their places. Their places come from the
+/// list's index, so drawing it doesn't go through the list
+fn make_fat_minimap<'a>(theme: &'a Theme, app: &App, window: Range<usize>) -> Minimap<'a> {
+    let index = &app.fat_view.index;
+    let marks = app
+        .marks
+        .blocks()
+        .iter()
+        .filter_map(|block| index.get(block))
+        .map(|row| (*row, theme.mark, Color::Cyan));
+    // the problems go last, so they win a cell they share with a mark
+    let problems = app
+        .problems
+        .iter()
+        .filter_map(|problem| index.get(&problem.block()?))
+        .map(|row| (*row, theme.warning, Color::Red));
+    Minimap {
+        len: app.fat_list_len(),
+        window,
+        thumb: theme.thumb,
+        markers: marks.chain(problems).collect(),
+    }
+}
+
+/// make a list view of the FAT table, highlighting the entries that changed, with a mark in the
+/// gutter of the marked blocks (struck through if they've been freed) and of blocks with notes.
+/// Entries holding the profile's reserved value are labelled as such rather than shown as a link,
+/// and entries that just changed say what they held before, under `title`. Only the entries in `window` are
+/// made, so a FAT of tens of thousands of entries costs no more to draw than a small one
+#[allow(clippy::too_many_arguments)]
+fn make_fat_table_view<'a>(
+    theme: &Theme,
+    fat_view: &'a FatView,
+    marks: &Marks,
+    session: &Session,
+    sort: SortMode,
+    title: String,
+    focused: bool,
+    window: Range<usize>,
+) -> List<'a> {
     // display the FAT table on the left. This is a list of all the occupied blocks,
     // and the block they point to, if any. Convert to ListItem
+    let fat_table = &fat_view.table;
     let list_items = fat_table
+        .get(window.start.min(fat_table.len())..window.end.min(fat_table.len()))
+        .unwrap_or_default()
         .iter()
         .map(|(block_num, next_block)| {
-            let block_num = format!("{:04x}", block_num);
-            let next_block = format!("{:04x}", next_block);
+            let entry = fat_view.profile.classify(*next_block);
+            let style = if marks.contains(*block_num) && entry == Entry::Free {
+                Style::default()
+                    .fg(Color::DarkGray)
+                    .add_modifier(Modifier::CROSSED_OUT)
+            } else if fat_view.changed.contains(block_num) {
+                Style::default().fg(Color::Magenta)
+            } else {
+                Style::default()
+            };
+            let mark = match marks.contains(*block_num) {
+                true => Span::styled(theme.mark, Style::default().fg(Color::Cyan)),
+                false => Span::raw(" "),
+            };
+            let note = match session.note(*block_num) {
+                Some(_) => Span::styled(theme.note, Style::default().fg(Color::Yellow)),
+                None => Span::raw(" "),
+            };
+            let next_block = match entry {
+                Entry::Reserved => Span::styled(
+                    format!("{:>1$}", "rsvd", fat_view.blocks.entry_width()),
+                    Style::default().fg(Color::Yellow),
+                ),
+                _ => Span::raw(format!(
+                    "{:>1$}",
+                    fat_view.blocks.block(*next_block),
+                    fat_view.blocks.entry_width()
+                )),
+            };
+            let previous = match fat_view.previous.get(block_num) {
+                Some(previous) => Span::styled(
+                    format!(" (was {})", fat_view.blocks.block(*previous)),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                None => Span::raw(""),
+            };
+            let chain = match session.fat_columns {
+                FatColumns::Plain => Span::raw(""),
+                FatColumns::Chain => make_chain_column(&fat_view.ownership, *block_num),
+            };
+            // pfview only sees the allocations made while it runs
+            let age = match (sort, fat_view.allocated_in.get(block_num)) {
+                (SortMode::Age, Some(generation)) => Span::styled(
+                    format!(" reload {}", generation),
+                    Style::default().fg(match *generation == fat_view.generation {
+                        true => Color::Green,
+                        false => Color::Cyan,
+                    }),
+                ),
+                (SortMode::Age, None) => {
+                    Span::styled(" unknown age", Style::default().fg(Color::DarkGray))
+                }
+                _ => Span::raw(""),
+            };
             tui::widgets::ListItem::new(Spans::from(vec![
-                Span::raw(block_num),
+                mark,
+                note,
+                Span::raw(format!(
+                    "{:>1$}",
+                    fat_view.blocks.block(*block_num),
+                    fat_view.blocks.block_width()
+                )),
                 Span::raw(" -> "),
-                Span::raw(next_block),
+                next_block,
+                chain,
+                age,
+                previous,
             ]))
+            .style(style)
         })
         .collect::<Vec<_>>();
+    // the bytes past the last data block have no block number, so they're listed after them
+    let list_items = match fat_view.unaddressed && window.end > fat_table.len() {
+        false => list_items,
+        true => list_items
+            .into_iter()
+            .chain([tui::widgets::ListItem::new("  unaddressed")
+                .style(Style::default().fg(Color::Yellow))])
+            .collect(),
+    };
 
-    let fat_table_block = Block::default()
-        .borders(Borders::ALL)
-        .style(Style::default().fg(Color::White))
-        .title("Fat Table")
-        .border_type(BorderType::Plain);
+    let fat_table_block = make_pane_frame(title, focused);
 
     List::new(list_items)
         .block(fat_table_block)
@@ -129,196 +793,2256 @@ fn make_fat_table_view<'a>(fat_table: &'a Vec<(u16, u16)>) -> List<'a> {
         )
 }
 
-fn main() -> Result<()> {
-    // accept one command line argument
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!(
-            "{}",
+/// make the title of the FAT list: its order, then the filter and how many entries it lets
+/// through (or how many there are), and how many blocks are marked
+fn make_fat_title(app: &App) -> Title {
+    let count = app.fat_view.table.len();
+    let shown = match &app.filter {
+        Some(filter) => format!("{} ({})", filter.show(app.fat_view.blocks), count),
+        None => format!("{} allocated", count),
+    };
+    let marked = match app.marks.len() {
+        0 => String::new(),
+        n => format!("{} marked", n),
+    };
+    Title::new(app.sort.title()).part(2, shown).part(3, marked)
+}
+
+/// make the chain column of a block in the FAT list: the length and extent count of the chain
+/// for the first block of a file, and the index in it for the others. Blocks of broken chains
+/// get a '?', as their chain can't be measured, and orphan blocks get nothing
+fn make_chain_column(ownership: &Ownership, block_num: u16) -> Span<'static> {
+    let (Some(owner), Some(shape)) = (ownership.owner(block_num), ownership.chain_shape(block_num))
+    else {
+        return Span::raw(format!("{:16}", ""));
+    };
+    if shape.broken {
+        return Span::styled(format!("{:>16}", "?"), Style::default().fg(Color::Yellow));
+    }
+    match owner.index {
+        0 => Span::raw(format!(" {:>4} blk {:>2} ext", shape.length, shape.extents)),
+        index => Span::styled(
+            format!("{:>16}", format!("#{}", index)),
+            Style::default().fg(Color::DarkGray),
+        ),
+    }
+}
+
+/// make the list of recently modified files, most recent first. Files that no longer exist
+/// are greyed out. Files changed in the latest reload say how the FAT entries of their chains
+/// changed. The list goes under `title`
+fn make_recent_view(
+    fs: &PennFat,
+    recent: &RecentFiles,
+    blame: &Blame,
+    title: String,
+    focused: bool,
+) -> List<'static> {
+    let items = recent
+        .changes()
+        .iter()
+        .map(|change| {
+            let time = DateTime::<Utc>::from(change.time).format("%H:%M:%S");
+            let style = match recent.exists(&change.path) {
+                true => Style::default(),
+                false => Style::default().fg(Color::DarkGray),
+            };
+            let entries = match change.generation == blame.generation() {
+                true => blame.chain_changes(fs, change.first_block),
+                false => Vec::new(),
+            };
+            let entries = match entries.as_slice() {
+                [] => String::new(),
+                [entry] => format!("  {}", entry.describe(fs.profile(), fs.block_format())),
+                [entry, rest @ ..] => {
+                    format!(
+                        "  {} (+{} more)",
+                        entry.describe(fs.profile(), fs.block_format()),
+                        rest.len()
+                    )
+                }
+            };
+            tui::widgets::ListItem::new(format!(
+                "{} {:<8} {}{}",
+                time,
+                change.kind.describe(),
+                change.path,
+                entries
+            ))
+            .style(style)
+        })
+        .collect::<Vec<_>>();
+    let items = match items.is_empty() {
+        true => vec![tui::widgets::ListItem::new("no changes yet")
+            .style(Style::default().fg(Color::DarkGray))],
+        false => items,
+    };
+    List::new(items)
+        .block(make_pane_frame(title, focused))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+/// make the header at the top of the block pane: the selected block's FAT entry decoded, where
+/// that entry is in the image file, the file the block belongs to and what kind of block it is.
+/// It all comes from the FAT view, so it's the same whichever way the block is shown. Under
+/// it, the entries of the blocks around it, to see how the allocator placed it
+fn make_block_header(
+    fat_view: &FatView,
+    block_num: u16,
+    next: u16,
+    neighbors: &[(u16, Option<u16>)],
+) -> Paragraph<'static> {
+    let separator = || Span::styled(" | ", Style::default().fg(Color::DarkGray));
+    let entry = fat_view.profile.classify(next);
+    let decoded = match entry {
+        Entry::Free => Span::styled("FREE", Style::default().fg(Color::Red)),
+        Entry::Eof => Span::raw("EOF"),
+        Entry::Reserved => Span::styled("RESERVED", Style::default().fg(Color::Yellow)),
+        Entry::Next(next) => Span::raw(format!("next: {}", fat_view.blocks.block(next))),
+    };
+    let owner = fat_view.ownership.owner(block_num);
+    let owned_by = match owner {
+        Some(owner) => {
+            // a broken chain's length can't be trusted
+            let length = match fat_view.ownership.chain_shape(block_num) {
+                Some(shape) if !shape.broken => format!(" of {}", shape.length),
+                _ => String::new(),
+            };
+            Span::styled(
+                format!("{} (block {}{})", owner.path, owner.index + 1, length),
+                Style::default().fg(Color::Green),
+            )
+        }
+        None => Span::styled("no owner", Style::default().fg(Color::DarkGray)),
+    };
+    let class = match (owner, entry) {
+        // a marked block that was freed
+        (_, Entry::Free) => Span::styled("freed", Style::default().fg(Color::DarkGray)),
+        (Some(owner), _) if owner.is_dir => Span::raw("directory"),
+        (Some(_), _) => Span::raw("file data"),
+        (None, _) => Span::styled("orphan", Style::default().fg(Color::Yellow)),
+    };
+    let mut strip = vec![Span::styled(
+        "around: ",
+        Style::default().fg(Color::DarkGray),
+    )];
+    for (i, (block, entry)) in neighbors.iter().enumerate() {
+        if i > 0 {
+            strip.push(Span::raw(" "));
+        }
+        let (value, style) = match entry.map(|entry| fat_view.profile.classify(entry)) {
+            None | Some(Entry::Free) => ("free".to_owned(), Style::default().fg(Color::DarkGray)),
+            Some(Entry::Eof) => ("EOF".to_owned(), Style::default()),
+            Some(Entry::Reserved) => ("RESERVED".to_owned(), Style::default().fg(Color::Yellow)),
+            Some(Entry::Next(next)) => (fat_view.blocks.block(next), Style::default()),
+        };
+        let text = format!("{}:{}", fat_view.blocks.block(*block), value);
+        strip.push(match *block == block_num {
+            true => Span::styled(
+                format!("[{}]", text),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            false => Span::styled(text, style),
+        });
+    }
+    Paragraph::new(vec![
+        Spans::from(vec![
+            decoded,
+            separator(),
+            Span::raw(format!(
+                "FAT entry at {}",
+                format_offset(block_num as u64 * 2)
+            )),
+            separator(),
+            owned_by,
+            separator(),
+            class,
+        ]),
+        Spans::from(strip),
+    ])
+}
+
+/// make the breadcrumb shown above the block pane: the path of the file the block belongs to,
+/// and the chain it's part of, with the current block highlighted. When the chain doesn't fit
+/// in `width`, the blocks far from the current one are elided
+fn make_breadcrumb(
+    theme: &Theme,
+    path: Option<&str>,
+    chain: &Chain,
+    current: u16,
+    width: u16,
+) -> Paragraph<'static> {
+    let arrow = || {
+        Span::styled(
+            format!(" {} ", theme.arrow),
+            Style::default().fg(Color::DarkGray),
+        )
+    };
+    let end = match chain.end {
+        ChainEnd::Eof => "EOF".to_owned(),
+        ChainEnd::Free => "FREE (broken chain)".to_owned(),
+        ChainEnd::Reserved => "RESERVED".to_owned(),
+        ChainEnd::OutOfRange(block) => format!("{} (out of range)", chain.format.block(block)),
+        ChainEnd::Cycle(block) => format!("{} (cycle)", chain.format.block(block)),
+        ChainEnd::TooLong(limit) => format!("... (longer than {} blocks)", limit),
+    };
+    let blocks = &chain.blocks;
+    let position = blocks.iter().position(|b| *b == current).unwrap_or(0);
+
+    // each block takes at most "0x0000 → ", each elision "… → ", and the current block's brackets 2
+    let arrow_width = theme.arrow.chars().count() + 2;
+    let path_width = path.map_or(0, |p| p.chars().count() + 2);
+    let room = (width as usize).saturating_sub(path_width + end.chars().count() + 2);
+
+    // show the first and last blocks and as many as fit around the current one,
+    // with "…" standing in for each run of hidden blocks
+    let shown = |radius: usize| {
+        (0..blocks.len())
+            .filter(|i| *i == 0 || *i + 1 == blocks.len() || i.abs_diff(position) <= radius)
+            .collect::<Vec<_>>()
+    };
+    let needed = |shown: &Vec<usize>| {
+        let gaps = shown.windows(2).filter(|w| w[1] != w[0] + 1).count();
+        shown.len() * (chain.format.block_width() + arrow_width)
+            + gaps * (theme.ellipsis.chars().count() + arrow_width)
+    };
+    let mut radius = 0;
+    while radius < blocks.len() && needed(&shown(radius + 1)) <= room {
+        radius += 1;
+    }
+
+    let mut spans = Vec::new();
+    if let Some(path) = path {
+        spans.push(Span::styled(
+            format!("{}: ", path),
+            Style::default().fg(Color::Green),
+        ));
+    }
+    let mut last_shown = None;
+    for i in shown(radius) {
+        if last_shown.is_some_and(|last| last + 1 != i) {
+            spans.push(Span::raw(theme.ellipsis));
+            spans.push(arrow());
+        }
+        if i == position {
+            spans.push(Span::styled(
+                format!("[{}]", chain.format.block(blocks[i])),
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ));
+        } else {
+            spans.push(Span::raw(chain.format.block(blocks[i])));
+        }
+        spans.push(arrow());
+        last_shown = Some(i);
+    }
+    let end_style = match chain.end {
+        ChainEnd::Eof => Style::default().fg(Color::DarkGray),
+        ChainEnd::Reserved => Style::default().fg(Color::Yellow),
+        _ => Style::default().fg(Color::Red),
+    };
+    spans.push(Span::styled(end, end_style));
+
+    Paragraph::new(Spans::from(spans))
+}
+
+/// width of the offset gutter in the raw view, including the separating space
+const RAW_GUTTER_WIDTH: u16 = 5;
+
+/// The fewest columns a dentry's name is cut down to, however narrow the block pane
+const MIN_NAME_WIDTH: usize = 12;
+
+/// get how many bytes a line of the raw view holds, for a block pane of the given width
+/// showing bytes in the given encoding
+fn raw_row_len(pane_width: u16, encoding: Encoding) -> usize {
+    // the borders take one column on each side
+    let usable = pane_width.saturating_sub(2 + RAW_GUTTER_WIDTH) as usize;
+    // a hex dump takes 3 columns per byte and one for its character, after a space
+    let bytes = match encoding {
+        Encoding::Binary => usable.saturating_sub(1) / 4,
+        text => usable * text.unit(),
+    };
+    // keep rows a multiple of 8 bytes when there's room, so offsets stay easy to read
+    if bytes >= 8 {
+        bytes - bytes % 8
+    } else {
+        bytes.max(encoding.unit())
+    }
+}
+
+/// scroll a line of the raw view across by `column` columns, keeping its gutter in place. A
+/// wide character cut in half leaves a space
+fn scroll_raw_line(line: Spans<'_>, column: usize) -> Spans<'_> {
+    let mut spans = line.0.into_iter();
+    let mut scrolled: Vec<Span> = spans.next().into_iter().collect();
+    let mut skip = column;
+    for span in spans {
+        let mut content = String::new();
+        for c in span.content.chars() {
+            let width = c.width().unwrap_or(0);
+            match skip {
+                0 => content.push(c),
+                _ if width > skip => {
+                    content.push_str(&" ".repeat(width - skip));
+                    skip = 0;
+                }
+                _ => skip -= width,
+            }
+        }
+        scrolled.push(Span::styled(content, span.style));
+    }
+    Spans::from(scrolled)
+}
+
+/// make the dimmed gutter of a line of the raw view, showing the offset of its first byte
+fn make_raw_gutter(offset: usize) -> Span<'static> {
+    Span::styled(
+        format!("{:04x} ", offset),
+        Style::default()
+            .fg(Color::DarkGray)
+            .add_modifier(Modifier::DIM),
+    )
+}
+
+/// make a row of a hex dump, laid out as [`encoding::rows`] lays it out, with each byte (both
+/// in hex and as a character) drawn in the style of the annotation covering it
+fn make_annotated_hex_row(
+    data: &[u8],
+    offset: usize,
+    row_len: usize,
+    annotations: &[Annotation],
+    style: Style,
+) -> Spans<'static> {
+    let chunk = data
+        .get(offset..(offset + row_len).min(data.len()))
+        .unwrap_or_default();
+    let style_at = |i: usize| annotate::style_at(annotations, offset + i).unwrap_or(style);
+    let mut spans = vec![make_raw_gutter(offset)];
+    for (i, byte) in chunk.iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::styled(" ", style));
+        }
+        spans.push(Span::styled(format!("{:02x}", byte), style_at(i)));
+    }
+    // short rows are padded, so the text lines up
+    let padding = row_len.saturating_sub(chunk.len()) * 3 + 1;
+    spans.push(Span::styled(" ".repeat(padding), style));
+    for (i, byte) in chunk.iter().enumerate() {
+        let c = match byte {
+            0x20..=0x7E => *byte as char,
+            _ => '.',
+        };
+        spans.push(Span::styled(c.to_string(), style_at(i)));
+    }
+    Spans::from(spans)
+}
+
+/// make the line naming what each color of an annotated hex dump stands for
+fn make_legend(annotations: &[Annotation]) -> Spans<'static> {
+    let mut spans = vec![Span::styled(
+        "fields: ",
+        Style::default().fg(Color::DarkGray),
+    )];
+    for (label, style) in annotate::legend(annotations) {
+        spans.push(Span::styled(label, style));
+        spans.push(Span::raw(" "));
+    }
+    spans.push(Span::styled(
+        "(A to hide)",
+        Style::default().fg(Color::DarkGray),
+    ));
+    Spans::from(spans)
+}
+
+/// make the lines of the raw view of a block, for a block pane of the given width, decoding
+/// text in `encoding` (or dumping it in hex, for binary) and drawing its control characters as
+/// `controls` says.
+/// Each line holds a fixed number of bytes (derived from the pane width), prefixed with
+/// a dimmed gutter showing the offset of the line's first byte within the block. Text with its
+/// control characters shown runs to the next line feed, however wide, unless it `wrap`s. If a file
+/// ends `eof` bytes into the block, the slack after that is struck through, below a marker.
+/// A hex dump draws the bytes `annotations` cover in their styles, under a legend
+fn make_raw_lines(
+    block: &pennfat::Block,
+    pane_width: u16,
+    eof: Option<(usize, u32)>,
+    encoding: Encoding,
+    controls: Controls,
+    annotations: &[Annotation],
+    wrap: bool,
+) -> Vec<Spans<'static>> {
+    let row_len = match (controls, encoding) {
+        (Controls::Visible { .. }, text) if !wrap && text != Encoding::Binary => usize::MAX,
+        _ => raw_row_len(pane_width, encoding),
+    };
+    let annotated = encoding == Encoding::Binary && !annotations.is_empty();
+    let rows = |range: Range<usize>, style| {
+        encoding::rows(&block.data, range, row_len, encoding, controls)
+            .into_iter()
+            .map(move |(offset, row)| match annotated {
+                true => make_annotated_hex_row(&block.data, offset, row_len, annotations, style),
+                false => Spans::from(vec![make_raw_gutter(offset), Span::styled(row, style)]),
+            })
+    };
+    let Some((eof, size)) = eof else {
+        let legend = annotated.then(|| make_legend(annotations));
+        return legend
+            .into_iter()
+            .chain(rows(0..block.data.len(), Style::default()))
+            .collect();
+    };
+    // a size that disagrees with the chain can put the end of file past the block
+    let eof = eof.min(block.data.len());
+    let marker = Spans::from(Span::styled(
+        format!("---- end of file (size {}), slack below ----", size),
+        Style::default().fg(Color::Yellow),
+    ));
+    let slack = Style::default()
+        .fg(Color::DarkGray)
+        .add_modifier(Modifier::CROSSED_OUT);
+    rows(0..eof, Style::default())
+        .chain([marker])
+        .chain(rows(eof..block.data.len(), slack))
+        .collect()
+}
+
+/// how many blocks' worth of the region past the last data block are dumped, at most
+const MAX_UNADDRESSED_CHUNKS: usize = 64;
+
+/// make the lines of the hex dump of the region of the image past the last data block, which no
+/// block number can address. It's dumped a block's worth at a time, each under a marker saying
+/// where in the image file it starts
+fn make_unaddressed_lines(fs: &PennFat, pane_width: u16) -> Vec<Spans<'static>> {
+    let start = fs.unaddressed_offset();
+    let block_size = fs.block_size() as usize;
+    let chunks = fs.unaddressed().chunks(block_size);
+    let more = chunks.len().saturating_sub(MAX_UNADDRESSED_CHUNKS);
+    let mut lines = Vec::new();
+    for (i, chunk) in chunks.take(MAX_UNADDRESSED_CHUNKS).enumerate() {
+        lines.push(Spans::from(Span::styled(
             format!(
-                "pfview {} - TUI PennFat viewer\nby {}",
-                env!("CARGO_PKG_VERSION"),
-                env!("CARGO_PKG_AUTHORS")
+                "---- {} bytes at {} ----",
+                chunk.len(),
+                format_offset(start + (i * block_size) as u64)
+            ),
+            Style::default().fg(Color::Yellow),
+        )));
+        lines.extend(make_raw_lines(
+            &pennfat::Block::from(chunk),
+            pane_width,
+            None,
+            Encoding::Binary,
+            Controls::Plain,
+            &[],
+            true,
+        ));
+    }
+    if more > 0 {
+        lines.push(Spans::from(Span::styled(
+            format!("---- {} more blocks' worth not shown ----", more),
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    lines
+}
+
+/// make the lines of the raw view of a block diffed against its previous contents: a summary
+/// of what changed, then the bytes, with the changed ones highlighted and the rest dimmed
+fn make_diff_lines(
+    block: &pennfat::Block,
+    previous: &[u8],
+    pane_width: u16,
+) -> Vec<Spans<'static>> {
+    let ranges = diff::changed_ranges(previous, &block.data);
+    let changed: usize = ranges.iter().map(|range| range.len()).sum();
+    let summary = format!(
+        "{} byte{} changed in {} range{}",
+        changed,
+        if changed == 1 { "" } else { "s" },
+        ranges.len(),
+        if ranges.len() == 1 { "" } else { "s" }
+    );
+    let mut lines = vec![Spans::from(Span::styled(
+        summary,
+        Style::default().fg(Color::Yellow),
+    ))];
+
+    let unchanged = Style::default()
+        .fg(Color::DarkGray)
+        .add_modifier(Modifier::DIM);
+    let highlighted = Style::default().fg(Color::Black).bg(Color::Yellow);
+    let row_len = raw_row_len(pane_width, Encoding::Utf8);
+    for (offset, row) in block.as_raw_rows(0..block.data.len(), row_len) {
+        let mut spans = vec![make_raw_gutter(offset)];
+        // consecutive bytes that are both changed or both not share a span
+        let mut run = String::new();
+        let mut run_changed = false;
+        for (i, c) in row.chars().enumerate() {
+            let is_changed = ranges.iter().any(|range| range.contains(&(offset + i)));
+            if is_changed != run_changed && !run.is_empty() {
+                let style = if run_changed { highlighted } else { unchanged };
+                spans.push(Span::styled(std::mem::take(&mut run), style));
+            }
+            run_changed = is_changed;
+            run.push(c);
+        }
+        let style = if run_changed { highlighted } else { unchanged };
+        spans.push(Span::styled(run, style));
+        lines.push(Spans::from(spans));
+    }
+    lines
+}
+
+/// describe a block of the directory `owner` from the usage of its dentry slots, e.g.
+/// "root dir — 5 entries used / 4 free slots (block 1 of 2 in chain)". With `packed`, warn about
+/// entries that come after a free slot
+fn describe_directory_block(
+    theme: &Theme,
+    owner: &Owner,
+    chain_len: usize,
+    usage: &DentryUsage,
+    packed: bool,
+) -> String {
+    let name = match owner.path.as_str() {
+        "/" => "root dir".to_owned(),
+        path => format!("dir {}", path),
+    };
+    let mut description = format!(
+        "{} {} {} entries used / {} free slots",
+        name, theme.dash, usage.used, usage.free
+    );
+    if usage.deleted > 0 {
+        description += &format!(" / {} deleted", usage.deleted);
+    }
+    description += &format!(" (block {} of {} in chain)", owner.index + 1, chain_len);
+    if packed && usage.unpacked {
+        description += &format!(" {} entry after a free slot", theme.warning);
+    }
+    description
+}
+
+/// describe a dentry of the directory at `dir_path` for the block pane, in a row `width` columns
+/// wide. Directories show their total size from `dir_sizes` (physical or logical), and symbolic
+/// links show where they point, and what's there. A name too long for the row has its middle cut
+/// out, and comes back whole as well
+fn describe_dentry(
+    fs: &PennFat,
+    theme: &Theme,
+    dir_path: &str,
+    dentry: &Dentry,
+    dir_sizes: &HashMap<u16, DirSize>,
+    physical: bool,
+    width: usize,
+) -> (String, Option<String>) {
+    if !dentry.is_in_use() {
+        return (dentry.show(fs.block_format()).to_string(), None);
+    }
+    let name = match dentry {
+        // directories show the total size of everything beneath them
+        _ if dentry.is_dir() => match dir_sizes.get(&dentry.first_block) {
+            Some(size) => format!(
+                "{} ({})",
+                dentry.display_name(),
+                size.describe(fs.block_size(), physical)
+            ),
+            None => dentry.display_name(),
+        },
+        _ if dentry.is_symlink() => {
+            let (target, lookup) = walk::resolve_link(fs, dir_path, dentry);
+            format!(
+                "{} -> {} ({})",
+                dentry.display_name(),
+                target,
+                lookup.describe()
             )
-            .bright_black()
-        );
-        // print usage in color and exit
-        println!("{}", format!("Usage: {} <filename>", args[0]));
-        exit(1);
+        }
+        _ => dentry.display_name(),
+    };
+    let perms = fs.dentry_layout().perms;
+    // the other fields keep their room; the name gets the rest, but never too little to read
+    let room = width
+        .saturating_sub(dentry.format_as("", perms, fs.block_format()).width())
+        .max(MIN_NAME_WIDTH);
+    let short = theme.truncate_middle(&name, room);
+    let row = dentry.format_as(&short, perms, fs.block_format());
+    match short == name {
+        true => (row, None),
+        false => (row, Some(name)),
     }
+}
 
-    let (tx, rx) = mpsc::channel();
-    // how often do we want to reload the file and redraw (when there are no events)?
-    // Note that decreasing this value will cause CPU usage, but probably not more than
-    // 2-3% (of one core). At 700ms, it's at 0.5-0.7%% on my machine.
-    let tick_rate = Duration::from_millis(700);
-    thread::spawn(move || {
-        let mut last_tick = Instant::now();
+/// open `contents` in the user's pager (or editor) as a temporary file named after `name`,
+/// handing the terminal over to it until it exits. The file is deleted afterwards
+fn open_externally(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    rx: &mpsc::Receiver<Event<KeyEvent>>,
+    input_paused: &AtomicBool,
+    focus_changes: bool,
+    name: &str,
+    contents: &[u8],
+) -> Result<()> {
+    let program = env::var("PAGER")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "less".to_owned());
+    let mut words = program.split_whitespace();
+    let Some(command) = words.next() else {
+        bail!("$PAGER is empty");
+    };
+    // keep the file's name (made safe) so the program can tell what kind of file it is
+    let mut file = tempfile::Builder::new()
+        .prefix("pfview-")
+        .suffix(&format!("-{}", walk::host_name(name)))
+        .tempfile()?;
+    file.write_all(contents)?;
+    file.flush()?;
+
+    let paused = PausedInput::pause(input_paused, rx)?;
+    disable_raw_mode()?;
+    terminal.show_cursor()?;
+    if focus_changes {
+        execute!(io::stdout(), DisableFocusChange)?;
+    }
+    let status = process::Command::new(command)
+        .args(words)
+        .arg(file.path())
+        .status();
+    // take the terminal back whatever the program did to it
+    let restored = enable_raw_mode()
+        .and_then(|_| terminal.hide_cursor())
+        .and_then(|_| terminal.clear())
+        .and_then(|_| match focus_changes {
+            true => execute!(io::stdout(), EnableFocusChange),
+            false => Ok(()),
+        });
+    drop(paused);
+    restored?;
+
+    match status {
+        Err(e) => bail!("launching {}: {}", command, e),
+        Ok(status) if !status.success() => bail!("{} exited with {}", command, status),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// the input thread kept from reading the terminal, for as long as it's kept, so it reads it
+/// again however running another program in the terminal ends
+struct PausedInput<'a>(&'a AtomicBool);
+
+impl<'a> PausedInput<'a> {
+    /// stop the input thread reading the terminal, and wait for it to confirm it has
+    fn pause(input_paused: &'a AtomicBool, rx: &mpsc::Receiver<Event<KeyEvent>>) -> Result<Self> {
+        input_paused.store(true, Ordering::SeqCst);
+        let paused = PausedInput(input_paused);
         loop {
-            let timeout = tick_rate
-                .checked_sub(last_tick.elapsed())
-                .unwrap_or_else(|| Duration::from_secs(0));
+            match rx.recv()? {
+                Event::Paused => return Ok(paused),
+                Event::InputError(e) => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+}
 
-            if event::poll(timeout).expect("poll works") {
-                if let CEvent::Key(key) = event::read().expect("can read events") {
-                    tx.send(Event::Input(key)).expect("can send events");
+impl Drop for PausedInput<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// has the terminal report when it gains and loses the focus, for as long as it's kept, so
+/// the reports stop however the viewer exits
+struct FocusChanges(bool);
+
+impl FocusChanges {
+    fn enable(enabled: bool) -> Self {
+        if enabled {
+            let _ = execute!(io::stdout(), EnableFocusChange);
+        }
+        FocusChanges(enabled)
+    }
+}
+
+impl Drop for FocusChanges {
+    fn drop(&mut self) {
+        if self.0 {
+            let _ = execute!(io::stdout(), DisableFocusChange);
+        }
+    }
+}
+
+/// the sample image the demo views, written out for as long as it's kept, so it's removed
+/// however the viewer exits
+struct DemoImage(PathBuf);
+
+impl DemoImage {
+    fn write(path: &str) -> io::Result<Self> {
+        fs::write(path, fuzz::sample())?;
+        Ok(DemoImage(PathBuf::from(path)))
+    }
+}
+
+impl Drop for DemoImage {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// make the box with the demo's hint on what's in view
+fn make_hint(topic: Topic) -> Paragraph<'static> {
+    Paragraph::new(topic.text())
+        .style(Style::default().fg(Color::White))
+        .wrap(Wrap { trim: true })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::Yellow))
+                .title("hint (esc to dismiss)")
+                .border_type(BorderType::Plain),
+        )
+}
+
+/// draw the viewer off-screen, at the terminal's size, and save it as text. Returns where it was
+/// saved
+fn save_screenshot(app: &mut App, theme: &Theme, size: Rect) -> Result<PathBuf> {
+    let mut terminal = Terminal::new(TestBackend::new(size.width, size.height))?;
+    terminal.draw(|rect| draw(rect, app, theme))?;
+    let text = screenshot::to_text(terminal.backend().buffer(), app.options.ansi_screenshots);
+    let path = screenshot::path(app.fs.path(), app.options.snapshot_dir.as_deref());
+    fs::write(&path, text)?;
+    Ok(path)
+}
+
+/// give up on the viewer after the terminal stopped working (e.g. the SSH session dropped), putting it
+/// back as well as can be done (it may well be gone)
+fn terminal_lost<B: Backend>(terminal: &mut Terminal<B>, error: io::Error) -> Result<()> {
+    let _ = disable_raw_mode();
+    let _ = terminal.show_cursor();
+    bail!("lost the terminal: {}", error)
+}
+
+/// draw the viewer
+fn draw<B: Backend>(rect: &mut Frame<B>, app: &mut App, theme: &Theme) {
+    let view = app.timeline.current().unwrap_or(&app.fs);
+    let fat_table = &app.fat_view.table;
+    let selected = app.list_state.selected().unwrap_or(0);
+    let is_dir_block = app.is_dir_block();
+    let raw_mode = app.raw_mode();
+    let alert = app.alert();
+    // both are kept by `prepare`; a frame drawn without it just shows nothing's known yet
+    let unknown = (HashMap::new(), HashMap::new());
+    let dir_sizes = app
+        .dir_sizes_cache
+        .as_ref()
+        .map_or(&unknown.0, |(_, dir_sizes)| dir_sizes);
+    let detected = app
+        .detected_cache
+        .as_ref()
+        .map_or(&unknown.1, |(_, detected)| detected);
+    // the block number is always in the title; the rest goes, least telling first, when the
+    // block pane is too narrow for it
+    let block_title = match fat_table.get(selected) {
+        Some((block_num, _)) => {
+            let note = app
+                .session
+                .note(*block_num)
+                .map_or(String::new(), |note| format!("\"{}\"", note));
+            // the order of the dentries
+            let order = match (raw_mode, app.dentry_sort) {
+                (false, sort) if sort != DentrySort::Slot => sort.describe().to_owned(),
+                _ => String::new(),
+            };
+            let owner = app.fat_view.ownership.owner(*block_num);
+            let class = match (owner, &app.selected_block) {
+                (Some(owner), Some(Ok(block))) if owner.is_dir => describe_directory_block(
+                    theme,
+                    owner,
+                    view.chain(owner.first_block).blocks.len(),
+                    &block.dentry_usage(view.dentry_layout()),
+                    view.dentry_layout().packed_dirs,
+                ),
+                _ if is_dir_block => "directory".to_owned(),
+                _ => "data".to_owned(),
+            };
+            // files sharing a chain each own it as much as the others, so they're all named
+            let shared = match owner.map(|owner| app.fat_view.ownership.paths(owner.first_block)) {
+                Some(paths) if paths.len() > 1 => format!("shared by {}", paths.join(", ")),
+                _ => String::new(),
+            };
+            // how the raw view reads the block, unless it's diffed byte by byte
+            let encoding = match &app.selected_block {
+                Some(Ok(block))
+                    if raw_mode && !(app.show_diff && app.previous_contents().is_some()) =>
+                {
+                    let forced = match app.encoding {
+                        Some(_) => " (forced)",
+                        None => "",
+                    };
+                    format!("{}{}", app.encoding(block).name(), forced)
                 }
+                _ => String::new(),
+            };
+            // where the block is in the image file, to line it up with a hex dump
+            let offset = match view.block_offset(*block_num) {
+                Ok(offset) => format!(
+                    "offset {}, length {}",
+                    format_offset(offset as u64),
+                    view.block_size()
+                ),
+                Err(_) => String::new(),
+            };
+            // blame is only tracked for the live image
+            let blame = match app.timeline.current() {
+                None => app.blame.describe(*block_num),
+                Some(_) => String::new(),
+            };
+            Title::new(format!("block {}", view.format_block(*block_num)))
+                .joined(" ", 4, note)
+                .joined(" ", 2, order)
+                .part(5, class)
+                .joined(", ", 2, shared)
+                .joined(", ", 3, encoding)
+                .joined(", ", 1, offset)
+                .part(3, blame)
+        }
+        None if app.unaddressed_selected() => Title::new("unaddressed region").joined(
+            " ",
+            2,
+            format!(
+                "({} bytes at {}, past block {})",
+                view.unaddressed().len(),
+                format_offset(view.unaddressed_offset()),
+                view.format_block(view.data_block_count())
+            ),
+        ),
+        None => Title::new("block"),
+    };
+    // with the FAT list hidden, the title is all that says which block this is
+    let block_title = match app.zoomed {
+        true => block_title.part(6, "zoomed (Z to restore)"),
+        false => block_title,
+    };
+    // symbolic links in the block resolve relative to the directory it belongs to
+    let dir_path = fat_table
+        .get(selected)
+        .and_then(|(block_num, _)| app.fat_view.ownership.owner(*block_num))
+        .filter(|owner| owner.is_dir)
+        .map_or("/", |owner| owner.path.as_str());
+    let breadcrumb = fat_table
+        .get(selected)
+        .map(|(block_num, _)| (*block_num, app.fat_view.chain_of(view, *block_num)));
+
+    let size = rect.size();
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Length(4),
+                Constraint::Min(2),
+                Constraint::Length(1),
+                Constraint::Length(4),
+            ]
+            .as_ref(),
+        )
+        .split(size);
+
+    let body_rect = chunks[1];
+    rect.render_widget(
+        make_overview(
+            view,
+            theme,
+            app.timeline.describe(),
+            app.root_warning.as_deref(),
+            alert,
+            // who writes to a snapshot doesn't matter
+            app.writers
+                .as_deref()
+                .filter(|_| app.timeline.current().is_none()),
+            app.checked.then_some(app.problems.as_slice()),
+            // only the live image goes idle; a snapshot never changes
+            app.timeline.current().is_none().then(|| {
+                make_idle_counter(
+                    app.last_change.elapsed(),
+                    app.options.idle_warn,
+                    app.options.idle_alert,
+                )
+            }),
+            chunks[0].width,
+        ),
+        chunks[0],
+    );
+    rect.render_widget(
+        make_status_line(&app.status, app.prompt.as_ref(), app.fat_view.blocks),
+        chunks[2],
+    );
+    // the keys go to whichever pane has the focus
+    let focus = match (app.browsing, app.show_tree) {
+        (true, _) => "file browser",
+        (_, true) => "tree view",
+        _ => app.focus.name(),
+    };
+    let help_title = Title::new("Help").part(1, format!("keys go to the {}", focus));
+    rect.render_widget(
+        make_instructions(
+            theme,
+            help_title.fit(theme, chunks[3].width.saturating_sub(2) as usize),
+        ),
+        chunks[3],
+    );
+
+    // as does the tree view
+    if app.show_tree {
+        draw_tree(rect, app, theme, body_rect);
+        draw_popups(rect, app, theme, size, body_rect);
+        theme.finish(rect);
+        return;
+    }
+    // the file browser takes the place of the FAT list and the block pane
+    if app.browsing {
+        let preview_rect = draw_browser(rect, app, theme, body_rect);
+        draw_popups(rect, app, theme, size, preview_rect);
+        theme.finish(rect);
+        return;
+    }
+
+    // the FAT list widens for the chain column, and while entries that just changed say what
+    // they held before. The block numbers are as wide as the image needs
+    let blocks = app.fat_view.blocks;
+    let fat_width = (8 + blocks.block_width() + blocks.entry_width()) as u16
+        + match app.session.fat_columns {
+            FatColumns::Plain => 0,
+            FatColumns::Chain => 16,
+        }
+        + match app.fat_view.previous.is_empty() {
+            true => 0,
+            false => 7 + blocks.entry_width() as u16,
+        };
+    // zoomed in, the FAT list isn't drawn at all and the block pane gets its width
+    let right_rect = match app.zoomed {
+        true => body_rect,
+        false => {
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Length(fat_width), Constraint::Min(10)].as_ref())
+                .split(body_rect);
+            // the borders take a row above and below
+            let window = pane::window(
+                app.fat_offset,
+                selected,
+                app.fat_list_len(),
+                chunks[0].height.saturating_sub(2) as usize,
+            );
+            app.fat_offset = window.start;
+            let mut state = ListState::default();
+            state.select(Some(selected.saturating_sub(window.start)));
+            rect.render_stateful_widget(
+                make_fat_table_view(
+                    theme,
+                    &app.fat_view,
+                    &app.marks,
+                    &app.session,
+                    app.sort,
+                    make_fat_title(app).fit(theme, fat_width.saturating_sub(2) as usize),
+                    app.focus == Pane::Fat,
+                    window.clone(),
+                ),
+                chunks[0],
+                &mut state,
+            );
+            // the minimap goes down the right border, between the corners
+            let list = chunks[0];
+            rect.render_widget(
+                make_fat_minimap(theme, app, window),
+                Rect::new(
+                    list.right().saturating_sub(1),
+                    list.y + 1,
+                    list.width.min(1),
+                    list.height.saturating_sub(2),
+                ),
+            );
+            chunks[1]
+        }
+    };
+
+    // the recently modified files go under the block pane
+    let right_rect = match app.show_recent {
+        true => {
+            let height = app.recent.changes().len().clamp(1, 8) as u16 + 2;
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(height)].as_ref())
+                .split(right_rect);
+            let title = Title::new("Recently modified")
+                .joined(" ", 2, format!("({} files)", app.recent.changes().len()))
+                .part(1, "w to close");
+            rect.render_stateful_widget(
+                make_recent_view(
+                    &app.fs,
+                    &app.recent,
+                    &app.blame,
+                    title.fit(theme, chunks[1].width.saturating_sub(2) as usize),
+                    app.focus == Pane::Recent,
+                ),
+                chunks[1],
+                &mut app.recent_state,
+            );
+            chunks[0]
+        }
+        false => right_rect,
+    };
+
+    // the breadcrumb goes on the line above the block pane, and the selected block's header
+    // on the two below it
+    let header = fat_table.get(selected);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Length(1),
+                Constraint::Length(header.is_some() as u16 * 2),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(right_rect);
+    let block_rect = chunks[2];
+    if let Some((block_num, (path, chain))) = &breadcrumb {
+        rect.render_widget(
+            make_breadcrumb(theme, *path, chain, *block_num, chunks[0].width),
+            chunks[0],
+        );
+    }
+    if let Some((block_num, next)) = header {
+        let neighbors = app.neighbors(*block_num);
+        rect.render_widget(
+            make_block_header(&app.fat_view, *block_num, *next, &neighbors),
+            chunks[1],
+        );
+    }
+
+    // clear the right chuck to overwrite the previous block
+    rect.render_widget(Paragraph::new("".to_owned()), block_rect);
+
+    // display the selected block on the right
+    // diffing shows the block raw, whatever the view
+    let previous = app.previous_contents().filter(|_| app.show_diff);
+    let mut truncated = Vec::new();
+    let mut block_text: Vec<Spans> = match &app.selected_block {
+        None if app.unaddressed_selected() => make_unaddressed_lines(view, block_rect.width),
+        None => vec![Spans::from("nothing selected")],
+        Some(Err(_)) => vec![],
+        Some(Ok(block)) if previous.is_some() => {
+            make_diff_lines(block, previous.unwrap_or_default(), block_rect.width)
+        }
+        Some(Ok(block)) if raw_mode => {
+            let annotations = match app.annotate && app.is_dir_block() {
+                true => annotate::dentry_fields(view.dentry_layout(), block.data.len()),
+                false => Vec::new(),
+            };
+            make_raw_lines(
+                block,
+                block_rect.width,
+                app.selected_eof(),
+                app.encoding(block),
+                app.controls(theme.ascii),
+                &annotations,
+                !app.block_pane.no_wrap,
+            )
+        }
+        Some(Ok(block)) => {
+            let rows: Vec<(String, Option<String>)> = app
+                .dentries(block)
+                .iter()
+                .map(|dentry| {
+                    // files out of view haven't been sniffed yet
+                    let is_file = dentry.is_in_use() && !dentry.is_dir() && !dentry.is_symlink();
+                    let links = app.fat_view.ownership.paths(dentry.first_block).len();
+                    let detected = match detected.get(&dentry.first_block) {
+                        Some(detected) if is_file => format!(" detected: {}", detected),
+                        _ => String::new(),
+                    };
+                    // files sharing their chain with others, as hard links do
+                    let detected = match links {
+                        2.. if is_file => format!(" links: {},{}", links, detected),
+                        _ => detected,
+                    };
+                    let (row, full) = describe_dentry(
+                        view,
+                        theme,
+                        dir_path,
+                        dentry,
+                        dir_sizes,
+                        app.physical_sizes,
+                        (block_rect.width.saturating_sub(2) as usize)
+                            .saturating_sub(detected.width()),
+                    );
+                    (row + &detected, full.map(|full| full + &detected))
+                })
+                .collect();
+            let (text, full): (Vec<String>, _) = rows.into_iter().unzip();
+            truncated = full;
+            text.into_iter().map(Spans::from).collect()
+        }
+    };
+    app.block_pane.truncated = truncated;
+
+    // unwrapped lines are cut off at the side, scrolled across no further than the longest
+    let text_width = block_rect.width.saturating_sub(2) as usize;
+    app.block_pane.overflow = match app.block_pane.no_wrap {
+        true => block_text
+            .iter()
+            .map(|line| line.width().saturating_sub(text_width))
+            .max()
+            .unwrap_or(0),
+        false => 0,
+    };
+    app.block_pane.column = app.block_pane.column.min(app.block_pane.overflow);
+    if app.block_pane.column > 0 {
+        block_text = block_text
+            .into_iter()
+            .map(|line| scroll_raw_line(line, app.block_pane.column))
+            .collect();
+    }
+    let block_title = match app.block_pane.no_wrap {
+        true => block_title.joined(", ", 4, format!("col {}", app.block_pane.column)),
+        false => block_title,
+    };
+
+    // the cursor row is highlighted, and kept in view, while the pane has the focus
+    app.block_pane.rows = block_text.len();
+    app.block_pane.cursor = app
+        .block_pane
+        .cursor
+        .min(block_text.len().saturating_sub(1));
+    app.block_pane.visible = block_rect.height.saturating_sub(2).max(1) as usize;
+    let mut scroll = 0;
+    if app.focus == Pane::Block {
+        if let Some(line) = block_text.get_mut(app.block_pane.cursor) {
+            for span in &mut line.0 {
+                span.style = span.style.add_modifier(Modifier::REVERSED);
             }
+        }
+        scroll = app.block_pane.visible_rows(true).start as u16;
+    }
 
-            if last_tick.elapsed() >= tick_rate {
-                if let Ok(_) = tx.send(Event::Tick) {
-                    last_tick = Instant::now();
-                }
+    if let Some(Err(e)) = &app.selected_block {
+        let error = format!("error reading block: {}", e);
+        rect.render_widget(make_block_error(&error, block_rect.height), block_rect);
+    } else {
+        let block = Paragraph::new(block_text)
+            .style(Style::default().fg(Color::LightCyan))
+            .alignment(Alignment::Left)
+            .scroll((scroll, 0))
+            .block(make_pane_frame(
+                block_title.fit(theme, text_width),
+                app.focus == Pane::Block,
+            ));
+        let block = match app.block_pane.no_wrap {
+            true => block,
+            false => block.wrap(Wrap { trim: false }),
+        };
+        rect.render_widget(block, block_rect);
+    }
+
+    draw_popups(rect, app, theme, size, block_rect);
+    theme.finish(rect);
+}
+
+/// draw what goes over the rest of the screen: the stats, the consistency problems, the hint,
+/// the dentry form and the command palette, whichever are open. The hint goes in the bottom
+/// corner of `pane_rect`
+fn draw_popups<B: Backend>(
+    rect: &mut Frame<B>,
+    app: &App,
+    theme: &Theme,
+    size: Rect,
+    pane_rect: Rect,
+) {
+    let stats = app
+        .stats_cache
+        .as_ref()
+        .filter(|_| app.show_stats)
+        .map(|(_, stats)| stats);
+    if let Some(stats) = stats {
+        // the image's stats, then where the allocations seen while watching it were placed
+        let mut rows = stats.rows();
+        rows.extend(
+            app.blame
+                .placement_rows(app.fs.profile(), app.fs.block_format()),
+        );
+        let area = centered_rect(70, rows.len() as u16 + 2, size);
+        rect.render_widget(Clear, area);
+        rect.render_widget(make_stats_popup(rows), area);
+    }
+    if let Some(path) = &app.stat_path {
+        let lines = match walk::lookup(app.view(), path, false) {
+            walk::Lookup::Found(entry) => commands::file_stat(app.view(), &entry),
+            _ => vec![format!("{} isn't there any more", path)],
+        };
+        let area = centered_rect(80, lines.len() as u16 + 2, size);
+        rect.render_widget(Clear, area);
+        rect.render_widget(make_stat_popup(lines), area);
+    }
+    if app.show_check {
+        let area = centered_rect(80, app.problems.len().max(1) as u16 + 2, size);
+        rect.render_widget(Clear, area);
+        rect.render_widget(make_check_popup(&app.problems, app.fs.block_format()), area);
+    }
+    // the hint goes in the bottom corner of the block pane, clear of the selection
+    if let Some(topic) = app.hints.as_ref().and_then(|hints| hints.current()) {
+        let width = pane_rect.width.min(60);
+        let height = (topic.text().width() as u16 / width.saturating_sub(2).max(1) + 3)
+            .min(pane_rect.height);
+        let area = Rect::new(
+            pane_rect.right() - width,
+            pane_rect.bottom() - height,
+            width,
+            height,
+        );
+        rect.render_widget(Clear, area);
+        rect.render_widget(make_hint(topic), area);
+    }
+    if let Some(form) = &app.dentry_form {
+        let rows = form
+            .confirm
+            .as_ref()
+            .map_or(FieldKind::ALL.len(), |changes| changes.len());
+        let area = centered_rect(72, rows as u16 + 4, size);
+        rect.render_widget(Clear, area);
+        rect.render_widget(
+            make_dentry_form(form, app.fs.dentry_layout().perms, app.fs.block_format()),
+            area,
+        );
+    }
+    if let Some(palette) = &app.palette {
+        let matches = actions::search(&palette.input);
+        let area = centered_rect(50, matches.len() as u16 + 2, size);
+        let mut state = ListState::default();
+        state.select(Some(palette.selected));
+        rect.render_widget(Clear, area);
+        rect.render_stateful_widget(
+            make_palette(theme, &palette.input, &matches),
+            area,
+            &mut state,
+        );
+    }
+}
+
+/// draw the file browser in `area`: the path to the directory being browsed on the top line,
+/// then the directory's entries on the left and a preview of the selected one on the right.
+/// Returns the area of the preview
+fn draw_browser<B: Backend>(rect: &mut Frame<B>, app: &mut App, theme: &Theme, area: Rect) -> Rect {
+    let controls = app.controls(theme.ascii);
+    let view = app.timeline.current().unwrap_or(&app.fs);
+    let browser = &mut app.browser;
+    let entries = browser.entries(view);
+    let selected = browser.state.selected().unwrap_or(0);
+    browser
+        .state
+        .select(Some(selected.min(entries.len().saturating_sub(1))));
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(1), Constraint::Min(0)].as_ref())
+        .split(area);
+    rect.render_widget(make_path_breadcrumb(theme, browser), chunks[0]);
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)].as_ref())
+        .split(chunks[1]);
+    rect.render_stateful_widget(
+        make_browser_table(&entries, view.dentry_layout().perms, view.block_format()),
+        panes[0],
+        &mut browser.state,
+    );
+
+    let dentry = browser.selected(view);
+    let (title, lines) = match &dentry {
+        Some(dentry) => (
+            Title::new(dentry.display_name())
+                .joined(" ", 2, format!("({} bytes)", dentry.size))
+                .part(
+                    1,
+                    commands::format_mode(dentry, view.dentry_layout().perms, false),
+                ),
+            make_preview_lines(view, browser, dentry, panes[1].width, controls),
+        ),
+        None => (Title::new("preview"), vec![Spans::from("nothing selected")]),
+    };
+    let title = title.fit(theme, panes[1].width.saturating_sub(2) as usize);
+    rect.render_widget(
+        Paragraph::new(lines)
+            .style(Style::default().fg(Color::LightCyan))
+            .wrap(Wrap { trim: false })
+            .block(make_pane_frame(title, false)),
+        panes[1],
+    );
+    panes[1]
+}
+
+/// make the line with the path to the directory being browsed, from the root
+fn make_path_breadcrumb(theme: &Theme, browser: &Browser) -> Paragraph<'static> {
+    let arrow = || {
+        Span::styled(
+            format!(" {} ", theme.arrow),
+            Style::default().fg(Color::DarkGray),
+        )
+    };
+    let mut spans = vec![Span::raw("/")];
+    for name in browser.names() {
+        spans.push(arrow());
+        spans.push(Span::raw(name.to_owned()));
+    }
+    Paragraph::new(Spans::from(spans))
+}
+
+/// make the table of the entries of the directory being browsed. An unreadable block of the
+/// directory gets a row saying so
+fn make_browser_table(
+    entries: &[Result<Dentry, PfError>],
+    perms: PermTable,
+    blocks: BlockFormat,
+) -> Table<'static> {
+    let rows = entries.iter().map(|entry| match entry {
+        Ok(dentry) => {
+            let name = match dentry.is_dir() {
+                true => format!("{}/", dentry.display_name()),
+                false => dentry.display_name(),
+            };
+            Row::new(vec![
+                name,
+                commands::format_mode(dentry, perms, false),
+                dentry.size.to_string(),
+                dentry.format_mtime(),
+                blocks.block(dentry.first_block),
+            ])
+        }
+        Err(e) => Row::new(vec![format!("error: {}", e)]).style(Style::default().fg(Color::Red)),
+    });
+    let header = Row::new(vec!["name", "mode", "size", "modified", "first block"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    Table::new(rows)
+        .header(header)
+        .block(make_pane_frame(format!("{} entries", entries.len()), true))
+        .widths(&[
+            Constraint::Min(12),
+            Constraint::Length(4),
+            Constraint::Length(10),
+            Constraint::Length(19),
+            Constraint::Length(11),
+        ])
+        .column_spacing(1)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+/// make the lines of the preview of an entry of the directory being browsed: a directory's
+/// entries as `ls` lists them, the head of a file as the raw view shows it, or where a symbolic
+/// link points
+fn make_preview_lines(
+    fs: &PennFat,
+    browser: &Browser,
+    dentry: &Dentry,
+    pane_width: u16,
+    controls: Controls,
+) -> Vec<Spans<'static>> {
+    let error = |e: &PfError| {
+        Spans::from(Span::styled(
+            format!("error: {}", e),
+            Style::default().fg(Color::Red),
+        ))
+    };
+    match browser.preview(fs, dentry) {
+        Preview::Listing(entries) if entries.is_empty() => vec![Spans::from("empty directory")],
+        Preview::Listing(entries) => {
+            let dir_path = format!("{}/{}", browser.path().trim_end_matches('/'), dentry.name());
+            entries
+                .iter()
+                .map(|entry| match entry {
+                    Ok(dentry) => Spans::from(commands::format_entry(fs, &dir_path, dentry, false)),
+                    Err(e) => error(e),
+                })
+                .collect()
+        }
+        Preview::Head(head, _) if head.is_empty() => vec![Spans::from("empty file")],
+        Preview::Head(head, size) => {
+            let block = pennfat::Block::from(head.as_slice());
+            let encoding = encoding::sniff(&head);
+            let mut lines = make_raw_lines(&block, pane_width, None, encoding, controls, &[], true);
+            if (size as usize) > head.len() {
+                lines.push(Spans::from(Span::styled(
+                    format!("... {} more bytes", size as usize - head.len()),
+                    Style::default().fg(Color::DarkGray),
+                )));
+            }
+            lines
+        }
+        Preview::Link(target, lookup) => vec![Spans::from(format!(
+            "-> {} ({})",
+            target,
+            lookup.describe()
+        ))],
+        Preview::Error(e) => vec![error(&e)],
+    }
+}
+
+/// draw the tree view in `area`: the root's entries, with the expanded directories' entries
+/// under them, and how many entries each directory has
+fn draw_tree<B: Backend>(rect: &mut Frame<B>, app: &mut App, theme: &Theme, area: Rect) {
+    let view = app.timeline.current().unwrap_or(&app.fs);
+    let rows = app.tree.rows(view);
+    let selected = app.tree.state.selected().unwrap_or(0);
+    app.tree
+        .state
+        .select(Some(selected.min(rows.len().saturating_sub(1))));
+    // kept by `prepare`, per reload; a frame drawn without it just shows no totals
+    let unknown = HashMap::new();
+    let dir_sizes = app
+        .dir_sizes_cache
+        .as_ref()
+        .map_or(&unknown, |(_, dir_sizes)| dir_sizes);
+    let entries = walk::count_children(view, view.root_block());
+    let total = dir_sizes
+        .get(&view.root_block())
+        .map_or(String::new(), |size| {
+            size.describe(view.block_size(), app.physical_sizes)
+        });
+    let sizes = match app.physical_sizes {
+        true => "physical sizes (u for logical)",
+        false => "logical sizes (u for physical)",
+    };
+    let title = Title::new("tree")
+        .joined(" ", 4, "/")
+        .joined(
+            " ",
+            3,
+            format!(
+                "({} entr{})",
+                entries,
+                if entries == 1 { "y" } else { "ies" }
+            ),
+        )
+        .part(2, total)
+        .part(1, sizes)
+        .fit(theme, area.width.saturating_sub(2) as usize);
+    rect.render_stateful_widget(
+        make_tree_table(theme, view, &rows, dir_sizes, app.physical_sizes, title),
+        area,
+        &mut app.tree.state,
+    );
+}
+
+/// make the table of the rows of the tree view: each entry indented by its depth, with a
+/// directory's marker saying whether it's expanded, and the total size of everything beneath
+/// it from `dir_sizes` (physical or logical). An unreadable block of a directory gets a row
+/// saying so, as does a directory with more entries than are listed
+fn make_tree_table(
+    theme: &Theme,
+    fs: &PennFat,
+    rows: &[tree::Row],
+    dir_sizes: &HashMap<u16, DirSize>,
+    physical: bool,
+    title: String,
+) -> Table<'static> {
+    let rows = rows.iter().map(|row| {
+        let indent = "  ".repeat(row.depth);
+        match &row.kind {
+            RowKind::Entry { dentry, dir, .. } => {
+                let (name, entries) = match dir {
+                    Some(dir) if dir.looped => (
+                        format!("{}  {}/ (inside itself)", indent, dentry.display_name()),
+                        format!(
+                            "{} entr{}",
+                            dir.entries,
+                            if dir.entries == 1 { "y" } else { "ies" }
+                        ),
+                    ),
+                    Some(dir) => (
+                        format!(
+                            "{}{} {}/",
+                            indent,
+                            match dir.expanded {
+                                true => theme.expanded,
+                                false => theme.collapsed,
+                            },
+                            dentry.display_name()
+                        ),
+                        format!(
+                            "{} entr{}",
+                            dir.entries,
+                            if dir.entries == 1 { "y" } else { "ies" }
+                        ),
+                    ),
+                    None => (
+                        format!("{}  {}", indent, dentry.display_name()),
+                        String::new(),
+                    ),
+                };
+                let beneath = dir
+                    .as_ref()
+                    .and_then(|_| dir_sizes.get(&dentry.first_block))
+                    .map_or(String::new(), |size| {
+                        size.describe(fs.block_size(), physical)
+                    });
+                Row::new(vec![
+                    name,
+                    entries,
+                    beneath,
+                    commands::format_mode(dentry, fs.dentry_layout().perms, false),
+                    dentry.size.to_string(),
+                    fs.format_block(dentry.first_block),
+                ])
             }
+            RowKind::Error(e) => Row::new(vec![format!("{}  error: {}", indent, e)])
+                .style(Style::default().fg(Color::Red)),
+            RowKind::More { shown, .. } => Row::new(vec![format!(
+                "{}  (showing first {}, press L to load more)",
+                indent, shown
+            )])
+            .style(Style::default().fg(Color::DarkGray)),
         }
     });
+    let header = Row::new(vec![
+        "name",
+        "entries",
+        "beneath",
+        "mode",
+        "size",
+        "first block",
+    ])
+    .style(Style::default().add_modifier(Modifier::BOLD));
+    Table::new(rows)
+        .header(header)
+        .block(make_pane_frame(title, true))
+        .widths(&[
+            Constraint::Min(20),
+            Constraint::Length(12),
+            Constraint::Length(30),
+            Constraint::Length(4),
+            Constraint::Length(10),
+            Constraint::Length(11),
+        ])
+        .column_spacing(1)
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+/// make the full-screen panel shown while the image fails to load
+fn make_load_error<'a>(path: &'a str, error: &PfError) -> Paragraph<'a> {
+    let lines = vec![
+        Spans::from(Span::styled(
+            format!("failed to load {}", path),
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        )),
+        Spans::default(),
+        Spans::from(error.to_string()),
+        Spans::default(),
+        Spans::from(Span::styled(
+            "retrying on every tick, press q to quit",
+            Style::default().fg(Color::DarkGray),
+        )),
+    ];
+    Paragraph::new(lines)
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().fg(Color::Red))
+                .title("PennFat Overview")
+                .border_type(BorderType::Plain),
+        )
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Err(e) = run(&args) {
+        // a script reading the JSON gets the error in it too, with a code to go by
+        if cli::Command::parse(&args[1..]).is_ok_and(|command| command.json()) {
+            commands::print_json_error(&e);
+        }
+        eprintln!("Error: {:?}", e);
+        exit(match e.is::<ProblemsFound>() {
+            true => cli::EXIT_PROBLEMS,
+            false => cli::EXIT_FAILURE,
+        });
+    }
+}
+
+/// print the banner and the usage text, to stdout when asked for and to stderr after a usage
+/// error
+fn print_usage(program: &str, error: Option<&str>) {
+    let to_stderr = error.is_some();
+    // the Windows console only understands the color codes once asked to
+    #[cfg(windows)]
+    let _ = colored::control::set_virtual_terminal(true);
+    // no color codes in a pipe, or on a terminal without colors
+    let is_terminal = match to_stderr {
+        true => io::stderr().is_terminal(),
+        false => io::stdout().is_terminal(),
+    };
+    if !is_terminal || Theme::detect_monochrome() {
+        colored::control::set_override(false);
+    }
+    let banner = format!(
+        "pfview {} - TUI PennFat viewer\nby {}",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_AUTHORS")
+    )
+    .bright_black();
+    match error {
+        Some(error) => eprintln!("{}\n{}\n\n{}", banner, error, cli::usage(program)),
+        None => println!("{}\n{}", banner, cli::usage(program)),
+    }
+}
+
+/// run the command the arguments ask for. Errors exit with [`cli::EXIT_FAILURE`], or
+/// [`cli::EXIT_PROBLEMS`] if the image turned out to have problems
+fn run(args: &[String]) -> Result<()> {
+    let command = match cli::Command::parse(&args[1..]) {
+        Ok(command) => command,
+        Err(e) => {
+            print_usage(&args[0], Some(&e));
+            exit(cli::EXIT_USAGE);
+        }
+    };
+    let options = match command {
+        cli::Command::Help => {
+            print_usage(&args[0], None);
+            return Ok(());
+        }
+        cli::Command::View(options) => options,
+        cli::Command::Ls {
+            image,
+            target,
+            trace,
+            numeric,
+        } => return commands::ls(&image, &target, trace, numeric),
+        cli::Command::Cat {
+            image,
+            target,
+            follow,
+            trace,
+        } => return commands::cat(&image, &target, follow, trace),
+        cli::Command::Extract {
+            image,
+            target,
+            dest,
+            follow,
+            slack,
+            trace,
+        } => return commands::extract(&image, &target, &dest, follow, slack, trace),
+        cli::Command::ExtractAll { image, dest } => return commands::extract_all(&image, &dest),
+        cli::Command::Stat {
+            image,
+            target,
+            follow,
+            trace,
+        } => return commands::stat(&image, &target, follow, trace),
+        cli::Command::Lookup {
+            image,
+            path,
+            follow,
+            trace,
+        } => return commands::lookup(&image, &path, follow, trace),
+        cli::Command::Graph {
+            image,
+            target,
+            follow,
+        } => return commands::graph(&image, target.as_ref(), follow),
+        cli::Command::Stats { image, json } => return commands::stats(&image, json),
+        cli::Command::Info { image, json } => return commands::info(&image, json),
+        cli::Command::Report {
+            image,
+            format,
+            full,
+        } => return commands::report(&image, format, full),
+        cli::Command::Locate { image, offset } => return commands::locate(&image, offset),
+        cli::Command::Notes { image, format } => return commands::notes(&image, format),
+        cli::Command::Doctor { image } => return commands::doctor(&image),
+        cli::Command::FuzzGen {
+            dir,
+            count,
+            seed,
+            corrupt,
+        } => return commands::fuzz_gen(&dir, count, seed, corrupt),
+        cli::Command::FatExport { image, out } => return commands::fat_export(&image, &out),
+        cli::Command::FatApply {
+            image,
+            patch,
+            dry_run,
+        } => return commands::fat_apply(&image, &patch, dry_run),
+        cli::Command::Verify {
+            image,
+            dir,
+            check_mtime,
+        } => return commands::verify(&image, &dir, check_mtime),
+        cli::Command::Cp {
+            src,
+            src_path,
+            dest,
+            dest_path,
+            dry_run,
+        } => return commands::cp(&src, &src_path, &dest, &dest_path, dry_run),
+        cli::Command::Fit { image } => return commands::fit(&image),
+        cli::Command::Repack {
+            image,
+            dest,
+            block_size_config,
+            fat_blocks,
+        } => return commands::repack(&image, &dest, block_size_config, fat_blocks),
+        cli::Command::Repair { image, apply, yes } => return commands::repair(&image, apply, yes),
+        cli::Command::Explain { code } => return commands::explain(code.as_deref()),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    // how often do we want to reload the file and redraw (when there are no events)?
+    // Note that decreasing this value will cause CPU usage, but probably not more than
+    // 2-3% (of one core). At 700ms, it's at 0.5-0.7%% on my machine.
+    let tick_rate = Duration::from_millis(700);
+    // while the terminal is unfocused (e.g. in a background tmux pane) the input thread ticks
+    // more slowly, at the rate the main loop leaves here, in milliseconds
+    let current_tick = Arc::new(AtomicU64::new(tick_rate.as_millis() as u64));
+    let tick = current_tick.clone();
+    // set while another program runs in the terminal, so the input thread doesn't steal its keys
+    let input_paused = Arc::new(AtomicBool::new(false));
+    let paused = input_paused.clone();
+    thread::spawn(move || read_input(&tx, &tick, &paused, event::poll, event::read));
 
-    let mut fs = PennFat::load(&args[1])?;
+    if let Some(path) = options
+        .log_file
+        .clone()
+        .or_else(|| env::var_os("PFVIEW_LOG").map(PathBuf::from))
+    {
+        logging::init(&path, options.log_level)
+            .map_err(|e| anyhow!("opening log file {}: {}", path.display(), e))?;
+        info!(
+            "pfview {} viewing {}",
+            env!("CARGO_PKG_VERSION"),
+            options.image.path
+        );
+    }
+    let monochrome = match options.color {
+        cli::ColorChoice::Auto => Theme::detect_monochrome(),
+        cli::ColorChoice::Always => false,
+        cli::ColorChoice::Never => true,
+    };
+    let theme = Theme::new(
+        options.ascii.unwrap_or_else(Theme::detect_ascii),
+        monochrome,
+    );
+
+    let _demo_image = match options.demo {
+        true => Some(
+            DemoImage::write(&options.image.path)
+                .map_err(|e| anyhow!("writing the demo image: {}", e))?,
+        ),
+        false => None,
+    };
+    let first_load = match PennFat::load(&options.image.path, options.mode, options.image.window) {
+        Err(e) if !options.watch_invalid => return Err(e.into()),
+        load => load,
+    };
 
-    enable_raw_mode().expect("can run in raw mode");
+    // put the terminal back before a panic is reported, or the message is mangled by raw mode
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), cursor::Show, DisableFocusChange);
+        default_hook(info);
+    }));
+    enable_raw_mode().map_err(|e| anyhow!("can't set up the terminal: {}", e))?;
     let stdout = io::stdout();
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     terminal.clear()?;
 
-    // state
-    let mut list_selected_state = ListState::default();
-    list_selected_state.select(Some(0));
-    let mut raw_mode = false;
+    // show the load failure until the image becomes valid (or the user gives up)
+    let mut load = first_load;
+    let mut fs = loop {
+        match load {
+            Ok(fs) => break fs,
+            Err(e) => {
+                terminal.draw(|rect| {
+                    rect.render_widget(make_load_error(&options.image.path, &e), rect.size());
+                    theme.finish(rect);
+                })?;
+                match rx.recv()? {
+                    Event::Input(event) if event.code == KeyCode::Char('q') => {
+                        disable_raw_mode()?;
+                        terminal.show_cursor()?;
+                        return Ok(());
+                    }
+                    Event::InputError(e) => return terminal_lost(&mut terminal, e),
+                    _ => {}
+                }
+                load = PennFat::load(&options.image.path, options.mode, options.image.window);
+            }
+        }
+    };
+    let root_warning = options.image.apply(&mut fs);
+    fs.set_poll_content(options.poll_content);
+    // the error screen may have left a differently laid out frame behind
+    terminal.clear()?;
+
+    // terminals that don't report focus changes just never send them, and keep ticking as usual
+    let _focus_changes = FocusChanges::enable(options.unfocused_tick.is_some());
+    let unfocused_tick = options.unfocused_tick;
+    let mut app = App::new(fs, options, root_warning);
 
     // loop to draw the tui
     loop {
-        fs.reload()?;
-        let fat_table: Vec<(u16, u16)> = fs.get_fat_table();
+        if let Some(problem) = app.reload() {
+            if app.options.fail_fast {
+                disable_raw_mode()?;
+                terminal.show_cursor()?;
+                bail!(ProblemsFound(format!(
+                    "the image became inconsistent: {}",
+                    problem.show(app.fs.block_format())
+                )));
+            }
+            if app.options.bell {
+                print!("\x07");
+                io::stdout().flush()?;
+            }
+        }
+        app.prepare();
+        if let Err(e) = terminal.draw(|rect| draw(rect, &mut app, &theme)) {
+            return terminal_lost(&mut terminal, e);
+        }
 
-        terminal.draw(|rect| {
-            let size = rect.size();
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints(
-                    [
-                        Constraint::Length(3),
-                        Constraint::Min(2),
-                        Constraint::Length(4),
-                    ]
-                    .as_ref(),
-                )
-                .split(size);
+        // crossterm can get stuck reading a terminal that hung up, so the input thread isn't
+        // relied on for ticks: redrawing on our own finds out the terminal is gone
+        let tick = Duration::from_millis(current_tick.load(Ordering::SeqCst));
+        let event = match rx.recv_timeout(tick * 2) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) => Event::Tick,
+            Err(e) => return Err(e.into()),
+        };
+        let action = match event {
+            Event::Input(key) => {
+                trace!("key {:?} {:?}", key.code, key.modifiers);
+                app.handle_key(&key)
+                    .and_then(|key_action| app.apply_key(key_action))
+            }
+            Event::Tick | Event::Paused => None,
+            // the loop redraws straight away, laying the panes out (and scrolling them to
+            // their selections) for the new size
+            Event::Resize(width, height) => {
+                debug!("resized to {}x{}", width, height);
+                None
+            }
+            // on coming back, the loop reloads and redraws straight away
+            Event::Focus(focused) => {
+                let tick = match unfocused_tick {
+                    Some(unfocused_tick) if !focused => unfocused_tick,
+                    _ => tick_rate,
+                };
+                debug!("focus {}, ticking every {:?}", focused, tick);
+                current_tick.store(tick.as_millis() as u64, Ordering::SeqCst);
+                None
+            }
+            Event::InputError(e) => return terminal_lost(&mut terminal, e),
+        };
+        let Some(action) = action else {
+            continue;
+        };
+        debug!("action {:?}", action);
+        match app.update(action) {
+            Some(Request::Quit) => {
+                disable_raw_mode()?;
+                terminal.show_cursor()?;
+                break;
+            }
+            Some(Request::OpenExternally { name, contents }) => {
+                if let Err(e) = open_externally(
+                    &mut terminal,
+                    &rx,
+                    &input_paused,
+                    unfocused_tick.is_some(),
+                    &name,
+                    &contents,
+                ) {
+                    app.status.error(format!("opening file: {}", e));
+                }
+            }
+            Some(Request::Screenshot) => {
+                let saved = terminal
+                    .size()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|size| save_screenshot(&mut app, &theme, size));
+                match saved {
+                    Ok(path) => app
+                        .status
+                        .info(format!("screen saved to {}", path.display())),
+                    Err(e) => app.status.error(format!("saving the screen: {}", e)),
+                }
+            }
+            None => {}
+        }
+    }
 
-            let body_rect = chunks[1];
-            rect.render_widget(make_overview(&fs), chunks[0]);
-            rect.render_widget(make_instructions(), chunks[2]);
+    Ok(())
+}
 
-            let chunks = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([Constraint::Length(15), Constraint::Min(10)].as_ref())
-                .split(body_rect);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actions::Action;
+    use cli::Options;
+    use pfview::testing;
 
-            let block_rect = chunks[1];
-            rect.render_stateful_widget(
-                make_fat_table_view(&fat_table),
-                chunks[0],
-                &mut list_selected_state,
+    /// Read input from a terminal whose polls and reads give `results` in turn, then fail
+    /// as a dropped connection does, collecting what the main loop would be sent
+    fn events_from(results: Vec<io::Result<CEvent>>) -> Vec<Event<KeyEvent>> {
+        let (tx, rx) = mpsc::channel();
+        let mut results = results.into_iter();
+        let next = std::cell::RefCell::new(None);
+        read_input(
+            &tx,
+            &AtomicU64::new(60_000),
+            &AtomicBool::new(false),
+            |_| match results.next() {
+                Some(Err(e)) => Err(e),
+                Some(result) => {
+                    *next.borrow_mut() = Some(result);
+                    Ok(true)
+                }
+                None => Err(io::Error::from(io::ErrorKind::BrokenPipe)),
+            },
+            || {
+                next.borrow_mut()
+                    .take()
+                    .expect("read only after a successful poll")
+            },
+        );
+        drop(tx);
+        rx.into_iter().collect()
+    }
+
+    #[test]
+    fn the_overview_title_drops_the_writers_before_what_is_being_viewed() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        let theme = Theme::new(false, false);
+        let writers = [Writer {
+            pid: 4242,
+            name: "pennfat".to_owned(),
+        }];
+        let title_at = |width| {
+            let overview = make_overview(
+                &fs,
+                &theme,
+                Some("snapshot 2 of 3".to_owned()),
+                Some("root directory guessed"),
+                false,
+                Some(&writers),
+                Some(&[]),
+                None,
+                width,
             );
+            let mut terminal = Terminal::new(TestBackend::new(width, 4)).unwrap();
+            terminal
+                .draw(|rect| rect.render_widget(overview, rect.size()))
+                .unwrap();
+            let text = screenshot::to_text(terminal.backend().buffer(), false);
+            text.lines().next().unwrap().to_owned()
+        };
+        let wide = title_at(200);
+        assert!(
+            wide.contains(&format!(
+                "PennFat Overview - viewing snapshot 2 of 3 - {}read-only - writer: pid 4242 \
+                 (pennfat) - root directory guessed",
+                theme.lock
+            )),
+            "{}",
+            wide
+        );
+        let narrow = title_at(90);
+        assert!(
+            narrow.contains(&format!(
+                "PennFat Overview - viewing snapshot 2 of 3 - {}read-only - root directory guessed",
+                theme.lock
+            )),
+            "{}",
+            narrow
+        );
+        let narrower = title_at(70);
+        assert!(
+            narrower
+                .contains("PennFat Overview - viewing snapshot 2 of 3 - root directory guessed"),
+            "{}",
+            narrower
+        );
+    }
 
-            // clear the right chuck to overwrite the previous block
-            rect.render_widget(Paragraph::new("".to_owned()), block_rect);
+    #[test]
+    fn a_lost_terminal_ends_the_input_thread_with_an_error() {
+        let events = events_from(vec![Ok(CEvent::Key(KeyEvent::from(KeyCode::Char('j'))))]);
+        assert!(matches!(
+            events.as_slice(),
+            [Event::Input(key), Event::InputError(e)]
+                if key.code == KeyCode::Char('j') && e.kind() == io::ErrorKind::BrokenPipe
+        ));
+    }
 
-            // display the selected block on the right
-            let selected = list_selected_state.selected().unwrap_or(0);
-            let block_string = if selected >= fat_table.len() {
-                "nothing selected".to_owned()
-            } else {
-                let block_num = fat_table[selected].0;
-                let block = fs.get_block(block_num);
-
-                match (raw_mode, block) {
-                    (true, Ok(block)) => block.as_raw(),
-                    (_, Err(e)) => format!("error reading block: {}", e),
-                    (false, Ok(block)) => {
-                        let mut block_string = String::new();
-                        let dentries = block.as_dentries();
-
-                        for dentry in dentries {
-                            block_string.push_str(&format!("{}\n", dentry.to_string()));
-                        }
-                        block_string
-                    }
-                }
+    #[test]
+    fn interrupted_polls_and_reads_are_retried() {
+        let events = events_from(vec![
+            Err(io::Error::from(io::ErrorKind::Interrupted)),
+            Ok(CEvent::Resize(80, 24)),
+            Ok(CEvent::FocusLost),
+        ]);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                Event::Resize(80, 24),
+                Event::Focus(false),
+                Event::InputError(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn the_input_thread_stops_once_nothing_listens() {
+        let (tx, rx) = mpsc::channel();
+        drop(rx);
+        // the terminal always has a key ready, so only the closed channel can end the thread
+        let handle = thread::spawn(move || {
+            read_input(
+                &tx,
+                &AtomicU64::new(60_000),
+                &AtomicBool::new(false),
+                |_| Ok(true),
+                || Ok(CEvent::Key(KeyEvent::from(KeyCode::Char('q')))),
+            )
+        });
+        assert!(handle.join().is_ok(), "the input thread panicked");
+    }
+
+    #[test]
+    fn input_is_only_paused_while_the_pause_is_kept() {
+        let input_paused = AtomicBool::new(false);
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::Tick).unwrap();
+        tx.send(Event::Paused).unwrap();
+        let paused = PausedInput::pause(&input_paused, &rx).unwrap();
+        assert!(input_paused.load(Ordering::SeqCst));
+        drop(paused);
+        assert!(!input_paused.load(Ordering::SeqCst));
+
+        // the terminal going away before the input thread confirms doesn't leave it paused
+        tx.send(Event::InputError(io::Error::from(
+            io::ErrorKind::BrokenPipe,
+        )))
+        .unwrap();
+        assert!(PausedInput::pause(&input_paused, &rx).is_err());
+        assert!(!input_paused.load(Ordering::SeqCst));
+        drop(tx);
+        assert!(PausedInput::pause(&input_paused, &rx).is_err());
+        assert!(!input_paused.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn a_paused_input_thread_stops_once_nothing_listens() {
+        let (tx, rx) = mpsc::channel();
+        drop(rx);
+        let handle = thread::spawn(move || {
+            read_input(
+                &tx,
+                &AtomicU64::new(60_000),
+                &AtomicBool::new(true),
+                |_| panic!("polled while paused"),
+                || panic!("read while paused"),
+            )
+        });
+        assert!(handle.join().is_ok(), "the input thread panicked");
+    }
+
+    /// Images every part of the viewer has to cope with, each with what its first frame
+    /// must show about what's wrong with it: generated ones with faults, geometries at their
+    /// extremes, bytes that only just load, dentries with every field at its extreme, and
+    /// images that don't load at all
+    fn adversarial_images() -> Vec<(Vec<u8>, &'static str)> {
+        let mut images: Vec<_> = (0..12)
+            .map(|seed| (fuzz::generate(seed, 0.5).0, ""))
+            .collect();
+        // 256-byte blocks after a FAT of one block or of as many as there can be, each FAT
+        // block holding 128 entries, and each entry but the first addressing a data block
+        let image = |fat_blocks: u8, fill: u8| {
+            let mut bytes = vec![fill; 256 * (fat_blocks as usize * 129 - 1)];
+            bytes[..2].copy_from_slice(&[0, fat_blocks]);
+            bytes
+        };
+        images.push((image(1, 0), "nothing selected"));
+        images.push((image(0xff, 0), "nothing selected"));
+        images.push((image(1, 0xff), "doesn't look like a root directory"));
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut noise = image(1, 0);
+        for byte in &mut noise[2..] {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = state as u8;
+        }
+        images.push((noise, "(out of range)"));
+
+        let mut extreme = fuzz::ImageBuilder::new(0, 1);
+        for (slot, type_) in [0xff, 0, 1, 2].into_iter().enumerate() {
+            let spec = fuzz::DentrySpec {
+                name: vec![0xff; 32],
+                size: u32::MAX,
+                first_block: 0xffff,
+                type_,
+                perm: 0xff,
+                mtime: u64::MAX,
             };
+            extreme.write_dentry(1, slot, &spec);
+        }
+        images.push((extreme.bytes(), "invalid (0xffffffffffffffff)"));
+
+        let mut truncated = testing::sample_image();
+        truncated.truncate(truncated.len() / 2);
+        images.push((truncated, "failed to load"));
+        images.push((vec![0xff; 2], "failed to load"));
+        images
+    }
 
-            // set block trailing space blank to avoid old text showing up
-
-            let block = Paragraph::new(block_string)
-                .style(Style::default().fg(Color::LightCyan))
-                .alignment(Alignment::Left)
-                .wrap(Wrap { trim: false })
-                .block(
-                    Block::default()
-                        .borders(Borders::ALL)
-                        .style(Style::default().fg(Color::White))
-                        .title("block")
-                        .border_type(BorderType::Plain),
-                );
-            rect.render_widget(block, block_rect);
-        })?;
-
-        match rx.recv()? {
-            Event::Input(event) => match event.code {
-                KeyCode::Char('q') => {
-                    disable_raw_mode()?;
-                    terminal.show_cursor()?;
-                    break;
+    #[test]
+    fn the_tree_view_shows_each_directorys_entries_under_it() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        let options = Options::parse(&["test.img".to_owned()]).unwrap();
+        let mut app = App::new(fs, options, None);
+        let theme = Theme::new(true, false);
+        let screen = |app: &mut App| {
+            app.prepare();
+            let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+            terminal.draw(|rect| draw(rect, app, &theme)).unwrap();
+            screenshot::to_text(terminal.backend().buffer(), false)
+        };
+        for action in [Action::Tree, Action::Down, Action::Select] {
+            app.update(action);
+        }
+        let text = screen(&mut app);
+        assert!(text.contains("tree / (4 entries)"), "{}", text);
+        assert!(text.contains("- docs/"), "{}", text);
+        assert!(text.contains("    guide.txt"), "{}", text);
+        assert!(text.contains("  + src/"), "{}", text);
+        assert!(text.contains("keys go to the tree view"), "{}", text);
+
+        // T again goes to the selected directory's block in the FAT list
+        app.update(Action::Tree);
+        screen(&mut app);
+        assert!(!app.show_tree);
+        assert_eq!(app.focus, Pane::Fat);
+        let docs = walk::lookup(app.view(), "/docs", false);
+        let walk::Lookup::Found(docs) = docs else {
+            panic!("no /docs");
+        };
+        assert_eq!(
+            app.fat_view
+                .table
+                .get(app.selected())
+                .map(|(block, _)| *block),
+            Some(docs.dentry.first_block)
+        );
+    }
+
+    #[test]
+    fn the_tree_view_totals_what_is_beneath_each_directory() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        let sizes = du::dir_sizes(&fs);
+        let options = Options::parse(&["test.img".to_owned()]).unwrap();
+        let mut app = App::new(fs, options, None);
+        let theme = Theme::new(true, false);
+        let screen = |app: &mut App| {
+            app.prepare();
+            let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+            terminal.draw(|rect| draw(rect, app, &theme)).unwrap();
+            screenshot::to_text(terminal.backend().buffer(), false)
+        };
+        let walk::Lookup::Found(docs) = walk::lookup(app.view(), "/docs", false) else {
+            panic!("no /docs");
+        };
+        let docs = sizes[&docs.dentry.first_block];
+        let root = sizes[&app.view().root_block()];
+        // a subdirectory's blocks count towards its parent's
+        assert!(root.blocks > docs.blocks && root.bytes > docs.bytes);
+
+        app.update(Action::Tree);
+        let line = |text: &str, needle: &str| {
+            text.lines()
+                .find(|line| line.contains(needle))
+                .unwrap_or_else(|| panic!("no {} in {}", needle, text))
+                .to_owned()
+        };
+        let text = screen(&mut app);
+        assert!(line(&text, "+ docs/").contains(&docs.describe(256, false)));
+        assert!(line(&text, "tree /").contains(&root.describe(256, false)));
+        assert!(text.contains("logical sizes (u for physical)"), "{}", text);
+
+        app.update(Action::ToggleSizes);
+        let text = screen(&mut app);
+        assert!(line(&text, "+ docs/").contains(&format!("{} bytes", docs.blocks * 256)));
+        assert!(text.contains("physical sizes (u for logical)"), "{}", text);
+    }
+
+    #[test]
+    fn adversarial_images_are_drawn_without_panicking() {
+        let flag_sets: [&[&str]; 4] = [
+            &[],
+            &["--root-block", "65535"],
+            &["--dentry-size", "1"],
+            &["--dentry-size", "4096"],
+        ];
+        // keys that only look around, never writing to the image or starting a program
+        let keys = "rdtjk\tlnpSKZBzCux 'mwOiUAWNHL\nTj\nj\nI"
+            .chars()
+            .map(|c| match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                c => KeyCode::Char(c),
+            })
+            .chain([KeyCode::Backspace, KeyCode::Esc]);
+        let theme = Theme::new(false, false);
+        let frame = |width, height, draw_frame: &mut dyn FnMut(&mut Frame<TestBackend>)| {
+            let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+            terminal.draw(|rect| draw_frame(rect)).unwrap();
+            screenshot::to_text(terminal.backend().buffer(), false)
+        };
+        for (index, (bytes, shown)) in adversarial_images().into_iter().enumerate() {
+            let mut fs = match PennFat::from_bytes(bytes) {
+                Ok(fs) => fs,
+                Err(e) => {
+                    let text = frame(80, 24, &mut |rect| {
+                        rect.render_widget(make_load_error("test.img", &e), rect.size())
+                    });
+                    assert!(text.contains(shown), "image {}: {}", index, text);
+                    continue;
                 }
-                KeyCode::Down | KeyCode::Char('j') => {
-                    let selected = list_selected_state.selected().unwrap_or(0);
-                    if selected < fat_table.len() - 1 {
-                        list_selected_state.select(Some(selected + 1));
-                    }
+            };
+            for flags in flag_sets {
+                let mut args = vec!["test.img".to_owned()];
+                args.extend(flags.iter().map(|flag| flag.to_string()));
+                let options = Options::parse(&args).unwrap();
+                let root_warning = options.image.apply(&mut fs);
+                let mut app = App::new(fs, options, root_warning);
+                app.prepare();
+                if flags.is_empty() {
+                    let text = frame(120, 30, &mut |rect| draw(rect, &mut app, &theme));
+                    assert!(text.contains(shown), "image {}: {}", index, text);
                 }
-                KeyCode::Up | KeyCode::Char('k') => {
-                    let selected = list_selected_state.selected().unwrap_or(0);
-                    if selected > 0 {
-                        list_selected_state.select(Some(selected - 1));
+                for key in keys.clone() {
+                    if let Some(action) = app
+                        .handle_key(&KeyEvent::from(key))
+                        .and_then(|key_action| app.apply_key(key_action))
+                    {
+                        app.update(action);
                     }
-                }
-                KeyCode::Right | KeyCode::Char('l') => {
-                    let selected = list_selected_state.selected().unwrap_or(0);
-                    if selected < fat_table.len() - 1 {
-                        let next = fat_table[selected as usize].1;
-                        if next != 0 && next != 0xffff {
-                            // binary search through the confirm if the next block is in the fat table
-                            let f = fat_table.binary_search_by(|probe| {
-                                if probe.0 < next {
-                                    Ordering::Less
-                                } else if probe.0 > next {
-                                    Ordering::Greater
-                                } else {
-                                    Ordering::Equal
-                                }
-                            });
-                            if let Ok(i) = f {
-                                list_selected_state.select(Some(i));
-                            }
-                        }
+                    app.prepare();
+                    for (width, height) in [(80, 24), (20, 5), (200, 60), (3, 2), (1, 1)] {
+                        frame(width, height, &mut |rect| draw(rect, &mut app, &theme));
                     }
                 }
-                KeyCode::Char('t') => {
-                    raw_mode = !raw_mode;
-                }
-                KeyCode::Char('r') => {
-                    raw_mode = true;
-                }
-                KeyCode::Char('d') => {
-                    raw_mode = false;
-                }
-
-                _ => {}
-            },
-
-            Event::Tick => {}
+                fs = app.fs;
+            }
         }
     }
-
-    Ok(())
 }
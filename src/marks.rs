@@ -0,0 +1,159 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+
+use crate::ownership::Ownership;
+use crate::pennfat::{Entry, PennFat};
+use crate::snapshot;
+use crate::stats;
+
+/// Blocks marked in the FAT list for bulk actions, in the order they were marked. Marks are
+/// block numbers, so they survive reloads, and stay on blocks that get freed
+#[derive(Default)]
+pub struct Marks {
+    blocks: Vec<u16>,
+}
+
+impl Marks {
+    /// Mark a block, or unmark it if it's marked. Returns whether it's marked now
+    pub fn toggle(&mut self, block: u16) -> bool {
+        match self.blocks.iter().position(|b| *b == block) {
+            Some(i) => {
+                self.blocks.remove(i);
+                false
+            }
+            None => {
+                self.blocks.push(block);
+                true
+            }
+        }
+    }
+
+    pub fn contains(&self, block: u16) -> bool {
+        self.blocks.contains(&block)
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// The marked blocks, in the order they were marked
+    pub fn blocks(&self) -> &[u16] {
+        &self.blocks
+    }
+}
+
+/// Combined statistics of the marked blocks
+pub struct MarkStats {
+    pub marked: usize,
+    /// Marked blocks that are free now
+    pub freed: usize,
+    /// Bytes of the marked blocks
+    pub bytes: usize,
+    /// The number of files the marked blocks belong to
+    pub files: usize,
+    /// Marked blocks not in the chain of any file (free ones aside)
+    pub orphaned: usize,
+    /// Runs of consecutive block numbers among the marked blocks
+    pub extents: usize,
+}
+
+impl MarkStats {
+    /// Gather the statistics of the marked blocks of a filesystem
+    pub fn collect(fs: &PennFat, ownership: &Ownership, marks: &Marks) -> Self {
+        let is_free = |block: u16| {
+            fs.fat_entry(block)
+                .is_none_or(|next| fs.profile().classify(next) == Entry::Free)
+        };
+        let freed = marks.blocks().iter().filter(|b| is_free(**b)).count();
+        let files: HashSet<u16> = marks
+            .blocks()
+            .iter()
+            .filter_map(|b| ownership.owner(*b))
+            .map(|owner| owner.first_block)
+            .collect();
+        let orphaned = marks
+            .blocks()
+            .iter()
+            .filter(|b| !is_free(**b) && ownership.owner(**b).is_none())
+            .count();
+        let mut sorted = marks.blocks().to_vec();
+        sorted.sort_unstable();
+        MarkStats {
+            marked: marks.len(),
+            freed,
+            bytes: marks.len() * fs.block_size() as usize,
+            files: files.len(),
+            orphaned,
+            extents: stats::extents(&sorted),
+        }
+    }
+}
+
+impl fmt::Display for MarkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} marked ({} freed, {} orphaned): {} bytes in {} files, {} runs",
+            self.marked, self.freed, self.orphaned, self.bytes, self.files, self.extents
+        )
+    }
+}
+
+/// Get the prefix of the names of files exported from an image
+fn export_prefix(image: &Path) -> String {
+    image
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image".to_owned())
+}
+
+/// Write each marked block to its own file, as `<image name>.block-<number>.bin` in `dir`
+/// (or next to the image). Returns the directory they went in
+pub fn export_each(fs: &PennFat, marks: &Marks, dir: Option<&Path>) -> io::Result<PathBuf> {
+    let dir = snapshot::snapshot_dir(fs.path(), dir);
+    for block in marks.blocks() {
+        let bytes = fs
+            .block_bytes(*block)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let name = format!(
+            "{}.block-{}.bin",
+            export_prefix(fs.path()),
+            fs.format_block(*block)
+        );
+        fs::write(dir.join(name), bytes)?;
+    }
+    Ok(dir)
+}
+
+/// Write the marked blocks one after the other, in the order they were marked, as
+/// `<image name>.marked-<timestamp>.bin` in `dir` (or next to the image)
+pub fn export_concatenated(fs: &PennFat, marks: &Marks, dir: Option<&Path>) -> io::Result<PathBuf> {
+    let name = format!(
+        "{}.marked-{}.bin",
+        export_prefix(fs.path()),
+        Utc::now().format("%Y%m%d-%H%M%S%.3f")
+    );
+    let path = snapshot::snapshot_dir(fs.path(), dir).join(name);
+    let mut file = fs::File::create(&path)?;
+    for block in marks.blocks() {
+        let bytes = fs
+            .block_bytes(*block)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        file.write_all(bytes)?;
+    }
+    Ok(path)
+}
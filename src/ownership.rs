@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use crate::{
+    pennfat::{ChainEnd, PennFat},
+    stats, walk,
+};
+
+/// The file a block belongs to
+pub struct Owner {
+    /// The absolute path of the file
+    pub path: String,
+    /// The first block of the file's chain
+    pub first_block: u16,
+    /// The position of the block in the file's chain (0 for the first block)
+    pub index: usize,
+    /// Whether the file is a directory
+    pub is_dir: bool,
+    /// The size of the file, from its dentry (0 for the root directory, which has none)
+    pub size: u32,
+}
+
+/// The shape of a file's chain, as the FAT links it
+pub struct ChainShape {
+    /// The number of blocks in the chain
+    pub length: usize,
+    /// The number of runs of consecutive blocks the chain is made of
+    pub extents: usize,
+    /// Whether the chain ends anywhere but an end-of-file entry, so its length can't be trusted
+    pub broken: bool,
+}
+
+/// Map from blocks to the files they belong to, built by walking the directory tree
+/// and following each file's chain
+pub struct Ownership {
+    owners: HashMap<u16, Owner>,
+    /// The shape of each file's chain, by its first block
+    chains: HashMap<u16, ChainShape>,
+    /// The paths of the files whose chain starts at each block, more than one if they share it
+    paths: HashMap<u16, Vec<String>>,
+}
+
+impl Ownership {
+    /// Build the ownership map of a filesystem
+    pub fn build(fs: &PennFat) -> Self {
+        let mut ownership = Ownership {
+            owners: HashMap::new(),
+            chains: HashMap::new(),
+            paths: HashMap::new(),
+        };
+        ownership.claim(fs, "/", fs.root_block(), true, 0);
+        for entry in walk::walk(fs) {
+            ownership.claim(
+                fs,
+                &entry.path,
+                entry.dentry.first_block,
+                entry.dentry.is_dir(),
+                entry.dentry.size,
+            );
+        }
+        ownership
+    }
+
+    /// Record the chain starting at `first_block` as belonging to `path`. Blocks already
+    /// claimed by another file keep their first owner
+    fn claim(&mut self, fs: &PennFat, path: &str, first_block: u16, is_dir: bool, size: u32) {
+        let chain = fs.chain(first_block);
+        self.paths
+            .entry(first_block)
+            .or_default()
+            .push(path.to_owned());
+        self.chains
+            .entry(first_block)
+            .or_insert_with(|| ChainShape {
+                length: chain.blocks.len(),
+                extents: stats::extents(&chain.blocks),
+                broken: !matches!(chain.end, ChainEnd::Eof | ChainEnd::Reserved),
+            });
+        for (index, block) in chain.blocks.into_iter().enumerate() {
+            self.owners.entry(block).or_insert_with(|| Owner {
+                path: path.to_owned(),
+                first_block,
+                index,
+                is_dir,
+                size,
+            });
+        }
+    }
+
+    /// Get the file a block belongs to, if any
+    pub fn owner(&self, block: u16) -> Option<&Owner> {
+        self.owners.get(&block)
+    }
+
+    /// Get the paths of every file whose chain starts at a block, in the order they were found
+    pub fn paths(&self, first_block: u16) -> &[String] {
+        self.paths.get(&first_block).map_or(&[], Vec::as_slice)
+    }
+
+    /// Get the shape of the chain of the file a block belongs to, if any
+    pub fn chain_shape(&self, block: u16) -> Option<&ChainShape> {
+        let owner = self.owner(block)?;
+        self.chains.get(&owner.first_block)
+    }
+}
@@ -0,0 +1,269 @@
+use std::ops::Range;
+
+use tui::{buffer::Buffer, layout::Rect, style::Color, widgets::Widget};
+
+use crate::actions::Action;
+
+/// A pane that can have the focus, and so receives the navigation keys
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Pane {
+    /// The list of FAT entries
+    #[default]
+    Fat,
+    /// The contents of the selected block
+    Block,
+    /// The recently modified files, when shown
+    Recent,
+}
+
+impl Pane {
+    /// The panes, in the order Tab goes through them
+    const RING: [Pane; 3] = [Pane::Fat, Pane::Block, Pane::Recent];
+
+    /// What the pane is called, as its title and the help say
+    pub fn name(self) -> &'static str {
+        match self {
+            Pane::Fat => "FAT list",
+            Pane::Block => "block pane",
+            Pane::Recent => "recent files",
+        }
+    }
+
+    /// The pane after this one, wrapping around
+    pub fn next(self) -> Self {
+        let i = Self::RING
+            .iter()
+            .position(|pane| *pane == self)
+            .unwrap_or(0);
+        Self::RING[(i + 1) % Self::RING.len()]
+    }
+
+    /// The pane before this one, wrapping around
+    pub fn previous(self) -> Self {
+        let i = Self::RING
+            .iter()
+            .position(|pane| *pane == self)
+            .unwrap_or(0);
+        Self::RING[(i + Self::RING.len() - 1) % Self::RING.len()]
+    }
+}
+
+/// The state of the block pane: a cursor over its rows (the dentries, or the lines of the raw
+/// view), kept while the same block stays selected
+#[derive(Default)]
+pub struct BlockPane {
+    /// The block the cursor is in
+    pub block: Option<u16>,
+    /// The row the cursor is on
+    pub cursor: usize,
+    /// The number of rows the block had when it was last drawn
+    pub rows: usize,
+    /// The full text of the rows that were cut short to fit when the block was last drawn
+    pub truncated: Vec<Option<String>>,
+    /// The number of rows that fit in the pane when it was last drawn (0 before it's drawn)
+    pub visible: usize,
+    /// Whether lines of text run off the side of the pane, to scroll across with H/L, rather
+    /// than wrapping
+    pub no_wrap: bool,
+    /// How many columns the lines are scrolled across by, without wrapping
+    pub column: usize,
+    /// How far the longest line ran past the side of the pane when it was last drawn
+    pub overflow: usize,
+}
+
+impl BlockPane {
+    /// Show a block, moving the cursor back to the top (and the lines back to their start) if
+    /// it's a different one
+    pub fn show(&mut self, block: Option<u16>) {
+        if self.block != block {
+            self.block = block;
+            self.cursor = 0;
+            self.column = 0;
+        }
+    }
+
+    /// Scroll the lines across by `columns`, to the right if it's positive, no further than
+    /// it takes to bring the end of the longest line into view
+    pub fn scroll_across(&mut self, columns: isize) {
+        self.column = self
+            .column
+            .saturating_add_signed(columns)
+            .min(self.overflow);
+    }
+
+    /// The rows in view, scrolled to keep the cursor in view while the pane has the focus.
+    /// Until the pane has been drawn, every row counts as in view
+    pub fn visible_rows(&self, focused: bool) -> Range<usize> {
+        if self.visible == 0 {
+            return 0..usize::MAX;
+        }
+        let first = match focused {
+            true => self.cursor.saturating_sub(self.visible - 1),
+            false => 0,
+        };
+        first..first + self.visible
+    }
+}
+
+/// Get the rows of a list of `len` rows to draw, when `height` of them fit: from `offset`
+/// (where the list started last time), scrolled as little as it takes to bring the `selected`
+/// row into view. Only these rows need building, however long the list
+pub fn window(offset: usize, selected: usize, len: usize, height: usize) -> Range<usize> {
+    let height = height.max(1);
+    let selected = selected.min(len.saturating_sub(1));
+    // a list that got shorter shouldn't leave the pane half empty
+    let mut first = offset.min(len.saturating_sub(height));
+    if selected < first {
+        first = selected;
+    } else if selected >= first + height {
+        first = selected + 1 - height;
+    }
+    first..(first + height).min(len)
+}
+
+/// Move a cursor over `len` rows by a navigation action, if it is one
+pub fn step(cursor: usize, len: usize, action: Action) -> Option<usize> {
+    match action {
+        Action::Down => Some((cursor + 1).min(len.saturating_sub(1))),
+        Action::Up => Some(cursor.saturating_sub(1)),
+        _ => None,
+    }
+}
+
+/// A scrollbar for a list of `len` rows of which `window` are in view, drawn down a pane's
+/// border: each cell stands for an equal share of the list, the cells over the rows in view
+/// are filled in with `thumb`, and each of the `markers` (a row, its glyph and colour) is put
+/// at its place. The cells with nothing on them are left as the border drew them
+pub struct Minimap<'a> {
+    pub len: usize,
+    pub window: Range<usize>,
+    pub thumb: &'a str,
+    pub markers: Vec<(usize, &'a str, Color)>,
+}
+
+impl Minimap<'_> {
+    /// The cell standing for a row, in a bar `height` cells long
+    fn cell(&self, row: usize, height: usize) -> usize {
+        (row * height / self.len.max(1)).min(height.saturating_sub(1))
+    }
+}
+
+impl Widget for Minimap<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = area.height as usize;
+        // a list that fits needs no scrollbar
+        if height == 0 || self.len <= height {
+            return;
+        }
+        let thumb = self.cell(self.window.start, height)
+            ..=self.cell(self.window.end.saturating_sub(1), height);
+        for y in thumb.clone() {
+            buf.get_mut(area.x, area.y + y as u16)
+                .set_symbol(self.thumb)
+                .set_fg(Color::DarkGray);
+        }
+        for (row, glyph, color) in &self.markers {
+            let y = self.cell(*row, height);
+            let cell = buf.get_mut(area.x, area.y + y as u16);
+            cell.set_symbol(glyph).set_fg(*color);
+            if thumb.contains(&y) {
+                cell.set_bg(Color::DarkGray);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focus_ring_wraps_both_ways() {
+        assert_eq!(Pane::Fat.next(), Pane::Block);
+        assert_eq!(Pane::Recent.next(), Pane::Fat);
+        assert_eq!(Pane::Fat.previous(), Pane::Recent);
+        for pane in Pane::RING {
+            assert_eq!(pane.next().previous(), pane);
+        }
+    }
+
+    #[test]
+    fn step_stays_within_the_rows() {
+        assert_eq!(step(0, 3, Action::Up), Some(0));
+        assert_eq!(step(1, 3, Action::Down), Some(2));
+        assert_eq!(step(2, 3, Action::Down), Some(2));
+        assert_eq!(step(0, 0, Action::Down), Some(0));
+        assert_eq!(step(1, 3, Action::Select), None);
+    }
+
+    #[test]
+    fn window_scrolls_as_little_as_it_takes() {
+        assert_eq!(window(0, 0, 100, 10), 0..10);
+        assert_eq!(window(0, 9, 100, 10), 0..10);
+        assert_eq!(window(0, 10, 100, 10), 1..11);
+        assert_eq!(window(50, 45, 100, 10), 45..55);
+        // a list shorter than the pane is shown whole
+        assert_eq!(window(5, 2, 4, 10), 0..4);
+        assert_eq!(window(0, 0, 0, 10), 0..0);
+    }
+
+    #[test]
+    fn window_handles_the_edges() {
+        // the top and the bottom rows
+        assert_eq!(window(0, 0, 65534, 40), 0..40);
+        assert_eq!(window(0, 65533, 65534, 40), 65494..65534);
+        assert_eq!(window(65494, 65533, 65534, 40), 65494..65534);
+        // a selection past the end is held to the last row
+        assert_eq!(window(0, 70000, 65534, 40), 65494..65534);
+        // selections above and below the last window bring it just far enough
+        assert_eq!(window(1000, 999, 65534, 40), 999..1039);
+        assert_eq!(window(1000, 1040, 65534, 40), 1001..1041);
+        // a selection far from the last window lands at its edge
+        assert_eq!(window(1000, 5, 65534, 40), 5..45);
+        assert_eq!(window(5, 1000, 65534, 40), 961..1001);
+        // a list that shrank under the old offset
+        assert_eq!(window(1000, 10, 20, 40), 0..20);
+        assert_eq!(window(1000, 90, 100, 40), 60..100);
+        // a pane too short for any row still shows the selection
+        assert_eq!(window(0, 7, 100, 0), 7..8);
+    }
+
+    #[test]
+    fn scrolling_through_frames_always_shows_the_selection() {
+        let (len, height) = (500, 40);
+        let mut offset = 0;
+        // down a row at a time, a page at a time, to the end and back to the top
+        let moves = (0..60)
+            .chain((60..len).step_by(height))
+            .chain([len - 1, 0, 250]);
+        for selected in moves {
+            let rows = window(offset, selected, len, height);
+            assert!(rows.contains(&selected), "{} not in {:?}", selected, rows);
+            assert_eq!(rows.len(), height);
+            offset = rows.start;
+        }
+        assert_eq!(offset, 250 - height + 1);
+    }
+
+    #[test]
+    fn block_pane_keeps_the_cursor_on_the_same_block() {
+        let mut block_pane = BlockPane::default();
+        block_pane.show(Some(3));
+        block_pane.cursor = 4;
+        block_pane.show(Some(3));
+        assert_eq!(block_pane.cursor, 4);
+        block_pane.show(Some(5));
+        assert_eq!(block_pane.cursor, 0);
+    }
+
+    #[test]
+    fn visible_rows_follow_the_cursor_only_when_focused() {
+        let block_pane = BlockPane {
+            cursor: 12,
+            visible: 5,
+            ..BlockPane::default()
+        };
+        assert_eq!(block_pane.visible_rows(true), 8..13);
+        assert_eq!(block_pane.visible_rows(false), 0..5);
+    }
+}
@@ -0,0 +1,131 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::pennfat::{BlockFormat, Entry, PennFat, ShowBlocks};
+
+/// FAT entries to write into an image, as shared in a JSON file: a repair to a FAT without
+/// the rest of the image
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FatPatch {
+    /// FAT entry 0 of the image the patch was made from, so it's only applied to an image of
+    /// the same geometry
+    pub config: u16,
+    /// The value of each entry to write, by block number
+    pub entries: BTreeMap<u16, u16>,
+}
+
+/// An entry a patch changes
+pub struct Change {
+    pub block: u16,
+    /// The entry in the image, or None if the image is too short to hold it
+    pub old: Option<u16>,
+    pub new: u16,
+}
+
+impl ShowBlocks for Change {
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result {
+        let old = self
+            .old
+            .map_or_else(|| "(missing)".to_owned(), |old| blocks.block(old));
+        write!(
+            f,
+            "block {}: {} -> {}",
+            blocks.block(self.block),
+            old,
+            blocks.block(self.new)
+        )
+    }
+}
+
+impl FatPatch {
+    /// Make a patch holding every entry of the image's FAT, up to its last data block. The
+    /// free ones are in it too, so applying it frees whatever blocks the image it's applied to
+    /// uses that this one doesn't
+    pub fn export(fs: &PennFat) -> Self {
+        let entries = (1..=fs.data_block_count())
+            .filter_map(|block| Some((block, fs.fat_entry(block)?)))
+            .collect();
+        FatPatch {
+            config: fs.config_entry(),
+            entries,
+        }
+    }
+
+    /// Check that the patch fits the image, and get the entries it would change. Every entry
+    /// is checked before anything is written, so a patch either applies whole or not at all.
+    /// Returns every reason it doesn't fit, if it doesn't
+    pub fn changes(&self, fs: &PennFat) -> Result<Vec<Change>, Vec<String>> {
+        if self.config != fs.config_entry() {
+            return Err(vec![format!(
+                "the patch is for an image configured as {:#06x}, this one is {:#06x}",
+                self.config,
+                fs.config_entry()
+            )]);
+        }
+        let last = fs.data_block_count();
+        let mut errors = Vec::new();
+        for (&block, &next) in &self.entries {
+            if block == 0 || block > last {
+                errors.push(format!(
+                    "block {} is outside the data region (1 to {})",
+                    fs.format_block(block),
+                    fs.format_block(last)
+                ));
+            }
+            if let Entry::Next(to) = fs.profile().classify(next) {
+                if to == 0 || to > last {
+                    errors.push(format!(
+                        "block {} would point to {}, outside the data region",
+                        fs.format_block(block),
+                        fs.format_block(to)
+                    ));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(self
+            .entries
+            .iter()
+            .map(|(&block, &new)| Change {
+                block,
+                old: fs.fat_entry(block),
+                new,
+            })
+            .filter(|change| change.old != Some(change.new))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pfview::testing;
+
+    #[test]
+    fn a_patch_freeing_blocks_frees_them_where_its_applied() {
+        let sample = testing::sample_image();
+        // the fix: free the orphan chain
+        let (_fixed_path, fixed) = testing::writable_image(&sample);
+        for block in 0x1e..=0x20 {
+            fixed.set_fat_entry(block, 0).unwrap();
+        }
+        let json = serde_json::to_string(&FatPatch::export(&fixed)).unwrap();
+        let patch: FatPatch = serde_json::from_str(&json).unwrap();
+        assert_eq!(patch.entries.len(), fixed.data_block_count() as usize);
+
+        let (_path, fs) = testing::writable_image(&sample);
+        let changes = patch.changes(&fs).unwrap();
+        let changed: Vec<(u16, u16)> = changes.iter().map(|c| (c.block, c.new)).collect();
+        assert_eq!(changed, [(0x1e, 0), (0x1f, 0), (0x20, 0)]);
+        for change in &changes {
+            fs.set_fat_entry(change.block, change.new).unwrap();
+        }
+        let fat_size = fs.fat_size() as usize;
+        assert_eq!(fs.bytes()[..fat_size], fixed.bytes()[..fat_size]);
+        assert!(patch.changes(&fs).unwrap().is_empty());
+    }
+}
@@ -1,74 +1,876 @@
-use std::{cmp::min, fs::File, time::SystemTime};
+use std::{
+    cell::{OnceCell, RefCell},
+    cmp::min,
+    collections::{hash_map::DefaultHasher, HashSet},
+    fmt,
+    fs::{File, OpenOptions, TryLockError},
+    hash::Hasher,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
 use chrono::{TimeZone, Utc};
-use memmap2::Mmap;
+use log::debug;
+use memmap2::MmapOptions;
+use serde::Deserialize;
+
+use crate::sigbus::Mapped;
+use crate::trace::Trace;
 
 /// PennFat filesystem representation
 pub struct PennFat {
+    /// The path of the filesystem file
+    path: PathBuf,
     /// The filesystem file
     file: File,
     /// The block size of the filesystem
     block_size: u16,
     /// The number of FAT blocks in the filesystem
     num_fat_blocks: u8,
-    /// The filesystem file as a memmap
-    bytes: Mmap,
+    /// Where in the file the image lies
+    window: Window,
+    /// The image's window of the file as a memmap
+    bytes: Mapped,
+    /// A copy of the image read from the file, once a read of the map found a page missing
+    /// (the file was cut shorter than the map), which is read instead of the map until the file
+    /// is mapped afresh
+    degraded: OnceCell<Vec<u8>>,
     /// The time of the last update to the filesystem file
     last_update: SystemTime,
+    /// The length of the file at the last update. Some platforms (e.g. Windows) update the
+    /// modification time coarsely or lazily, so a change in length counts as an update too
+    last_len: u64,
+    /// A hash of the file's contents at the last update, to tell a change to them from the
+    /// file just being touched. Only kept for images that are watched
+    content_hash: u64,
+    /// Whether to pick up changes to the file on reload
+    watch: bool,
+    /// Whether to also look for changes to the FAT on reload when the file seems unchanged, for
+    /// writes the modification time doesn't register
+    poll_content: bool,
+    /// A hash of the start of the FAT at the last update, to poll it against
+    fat_hash: u64,
+    /// How many modification times the file has been seen with, and whether any had a
+    /// fraction of a second, to tell a filesystem that keeps times to the second
+    mtimes_seen: u32,
+    subsecond_mtimes: bool,
+    /// Whether the file may be written to
+    mode: Mode,
+    /// Whether another process held an exclusive lock on the file when last checked
+    writer_locked: bool,
+    /// The layout of directory entries in directory blocks
+    dentry_layout: DentryLayout,
+    /// The first block of the root directory
+    root_block: u16,
+    /// What the special values of FAT entries mean
+    profile: Profile,
+    /// The most blocks a chain is followed for, or None for as many as the data region has
+    max_chain_len: Option<u16>,
+    /// The reads made while tracing, or None when not tracing
+    trace: RefCell<Option<Trace>>,
+    /// Show block numbers in decimal rather than hex
+    decimal_blocks: bool,
+    /// Whether the image was loaded from bytes in memory, so has no file of its own on disk
+    in_memory: bool,
 }
 
-/// PennFat filesystem errors
+/// PennFat filesystem errors, by what went wrong. Every error has a stable code (PF001,
+/// PF002, ...), which its message starts with and `pfview explain` describes, so scripts can
+/// tell errors apart without reading the prose
 #[derive(thiserror::Error, Debug)]
 pub enum PfError {
-    #[error("IO error: {0}")]
+    /// The image file couldn't be read or written
+    #[error("{}: IO error: {0}", self.code())]
     Io(#[from] std::io::Error),
-    #[error("File size does not match FAT configuration")]
-    FileSize,
-    #[error("Invalid block number {0}, must be >=1 and <= {1}")]
-    InvalidBlockNumber(u16, u16),
+    /// The file doesn't hold an image where, or how, it was said to
+    #[error("{}: {0}", self.code())]
+    Format(FormatError),
+    /// The filesystem refers to something it can't hold
+    #[error("{}: {0}", self.code())]
+    Integrity(IntegrityError),
+    /// The image is too large for this platform to address
+    #[error("{}: {0}", self.code())]
+    Unsupported(UnsupportedError),
+    #[error("{}: The image is open read-only (use --writable to edit it)", self.code())]
+    ReadOnly,
+}
+
+/// The ways a file can fail to hold a PennFat image
+#[derive(thiserror::Error, Debug)]
+pub enum FormatError {
+    #[error(
+        "Image size ({actual} bytes) does not match FAT configuration (expected {expected} bytes)"
+    )]
+    FileSize { expected: u64, actual: u64 },
+    #[error("File is too small to hold the FAT configuration ({len} bytes)")]
+    TooSmall { len: u64 },
+    #[error("Invalid FAT configuration: {reason}")]
+    InvalidConfig { reason: String },
+    #[error("The image's offset ({offset} bytes) is past the end of the file ({len} bytes)")]
+    OffsetPastEnd { offset: u64, len: u64 },
+    #[error("The image would end at byte {end}, past the end of the file ({len} bytes)")]
+    WindowPastEnd { end: u64, len: u64 },
+}
+
+/// The ways a filesystem can refer to something it can't hold
+#[derive(thiserror::Error, Debug)]
+pub enum IntegrityError {
+    #[error(
+        "Invalid block number {}, must be >=1 and <= {}",
+        .blocks.block(*.block),
+        .blocks.block(*.max)
+    )]
+    InvalidBlockNumber {
+        block: u16,
+        max: u16,
+        blocks: BlockFormat,
+    },
+    #[error(
+        "Block {} lies beyond the end of the file (was it truncated?)",
+        .blocks.block(*.block)
+    )]
+    Truncated { block: u16, blocks: BlockFormat },
+    #[error(
+        "Block {} lies beyond the declared length of the embedded image",
+        .blocks.block(*.block)
+    )]
+    PastWindow { block: u16, blocks: BlockFormat },
+    #[error(
+        "The chain starting at block {} is longer than {limit} blocks",
+        .blocks.block(*.start)
+    )]
+    ChainTooLong {
+        start: u16,
+        limit: u16,
+        blocks: BlockFormat,
+    },
+    #[error("Writing {len} bytes at offset {offset} goes past the end of the image")]
+    WriteOutOfBounds { offset: u64, len: usize },
+}
+
+/// The ways an image can be too large for this platform
+#[derive(thiserror::Error, Debug)]
+pub enum UnsupportedError {
+    #[error(
+        "Offset of block {} is too large to address on this platform",
+        .blocks.block(*.block)
+    )]
+    OffsetOverflow { block: u16, blocks: BlockFormat },
+    #[error("The image ({len} bytes) is too large to map on this platform")]
+    WindowTooLarge { len: u64 },
+}
+
+impl PfError {
+    /// The error's stable code, e.g. "PF008"
+    pub fn code(&self) -> &'static str {
+        match self {
+            PfError::Io(_) => "PF001",
+            PfError::Format(e) => match e {
+                FormatError::FileSize { .. } => "PF002",
+                FormatError::TooSmall { .. } => "PF003",
+                FormatError::InvalidConfig { .. } => "PF004",
+                FormatError::OffsetPastEnd { .. } => "PF005",
+                FormatError::WindowPastEnd { .. } => "PF006",
+            },
+            PfError::Integrity(e) => match e {
+                IntegrityError::InvalidBlockNumber { .. } => "PF007",
+                IntegrityError::Truncated { .. } => "PF008",
+                IntegrityError::PastWindow { .. } => "PF009",
+                IntegrityError::ChainTooLong { .. } => "PF010",
+                IntegrityError::WriteOutOfBounds { .. } => "PF011",
+            },
+            PfError::Unsupported(e) => match e {
+                UnsupportedError::OffsetOverflow { .. } => "PF012",
+                UnsupportedError::WindowTooLarge { .. } => "PF013",
+            },
+            PfError::ReadOnly => "PF014",
+        }
+    }
+
+    /// The name of the error's category, e.g. "integrity"
+    pub fn category(&self) -> &'static str {
+        match self {
+            PfError::Io(_) => "io",
+            PfError::Format(_) => "format",
+            PfError::Integrity(_) => "integrity",
+            PfError::Unsupported(_) => "unsupported",
+            PfError::ReadOnly => "read-only",
+        }
+    }
+}
+
+impl From<FormatError> for PfError {
+    fn from(e: FormatError) -> Self {
+        PfError::Format(e)
+    }
+}
+
+impl From<IntegrityError> for PfError {
+    fn from(e: IntegrityError) -> Self {
+        PfError::Integrity(e)
+    }
+}
+
+impl From<UnsupportedError> for PfError {
+    fn from(e: UnsupportedError) -> Self {
+        PfError::Unsupported(e)
+    }
+}
+
+/// Format a byte offset in the image file in hex, with the digits grouped in fours
+/// (e.g. 0x0001_2400), so it's easy to compare with a hex dump
+pub fn format_offset(offset: u64) -> String {
+    let digits = format!("{:08x}", offset);
+    let groups: Vec<&str> = digits
+        .as_bytes()
+        .rchunks(4)
+        .rev()
+        .map(|group| std::str::from_utf8(group).expect("hex digits are ascii"))
+        .collect();
+    format!("0x{}", groups.join("_"))
+}
+
+/// Where a byte of the image file lies in the filesystem
+pub enum Location {
+    /// In the FAT entry of a block
+    Fat { entry: u16 },
+    /// In a data block, at an offset from its start
+    Data { block: u16, offset: u16 },
+    /// Past the last block the FAT can address, at an offset from the end of that block
+    Unaddressed { offset: u64 },
+}
+
+impl ShowBlocks for Location {
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result {
+        match self {
+            Location::Fat { entry } => write!(f, "FAT region, entry {}", blocks.block(*entry)),
+            Location::Data { block, offset } => write!(
+                f,
+                "data block {}, offset {:#06x}",
+                blocks.block(*block),
+                offset
+            ),
+            Location::Unaddressed { offset } => write!(
+                f,
+                "unaddressed region past the last data block, offset {:#x}",
+                offset
+            ),
+        }
+    }
+}
+
+/// How many times to retry opening or mapping a file another process has locked
+const SHARING_RETRIES: u32 = 5;
+
+/// The most bytes of the FAT hashed to poll it for changes. Hashing this much takes well
+/// under a millisecond, so it can be done on every tick
+const POLL_BYTES: usize = 128 << 10;
+
+/// Hash some of an image's contents (or the whole of them), quickly rather than securely
+fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Run an IO operation on the image, retrying it for a little while if it fails because another
+/// process has the file locked. Windows refuses to open or map a file while its writer holds it
+/// without sharing it, which usually only lasts for the duration of the write
+fn retry_sharing_violation<T>(mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut retries = 0;
+    loop {
+        match op() {
+            Err(e) if is_sharing_violation(&e) && retries < SHARING_RETRIES => {
+                retries += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether an IO error is a sharing or lock violation
+#[cfg(windows)]
+fn is_sharing_violation(e: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    matches!(e.raw_os_error(), Some(32 | 33))
+}
+
+/// Whether an IO error is a sharing or lock violation, which only Windows has
+#[cfg(not(windows))]
+fn is_sharing_violation(_: &io::Error) -> bool {
+    false
+}
+
+/// What reloading an image found
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Reload {
+    /// The file wasn't modified since the last load
+    Unchanged,
+    /// The file was modified, but its contents are the same (e.g. it was touched, or copied
+    /// over with the same bytes)
+    Touched,
+    /// The contents of the file changed
+    Changed,
+}
+
+/// How an image is opened
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// The file is opened read-only, and every write fails with [`PfError::ReadOnly`]
+    ReadOnly,
+    /// The file is opened for writing too, so it can be edited
+    Writable,
+}
+
+/// Where an image lies in its file: the whole file, or a part of it for an image embedded in a
+/// larger one (e.g. a disk image with a partition table). Every offset in the image, as read
+/// and shown, is relative to the start of the window
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Window {
+    /// The byte the image starts at
+    pub offset: u64,
+    /// How many bytes long the image is, or None if it runs to the end of the file
+    pub length: Option<u64>,
+}
+
+impl Window {
+    /// Map the window of a file `len` bytes long, checking that it lies within the file
+    fn map(&self, file: &File, len: u64) -> Result<Mapped> {
+        if self.offset > len {
+            return Err(FormatError::OffsetPastEnd {
+                offset: self.offset,
+                len,
+            }
+            .into());
+        }
+        let length = self.length.unwrap_or(len - self.offset);
+        let end = self.offset.saturating_add(length);
+        if end > len {
+            return Err(FormatError::WindowPastEnd { end, len }.into());
+        }
+        let length = usize::try_from(length)
+            .map_err(|_| UnsupportedError::WindowTooLarge { len: length })?;
+        // make sure the mmap updates if the file changes
+        let bytes = retry_sharing_violation(|| unsafe {
+            MmapOptions::new().offset(self.offset).len(length).map(file)
+        })?;
+        Ok(Mapped::new(bytes))
+    }
 }
 
-type Result<T> = std::result::Result<T, PfError>;
+pub type Result<T> = std::result::Result<T, PfError>;
+
+/// How block numbers are shown: in hex with a 0x prefix, with as many digits (at least 2) as
+/// the last data block of the image needs, or in decimal. Each image has its own, see
+/// [`PennFat::block_format`]
+///
+/// ```
+/// use pfview::pennfat::BlockFormat;
+///
+/// let small = BlockFormat { last: 0x7f, decimal: false };
+/// assert_eq!(small.block(0x2a), "0x2a");
+/// // values past the last block, such as 0xffff, take the digits they need
+/// assert_eq!(small.block(0xffff), "0xffff");
+/// assert_eq!(BlockFormat { decimal: true, ..small }.block(0x2a), "42");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BlockFormat {
+    /// The last data block, which hex block numbers are sized to fit
+    pub last: u16,
+    /// Show block numbers in decimal rather than hex
+    pub decimal: bool,
+}
+
+impl Default for BlockFormat {
+    /// Hex, with the four digits the largest image needs
+    fn default() -> Self {
+        BlockFormat {
+            last: 0xfffe,
+            decimal: false,
+        }
+    }
+}
+
+impl BlockFormat {
+    /// Format a block number
+    pub fn block(self, block: u16) -> String {
+        let digits = ((16 - self.last.leading_zeros() as usize).div_ceil(4)).max(2);
+        match self.decimal {
+            true => block.to_string(),
+            false => format!("0x{:0digits$x}", block),
+        }
+    }
+
+    /// How wide a formatted block number is at most, to line up a column of them
+    pub fn block_width(self) -> usize {
+        self.block(self.last).len()
+    }
+
+    /// How wide a formatted FAT entry is at most, to line up a column of them: entries can
+    /// hold any value, not just block numbers
+    pub fn entry_width(self) -> usize {
+        self.block(u16::MAX).len().max(self.block_width())
+    }
+}
+
+/// Something whose description has block numbers in it, so it's shown with a [`BlockFormat`]
+/// rather than on its own
+pub trait ShowBlocks {
+    /// Write the description, formatting block numbers as `blocks` says
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result;
+
+    /// Show it with its block numbers formatted as `blocks` says
+    fn show(&self, blocks: BlockFormat) -> Shown<'_, Self> {
+        Shown {
+            value: self,
+            blocks,
+        }
+    }
+}
+
+/// Something with block numbers in it, shown with a block format. See [`ShowBlocks::show`]
+pub struct Shown<'a, T: ?Sized> {
+    value: &'a T,
+    blocks: BlockFormat,
+}
+
+impl<T: ShowBlocks + ?Sized> fmt::Display for Shown<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt_blocks(f, self.blocks)
+    }
+}
 
 impl PennFat {
     /// Load a PennFat filesystem from a file on disk
-    /// This will mmap the file, so it will be updated if the file changes
-    pub fn load(path: &str) -> Result<Self> {
-        let file = File::open(path).unwrap();
-        // make sure the mmap updates if the file changes
-        let bytes = unsafe { Mmap::map(&file).unwrap() };
-        let last_update = file.metadata()?.modified()?;
+    /// This will mmap the file, so it will be updated if the file changes. Only the window of
+    /// the file is mapped, and it stays the same on reload
+    pub fn load(path: impl AsRef<Path>, mode: Mode, window: Window) -> Result<Self> {
+        Self::open(path.as_ref(), mode, window, true)
+    }
 
+    /// Load a snapshot of a PennFat filesystem. Snapshots never change, so unlike
+    /// [`PennFat::load`], reloading never picks up changes to the file
+    pub fn load_snapshot(path: &Path) -> Result<Self> {
+        Self::open(path, Mode::ReadOnly, Window::default(), false)
+    }
+
+    /// Load a PennFat filesystem from bytes in memory, such as an image built for a test. It's
+    /// read-only, and never reloads. The bytes are kept in an anonymous temporary file, which
+    /// nothing else can open and which goes away with the filesystem
+    ///
+    /// ```
+    /// use pfview::pennfat::{Entry, PennFat};
+    ///
+    /// let fs = PennFat::from_bytes(pfview::testing::sample_image()).unwrap();
+    /// // the FAT lists the allocated blocks, with the block after each in its chain
+    /// let fat = fs.get_fat_table();
+    /// assert!(fat.contains(&(0x04, 0x0c)));
+    /// assert!(fs.profile().classify(0xffff) == Entry::Eof);
+    /// ```
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let mut file = tempfile::tempfile()?;
+        io::Write::write_all(&mut file, &bytes)?;
+        let mut fs = Self::from_file(
+            PathBuf::from("<memory>"),
+            file,
+            Mode::ReadOnly,
+            Window::default(),
+            false,
+        )?;
+        fs.in_memory = true;
+        Ok(fs)
+    }
+
+    fn open(path: &Path, mode: Mode, window: Window, watch: bool) -> Result<Self> {
+        let file = retry_sharing_violation(|| {
+            OpenOptions::new()
+                .read(true)
+                .write(mode == Mode::Writable)
+                .open(path)
+        })?;
+        Self::from_file(PathBuf::from(path), file, mode, window, watch)
+    }
+
+    fn from_file(
+        path: PathBuf,
+        file: File,
+        mode: Mode,
+        window: Window,
+        watch: bool,
+    ) -> Result<Self> {
+        let metadata = file.metadata()?;
+        let (last_update, last_len) = (metadata.modified()?, metadata.len());
+        let bytes = window.map(&file, last_len)?;
+
+        if bytes.len() < 2 {
+            return Err(FormatError::TooSmall {
+                len: bytes.len() as u64,
+            }
+            .into());
+        }
         let block_size_config = bytes[0];
         // second byte is the number of blocks, as an unsigned 8-bit integer
         let num_fat_blocks: u8 = bytes[1];
+        // anything larger doesn't fit the 16-bit block size
+        if block_size_config > 7 {
+            return Err(FormatError::InvalidConfig {
+                reason: format!(
+                    "block size config {} is out of range (0-7)",
+                    block_size_config
+                ),
+            }
+            .into());
+        }
+        if num_fat_blocks == 0 {
+            return Err(FormatError::InvalidConfig {
+                reason: "the FAT must span at least one block".to_owned(),
+            }
+            .into());
+        }
         let block_size: u16 = 256 << block_size_config;
 
-        let s = Self {
+        let mut s = Self {
+            path,
             file,
             block_size,
             num_fat_blocks,
+            window,
             bytes,
+            degraded: OnceCell::new(),
             last_update,
+            last_len,
+            content_hash: 0,
+            watch,
+            poll_content: false,
+            fat_hash: 0,
+            mtimes_seen: 0,
+            subsecond_mtimes: false,
+            mode,
+            writer_locked: false,
+            dentry_layout: DentryLayout::default(),
+            // conventionally, the root directory starts at the first data block
+            root_block: 1,
+            profile: Profile::default(),
+            max_chain_len: None,
+            trace: RefCell::new(None),
+            decimal_blocks: false,
+            in_memory: false,
         };
 
-        if s.file.metadata()?.len() != s.fat_size() as u64 + s.data_size() {
-            return Err(PfError::FileSize);
+        s.check_size(s.bytes.len() as u64)?;
+        s.note_mtime(last_update);
+        if watch {
+            s.content_hash = content_hash(s.contents());
+            s.fat_hash = s.hash_fat();
         }
+        s.check_writer_lock();
 
         Ok(s)
     }
 
-    /// Reload the filesystem from disk if it has changed since the last load
-    pub fn reload(&mut self) -> Result<()> {
+    /// Check that the file is as long as its FAT configuration says. The data region has a
+    /// block per FAT entry after the first, but no more than block numbers can address. A FAT
+    /// spanning more blocks than that is a valid geometry, just a wasteful one: the image is
+    /// sized by its data region, and the entries past the last block go unused (see
+    /// `usable_entries`). A writer that doesn't know about the cap writes the blocks past it
+    /// anyway, so with such a FAT, a longer file is read with the excess left unaddressed
+    fn check_size(&self, actual: u64) -> Result<()> {
+        let expected = self.fat_size() as u64 + self.data_size();
+        let capped = self.num_fat_entries() - 1 > self.data_block_count() as u32;
+        match actual == expected || (capped && actual > expected) {
+            true => Ok(()),
+            false => Err(FormatError::FileSize { expected, actual }.into()),
+        }
+    }
+
+    /// Check whether another process holds an exclusive lock on the file, which usually means
+    /// it's in the middle of writing it. The shared lock taken to find out is let go right
+    /// away, so a writer that locks the file is never kept waiting on the viewer
+    fn check_writer_lock(&mut self) {
+        if !self.watch || self.mode == Mode::Writable {
+            return;
+        }
+        self.writer_locked = match self.file.try_lock_shared() {
+            Ok(()) => {
+                let _ = self.file.unlock();
+                false
+            }
+            Err(TryLockError::WouldBlock) => true,
+            // e.g. a filesystem without locks; nothing to go on
+            Err(TryLockError::Error(_)) => false,
+        };
+    }
+
+    /// Open another view of the same image, read the same way, for use on another thread.
+    /// It maps the file afresh, so it sees the file as it is now, and never reloads
+    pub fn try_clone(&self) -> Result<Self> {
+        let file = self.file.try_clone()?;
+        let bytes = self.window.map(&file, file.metadata()?.len())?;
+        Ok(Self {
+            path: self.path.clone(),
+            file,
+            bytes,
+            degraded: OnceCell::new(),
+            watch: false,
+            writer_locked: false,
+            trace: RefCell::new(None),
+            ..*self
+        })
+    }
+
+    /// Reload the filesystem from disk if it has changed since the last load.
+    /// Returns whether the file was modified, and if so whether its contents changed
+    pub fn reload(&mut self) -> Result<Reload> {
+        self.check_writer_lock();
+        if !self.watch {
+            return Ok(Reload::Unchanged);
+        }
         // reload the file, but only if it has changed
-        if self.file.metadata()?.modified()? == self.last_update {
-            return Ok(());
+        let metadata = self.file.metadata()?;
+        let (modified, len) = (metadata.modified()?, metadata.len());
+        if modified == self.last_update && len == self.last_len {
+            if !self.poll_content || self.hash_fat() == self.fat_hash {
+                return Ok(Reload::Unchanged);
+            }
+            debug!("the FAT changed, but the modification time didn't");
         }
-        self.bytes = unsafe { Mmap::map(&self.file).unwrap() };
-        self.last_update = self.file.metadata()?.modified()?;
+        self.remap(modified, len)
+    }
 
-        Ok(())
+    /// Look for changes to the FAT on every reload, as well as to the file's modification time
+    /// and length. Only the first [`POLL_BYTES`] of the FAT are hashed, so it's cheap enough to
+    /// do at the tick rate; changes past that, or to data blocks alone, are still missed
+    pub fn set_poll_content(&mut self, poll_content: bool) {
+        self.poll_content = poll_content;
+    }
+
+    /// Hash the first [`POLL_BYTES`] of the FAT, as mapped now
+    fn hash_fat(&self) -> u64 {
+        let contents = self.contents();
+        let len = (self.fat_size() as usize)
+            .min(POLL_BYTES)
+            .min(contents.len());
+        content_hash(&contents[..len])
+    }
+
+    /// Count a modification time the file was seen with
+    fn note_mtime(&mut self, modified: SystemTime) {
+        self.mtimes_seen += 1;
+        let subsec = modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.subsec_nanos());
+        self.subsecond_mtimes |= subsec != 0;
+    }
+
+    /// Get whether the file's modification times seem to be kept to the second: every one seen,
+    /// across at least two updates, was a whole second. Then two writes within a second of each
+    /// other look like one, and a reload between them misses the second
+    pub fn coarse_mtimes(&self) -> bool {
+        self.mtimes_seen >= 2 && !self.subsecond_mtimes
+    }
+
+    /// Reload the filesystem from disk whether or not it seems to have changed, for when the
+    /// modification time can't be trusted (e.g. a rewrite within the same second, on a
+    /// filesystem that keeps times to the second). Snapshots never change, so they're left be
+    pub fn reload_force(&mut self) -> Result<Reload> {
+        self.check_writer_lock();
+        if !self.watch {
+            return Ok(Reload::Unchanged);
+        }
+        let metadata = self.file.metadata()?;
+        self.remap(metadata.modified()?, metadata.len())
+    }
+
+    /// Map the file afresh, as it was when modified at `modified` with `len` bytes, and find
+    /// out whether its contents changed
+    fn remap(&mut self, modified: SystemTime, len: u64) -> Result<Reload> {
+        let start = Instant::now();
+        self.bytes = self.window.map(&self.file, len)?;
+        self.degraded = OnceCell::new();
+        self.last_update = modified;
+        self.last_len = len;
+        self.note_mtime(modified);
+        self.fat_hash = self.hash_fat();
+        debug!(
+            "reloaded {} ({} bytes) in {:?}",
+            self.path.display(),
+            len,
+            start.elapsed()
+        );
+
+        // the writer may have truncated or grown the file; the accessors stay bounds-checked,
+        // but let the caller know the image no longer matches its FAT configuration
+        self.check_size(self.bytes.len() as u64)?;
+
+        // a writer that copies the image into place, or `touch`, bumps the modification time
+        // without changing a byte
+        let hash = content_hash(self.contents());
+        if hash == self.content_hash {
+            return Ok(Reload::Touched);
+        }
+        self.content_hash = hash;
+        Ok(Reload::Changed)
+    }
+
+    /// Get whether the file may be written to
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Get whether another process held an exclusive lock on the file when it was last loaded
+    /// or reloaded
+    pub fn writer_locked(&self) -> bool {
+        self.writer_locked
+    }
+
+    /// Get the path of the filesystem file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the image was loaded from bytes in memory, rather than from a file. Its path
+    /// names no file, so nothing should be written next to it
+    pub fn in_memory(&self) -> bool {
+        self.in_memory
+    }
+
+    /// Get where in the file the image lies
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
+    /// Get the raw contents of the image, as currently mapped (or as copied, once a read found
+    /// the file shorter than the map; see [`PennFat::degraded`])
+    pub fn bytes(&self) -> &[u8] {
+        self.contents()
+    }
+
+    /// Get the image's bytes to read. This runs for every read, so it doesn't ask the file
+    /// for its length: a reload already does, mapping the file afresh when the length changed.
+    ///
+    /// Touching a page of the map past the end of the file raises SIGBUS, as when a writer
+    /// truncates the file between reloads. On Unix, the SIGBUS handler in `sigbus` gives that
+    /// read zeros for the missing page and marks the map faulted. From then on, what's left of
+    /// the image is read into a buffer, and read from there until a reload maps the file
+    /// afresh. Elsewhere there's no signal to survive, but nor can a mapped file be truncated
+    fn contents(&self) -> &[u8] {
+        if let Some(buffer) = self.degraded.get() {
+            return buffer;
+        }
+        if !self.bytes.faulted() {
+            return &self.bytes;
+        }
+        match self.file.metadata() {
+            Ok(metadata) => self
+                .degraded
+                .get_or_init(|| self.read_window(metadata.len())),
+            // if the length can't be had, there's nothing better to go on than the map
+            Err(_) => &self.bytes,
+        }
+    }
+
+    /// Read what's left of the image's window from a file that's now `len` bytes long, without
+    /// going through the map. Stops short at the end of the file, or on an error
+    fn read_window(&self, len: u64) -> Vec<u8> {
+        let available = len.saturating_sub(self.window.offset);
+        let mut buffer = vec![0; min(available, self.bytes.len() as u64) as usize];
+        let mut read = 0;
+        while read < buffer.len() {
+            let offset = self.window.offset + read as u64;
+            #[cfg(unix)]
+            let result =
+                std::os::unix::fs::FileExt::read_at(&self.file, &mut buffer[read..], offset);
+            #[cfg(not(unix))]
+            let result = {
+                use std::io::{Read, Seek, SeekFrom};
+                let mut file = &self.file;
+                file.seek(SeekFrom::Start(offset))
+                    .and_then(|_| file.read(&mut buffer[read..]))
+            };
+            match result {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    debug!("reading {} past its map: {}", self.path.display(), e);
+                    break;
+                }
+            }
+        }
+        buffer.truncate(read);
+        debug!(
+            "{} is shorter than its map ({} bytes), reading {} bytes from a copy",
+            self.path.display(),
+            self.bytes.len(),
+            read
+        );
+        buffer
+    }
+
+    /// Get whether a read found the file shorter than the map since it was last mapped, so the
+    /// image is read from a copy until the next reload
+    pub fn degraded(&self) -> bool {
+        self.degraded.get().is_some()
+    }
+
+    /// Get the layout of directory entries in directory blocks
+    pub fn dentry_layout(&self) -> &DentryLayout {
+        &self.dentry_layout
+    }
+
+    /// Set the layout of directory entries in directory blocks, for images following
+    /// a spec that differs from the standard one
+    pub fn set_dentry_layout(&mut self, layout: DentryLayout) {
+        self.dentry_layout = layout;
+    }
+
+    /// Get the first block of the root directory
+    pub fn root_block(&self) -> u16 {
+        self.root_block
+    }
+
+    /// Set the first block of the root directory, for images that don't keep it in block 1
+    pub fn set_root_block(&mut self, block: u16) {
+        self.root_block = block;
+    }
+
+    /// Get what the special values of FAT entries mean
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    /// Set what the special values of FAT entries mean, for images following a spec that
+    /// differs from the standard one
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+    }
+
+    /// Get whether block numbers are shown in decimal
+    pub fn decimal_blocks(&self) -> bool {
+        self.decimal_blocks
+    }
+
+    /// Show block numbers in decimal (or back in hex)
+    pub fn set_decimal_blocks(&mut self, decimal: bool) {
+        self.decimal_blocks = decimal;
+    }
+
+    /// Get how the image shows block numbers: sized to its last data block, in hex unless
+    /// it's been set to decimal
+    pub fn block_format(&self) -> BlockFormat {
+        BlockFormat {
+            last: self.data_block_count(),
+            decimal: self.decimal_blocks,
+        }
+    }
+
+    /// Format a block number the way the image shows them, see [`PennFat::block_format`]
+    pub fn format_block(&self, block: u16) -> String {
+        self.block_format().block(block)
     }
 
     /// Get the block size of the filesystem
@@ -93,7 +895,34 @@ impl PennFat {
 
     /// Get the number of data blocks in the filesystem
     pub fn data_block_count(&self) -> u16 {
-        min((self.num_fat_entries() - 1) as u16, 0xFFFF - 1)
+        // block numbers are 16-bit, and 0xFFFF marks the end of a chain
+        min(self.num_fat_entries() - 1, 0xFFFF - 1) as u16
+    }
+
+    /// Get the number of FAT entries that can mean anything: entry 0 and one per data block.
+    /// The FAT may have more, when it spans more blocks than the data region can use, but
+    /// nothing can link to the entries past these
+    pub fn usable_entries(&self) -> u32 {
+        self.data_block_count() as u32 + 1
+    }
+
+    /// Get the bytes of FAT past the usable entries, which can never be used
+    pub fn wasted_fat_bytes(&self) -> u32 {
+        (self.num_fat_entries() - self.usable_entries()) * 2
+    }
+
+    /// Get the FAT entries past the usable ones that aren't zero, as (entry, value) tuples.
+    /// Nothing can reach them, so they're likely written by a bug that got the geometry wrong
+    pub fn unreachable_entries(&self) -> Vec<(u32, u16)> {
+        let bytes = self.contents();
+        let num_entries = min(self.num_fat_entries(), bytes.len() as u32 / 2);
+        (self.usable_entries()..num_entries)
+            .filter_map(|i| {
+                let offset = (i * 2) as usize;
+                let entry = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+                (entry != 0).then_some((i, entry))
+            })
+            .collect()
     }
 
     /// Get the size of the data in bytes (not including the FAT)
@@ -101,54 +930,550 @@ impl PennFat {
         self.block_size as u64 * self.data_block_count() as u64
     }
 
-    /// Get the FAT table as a vector of (block_num, next_block) tuples
+    /// Get where the bytes past the last data block start in the file
+    pub fn unaddressed_offset(&self) -> u64 {
+        self.fat_size() as u64 + self.data_size()
+    }
+
+    /// Get the bytes of the file past the last data block, which no block number can address.
+    /// Only an image whose FAT has more entries than block numbers go up to can have any
+    pub fn unaddressed(&self) -> &[u8] {
+        usize::try_from(self.unaddressed_offset())
+            .ok()
+            .and_then(|start| self.contents().get(start..))
+            .unwrap_or_default()
+    }
+
+    /// Get the FAT table as a vector of (block_num, next_block) tuples, for the allocated
+    /// blocks. Entry 0 holds the configuration rather than a link, so it's left out, as are the
+    /// entries past the last data block (see [`PennFat::unreachable_entries`])
+    ///
+    /// ```
+    /// use pfview::pennfat::PennFat;
+    ///
+    /// let fs = PennFat::from_bytes(pfview::testing::sample_image()).unwrap();
+    /// for (block, next) in fs.get_fat_table() {
+    ///     println!("{} -> {}", fs.format_block(block), fs.format_block(next));
+    /// }
+    /// // the root directory's chain, blocks 0x01 and 0x09
+    /// assert_eq!(fs.fat_entry(0x01), Some(0x09));
+    /// assert_eq!(fs.chain(0x01).blocks, [0x01, 0x09]);
+    /// ```
     pub fn get_fat_table(&self) -> Vec<(u16, u16)> {
-        let mut fat_table = Vec::new();
-        for i in 0..self.num_fat_entries() {
-            let offset = (i * 2) as usize;
-            let entry = u16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]]);
-            if entry != 0 {
-                fat_table.push((i as u16, entry));
+        self.scan_fat().entries
+    }
+
+    /// Scan the FAT for the allocated blocks, as [`PennFat::get_fat_table`] lists them, along
+    /// with where each one is in the list. Runs of free entries are skipped 8 at a time, so a
+    /// large, mostly empty FAT is quick to scan
+    pub fn scan_fat(&self) -> FatTable {
+        // a truncated file may not even hold the whole FAT
+        let bytes = self.contents();
+        let num_entries = min(self.usable_entries(), bytes.len() as u32 / 2) as usize;
+        let mut entries = Vec::new();
+        let mut positions = vec![u32::MAX; num_entries];
+        // zeros only mean free under profiles where the free entry is 0
+        let skip_zeros = self.profile.free == 0;
+        for (n, chunk) in bytes[..num_entries * 2].chunks(16).enumerate() {
+            if skip_zeros
+                && chunk
+                    .try_into()
+                    .is_ok_and(|chunk| u128::from_ne_bytes(chunk) == 0)
+            {
+                continue;
+            }
+            for (j, entry) in chunk.chunks_exact(2).enumerate() {
+                let i = n * 8 + j;
+                let entry = u16::from_le_bytes([entry[0], entry[1]]);
+                // entry 0 is the configuration
+                if i > 0 && self.profile.classify(entry) != Entry::Free {
+                    positions[i] = entries.len() as u32;
+                    entries.push((i as u16, entry));
+                }
+            }
+        }
+        FatTable { entries, positions }
+    }
+
+    /// Get FAT entry 0 as the image was loaded: the block size config in the low byte and the
+    /// number of FAT blocks in the high one. The image is read with this configuration, even
+    /// if the entry changes in a reload
+    pub fn config_entry(&self) -> u16 {
+        let block_size_config = self.block_size.trailing_zeros() as u16 - 8;
+        block_size_config | (self.num_fat_blocks as u16) << 8
+    }
+
+    /// Get the FAT entry of a block, or None if it's outside the FAT (or the file is truncated)
+    pub fn fat_entry(&self, block_num: u16) -> Option<u16> {
+        if block_num as u32 >= self.num_fat_entries() {
+            return None;
+        }
+        let offset = block_num as usize * 2;
+        let entry = self
+            .contents()
+            .get(offset..offset + 2)
+            .map(|entry| u16::from_le_bytes([entry[0], entry[1]]));
+        self.trace(
+            "fat entry",
+            Some(block_num),
+            Some(offset as u64),
+            || match entry.map(|entry| self.profile.classify(entry)) {
+                Some(Entry::Next(next)) => format!("next {}", self.format_block(next)),
+                Some(Entry::Eof) => "EOF".to_owned(),
+                Some(Entry::Free) => "FREE".to_owned(),
+                Some(Entry::Reserved) => "RESERVED".to_owned(),
+                None => "past the end of the file".to_owned(),
+            },
+        );
+        entry
+    }
+
+    /// Start recording the reads made from the image (FAT entries, blocks and the dentries
+    /// searched), for [`PennFat::finish_trace`] to return
+    pub fn start_trace(&self) {
+        *self.trace.borrow_mut() = Some(Trace::default());
+    }
+
+    /// Stop recording reads, returning the ones made since [`PennFat::start_trace`]
+    pub fn finish_trace(&self) -> Option<Trace> {
+        self.trace.borrow_mut().take()
+    }
+
+    /// Record a read while tracing. What it read is only described while tracing
+    pub fn trace(
+        &self,
+        operation: &'static str,
+        block: Option<u16>,
+        offset: Option<u64>,
+        value: impl FnOnce() -> String,
+    ) {
+        // described before borrowing the trace, in case describing reads the image too
+        if self.trace.borrow().is_none() {
+            return;
+        }
+        let value = value();
+        if let Some(trace) = self.trace.borrow_mut().as_mut() {
+            trace.step(operation, block, offset, value);
+        }
+    }
+
+    /// Record the start of an operation while tracing, nesting the reads after it under it
+    /// until [`PennFat::trace_leave`]
+    pub fn trace_enter(
+        &self,
+        operation: &'static str,
+        block: Option<u16>,
+        value: impl FnOnce() -> String,
+    ) {
+        if self.trace.borrow().is_none() {
+            return;
+        }
+        let value = value();
+        if let Some(trace) = self.trace.borrow_mut().as_mut() {
+            trace.enter(operation, block, None, value);
+        }
+    }
+
+    /// Record the end of the operation being traced
+    pub fn trace_leave(&self) {
+        if let Some(trace) = self.trace.borrow_mut().as_mut() {
+            trace.leave();
+        }
+    }
+
+    /// Get the most blocks a chain is followed for. Unless set lower, it's the number of data
+    /// blocks, which only a cycle could go past
+    pub fn max_chain_len(&self) -> u16 {
+        self.max_chain_len.unwrap_or(self.data_block_count())
+    }
+
+    /// Set the most blocks a chain is followed for, so a pathologically long chain can't make
+    /// everything that walks chains slow
+    pub fn set_max_chain_len(&mut self, limit: u16) {
+        self.max_chain_len = Some(limit);
+    }
+
+    /// Get the chain of blocks starting at the given block, following the FAT.
+    /// The walk stops at the end of the chain, at a block outside the data region,
+    /// at a block already visited (so a cycle in the FAT can't loop forever), or once it has
+    /// [`PennFat::max_chain_len`] blocks
+    pub fn chain(&self, first_block: u16) -> Chain {
+        let mut blocks = Vec::new();
+        let mut visited = HashSet::new();
+        let mut block = first_block;
+        let limit = self.max_chain_len();
+        let end = loop {
+            if block == 0 || block > self.data_block_count() {
+                break ChainEnd::OutOfRange(block);
+            }
+            if !visited.insert(block) {
+                break ChainEnd::Cycle(block);
             }
+            if blocks.len() >= limit as usize {
+                break ChainEnd::TooLong(limit);
+            }
+            blocks.push(block);
+            match self
+                .fat_entry(block)
+                .map(|entry| self.profile.classify(entry))
+            {
+                Some(Entry::Eof) => break ChainEnd::Eof,
+                Some(Entry::Reserved) => break ChainEnd::Reserved,
+                Some(Entry::Free) | None => break ChainEnd::Free,
+                Some(Entry::Next(next)) => block = next,
+            }
+        };
+        Chain {
+            blocks,
+            end,
+            format: self.block_format(),
         }
-        fat_table
     }
 
     /// Get a block from the filesystem by block number
     pub fn get_block(&self, block_num: u16) -> Result<Block> {
+        self.block_bytes(block_num).map(Block::from)
+    }
+
+    /// Get the offset of a data block within the file. The math is done in u64, so it can't
+    /// wrap around; an offset that doesn't fit in a usize (e.g. on a 32-bit target) is an error
+    pub fn block_offset(&self, block_num: u16) -> Result<usize> {
+        let offset =
+            self.fat_size() as u64 + (block_num as u64).saturating_sub(1) * self.block_size as u64;
+        usize::try_from(offset).map_err(|_| {
+            UnsupportedError::OffsetOverflow {
+                block: block_num,
+                blocks: self.block_format(),
+            }
+            .into()
+        })
+    }
+
+    /// Find where a byte offset of the image file lies in the filesystem. Offsets past the end
+    /// of the file have no location
+    pub fn locate(&self, offset: u64) -> Option<Location> {
+        if offset >= self.contents().len() as u64 {
+            return None;
+        }
+        let fat_size = self.fat_size() as u64;
+        if offset < fat_size {
+            return Some(Location::Fat {
+                entry: (offset / 2) as u16,
+            });
+        }
+        let block = (offset - fat_size) / self.block_size as u64 + 1;
+        if block > self.data_block_count() as u64 {
+            return Some(Location::Unaddressed {
+                offset: offset - self.unaddressed_offset(),
+            });
+        }
+        Some(Location::Data {
+            block: block as u16,
+            offset: ((offset - fat_size) % self.block_size as u64) as u16,
+        })
+    }
+
+    /// Get the bytes of a block by block number, without copying them
+    pub fn block_bytes(&self, block_num: u16) -> Result<&[u8]> {
         if block_num == 0 || block_num > self.data_block_count() {
-            return Err(PfError::InvalidBlockNumber(
-                block_num,
-                self.data_block_count(),
-            ));
+            return Err(IntegrityError::InvalidBlockNumber {
+                block: block_num,
+                max: self.data_block_count(),
+                blocks: self.block_format(),
+            }
+            .into());
         }
-        let offset: usize =
-            self.fat_size() as usize + (block_num as usize - 1) * self.block_size as usize;
-        Ok(Block::from(
-            &self.bytes[offset..offset + self.block_size as usize],
-        ))
+        let start = self.block_offset(block_num)?;
+        let end = start.checked_add(self.block_size as usize).ok_or(
+            UnsupportedError::OffsetOverflow {
+                block: block_num,
+                blocks: self.block_format(),
+            },
+        )?;
+        let bytes = self.contents().get(start..end);
+        self.trace(
+            "read",
+            Some(block_num),
+            Some(start as u64),
+            || match bytes {
+                Some(bytes) => format!("{} bytes", bytes.len()),
+                None => "past the end of the file".to_owned(),
+            },
+        );
+        bytes.ok_or(match self.window.length {
+            Some(_) => IntegrityError::PastWindow {
+                block: block_num,
+                blocks: self.block_format(),
+            }
+            .into(),
+            None => IntegrityError::Truncated {
+                block: block_num,
+                blocks: self.block_format(),
+            }
+            .into(),
+        })
     }
 
-    /// Get a file from the filesystem, starting at the given block number
-    #[allow(dead_code)]
-    pub fn get_file(&self, block_num: u16) -> Result<Vec<u8>> {
+    /// Read a file's contents along with the slack after them: the data of every block of its
+    /// chain, whatever the size in its dentry
+    pub fn read_allocated(&self, dentry: &Dentry) -> Result<Vec<u8>> {
         let mut file = Vec::new();
-        let mut block = block_num;
-        loop {
-            let next_block = u16::from_le_bytes([
-                self.bytes[2 + block as usize * 2],
-                self.bytes[2 + block as usize * 2 + 1],
-            ]);
-            file.extend_from_slice(&self.get_block(block)?.data);
-            if next_block == 0xFFFF {
+        for block in self.chain(dentry.first_block).blocks {
+            file.extend_from_slice(self.block_bytes(block)?);
+        }
+        Ok(file)
+    }
+
+    /// Read the contents of a file: the data of its chain, cut to the size in its dentry.
+    /// A broken chain gives whatever data it holds
+    ///
+    /// ```
+    /// use pfview::pennfat::PennFat;
+    /// use pfview::walk::{self, Lookup};
+    ///
+    /// let fs = PennFat::from_bytes(pfview::testing::sample_image()).unwrap();
+    /// let Lookup::Found(notes) = walk::lookup(&fs, "/notes.txt", true) else {
+    ///     panic!("no /notes.txt");
+    /// };
+    /// // its chain is scattered, but it reads in order
+    /// let contents = fs.read_file(&notes.dentry).unwrap();
+    /// assert_eq!(contents.len(), 940);
+    /// assert!(contents.starts_with(b"note 01: "));
+    /// ```
+    pub fn read_file(&self, dentry: &Dentry) -> Result<Vec<u8>> {
+        let mut file = Vec::new();
+        let chain = self.chain(dentry.first_block);
+        for block in &chain.blocks {
+            if file.len() >= dentry.size as usize {
                 break;
             }
-            block = next_block;
+            file.extend_from_slice(self.block_bytes(*block)?);
+        }
+        // a chain cut short by the limit only matters if the file goes on past it
+        if file.len() < dentry.size as usize {
+            chain.check_len()?;
         }
+        file.truncate(dentry.size as usize);
         Ok(file)
     }
 }
 
+/// Writing to the filesystem file. Every write fails with [`PfError::ReadOnly`] unless it was
+/// loaded with [`Mode::Writable`]. The mmap picks the changes up on the next reload
+impl PennFat {
+    /// Write bytes at an offset in the image, which must stay within the image. On Unix this is
+    /// a single pwrite, so the file offset a writer sharing the file may rely on isn't moved
+    pub fn write_bytes(&self, offset: u64, data: &[u8]) -> Result<()> {
+        if self.mode != Mode::Writable {
+            return Err(PfError::ReadOnly);
+        }
+        let len = data.len();
+        if offset.saturating_add(len as u64) > self.bytes.len() as u64 {
+            return Err(IntegrityError::WriteOutOfBounds { offset, len }.into());
+        }
+        let offset = self.window.offset + offset;
+        #[cfg(unix)]
+        std::os::unix::fs::FileExt::write_all_at(&self.file, data, offset)?;
+        #[cfg(not(unix))]
+        {
+            use std::io::{Seek, SeekFrom, Write};
+            let mut file = &self.file;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    /// Set the FAT entry of a data block, i.e. the block after it in its chain. Entry 0 holds
+    /// the configuration, not a link, and the entries past the last data block belong to no
+    /// block, so neither can be set this way
+    pub fn set_fat_entry(&self, block_num: u16, next: u16) -> Result<()> {
+        if block_num == 0 || block_num > self.data_block_count() {
+            return Err(IntegrityError::InvalidBlockNumber {
+                block: block_num,
+                max: self.data_block_count(),
+                blocks: self.block_format(),
+            }
+            .into());
+        }
+        self.write_bytes(block_num as u64 * 2, &next.to_le_bytes())
+    }
+
+    /// Overwrite a data block, or the start of it if `data` is shorter than a block
+    pub fn write_block(&self, block_num: u16, data: &[u8]) -> Result<()> {
+        // make sure the block is valid and in the file first
+        self.block_bytes(block_num)?;
+        let offset = self.block_offset(block_num)? as u64;
+        if data.len() > self.block_size as usize {
+            return Err(IntegrityError::WriteOutOfBounds {
+                offset,
+                len: data.len(),
+            }
+            .into());
+        }
+        self.write_bytes(offset, data)
+    }
+}
+
+/// How a chain of blocks ends
+#[derive(Clone, Copy)]
+pub enum ChainEnd {
+    /// The last block is marked as the end of the chain
+    Eof,
+    /// The last block is marked free, so the chain is broken
+    Free,
+    /// The last block's entry holds the reserved value rather than a link
+    Reserved,
+    /// The chain points to a block outside the data region
+    OutOfRange(u16),
+    /// The chain points back to a block already in it
+    Cycle(u16),
+    /// The chain goes on past the most blocks a chain is followed for
+    TooLong(u16),
+}
+
+/// A chain of blocks, as linked by the FAT
+pub struct Chain {
+    /// The blocks in the chain, in order
+    pub blocks: Vec<u16>,
+    /// How the chain ends
+    pub end: ChainEnd,
+    /// How the image the chain is in shows block numbers, for its errors
+    pub format: BlockFormat,
+}
+
+impl Chain {
+    /// Fail with [`IntegrityError::ChainTooLong`] if the chain was cut short by the limit on its
+    /// length, so what's read from it would be incomplete
+    pub fn check_len(&self) -> Result<()> {
+        match (self.end, self.blocks.first()) {
+            (ChainEnd::TooLong(limit), Some(&start)) => Err(IntegrityError::ChainTooLong {
+                start,
+                limit,
+                blocks: self.format,
+            }
+            .into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What the special values of FAT entries mean. Specs differ on these (some set a value aside
+/// for special purposes), so they're looked up in a profile rather than assumed
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// The name the profile is selected by, with `--profile`
+    pub name: &'static str,
+    /// The entry of a free block
+    pub free: u16,
+    /// The entry of the last block of a chain
+    pub eof: u16,
+    /// A value set aside for special purposes, rather than a link to a block
+    pub reserved: Option<u16>,
+}
+
+/// What a FAT entry means, under a profile
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    Free,
+    Eof,
+    Reserved,
+    /// A link to the next block of the chain
+    Next(u16),
+}
+
+impl Default for Profile {
+    /// The standard PennFat profile
+    fn default() -> Self {
+        Profile::PENNFAT
+    }
+}
+
+impl Profile {
+    /// The standard PennFat meanings: 0 is free and 0xFFFF the end of a chain
+    pub const PENNFAT: Profile = Profile {
+        name: "pennfat",
+        free: 0,
+        eof: 0xFFFF,
+        reserved: None,
+    };
+
+    /// PennFat, with the value 1 reserved. Block 1 starts the root directory, so no entry
+    /// would link to it anyway
+    pub const RESERVED: Profile = Profile {
+        name: "reserved",
+        reserved: Some(1),
+        ..Profile::PENNFAT
+    };
+
+    /// Every profile, by name
+    pub const ALL: [Profile; 2] = [Profile::PENNFAT, Profile::RESERVED];
+
+    /// Get the profile with the given name
+    pub fn by_name(name: &str) -> Option<Profile> {
+        Self::ALL.into_iter().find(|profile| profile.name == name)
+    }
+
+    /// Guess the profile of an image from its FAT, going by the standard profile unless the
+    /// FAT can't be read by it. That's only certain when the root directory starts in block 1:
+    /// nothing links to the start of a chain, so entries of 1 then mean the value is reserved.
+    /// With the root elsewhere, block 1 is an ordinary block, and entries of 1 links to it
+    pub fn detect(fs: &PennFat) -> Profile {
+        let Some(reserved) = Profile::RESERVED.reserved else {
+            return Profile::PENNFAT;
+        };
+        if fs.root_block() != reserved {
+            return Profile::PENNFAT;
+        }
+        match fs
+            .get_fat_table()
+            .iter()
+            .any(|(block, next)| *next == reserved && *block != reserved)
+        {
+            true => Profile::RESERVED,
+            false => Profile::PENNFAT,
+        }
+    }
+
+    /// Tell what a FAT entry means
+    pub fn classify(&self, entry: u16) -> Entry {
+        match entry {
+            _ if entry == self.free => Entry::Free,
+            _ if entry == self.eof => Entry::Eof,
+            _ if Some(entry) == self.reserved => Entry::Reserved,
+            next => Entry::Next(next),
+        }
+    }
+}
+
+/// The allocated blocks of a FAT, as scanned once for a version of the image
+pub struct FatTable {
+    /// The (block_num, next_block) entry of every allocated block, in block order
+    pub entries: Vec<(u16, u16)>,
+    /// Where each block's entry is in `entries` (index = block), or u32::MAX if it's free
+    positions: Vec<u32>,
+}
+
+impl FatTable {
+    /// Get where a block's entry is in the list, or None if the block isn't allocated
+    pub fn position(&self, block_num: u16) -> Option<usize> {
+        match self.positions.get(block_num as usize) {
+            Some(&i) if i != u32::MAX => Some(i as usize),
+            _ => None,
+        }
+    }
+
+    /// Whether a block is allocated
+    pub fn is_allocated(&self, block_num: u16) -> bool {
+        self.position(block_num).is_some()
+    }
+
+    /// Get a block's FAT entry, or None if the block is free
+    pub fn entry(&self, block_num: u16) -> Option<u16> {
+        self.position(block_num).map(|i| self.entries[i].1)
+    }
+}
+
 /// A PennFat block
 pub struct Block {
     /// The block data
@@ -165,32 +1490,346 @@ impl From<&[u8]> for Block {
 }
 
 impl Block {
-    /// Get the block as a string, replacing non-printable characters with '.'
-    pub fn as_raw(&self) -> String {
-        let mut string = String::new();
-        for byte in &self.data {
-            if *byte < 32 || *byte > 176 {
-                string.push('.');
-            } else {
-                string.push(*byte as char);
-            }
+    /// Get the printable representation of a byte, replacing non-printable characters with '.'
+    fn raw_char(byte: u8) -> char {
+        if !(32..=176).contains(&byte) {
+            '.'
+        } else {
+            byte as char
         }
-        string
     }
 
-    /// Get the block as a vector of dentries
-    pub fn as_dentries(&self) -> Vec<Dentry> {
+    /// Get a range of the block as rows of `row_len` bytes, each paired with the offset of its
+    /// first byte within the block, with non-printable characters replaced by '.'
+    pub fn as_raw_rows(&self, range: Range<usize>, row_len: usize) -> Vec<(usize, String)> {
+        let start = range.start.min(self.data.len());
+        let end = range.end.clamp(start, self.data.len());
+        self.data[start..end]
+            .chunks(row_len.max(1))
+            .enumerate()
+            .map(|(i, chunk)| {
+                (
+                    start + i * row_len.max(1),
+                    chunk.iter().map(|b| Self::raw_char(*b)).collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// Get the block as a vector of dentries laid out as described by `layout`
+    pub fn as_dentries(&self, layout: &DentryLayout) -> Vec<Dentry> {
+        // a short trailing chunk can't hold a dentry, so it is skipped
         self.data
-            .chunks(64)
-            .map(|chunk| Dentry::from(chunk))
+            .chunks_exact(layout.size)
+            .filter_map(|chunk| Dentry::parse(chunk, layout))
             .collect()
     }
+
+    /// Count how the dentry slots of the block are used
+    pub fn dentry_usage(&self, layout: &DentryLayout) -> DentryUsage {
+        let mut usage = DentryUsage::default();
+        for dentry in self.as_dentries(layout) {
+            if dentry.is_free() {
+                usage.free += 1;
+            } else if dentry.is_in_use() {
+                usage.used += 1;
+                // directories are meant to be packed: nothing in use after the first free slot
+                usage.unpacked |= usage.free > 0;
+            } else {
+                usage.deleted += 1;
+            }
+        }
+        usage
+    }
+}
+
+/// How the dentry slots of a directory block are used
+#[derive(Default)]
+pub struct DentryUsage {
+    /// Slots holding an entry in use
+    pub used: usize,
+    /// Free slots (name starting with 0)
+    pub free: usize,
+    /// Slots holding a deleted entry
+    pub deleted: usize,
+    /// Whether an entry in use comes after a free slot
+    pub unpacked: bool,
+}
+
+/// Where a field of a directory entry lives within it
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Field {
+    /// Offset of the field from the start of the entry
+    pub offset: usize,
+    /// Length of the field in bytes
+    pub len: usize,
+}
+
+impl Field {
+    /// Get where the field lies within an entry of `size` bytes, cut short to fit in it
+    pub fn within(&self, size: usize) -> Range<usize> {
+        let start = self.offset.min(size);
+        let end = (self.offset + self.len).min(size);
+        start..end
+    }
+
+    /// Get the bytes of the field within an entry, or as much of them as fits in it
+    fn bytes<'a>(&self, entry: &'a [u8]) -> &'a [u8] {
+        &entry[self.within(entry.len())]
+    }
+
+    /// Read the field within an entry as a little-endian unsigned integer
+    /// (of at most 8 bytes). Bytes that don't fit in the entry read as 0
+    fn read_le(&self, entry: &[u8]) -> u64 {
+        self.bytes(entry)
+            .iter()
+            .take(8)
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as u64)
+    }
+}
+
+/// The unit a directory entry's modification time is counted in, since the epoch
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeUnit {
+    #[default]
+    Milliseconds,
+    Seconds,
+}
+
+impl TimeUnit {
+    /// The number of milliseconds in the unit
+    pub fn millis(self) -> u64 {
+        match self {
+            TimeUnit::Milliseconds => 1,
+            TimeUnit::Seconds => 1000,
+        }
+    }
+}
+
+/// Which values of a directory entry's perm byte are legal. Each is read as the rwx bits of
+/// `ls -l`, e.g. 5 is read+execute
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PermTable {
+    /// The spec's table: none (0), write (2), read (4), read+execute (5), read+write (6) and
+    /// read+write+execute (7)
+    #[default]
+    Spec,
+    /// Any combination of the rwx bits, 0 to 7
+    Bits,
+}
+
+impl PermTable {
+    /// The legal perm values, in order
+    pub fn legal(self) -> &'static [u8] {
+        match self {
+            PermTable::Spec => &[0, 2, 4, 5, 6, 7],
+            PermTable::Bits => &[0, 1, 2, 3, 4, 5, 6, 7],
+        }
+    }
+
+    pub fn is_legal(self, perm: u8) -> bool {
+        self.legal().contains(&perm)
+    }
+
+    /// Get what a perm value means, e.g. "read+write", or None if it isn't legal
+    pub fn meaning(self, perm: u8) -> Option<String> {
+        if !self.is_legal(perm) {
+            return None;
+        }
+        let bits: Vec<&str> = [(4, "read"), (2, "write"), (1, "execute")]
+            .into_iter()
+            .filter(|(bit, _)| perm & bit != 0)
+            .map(|(_, name)| name)
+            .collect();
+        Some(match bits.is_empty() {
+            true => "none".to_owned(),
+            false => bits.join("+"),
+        })
+    }
+
+    /// Describe a perm value as the dentry table shows it, e.g. "6 (read+write)"
+    pub fn describe(self, perm: u8) -> String {
+        match self.meaning(perm) {
+            Some(meaning) => format!("{} ({})", perm, meaning),
+            None => format!("{} (invalid)", perm),
+        }
+    }
+}
+
+/// The layout of a directory entry: its size, where each field lives within it, and the unit
+/// of its modification time, along with what the fork allows dentries to do. Forks of PennFat
+/// lay their entries out differently, so this can be read from a TOML file as well as picked
+/// from the built-in ones
+#[derive(Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DentryLayout {
+    /// Size of a directory entry in bytes
+    pub size: usize,
+    pub name: Field,
+    pub file_size: Field,
+    pub first_block: Field,
+    #[serde(rename = "type")]
+    pub type_: Field,
+    pub perm: Field,
+    pub mtime: Field,
+    #[serde(default)]
+    pub mtime_unit: TimeUnit,
+    /// Which perm values are legal
+    #[serde(default)]
+    pub perms: PermTable,
+    /// Whether files may share a chain, as hard links do, rather than that being corruption
+    #[serde(default)]
+    pub allow_shared_chains: bool,
+    /// Whether directories must be packed, with no entry in use after a free slot
+    #[serde(default)]
+    pub packed_dirs: bool,
+    /// Whether the entries in use of a directory must be sorted by name
+    #[serde(default)]
+    pub sorted_dirs: bool,
+}
+
+impl Default for DentryLayout {
+    /// The standard 64-byte PennFat directory entry
+    fn default() -> Self {
+        DentryLayout::PENNFAT_V1
+    }
+}
+
+impl DentryLayout {
+    /// The standard 64-byte PennFat directory entry
+    pub const PENNFAT_V1: DentryLayout = DentryLayout {
+        size: 64,
+        name: Field { offset: 0, len: 32 },
+        file_size: Field { offset: 32, len: 4 },
+        first_block: Field { offset: 36, len: 2 },
+        type_: Field { offset: 38, len: 1 },
+        perm: Field { offset: 39, len: 1 },
+        mtime: Field { offset: 40, len: 8 },
+        mtime_unit: TimeUnit::Milliseconds,
+        perms: PermTable::Spec,
+        allow_shared_chains: false,
+        packed_dirs: false,
+        sorted_dirs: false,
+    };
+
+    /// A 64-byte entry with a 40-byte name, and a 32-bit mtime in seconds to make room for it
+    pub const PENNFAT_WIDE: DentryLayout = DentryLayout {
+        size: 64,
+        name: Field { offset: 0, len: 40 },
+        file_size: Field { offset: 40, len: 4 },
+        first_block: Field { offset: 44, len: 2 },
+        type_: Field { offset: 46, len: 1 },
+        perm: Field { offset: 47, len: 1 },
+        mtime: Field { offset: 48, len: 4 },
+        mtime_unit: TimeUnit::Seconds,
+        perms: PermTable::Spec,
+        allow_shared_chains: false,
+        packed_dirs: false,
+        sorted_dirs: false,
+    };
+
+    /// Every built-in layout, by name
+    pub const ALL: [(&'static str, DentryLayout); 2] = [
+        ("pennfat-v1", DentryLayout::PENNFAT_V1),
+        ("pennfat-wide", DentryLayout::PENNFAT_WIDE),
+    ];
+
+    /// Get the built-in layout with the given name
+    pub fn by_name(name: &str) -> Option<DentryLayout> {
+        Self::ALL
+            .into_iter()
+            .find(|(layout_name, _)| *layout_name == name)
+            .map(|(_, layout)| layout)
+    }
+
+    /// Check that the layout makes sense: every field fits in the entry, and the integer
+    /// fields are no wider than what they're read into
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.size == 0 {
+            return Err("the entry size can't be 0".to_owned());
+        }
+        let fields = [
+            ("name", self.name, self.size),
+            ("file_size", self.file_size, 4),
+            ("first_block", self.first_block, 2),
+            ("type", self.type_, 1),
+            ("perm", self.perm, 1),
+            ("mtime", self.mtime, 8),
+        ];
+        for (name, field, max_len) in fields {
+            if field.len == 0 || field.len > max_len {
+                return Err(format!("{} must be 1 to {} bytes long", name, max_len));
+            }
+            if field.offset + field.len > self.size {
+                return Err(format!(
+                    "{} doesn't fit in a {}-byte entry",
+                    name, self.size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The layout, for directory entries of a different size. The fields keep their offsets;
+    /// in smaller entries, fields that don't fit are cut short (or read as 0), and in larger
+    /// ones the extra space is reserved
+    pub fn with_size(self, size: usize) -> Self {
+        DentryLayout { size, ..self }
+    }
+
+    /// Every field of the entry, named, with the reserved space after them last
+    pub fn fields(&self) -> [(&'static str, Field); 7] {
+        [
+            ("name", self.name),
+            ("size", self.file_size),
+            ("first_block", self.first_block),
+            ("type", self.type_),
+            ("perm", self.perm),
+            ("mtime", self.mtime),
+            ("reserved", self.reserved()),
+        ]
+    }
+
+    /// The space after the last field, reserved for future use
+    fn reserved(&self) -> Field {
+        let end = [
+            self.name,
+            self.file_size,
+            self.first_block,
+            self.type_,
+            self.perm,
+            self.mtime,
+        ]
+        .iter()
+        .map(|field| field.offset + field.len)
+        .max()
+        .unwrap_or(0);
+        Field {
+            offset: end,
+            len: self.size.saturating_sub(end),
+        }
+    }
+}
+
+/// Format a modification time, in milliseconds since the epoch, as dentries show it
+pub fn format_mtime(mtime: u64) -> String {
+    let time = i64::try_from(mtime)
+        .ok()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single());
+    match time {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => format!("invalid ({:#x})", mtime),
+    }
 }
 
 /// A PennFat directory entry
 pub struct Dentry {
     /// The name of the file
-    pub name: [u8; 32],
+    pub name: Vec<u8>,
     /// The size of the file in bytes
     pub size: u32,
     /// The first block of the file
@@ -199,51 +1838,533 @@ pub struct Dentry {
     pub type_: u8,
     /// The permissions of the file
     pub perm: u8,
-    /// The modification time of the file
+    /// The modification time of the file, in milliseconds since the epoch (whatever unit the
+    /// layout keeps it in)
     pub mtime: u64,
     /// Reserved bytes
-    pub _reserved: [u8; 16],
+    pub _reserved: Vec<u8>,
 }
 
-impl std::fmt::Display for Dentry {
-    /// Format a dentry for printing
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = String::from_utf8_lossy(&self.name);
-        let size = self.size;
-        let first_block = self.first_block;
-        let _type = self.type_;
-        let perm = self.perm;
-        // check if mtime is valid unix timestamp
-        let time = if self.mtime > 253402300799 {
-            "invalid".to_owned()
-        } else {
-            let naive = Utc
-                .timestamp_millis_opt(self.mtime.try_into().unwrap())
-                .single()
-                .unwrap();
-            // format to human readable form
-            naive.format("%Y-%m-%d %H:%M:%S").to_string()
-        };
+impl Dentry {
+    /// Get the name of the file, up to the first NUL
+    pub fn name(&self) -> String {
+        let len = self
+            .name
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.name.len());
+        String::from_utf8_lossy(&self.name[..len]).into_owned()
+    }
 
-        write!(
-            f,
+    /// Get the name as paths and listings show it. A name that can't be a component of a path
+    /// is escaped, '/' as "\x2f" and the dots of "." and ".." as "\x2e", so it still reads as
+    /// one component and never as another file's path
+    pub fn display_name(&self) -> String {
+        match self.name() {
+            name if name == "." || name == ".." => name.replace('.', "\\x2e"),
+            name => name.replace('/', "\\x2f"),
+        }
+    }
+
+    /// Whether the dentry is in use, i.e. neither a free slot (name starting with 0)
+    /// nor a deleted entry (name starting with 1 or 2)
+    pub fn is_in_use(&self) -> bool {
+        self.name.first().is_some_and(|b| *b > 2)
+    }
+
+    /// Whether the dentry is a free slot (name starting with 0)
+    pub fn is_free(&self) -> bool {
+        self.name.first().is_some_and(|b| *b == 0)
+    }
+
+    /// Whether the dentry is a directory
+    pub fn is_dir(&self) -> bool {
+        self.type_ == 1
+    }
+
+    /// Whether the dentry is a symbolic link, whose contents are the path it points to
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == 2
+    }
+
+    /// Format the modification time, in milliseconds since the epoch, in a human readable
+    /// form. Garbage that isn't a representable time (as in a directory block that isn't one)
+    /// is shown as "invalid", with the raw value
+    pub fn format_mtime(&self) -> String {
+        format_mtime(self.mtime)
+    }
+
+    /// Format the dentry as for printing, with `name` in place of its name, its perm decoded
+    /// by `perms` and its first block formatted as `blocks` says
+    pub fn format_as(&self, name: &str, perms: PermTable, blocks: BlockFormat) -> String {
+        let time = self.format_mtime();
+        format!(
             "name: {}, size: {}, first_block: {}, type: {}, perm: {}, mtime: {},",
-            name, size, first_block, _type, perm, time
+            name,
+            self.size,
+            blocks.block(self.first_block),
+            self.type_,
+            perms.describe(self.perm),
+            time
         )
     }
 }
 
-impl From<&[u8]> for Dentry {
-    /// Create a dentry from a slice of bytes
-    fn from(block: &[u8]) -> Self {
-        Dentry {
-            name: block[0..32].try_into().unwrap(),
-            size: u32::from_le_bytes(block[32..36].try_into().unwrap()),
-            first_block: u16::from_le_bytes(block[36..38].try_into().unwrap()),
-            type_: block[38],
-            perm: block[39],
-            mtime: u64::from_le_bytes(block[40..48].try_into().unwrap()),
-            _reserved: block[48..64].try_into().unwrap(),
+impl ShowBlocks for Dentry {
+    /// Format a dentry for printing, with its perm decoded by the spec's table
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result {
+        let name = String::from_utf8_lossy(&self.name);
+        f.write_str(&self.format_as(&name, PermTable::default(), blocks))
+    }
+}
+
+impl Dentry {
+    /// Parse a dentry laid out as described by `layout` from a slice of bytes,
+    /// or None if the slice is too short to hold one
+    pub fn parse(entry: &[u8], layout: &DentryLayout) -> Option<Self> {
+        if entry.len() < layout.size {
+            return None;
         }
+        let entry = &entry[..layout.size];
+        Some(Dentry {
+            name: layout.name.bytes(entry).to_vec(),
+            size: layout.file_size.read_le(entry) as u32,
+            first_block: layout.first_block.read_le(entry) as u16,
+            type_: layout.type_.read_le(entry) as u8,
+            perm: layout.perm.read_le(entry) as u8,
+            mtime: layout
+                .mtime
+                .read_le(entry)
+                .saturating_mul(layout.mtime_unit.millis()),
+            _reserved: layout.reserved().bytes(entry).to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    /// Open an image of the largest geometry there is: 32 KiB blocks, a FAT spanning 255 of
+    /// them, and the 0xfffe data blocks that block numbers go up to. The file is sparse, so it
+    /// takes next to no room on disk
+    fn largest() -> PennFat {
+        let mut file = tempfile::tempfile().unwrap();
+        io::Write::write_all(&mut file, &[7, 255]).unwrap();
+        file.set_len(255 * 32768 + 0xfffe * 32768).unwrap();
+        PennFat::from_file(
+            PathBuf::from("<largest>"),
+            file,
+            Mode::ReadOnly,
+            Window::default(),
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn largest_geometry_offsets() {
+        let fs = largest();
+        assert_eq!(fs.block_size(), 32768);
+        assert_eq!(fs.fat_size(), 255 * 32768);
+        // more FAT entries than block numbers, capped rather than cut to 16 bits
+        assert!(fs.num_fat_entries() > 0x10000);
+        assert_eq!(fs.data_block_count(), 0xfffe);
+        assert_eq!(fs.block_offset(1).unwrap(), 255 * 32768);
+        assert_eq!(
+            fs.block_offset(0xfffe).unwrap() as u64,
+            255 * 32768 + 0xfffd * 32768
+        );
+        assert_eq!(
+            fs.unaddressed_offset(),
+            fs.bytes().len() as u64,
+            "the last block ends the file"
+        );
+        assert_eq!(fs.block_bytes(0xfffe).unwrap().len(), 32768);
+        assert!(fs.unaddressed().is_empty());
+    }
+
+    #[test]
+    fn largest_geometry_rejects_blocks_past_the_end() {
+        let fs = largest();
+        assert!(matches!(
+            fs.block_bytes(0xffff),
+            Err(PfError::Integrity(
+                IntegrityError::InvalidBlockNumber { .. }
+            ))
+        ));
+        assert!(fs.block_bytes(0).is_err());
+        assert!(fs.locate(fs.bytes().len() as u64).is_none());
+    }
+
+    #[test]
+    fn only_data_blocks_fat_entries_are_set() {
+        let (_path, fs) = crate::testing::writable_image(&crate::testing::sample_image());
+        let before = fs.bytes().to_vec();
+        let last = fs.data_block_count();
+        for block in [0, last + 1, u16::MAX] {
+            assert_eq!(
+                fs.set_fat_entry(block, 3).err().unwrap().to_string(),
+                format!(
+                    "PF007: Invalid block number {}, must be >=1 and <= 0x7f",
+                    fs.format_block(block)
+                )
+            );
+        }
+        assert_eq!(fs.bytes(), before);
+        fs.set_fat_entry(last, 3).unwrap();
+        assert_eq!(fs.fat_entry(last), Some(3));
+
+        // a FAT with entries to spare still has none for blocks past the last
+        assert!(matches!(
+            largest().set_fat_entry(0xffff, 3),
+            Err(PfError::Integrity(
+                IntegrityError::InvalidBlockNumber { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn truncating_the_file_while_it_is_read_is_survived() {
+        let sample = crate::testing::sample_image();
+        let (path, mut fs) = crate::testing::writable_image(&sample);
+        let len = sample.len() as u64;
+        let done = Arc::new(AtomicBool::new(false));
+        // cut the file short and grow it back, over and over
+        let writer = {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    file.set_len(len / 3).unwrap();
+                    file.set_len(len).unwrap();
+                }
+            })
+        };
+        // and read every block meanwhile, mapping the file afresh between rounds
+        let mut cut_short = 0;
+        for _ in 0..2000 {
+            for block in 1..=fs.data_block_count() {
+                if let Ok(bytes) = fs.block_bytes(block) {
+                    let start = fs.block_offset(block).unwrap();
+                    // a block is read whole, as zeros where the file was cut
+                    let original = &sample[start..start + bytes.len()];
+                    assert!(bytes
+                        .iter()
+                        .zip(original)
+                        .all(|(&read, &byte)| read == byte || read == 0));
+                }
+            }
+            cut_short += fs.degraded() as usize;
+            let _ = fs.reload_force();
+        }
+        done.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+        assert!(cut_short > 0, "the reads never met a truncated file");
+    }
+
+    #[test]
+    fn block_numbers_are_sized_to_each_image() {
+        let small = PennFat::from_bytes(crate::testing::sample_image()).unwrap();
+        let mut large = largest();
+        assert_eq!(small.format_block(5), "0x05");
+        assert_eq!(small.block_format().block_width(), 4);
+        assert_eq!(small.block_format().entry_width(), 6);
+        assert_eq!(large.format_block(5), "0x0005");
+        assert_eq!(large.block_format().block_width(), 6);
+        // switching one image to decimal leaves the other as it was
+        large.set_decimal_blocks(true);
+        assert_eq!(large.format_block(0xfffe), "65534");
+        assert_eq!(large.block_format().block_width(), 5);
+        assert_eq!(small.format_block(0x7f), "0x7f");
+    }
+
+    #[test]
+    fn errors_show_blocks_as_their_image_does() {
+        let mut fs = PennFat::from_bytes(crate::testing::sample_image()).unwrap();
+        let e = fs.block_bytes(0x80).unwrap_err();
+        assert!(e.to_string().contains("0x80"), "{}", e);
+        assert!(e.to_string().contains("0x7f"), "{}", e);
+        fs.set_decimal_blocks(true);
+        let e = fs.block_bytes(0x80).unwrap_err();
+        assert!(e.to_string().contains("128"), "{}", e);
+        assert!(e.to_string().contains("127"), "{}", e);
+    }
+
+    #[test]
+    fn mtimes_that_arent_times_show_their_raw_value() {
+        assert_eq!(format_mtime(0), "1970-01-01 00:00:00");
+        assert_eq!(format_mtime(1_700_000_000_000), "2023-11-14 22:13:20");
+        assert_eq!(format_mtime(1 << 63), "invalid (0x8000000000000000)");
+        assert_eq!(format_mtime(u64::MAX), "invalid (0xffffffffffffffff)");
+        // fits an i64, but is no date chrono can represent
+        assert_eq!(
+            format_mtime(i64::MAX as u64),
+            "invalid (0x7fffffffffffffff)"
+        );
+    }
+
+    #[test]
+    fn garbage_dentries_display_without_panicking() {
+        let layout = DentryLayout::default();
+        for byte in [0x00, 0x7f, 0xff] {
+            let dentry = Dentry::parse(&vec![byte; layout.size], &layout).unwrap();
+            let shown = dentry.show(BlockFormat::default()).to_string();
+            assert!(shown.contains("mtime: "), "{}", shown);
+        }
+        let dentry = Dentry::parse(&vec![0xff; layout.size], &layout).unwrap();
+        assert!(dentry.format_mtime().starts_with("invalid (0x"));
+    }
+
+    /// Build an image with a file and a directory in its root, their dentries laid out as
+    /// `layout` says, with mtimes in the layout's unit
+    fn image_in_layout(layout: &DentryLayout, name: &str) -> Vec<u8> {
+        let mut image = crate::fuzz::ImageBuilder::new(0, 1);
+        let file = image.allocate_lowest(2).unwrap();
+        image.write(&file, &[b'x'; 300]);
+        let dir = image.allocate_lowest(1).unwrap();
+        let seconds = 1_700_000_000;
+        let mtime = match layout.mtime_unit {
+            TimeUnit::Seconds => seconds,
+            TimeUnit::Milliseconds => seconds * 1000,
+        };
+        let dentry = |name: &str, size, first_block, type_| crate::fuzz::DentrySpec {
+            name: name.as_bytes().to_vec(),
+            size,
+            first_block,
+            type_,
+            perm: 6,
+            mtime,
+        };
+        image.write_dentry_as(1, 0, &dentry(name, 300, file[0], 0), layout);
+        image.write_dentry_as(1, 1, &dentry("dir", 0, dir[0], 1), layout);
+        image.bytes()
+    }
+
+    #[test]
+    fn each_builtin_layout_reads_its_own_images() {
+        for (layout_name, layout) in DentryLayout::ALL {
+            let name = "n".repeat(layout.name.len);
+            let mut fs = PennFat::from_bytes(image_in_layout(&layout, &name)).unwrap();
+            fs.set_dentry_layout(layout);
+            let entries = crate::walk::walk(&fs);
+            let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+            assert_eq!(
+                paths,
+                [format!("/{}", name).as_str(), "/dir"],
+                "{}",
+                layout_name
+            );
+            let file = &entries[0].dentry;
+            assert_eq!(file.size, 300, "{}", layout_name);
+            assert_eq!(file.first_block, 2, "{}", layout_name);
+            assert_eq!(file.perm, 6, "{}", layout_name);
+            assert_eq!(file.mtime, 1_700_000_000_000, "{}", layout_name);
+            assert_eq!(
+                file.format_mtime(),
+                "2023-11-14 22:13:20",
+                "{}",
+                layout_name
+            );
+            assert!(entries[1].dentry.is_dir(), "{}", layout_name);
+        }
+    }
+
+    #[test]
+    fn a_long_name_only_reads_in_the_layout_that_has_room_for_it() {
+        let name = "n".repeat(40);
+        let bytes = image_in_layout(&DentryLayout::PENNFAT_WIDE, &name);
+        let fs = PennFat::from_bytes(bytes).unwrap();
+        assert_eq!(fs.dentry_layout().name.len, 32);
+        // read as pennfat-v1, the name is cut at 32 bytes and the rest is garbage
+        let entries = crate::walk::walk(&fs);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.path != format!("/{}", name)));
+    }
+
+    /// List the allocated blocks one FAT entry at a time, as the scan would without skipping
+    fn naive_scan(fs: &PennFat) -> Vec<(u16, u16)> {
+        (1..fs.usable_entries())
+            .filter_map(|block| Some((block as u16, fs.fat_entry(block as u16)?)))
+            .filter(|(_, next)| fs.profile().classify(*next) != Entry::Free)
+            .collect()
+    }
+
+    #[test]
+    fn scanning_matches_reading_each_entry() {
+        let mut rng = crate::fuzz::Rng::new(421);
+        let mut bytes = crate::fuzz::ImageBuilder::new(0, 4).bytes();
+        // runs of free entries between allocated ones, some runs long enough to skip
+        let mut entry = 2;
+        while entry < 4 * 256 {
+            let run = rng.below(40) as usize;
+            entry += run * 2;
+            if entry + 2 > 4 * 256 {
+                break;
+            }
+            let value = rng.next_u64() as u16;
+            bytes[entry..entry + 2].copy_from_slice(&value.to_le_bytes());
+            entry += 2;
+        }
+        let mut fs = PennFat::from_bytes(bytes).unwrap();
+        for profile in [
+            Profile::PENNFAT,
+            Profile::RESERVED,
+            Profile {
+                name: "ones",
+                free: 0xffff,
+                eof: 0xfffe,
+                reserved: None,
+            },
+        ] {
+            fs.set_profile(profile);
+            let scan = fs.scan_fat();
+            assert_eq!(scan.entries, naive_scan(&fs), "{}", profile.name);
+            for (i, (block, _)) in scan.entries.iter().enumerate() {
+                assert_eq!(scan.position(*block), Some(i));
+            }
+            assert_eq!(scan.position(0), None, "entry 0 isn't a block");
+        }
+    }
+
+    #[test]
+    fn scanning_a_mostly_empty_maximal_fat_is_quick() {
+        let fs = largest();
+        // the quickest of a few runs, so a busy machine doesn't fail the test
+        let fastest = (0..5)
+            .map(|_| {
+                let start = std::time::Instant::now();
+                let scan = fs.scan_fat();
+                let elapsed = start.elapsed();
+                assert!(scan.entries.is_empty());
+                elapsed
+            })
+            .min()
+            .unwrap();
+        let limit = match cfg!(debug_assertions) {
+            true => Duration::from_millis(20),
+            false => Duration::from_millis(1),
+        };
+        assert!(fastest < limit, "scanning took {:?}", fastest);
+    }
+
+    /// An image whose root holds a file "long", `len` bytes long, stored in every block the
+    /// root doesn't use: the longest chain the image can hold
+    fn image_with_longest_chain(len: u32) -> (PennFat, Dentry) {
+        let mut image = crate::fuzz::ImageBuilder::new(0, 1);
+        let blocks = image.data_blocks() - 1;
+        let chain = image.allocate_lowest(blocks).unwrap();
+        image.write_dentry(
+            1,
+            0,
+            &crate::fuzz::DentrySpec {
+                name: b"long".to_vec(),
+                size: len,
+                first_block: chain[0],
+                type_: 0,
+                perm: 6,
+                mtime: 0,
+            },
+        );
+        let fs = PennFat::from_bytes(image.bytes()).unwrap();
+        let dentry = crate::walk::children(&fs, 1).next().unwrap().unwrap();
+        (fs, dentry)
+    }
+
+    #[test]
+    fn the_longest_legal_chain_is_followed_to_its_end() {
+        let (fs, dentry) = image_with_longest_chain(126 * 256);
+        assert_eq!(fs.max_chain_len(), fs.data_block_count());
+        let chain = fs.chain(dentry.first_block);
+        assert_eq!(chain.blocks.len(), 126);
+        assert!(matches!(chain.end, ChainEnd::Eof));
+        assert!(chain.check_len().is_ok());
+        assert_eq!(fs.read_file(&dentry).unwrap().len(), 126 * 256);
+    }
+
+    #[test]
+    fn chains_past_the_limit_are_cut_short() {
+        let (mut fs, dentry) = image_with_longest_chain(126 * 256);
+        fs.set_max_chain_len(10);
+        let chain = fs.chain(dentry.first_block);
+        assert_eq!(chain.blocks.len(), 10);
+        assert!(matches!(chain.end, ChainEnd::TooLong(10)));
+        let e = fs.read_file(&dentry).unwrap_err();
+        assert!(matches!(
+            e,
+            PfError::Integrity(IntegrityError::ChainTooLong {
+                start: 2,
+                limit: 10,
+                ..
+            })
+        ));
+        assert_eq!(
+            e.to_string(),
+            "PF010: The chain starting at block 0x02 is longer than 10 blocks"
+        );
+        // the ownership map gives up at the limit too
+        let ownership = crate::ownership::Ownership::build(&fs);
+        assert!(ownership.owner(chain.blocks[9]).is_some());
+        assert!(ownership.owner(chain.blocks[9] + 1).is_none());
+    }
+
+    #[test]
+    fn files_within_the_limit_read_whole_from_a_chain_past_it() {
+        // the file ends in the limit's blocks, whatever the chain does after
+        let (mut fs, dentry) = image_with_longest_chain(10 * 256);
+        fs.set_max_chain_len(10);
+        assert_eq!(fs.read_file(&dentry).unwrap().len(), 10 * 256);
+    }
+
+    #[test]
+    fn each_perm_in_the_spec_has_its_meaning() {
+        let spec = PermTable::Spec;
+        for (perm, meaning) in [
+            (0, "none"),
+            (2, "write"),
+            (4, "read"),
+            (5, "read+execute"),
+            (6, "read+write"),
+            (7, "read+write+execute"),
+        ] {
+            assert_eq!(spec.meaning(perm).as_deref(), Some(meaning));
+            assert_eq!(spec.describe(perm), format!("{} ({})", perm, meaning));
+        }
+        for perm in [1, 3, 8, 0xff] {
+            assert_eq!(spec.meaning(perm), None);
+            assert_eq!(spec.describe(perm), format!("{} (invalid)", perm));
+        }
+        // a fork that takes any rwx combination
+        assert_eq!(PermTable::Bits.describe(3), "3 (write+execute)");
+        assert_eq!(PermTable::Bits.describe(8), "8 (invalid)");
+    }
+
+    #[test]
+    fn detection_only_reserves_1_with_the_root_in_block_1() {
+        let mut fs = PennFat::from_bytes(crate::testing::sample_image()).unwrap();
+        assert_eq!(Profile::detect(&fs).name, "pennfat");
+        let mut image = crate::fuzz::ImageBuilder::new(0, 1);
+        // a chain ending in 1 rather than 0xffff
+        image.link(&[2, 3]);
+        image.link(&[3, 1]);
+        fs = PennFat::from_bytes(image.bytes()).unwrap();
+        assert_eq!(Profile::detect(&fs).name, "reserved");
+        fs.set_root_block(4);
+        assert_eq!(Profile::detect(&fs).name, "pennfat");
+    }
+
+    #[test]
+    fn overflow_is_reported_as_unsupported() {
+        let e = PfError::from(UnsupportedError::OffsetOverflow {
+            block: 0xfffe,
+            blocks: BlockFormat::default(),
+        });
+        assert_eq!(e.category(), "unsupported");
+        assert_eq!(e.code(), "PF012");
     }
 }
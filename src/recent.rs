@@ -0,0 +1,159 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::SystemTime,
+};
+
+use crate::history::checksum;
+use crate::pennfat::PennFat;
+use crate::walk;
+
+/// How many changes the recently modified files panel remembers
+const CAPACITY: usize = 50;
+
+/// How a file changed in a reload
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The file appeared
+    Created,
+    /// The file's contents (or size) changed
+    Content,
+    /// Only the file's dentry changed: its mtime, permissions or type
+    Metadata,
+    /// The file is gone
+    Deleted,
+}
+
+impl ChangeKind {
+    pub fn describe(self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Content => "content",
+            ChangeKind::Metadata => "metadata",
+            ChangeKind::Deleted => "deleted",
+        }
+    }
+}
+
+/// What's remembered of a file to tell how it changed
+#[derive(PartialEq, Eq)]
+struct FileState {
+    first_block: u16,
+    size: u32,
+    type_: u8,
+    perm: u8,
+    mtime: u64,
+    /// Checksum of the file's contents
+    checksum: u32,
+}
+
+/// A change to a file, picked up by a reload
+pub struct Change {
+    /// The absolute path of the file
+    pub path: String,
+    pub kind: ChangeKind,
+    /// The first block of the file (as it was, for a deleted file)
+    pub first_block: u16,
+    /// When the image changed
+    pub time: SystemTime,
+    /// The reload generation the change was picked up in
+    pub generation: u32,
+}
+
+/// The files that changed most recently across reloads, found by diffing the directory tree
+/// of every reload against the one before
+pub struct RecentFiles {
+    /// The files of the image as of the last reload, by path
+    files: HashMap<String, FileState>,
+    /// The changes, most recent first
+    changes: VecDeque<Change>,
+}
+
+impl RecentFiles {
+    /// Start tracking changes to the files of a filesystem, as currently loaded
+    pub fn new(fs: &PennFat) -> Self {
+        RecentFiles {
+            files: Self::scan(fs),
+            changes: VecDeque::new(),
+        }
+    }
+
+    /// Walk the directory tree, checksumming each file's contents
+    fn scan(fs: &PennFat) -> HashMap<String, FileState> {
+        walk::walk(fs)
+            .into_iter()
+            .map(|entry| {
+                let dentry = entry.dentry;
+                // a file that can't be read (in a truncated image) gets a checksum of 0
+                let contents = fs.read_file(&dentry).unwrap_or_default();
+                let state = FileState {
+                    first_block: dentry.first_block,
+                    size: dentry.size,
+                    type_: dentry.type_,
+                    perm: dentry.perm,
+                    mtime: dentry.mtime,
+                    checksum: checksum(&contents),
+                };
+                (entry.path, state)
+            })
+            .collect()
+    }
+
+    /// Record how the files changed in a reload that picked up changes to the image, as of
+    /// reload generation `generation`
+    pub fn update(&mut self, fs: &PennFat, generation: u32) {
+        let files = Self::scan(fs);
+        let time = fs.last_update_time();
+        let mut changes: Vec<Change> = Vec::new();
+        for (path, state) in &files {
+            let kind = match self.files.get(path) {
+                None => ChangeKind::Created,
+                Some(old) if old == state => continue,
+                Some(old)
+                    if old.checksum != state.checksum
+                        || old.size != state.size
+                        || old.first_block != state.first_block =>
+                {
+                    ChangeKind::Content
+                }
+                Some(_) => ChangeKind::Metadata,
+            };
+            changes.push(Change {
+                path: path.clone(),
+                kind,
+                first_block: state.first_block,
+                time,
+                generation,
+            });
+        }
+        for (path, state) in &self.files {
+            if !files.contains_key(path) {
+                changes.push(Change {
+                    path: path.clone(),
+                    kind: ChangeKind::Deleted,
+                    first_block: state.first_block,
+                    time,
+                    generation,
+                });
+            }
+        }
+        // the order within a reload doesn't mean anything, so keep it stable
+        changes.sort_by(|a, b| b.path.cmp(&a.path));
+        for change in changes {
+            // a file shows up once, with its latest change
+            self.changes.retain(|c| c.path != change.path);
+            self.changes.push_front(change);
+        }
+        self.changes.truncate(CAPACITY);
+        self.files = files;
+    }
+
+    /// The changes, most recent first
+    pub fn changes(&self) -> &VecDeque<Change> {
+        &self.changes
+    }
+
+    /// Whether a file exists as of the last reload
+    pub fn exists(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::fuzz::{DentrySpec, ImageBuilder};
+use crate::pennfat::{Dentry, DentryLayout, PennFat, PfError};
+use crate::walk;
+
+/// The layout repacked images' directory entries are written in
+const LAYOUT: DentryLayout = DentryLayout::PENNFAT_V1;
+
+/// Errors repacking an image
+#[derive(thiserror::Error, Debug)]
+pub enum RepackError {
+    #[error("reading {path}: {source}")]
+    Read { path: String, source: PfError },
+    #[error("the contents don't fit in {} bytes of data", .0)]
+    Full(u64),
+}
+
+/// What an image's directory tree holds, as far as the space it takes goes. A chain that more
+/// than one entry leads to is counted once, as repacking copies it once
+pub struct Contents {
+    /// The number of entries in use of each directory, the root's first
+    directories: Vec<usize>,
+    /// The size of each file and symbolic link, as its entry says
+    files: Vec<u64>,
+}
+
+/// A geometry an image could have, and how its contents would fit in it
+pub struct Fit {
+    pub block_size_config: u8,
+    pub fat_blocks: u8,
+    /// The blocks the contents take at this block size, the root directory's included
+    pub blocks_needed: u64,
+    /// The data blocks the geometry has
+    pub data_blocks: u64,
+    /// The size of an image of this geometry, FAT included
+    pub image_size: u64,
+}
+
+impl Fit {
+    pub fn new(block_size_config: u8, fat_blocks: u8, blocks_needed: u64) -> Self {
+        let block_size = 256u64 << block_size_config;
+        let fat_size = block_size * fat_blocks as u64;
+        let data_blocks = (fat_size / 2 - 1).min(0xfffe);
+        Fit {
+            block_size_config,
+            fat_blocks,
+            blocks_needed,
+            data_blocks,
+            image_size: fat_size + data_blocks * block_size,
+        }
+    }
+
+    pub fn block_size(&self) -> u64 {
+        256 << self.block_size_config
+    }
+
+    /// Whether the contents fit in the geometry
+    pub fn fits(&self) -> bool {
+        self.blocks_needed <= self.data_blocks
+    }
+}
+
+impl Contents {
+    /// Walk the image's directory tree, counting what's in it. Unreadable directory blocks are
+    /// skipped
+    pub fn collect(fs: &PennFat) -> Self {
+        let mut contents = Contents {
+            directories: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut seen = HashSet::from([fs.root_block()]);
+        let mut directories = vec![fs.root_block()];
+        while let Some(first_block) = directories.pop() {
+            let children: Vec<Dentry> = walk::children(fs, first_block).flatten().collect();
+            contents.directories.push(children.len());
+            for dentry in children {
+                if dentry.first_block != 0 && !seen.insert(dentry.first_block) {
+                    continue;
+                }
+                match dentry.is_dir() {
+                    true if dentry.first_block != 0 => directories.push(dentry.first_block),
+                    true => {}
+                    false => contents.files.push(dentry.size as u64),
+                }
+            }
+        }
+        contents
+    }
+
+    /// Count the blocks the contents take with blocks of `block_size` bytes. Every directory
+    /// takes at least a block, even an empty one
+    pub fn blocks_needed(&self, block_size: u64) -> u64 {
+        let slots = (block_size / LAYOUT.size as u64) as usize;
+        let directories: usize = self
+            .directories
+            .iter()
+            .map(|entries| entries.div_ceil(slots).max(1))
+            .sum();
+        let files: u64 = self
+            .files
+            .iter()
+            .map(|size| size.div_ceil(block_size))
+            .sum();
+        directories as u64 + files
+    }
+
+    /// Work out how the contents fit in every geometry: each block size, with each number of
+    /// FAT blocks
+    pub fn fits(&self) -> Vec<Fit> {
+        (0..=7u8)
+            .flat_map(|config| {
+                let needed = self.blocks_needed(256 << config);
+                (1..=u8::MAX).map(move |fat_blocks| Fit::new(config, fat_blocks, needed))
+            })
+            .collect()
+    }
+}
+
+/// An image being repacked: the tree of the image it's copied from, written into a fresh one
+struct Repacker<'a> {
+    fs: &'a PennFat,
+    builder: ImageBuilder,
+    /// The first block each chain copied so far was given, by its first block in the old image
+    copied: HashMap<u16, u16>,
+}
+
+impl Repacker<'_> {
+    /// Allocate the lowest `n` free blocks of the new image
+    fn allocate(&mut self, n: usize) -> Result<Vec<u16>, RepackError> {
+        let data_size = (self.builder.data_blocks() * self.builder.block_size()) as u64;
+        self.builder
+            .allocate_lowest(n)
+            .ok_or(RepackError::Full(data_size))
+    }
+
+    /// Copy the directory at `first_block` and everything under it, returning its first block
+    /// in the new image. The root directory is put at block 1
+    fn directory(&mut self, path: &str, first_block: u16, root: bool) -> Result<u16, RepackError> {
+        let children = walk::children(self.fs, first_block)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|source| RepackError::Read {
+                path: path.to_owned(),
+                source,
+            })?;
+        let slots = self.builder.block_size() / LAYOUT.size;
+        let blocks = children.len().div_ceil(slots).max(1);
+        let chain = match root {
+            true => {
+                let mut chain = vec![1];
+                chain.extend(self.allocate(blocks - 1)?);
+                self.builder.link(&chain);
+                chain
+            }
+            false => self.allocate(blocks)?,
+        };
+        // before copying its entries, so a directory linked into itself leads back to its copy
+        self.copied.insert(first_block, chain[0]);
+        for (i, dentry) in children.iter().enumerate() {
+            let child_path = format!("{}/{}", path, dentry.display_name());
+            let first_block = self.chain(&child_path, dentry)?;
+            let spec = DentrySpec {
+                name: dentry.name.clone(),
+                size: dentry.size,
+                first_block,
+                type_: dentry.type_,
+                perm: dentry.perm,
+                mtime: dentry.mtime,
+            };
+            self.builder
+                .write_dentry(chain[i / slots], i % slots, &spec);
+        }
+        Ok(chain[0])
+    }
+
+    /// Copy the chain an entry leads to, unless it was already copied, returning its first
+    /// block in the new image (0 for an empty file)
+    fn chain(&mut self, path: &str, dentry: &Dentry) -> Result<u16, RepackError> {
+        if dentry.first_block == 0 {
+            return Ok(0);
+        }
+        if let Some(first_block) = self.copied.get(&dentry.first_block) {
+            return Ok(*first_block);
+        }
+        if dentry.is_dir() {
+            return self.directory(path, dentry.first_block, false);
+        }
+        let contents = self
+            .fs
+            .read_file(dentry)
+            .map_err(|source| RepackError::Read {
+                path: path.to_owned(),
+                source,
+            })?;
+        let chain = self.allocate(contents.len().div_ceil(self.builder.block_size()))?;
+        self.builder.write(&chain, &contents);
+        let first_block = chain.first().copied().unwrap_or(0);
+        self.copied.insert(dentry.first_block, first_block);
+        Ok(first_block)
+    }
+}
+
+/// Copy the image's directory tree into a fresh image of the given geometry, with each file
+/// and directory in the lowest free blocks, as the tree is walked. Deleted entries, orphan
+/// chains and slack are left behind. Returns the new image's bytes
+pub fn repack(fs: &PennFat, block_size_config: u8, fat_blocks: u8) -> Result<Vec<u8>, RepackError> {
+    let mut repacker = Repacker {
+        fs,
+        builder: ImageBuilder::new(block_size_config, fat_blocks),
+        copied: HashMap::new(),
+    };
+    repacker.directory("", fs.root_block(), true)?;
+    Ok(repacker.builder.bytes())
+}
+
+/// Compare a repacked image with the image it was repacked from: every path, with its type,
+/// permissions, modification time and contents. Returns the differences, and how many entries
+/// were compared
+pub fn compare(old: &PennFat, new: &PennFat) -> (Vec<String>, usize) {
+    let mut new_entries: HashMap<String, Dentry> = walk::walk(new)
+        .into_iter()
+        .map(|entry| (entry.path, entry.dentry))
+        .collect();
+    let old_entries = walk::walk(old);
+    let mut differences = Vec::new();
+    for entry in &old_entries {
+        let Some(copy) = new_entries.remove(&entry.path) else {
+            differences.push(format!("{}: missing", entry.path));
+            continue;
+        };
+        let (dentry, path) = (&entry.dentry, &entry.path);
+        if (dentry.type_, dentry.perm, dentry.mtime) != (copy.type_, copy.perm, copy.mtime) {
+            differences.push(format!("{}: type, permissions or mtime differ", path));
+        } else if !dentry.is_dir() && old.read_file(dentry).ok() != new.read_file(&copy).ok() {
+            differences.push(format!("{}: contents differ", path));
+        }
+    }
+    let mut extra: Vec<_> = new_entries.into_keys().collect();
+    extra.sort();
+    differences.extend(
+        extra
+            .into_iter()
+            .map(|path| format!("{}: not in the original", path)),
+    );
+    (differences, old_entries.len())
+}
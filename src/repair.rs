@@ -0,0 +1,494 @@
+use std::collections::HashSet;
+
+use crate::check::{self, Problem};
+use crate::edit::Adoption;
+use crate::ownership::Ownership;
+use crate::pennfat::{self, Dentry, Entry, PennFat};
+use crate::walk::{self, Lookup};
+
+/// The directory orphan chains are adopted into, if the image has one (the root otherwise)
+const LOST_AND_FOUND: &str = "/lost+found";
+
+/// The byte the name of a deleted dentry starts with
+const DELETED: u8 = 1;
+
+/// What a step of a repair writes to the image
+pub enum Fix {
+    /// Set a block's FAT entry
+    SetEntry { block: u16, value: u16 },
+    /// Free a FAT entry past the last data block, which no block has
+    ClearEntry { entry: u32 },
+    /// Mark the dentry in a slot of a directory block deleted
+    Delete { block: u16, slot: usize },
+    /// Give the orphan chain starting at `head` a dentry, so it's the file at `path`
+    Adopt { head: u16, path: String },
+}
+
+/// A step of a repair: what it writes, what for, and what it could cost
+pub struct Step {
+    pub fix: Fix,
+    /// What the step does and why, e.g. `set FAT[0x2a] = 0xffff to end the cycle in /a.txt`
+    pub action: String,
+    /// What could be lost or made up by taking the step
+    pub risk: String,
+}
+
+/// The repair of an image, worked out before anything is written: a step for each problem it
+/// has a fix for, then the dentries that point outside the data region and the orphan chains
+pub struct Plan {
+    pub steps: Vec<Step>,
+    /// The problems no step fixes, and why
+    pub unfixed: Vec<(Problem, &'static str)>,
+}
+
+/// Work out how to repair an image
+pub fn plan(fs: &PennFat) -> Plan {
+    let ownership = Ownership::build(fs);
+    let mut plan = Plan {
+        steps: Vec::new(),
+        unfixed: Vec::new(),
+    };
+    for problem in check::check_all(fs) {
+        match fix(fs, &ownership, &problem) {
+            Ok(steps) => plan.steps.extend(steps),
+            Err(why) => plan.unfixed.push((problem, why)),
+        }
+    }
+    plan.steps.extend(bad_dentries(fs, &ownership));
+    plan.steps.extend(orphans(fs, &ownership));
+    plan
+}
+
+/// Get whether the problems after a change, such as a step of a repair or a FAT patch, are worse
+/// than before it: more of them, or more that make the image read wrongly
+pub fn worse(before: &[Problem], after: &[Problem]) -> bool {
+    let severe = |problems: &[Problem]| problems.iter().filter(|p| p.severe()).count();
+    after.len() > before.len() || severe(after) > severe(before)
+}
+
+/// Describe what a block belongs to, e.g. "/a.txt" or "an orphan chain"
+fn describe_owner(ownership: &Ownership, block: u16) -> String {
+    match ownership.owner(block) {
+        Some(owner) => owner.path.clone(),
+        None => "an orphan chain".to_owned(),
+    }
+}
+
+/// Work out the steps fixing a problem, or why there are none
+fn fix(fs: &PennFat, ownership: &Ownership, problem: &Problem) -> Result<Vec<Step>, &'static str> {
+    let eof = fs.profile().eof;
+    let end = |block: u16, why: String, risk: String| Step {
+        fix: Fix::SetEntry { block, value: eof },
+        action: format!(
+            "set FAT[{}] = {} to {}",
+            fs.format_block(block),
+            fs.format_block(eof),
+            why
+        ),
+        risk,
+    };
+    let cut_short = |owner: &str, block: u16| {
+        format!(
+            "{} ends at block {}, so it reads short if its size says it goes on",
+            owner,
+            fs.format_block(block)
+        )
+    };
+    match *problem {
+        Problem::OutOfRange { block, next } => {
+            let owner = describe_owner(ownership, block);
+            Ok(vec![end(
+                block,
+                format!(
+                    "end {} there, instead of at {} outside the data region",
+                    owner,
+                    fs.format_block(next)
+                ),
+                cut_short(&owner, block),
+            )])
+        }
+        Problem::CrossLink { block, ref from } => {
+            // the link the block's owner follows to it stays, unless the block starts a file
+            let keep = match ownership.owner(block) {
+                Some(owner) if owner.index > 0 => fs
+                    .chain(owner.first_block)
+                    .blocks
+                    .get(owner.index - 1)
+                    .copied(),
+                Some(_) => None,
+                None => from.first().copied(),
+            };
+            let user = describe_owner(ownership, block);
+            Ok(from
+                .iter()
+                .filter(|from| Some(**from) != keep)
+                .map(|from| {
+                    let owner = describe_owner(ownership, *from);
+                    end(
+                        *from,
+                        format!(
+                            "unlink {} from block {}, which {} uses",
+                            owner,
+                            fs.format_block(block),
+                            user
+                        ),
+                        format!(
+                            "{} ends at block {}, losing the blocks it shared with {}",
+                            owner,
+                            fs.format_block(*from),
+                            user
+                        ),
+                    )
+                })
+                .collect())
+        }
+        Problem::Cycle { block, .. } => {
+            // the link back into the loop goes, as the file's walk would first meet it
+            let start = ownership
+                .owner(block)
+                .map_or(block, |owner| owner.first_block);
+            let mut seen = HashSet::new();
+            let mut last = start;
+            loop {
+                seen.insert(last);
+                match fs.fat_entry(last).map(|entry| fs.profile().classify(entry)) {
+                    Some(Entry::Next(next)) if seen.contains(&next) => break,
+                    Some(Entry::Next(next)) => last = next,
+                    _ => return Err("the loop couldn't be followed again"),
+                }
+            }
+            let owner = describe_owner(ownership, block);
+            Ok(vec![end(
+                last,
+                format!("end the cycle in {}", owner),
+                cut_short(&owner, last),
+            )])
+        }
+        Problem::Unreachable { entry, .. } => {
+            let free = fs.profile().free;
+            Ok(vec![Step {
+                fix: Fix::ClearEntry { entry },
+                action: format!(
+                    "set FAT[{:#06x}] = {} to clear an entry past the last data block",
+                    entry,
+                    fs.format_block(free)
+                ),
+                risk: "none: no block can use the entry".to_owned(),
+            }])
+        }
+        Problem::ConfigChanged { .. } => {
+            Err("which configuration is right can't be told from the image")
+        }
+        Problem::Unpacked { .. } | Problem::Unsorted { .. } => {
+            Err("the directory would have to be rewritten")
+        }
+    }
+}
+
+/// Work out the steps deleting the dentries whose first block is outside the data region, so
+/// nothing can follow them off the end of the image. Only a directory's own blocks are looked
+/// at: one cross-linked into another file's blocks would read that file's contents as dentries
+fn bad_dentries(fs: &PennFat, ownership: &Ownership) -> Vec<Step> {
+    let layout = fs.dentry_layout();
+    let mut dirs = vec![(String::new(), fs.root_block())];
+    dirs.extend(
+        walk::walk(fs)
+            .into_iter()
+            .filter(|entry| entry.dentry.is_dir())
+            .map(|entry| (entry.path, entry.dentry.first_block)),
+    );
+    let mut checked = HashSet::new();
+    let mut steps = Vec::new();
+    for (dir, first_block) in dirs {
+        if !checked.insert(first_block) {
+            continue;
+        }
+        for block in fs.chain(first_block).blocks {
+            if ownership
+                .owner(block)
+                .is_some_and(|owner| owner.first_block != first_block)
+            {
+                continue;
+            }
+            let Ok(data) = fs.block_bytes(block) else {
+                continue;
+            };
+            for (slot, entry) in data.chunks_exact(layout.size).enumerate() {
+                let Some(dentry) = Dentry::parse(entry, layout) else {
+                    continue;
+                };
+                if !dentry.is_in_use() || dentry.first_block <= fs.data_block_count() {
+                    continue;
+                }
+                let path = format!("{}/{}", dir, dentry.display_name());
+                steps.push(Step {
+                    fix: Fix::Delete { block, slot },
+                    action: format!(
+                        "mark dentry slot {} in block {} deleted ({}: first_block {} out of range)",
+                        slot,
+                        fs.format_block(block),
+                        path,
+                        fs.format_block(dentry.first_block)
+                    ),
+                    risk: format!(
+                        "{} is gone from its directory, though none of it could be read",
+                        path
+                    ),
+                });
+            }
+        }
+    }
+    steps
+}
+
+/// Work out the steps adopting the orphan chains: the allocated blocks no file owns and no
+/// block links to. Orphan chains that loop have no start, and are left to the cycle's fix
+fn orphans(fs: &PennFat, ownership: &Ownership) -> Vec<Step> {
+    let table = fs.get_fat_table();
+    let linked: HashSet<u16> = table
+        .iter()
+        .filter_map(|(_, next)| match fs.profile().classify(*next) {
+            Entry::Next(next) => Some(next),
+            _ => None,
+        })
+        .collect();
+    let dir = match walk::lookup(fs, LOST_AND_FOUND, true) {
+        Lookup::Found(entry) if entry.dentry.is_dir() => LOST_AND_FOUND,
+        _ => "",
+    };
+    let mut n = 0;
+    let mut steps = Vec::new();
+    for (head, _) in table {
+        if ownership.owner(head).is_some() || linked.contains(&head) {
+            continue;
+        }
+        // a name nothing has yet
+        let path = loop {
+            let path = format!("{}/orphan{}", dir, n);
+            n += 1;
+            if matches!(walk::lookup(fs, &path, false), Lookup::Missing) {
+                break path;
+            }
+        };
+        steps.push(Step {
+            action: format!(
+                "adopt orphan chain at {} as {}",
+                fs.format_block(head),
+                path
+            ),
+            risk: format!(
+                "{} gets every byte of the chain's {} bytes, slack included, and a made-up perm and mtime",
+                path,
+                Adoption::estimated_size(fs, head)
+            ),
+            fix: Fix::Adopt { head, path },
+        });
+    }
+    steps
+}
+
+/// The bytes of the image a step overwrote, to put back if it made things worse
+pub struct Undo {
+    saved: Vec<(u64, Vec<u8>)>,
+}
+
+impl Undo {
+    /// Save the bytes of the image in each stretch of `len` bytes at `offset`
+    fn save(fs: &PennFat, stretches: Vec<(u64, usize)>) -> Self {
+        let saved = stretches
+            .into_iter()
+            .filter_map(|(offset, len)| {
+                let bytes = fs.bytes().get(offset as usize..offset as usize + len)?;
+                Some((offset, bytes.to_vec()))
+            })
+            .collect();
+        Undo { saved }
+    }
+
+    /// Write the saved bytes back
+    pub fn restore(&self, fs: &PennFat) -> pennfat::Result<()> {
+        for (offset, bytes) in &self.saved {
+            fs.write_bytes(*offset, bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl Step {
+    /// Write the step to the image, saving the bytes it overwrites first. A step that fails
+    /// part way is undone. An adoption is worked out again against the image as it is now, as
+    /// the steps before it may have taken the slot it was going to use
+    pub fn apply(&self, fs: &PennFat) -> anyhow::Result<Undo> {
+        let (undo, written) = match &self.fix {
+            Fix::SetEntry { block, value } => {
+                let offset = *block as u64 * 2;
+                let undo = Undo::save(fs, vec![(offset, 2)]);
+                (undo, fs.set_fat_entry(*block, *value).map_err(Into::into))
+            }
+            Fix::ClearEntry { entry } => {
+                let offset = *entry as u64 * 2;
+                let undo = Undo::save(fs, vec![(offset, 2)]);
+                let free = fs.profile().free.to_le_bytes();
+                (undo, fs.write_bytes(offset, &free).map_err(Into::into))
+            }
+            Fix::Delete { block, slot } => {
+                let layout = fs.dentry_layout();
+                let offset = (fs.block_offset(*block)?
+                    + slot * layout.size
+                    + layout.name.within(layout.size).start) as u64;
+                let undo = Undo::save(fs, vec![(offset, 1)]);
+                (undo, fs.write_bytes(offset, &[DELETED]).map_err(Into::into))
+            }
+            Fix::Adopt { head, path } => {
+                let adoption =
+                    Adoption::plan(fs, *head, path, false).map_err(anyhow::Error::msg)?;
+                let undo = Undo::save(fs, adoption.footprint(fs)?);
+                (undo, adoption.write(fs))
+            }
+        };
+        if let Err(e) = written {
+            undo.restore(fs)?;
+            return Err(e);
+        }
+        Ok(undo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::{DentrySpec, ImageBuilder};
+    use crate::pennfat::{Mode, Window};
+    use crate::testing;
+    use std::io::Write;
+
+    /// An image with one of each fault the planner fixes: a file whose chain loops, one whose
+    /// chain runs out of the data region, two sharing a block, a dentry whose first block is
+    /// out of range, and an orphan chain
+    fn damaged() -> Vec<u8> {
+        let mut image = ImageBuilder::new(2, 1);
+        let file = |image: &mut ImageBuilder, slot, name: &str, chain: &[u16]| {
+            image.link(chain);
+            let spec = DentrySpec {
+                name: name.as_bytes().to_vec(),
+                size: 1024 * chain.len() as u32,
+                first_block: chain.first().copied().unwrap_or(0x400),
+                type_: 0,
+                perm: 6,
+                mtime: 0,
+            };
+            image.write_dentry(1, slot, &spec);
+        };
+        file(&mut image, 0, "cycle", &[2, 3, 4]);
+        file(&mut image, 1, "out", &[5, 6]);
+        file(&mut image, 2, "a", &[7, 8]);
+        file(&mut image, 3, "b", &[9, 8]);
+        file(&mut image, 4, "bad", &[]);
+        image.link(&[10, 11]);
+        let mut bytes = image.bytes();
+        // 0x04 links back to 0x02, and 0x06 to past the last block
+        bytes[4 * 2..4 * 2 + 2].copy_from_slice(&2u16.to_le_bytes());
+        bytes[6 * 2..6 * 2 + 2].copy_from_slice(&0x300u16.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn damage_is_planned_for() {
+        let fs = PennFat::from_bytes(damaged()).unwrap();
+        let plan = plan(&fs);
+        let actions: Vec<&str> = plan.steps.iter().map(|step| step.action.as_str()).collect();
+        assert_eq!(
+            actions,
+            [
+                "set FAT[0x004] = 0xffff to end the cycle in /cycle",
+                "set FAT[0x006] = 0xffff to end /out there, instead of at 0x300 outside the data region",
+                "set FAT[0x009] = 0xffff to unlink /b from block 0x008, which /a uses",
+                "mark dentry slot 4 in block 0x001 deleted (/bad: first_block 0x400 out of range)",
+                "adopt orphan chain at 0x00a as /orphan0",
+            ]
+        );
+        assert_eq!(
+            plan.steps[2].risk,
+            "/b ends at block 0x009, losing the blocks it shared with /a"
+        );
+        assert!(plan.unfixed.is_empty());
+    }
+
+    #[test]
+    fn applying_the_plan_leaves_nothing_to_repair() {
+        let (path, fs) = testing::writable_image(&damaged());
+        for step in plan(&fs).steps {
+            step.apply(&fs).unwrap();
+        }
+        let repaired = PennFat::load(&path, Mode::ReadOnly, Window::default()).unwrap();
+        assert!(check::check_all(&repaired).is_empty());
+        assert!(plan(&repaired).steps.is_empty());
+        let paths: Vec<String> = walk::walk(&repaired)
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect();
+        assert_eq!(paths, ["/cycle", "/out", "/a", "/b", "/orphan0"]);
+        assert_eq!(repaired.chain(2).blocks, [2, 3, 4]);
+        assert_eq!(repaired.chain(10).blocks, [10, 11]);
+    }
+
+    #[test]
+    fn undoing_a_step_puts_back_every_byte() {
+        let original = damaged();
+        let (path, fs) = testing::writable_image(&original);
+        for step in plan(&fs).steps {
+            let undo = step.apply(&fs).unwrap();
+            assert_ne!(std::fs::read(&path).unwrap(), original, "{}", step.action);
+            undo.restore(&fs).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), original, "{}", step.action);
+        }
+    }
+
+    #[test]
+    fn entries_past_the_last_data_block_are_cleared() {
+        // 1024-byte blocks and a FAT of 129 of them has 66048 entries, for 65534 data blocks
+        let fat_size = 129 * 1024;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut fat = vec![0; fat_size];
+        fat[..2].copy_from_slice(&[2, 129]);
+        fat[2..4].copy_from_slice(&0xffffu16.to_le_bytes());
+        fat[0x10000 * 2..0x10000 * 2 + 2].copy_from_slice(&5u16.to_le_bytes());
+        file.write_all(&fat).unwrap();
+        file.as_file()
+            .set_len((fat_size + 65534 * 1024) as u64)
+            .unwrap();
+        let path = file.into_temp_path();
+        let fs = PennFat::load(&path, Mode::Writable, Window::default()).unwrap();
+
+        let steps = plan(&fs).steps;
+        assert_eq!(steps.len(), 1);
+        assert_eq!(
+            steps[0].action,
+            "set FAT[0x10000] = 0x0000 to clear an entry past the last data block"
+        );
+        let undo = steps[0].apply(&fs).unwrap();
+        assert!(check::check_all(&fs).is_empty());
+        undo.restore(&fs).unwrap();
+        assert_eq!(fs.unreachable_entries(), [(0x10000, 5)]);
+    }
+
+    #[test]
+    fn a_step_that_adds_problems_is_worse() {
+        let fs = PennFat::from_bytes(damaged()).unwrap();
+        let before = check::check_all(&fs);
+        assert!(!worse(&before, &before));
+        assert!(worse(&before, &[before.clone(), before.clone()].concat()));
+        assert!(!worse(&before, &before[1..]));
+        // as many problems, but one that reads wrongly for one that's only untidy
+        let mild = [Problem::Unreachable {
+            entry: 0x90,
+            value: 0xffff,
+        }];
+        let severe = [Problem::OutOfRange {
+            block: 0x02,
+            next: 0x90,
+        }];
+        assert!(worse(&mild, &severe));
+        assert!(!worse(&severe, &mild));
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+
+use crate::check::{self, Problem};
+use crate::ownership::Ownership;
+use crate::pennfat::{BlockFormat, PennFat, ShowBlocks};
+use crate::stats::{self, Stats};
+use crate::walk;
+
+/// The configuration of the filesystem, as read from its first FAT entry
+#[derive(Serialize)]
+pub struct Overview {
+    pub image: String,
+    pub block_size: u16,
+    pub fat_size: u32,
+    pub fat_entries: u32,
+    pub data_blocks: u16,
+    /// Bytes of FAT past the entry of the last data block, which can never be used
+    pub wasted_fat_bytes: u32,
+    /// Bytes of the file past the last data block, which no block number can address
+    pub unaddressed_bytes: usize,
+    pub root_block: u16,
+}
+
+/// A file in the directory tree
+#[derive(Serialize)]
+pub struct TreeEntry {
+    pub path: String,
+    /// "file", "directory" or "symlink"
+    pub kind: &'static str,
+    pub size: u32,
+    pub first_block: u16,
+    /// Number of blocks in the file's chain
+    pub blocks: usize,
+    /// Number of runs of consecutive blocks the chain breaks into
+    pub extents: usize,
+}
+
+/// Everything pfview can tell about an image, for the report and the JSON output
+#[derive(Serialize)]
+pub struct Report {
+    pub overview: Overview,
+    pub stats: Stats,
+    pub tree: Vec<TreeEntry>,
+    pub problems: Vec<Problem>,
+    /// Chains of allocated blocks that no file's chain reaches
+    pub orphans: Vec<Vec<u16>>,
+    /// The whole FAT (allocated entries only), only included when asked for
+    pub fat: Option<Vec<(u16, u16)>>,
+    /// How the image shows block numbers
+    #[serde(skip)]
+    pub blocks: BlockFormat,
+}
+
+/// The output formats of a report
+#[derive(Clone, Copy)]
+pub enum Format {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" | "md" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown report format {}", s)),
+        }
+    }
+}
+
+/// Group the orphaned blocks into the chains they form, starting from blocks no other orphaned
+/// block points to. Orphaned cycles start from their lowest block
+fn orphan_chains(fs: &PennFat, ownership: &Ownership) -> Vec<Vec<u16>> {
+    let table = fs.get_fat_table();
+    let orphans: HashSet<u16> = table
+        .iter()
+        .map(|(block, _)| *block)
+        .filter(|block| ownership.owner(*block).is_none())
+        .collect();
+    let pointed_to: HashSet<u16> = table
+        .iter()
+        .filter(|(block, _)| orphans.contains(block))
+        .map(|(_, next)| *next)
+        .collect();
+
+    let mut starts: Vec<u16> = orphans.iter().copied().collect();
+    starts.sort_unstable();
+    // chain heads first, then whatever is left (cycles)
+    starts.sort_by_key(|block| pointed_to.contains(block));
+
+    let mut seen = HashSet::new();
+    let mut chains = Vec::new();
+    for start in starts {
+        let chain: Vec<u16> = fs
+            .chain(start)
+            .blocks
+            .into_iter()
+            .take_while(|block| orphans.contains(block) && seen.insert(*block))
+            .collect();
+        if !chain.is_empty() {
+            chains.push(chain);
+        }
+    }
+    chains
+}
+
+impl Report {
+    /// Gather the report of an image. The full FAT is only included with `full`
+    pub fn collect(fs: &PennFat, full: bool) -> Self {
+        let ownership = Ownership::build(fs);
+        let mut tree: Vec<TreeEntry> = walk::walk(fs)
+            .into_iter()
+            .map(|entry| {
+                let blocks = fs.chain(entry.dentry.first_block).blocks;
+                TreeEntry {
+                    kind: match entry.dentry.type_ {
+                        1 => "directory",
+                        2 => "symlink",
+                        _ => "file",
+                    },
+                    path: entry.path,
+                    size: entry.dentry.size,
+                    first_block: entry.dentry.first_block,
+                    blocks: blocks.len(),
+                    extents: stats::extents(&blocks),
+                }
+            })
+            .collect();
+        tree.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Report {
+            overview: Overview {
+                image: fs.path().display().to_string(),
+                block_size: fs.block_size(),
+                fat_size: fs.fat_size(),
+                fat_entries: fs.num_fat_entries(),
+                data_blocks: fs.data_block_count(),
+                wasted_fat_bytes: fs.wasted_fat_bytes(),
+                unaddressed_bytes: fs.unaddressed().len(),
+                root_block: fs.root_block(),
+            },
+            stats: Stats::collect(fs),
+            tree,
+            problems: check::check_all(fs),
+            orphans: orphan_chains(fs, &ownership),
+            fat: full.then(|| fs.get_fat_table()),
+            blocks: fs.block_format(),
+        }
+    }
+
+    /// Render the report in the given format
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Markdown => self.render_document(&Markdown),
+            Format::Html => self.render_document(&Html),
+            Format::Json => serde_json::to_string_pretty(self).expect("reports serialize"),
+        }
+    }
+
+    /// Render the report as a document, section by section
+    fn render_document(&self, doc: &dyn Document) -> String {
+        let block = |b: u16| doc.code(&self.blocks.block(b));
+        let mut out = doc.start(&format!("pfview report: {}", self.overview.image));
+
+        out += &doc.heading("Overview");
+        let overview = &self.overview;
+        out += &doc.table(
+            &["", ""],
+            &[
+                vec!["block size".to_owned(), overview.block_size.to_string()],
+                vec!["FAT size".to_owned(), overview.fat_size.to_string()],
+                vec!["FAT entries".to_owned(), overview.fat_entries.to_string()],
+                vec!["data blocks".to_owned(), overview.data_blocks.to_string()],
+                vec![
+                    "wasted FAT bytes".to_owned(),
+                    overview.wasted_fat_bytes.to_string(),
+                ],
+                vec![
+                    "unaddressed bytes".to_owned(),
+                    overview.unaddressed_bytes.to_string(),
+                ],
+                vec!["root directory".to_owned(), block(overview.root_block)],
+            ],
+        );
+
+        out += &doc.heading("Usage");
+        let rows: Vec<Vec<String>> = self
+            .stats
+            .rows()
+            .into_iter()
+            .map(|(key, value)| vec![key.to_owned(), doc.escape(&value)])
+            .collect();
+        out += &doc.table(&["", ""], &rows);
+
+        out += &doc.heading("Directory tree");
+        let rows: Vec<Vec<String>> = self
+            .tree
+            .iter()
+            .map(|entry| {
+                vec![
+                    doc.escape(&entry.path),
+                    entry.kind.to_owned(),
+                    entry.size.to_string(),
+                    block(entry.first_block),
+                    entry.blocks.to_string(),
+                    entry.extents.to_string(),
+                ]
+            })
+            .collect();
+        out += &doc.table(
+            &["path", "type", "size", "first block", "blocks", "extents"],
+            &rows,
+        );
+
+        out += &doc.heading("Consistency");
+        let problems: Vec<String> = self
+            .problems
+            .iter()
+            .map(|problem| doc.escape(&problem.show(self.blocks).to_string()))
+            .collect();
+        out += &doc.list(&problems, "no problems found");
+
+        out += &doc.heading("Orphaned blocks");
+        let orphans: Vec<String> = self
+            .orphans
+            .iter()
+            .map(|chain| {
+                let blocks: Vec<String> = chain.iter().map(|b| block(*b)).collect();
+                blocks.join(" → ")
+            })
+            .collect();
+        out += &doc.list(&orphans, "no orphaned blocks");
+
+        if let Some(fat) = &self.fat {
+            out += &doc.heading("FAT");
+            let rows: Vec<Vec<String>> = fat
+                .iter()
+                .map(|(b, next)| vec![block(*b), block(*next)])
+                .collect();
+            out += &doc.table(&["block", "next"], &rows);
+        }
+
+        out + &doc.end()
+    }
+}
+
+/// A note on a block, as listed by the notes output
+#[derive(Serialize)]
+struct Note<'a> {
+    block: u16,
+    note: &'a str,
+}
+
+/// Render the notes on the blocks of an image in the given format, in block order
+pub fn render_notes(
+    image: &str,
+    notes: &BTreeMap<u16, String>,
+    blocks: BlockFormat,
+    format: Format,
+) -> String {
+    let render_document = |doc: &dyn Document| {
+        let items: Vec<String> = notes
+            .iter()
+            .map(|(block, note)| {
+                format!("{}: {}", doc.code(&blocks.block(*block)), doc.escape(note))
+            })
+            .collect();
+        doc.start(&format!("pfview notes: {}", image)) + &doc.list(&items, "no notes") + &doc.end()
+    };
+    match format {
+        Format::Markdown => render_document(&Markdown),
+        Format::Html => render_document(&Html),
+        Format::Json => {
+            let notes: Vec<Note> = notes
+                .iter()
+                .map(|(block, note)| Note {
+                    block: *block,
+                    note,
+                })
+                .collect();
+            serde_json::to_string_pretty(&notes).expect("notes serialize") + "\n"
+        }
+    }
+}
+
+/// A document format the report can be rendered in
+trait Document {
+    fn start(&self, title: &str) -> String;
+    fn end(&self) -> String;
+    fn heading(&self, text: &str) -> String;
+    /// Inline code, such as a block number
+    fn code(&self, text: &str) -> String;
+    /// Escape text so it shows as is
+    fn escape(&self, text: &str) -> String;
+    fn table(&self, header: &[&str], rows: &[Vec<String>]) -> String;
+    /// A bulleted list, or `empty` if there are no items
+    fn list(&self, items: &[String], empty: &str) -> String;
+}
+
+struct Markdown;
+
+impl Document for Markdown {
+    fn start(&self, title: &str) -> String {
+        format!("# {}\n", title)
+    }
+
+    fn end(&self) -> String {
+        String::new()
+    }
+
+    fn heading(&self, text: &str) -> String {
+        format!("\n## {}\n\n", text)
+    }
+
+    fn code(&self, text: &str) -> String {
+        format!("`{}`", text)
+    }
+
+    fn escape(&self, text: &str) -> String {
+        text.replace('|', "\\|")
+    }
+
+    fn table(&self, header: &[&str], rows: &[Vec<String>]) -> String {
+        let mut out = format!("| {} |\n", header.join(" | "));
+        out += &format!("|{}\n", "---|".repeat(header.len()));
+        for row in rows {
+            out += &format!("| {} |\n", row.join(" | "));
+        }
+        out
+    }
+
+    fn list(&self, items: &[String], empty: &str) -> String {
+        match items {
+            [] => format!("{}\n", empty),
+            _ => items.iter().map(|item| format!("- {}\n", item)).collect(),
+        }
+    }
+}
+
+struct Html;
+
+/// The stylesheet embedded in HTML reports
+const STYLESHEET: &str = "body { font-family: sans-serif; max-width: 60em; margin: auto; } \
+    table { border-collapse: collapse; } \
+    td, th { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; } \
+    code { background: #f4f4f4; }";
+
+impl Document for Html {
+    fn start(&self, title: &str) -> String {
+        let title = self.escape(title);
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n\
+             <style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            title, STYLESHEET, title
+        )
+    }
+
+    fn end(&self) -> String {
+        "</body>\n</html>\n".to_owned()
+    }
+
+    fn heading(&self, text: &str) -> String {
+        format!("<h2>{}</h2>\n", text)
+    }
+
+    fn code(&self, text: &str) -> String {
+        format!("<code>{}</code>", self.escape(text))
+    }
+
+    fn escape(&self, text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    fn table(&self, header: &[&str], rows: &[Vec<String>]) -> String {
+        let mut out = "<table>\n".to_owned();
+        if header.iter().any(|h| !h.is_empty()) {
+            let cells: String = header.iter().map(|h| format!("<th>{}</th>", h)).collect();
+            out += &format!("<tr>{}</tr>\n", cells);
+        }
+        for row in rows {
+            let cells: String = row.iter().map(|c| format!("<td>{}</td>", c)).collect();
+            out += &format!("<tr>{}</tr>\n", cells);
+        }
+        out + "</table>\n"
+    }
+
+    fn list(&self, items: &[String], empty: &str) -> String {
+        match items {
+            [] => format!("<p>{}</p>\n", empty),
+            _ => {
+                let items: String = items.iter().map(|i| format!("<li>{}</li>\n", i)).collect();
+                format!("<ul>\n{}</ul>\n", items)
+            }
+        }
+    }
+}
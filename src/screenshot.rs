@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use tui::{
+    buffer::{Buffer, Cell},
+    style::{Color, Modifier},
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::snapshot;
+
+/// Get the path to save a screenshot of the viewer to, as
+/// `pfview-screenshot-<timestamp>.txt` in `dir` (or next to the image)
+pub fn path(image: &Path, dir: Option<&Path>) -> PathBuf {
+    let name = format!(
+        "pfview-screenshot-{}.txt",
+        Utc::now().format("%Y%m%d-%H%M%S%.3f")
+    );
+    snapshot::snapshot_dir(image, dir).join(name)
+}
+
+/// Turn a drawn screen into text, a line per row with the trailing spaces trimmed. With `ansi`,
+/// the colors and modifiers are kept as ANSI escape codes, for `less -R` or `cat` in a terminal
+pub fn to_text(buffer: &Buffer, ansi: bool) -> String {
+    let area = buffer.area;
+    let mut text = String::new();
+    for y in area.top()..area.bottom() {
+        let mut line = String::new();
+        let mut style = Cell::default();
+        // the cells a wide character covers after its own are blank
+        let mut covered = 0;
+        for x in area.left()..area.right() {
+            if covered > 0 {
+                covered -= 1;
+                continue;
+            }
+            let cell = buffer.get(x, y);
+            if ansi && (cell.fg, cell.bg, cell.modifier) != (style.fg, style.bg, style.modifier) {
+                style = cell.clone();
+                line += &escape(cell);
+            }
+            line += &cell.symbol;
+            covered = cell.symbol.width().saturating_sub(1);
+        }
+        // styled spaces at the end of a line may matter; plain ones don't
+        if ansi {
+            line += "\x1b[0m";
+        } else {
+            line.truncate(line.trim_end().len());
+        }
+        text += &line;
+        text.push('\n');
+    }
+    text
+}
+
+/// Make the escape code that resets the terminal's style and then sets the style of `cell`
+fn escape(cell: &Cell) -> String {
+    let mut codes = vec!["0".to_owned()];
+    let modifiers = [
+        (Modifier::BOLD, "1"),
+        (Modifier::DIM, "2"),
+        (Modifier::ITALIC, "3"),
+        (Modifier::UNDERLINED, "4"),
+        (Modifier::REVERSED, "7"),
+        (Modifier::CROSSED_OUT, "9"),
+    ];
+    for (modifier, code) in modifiers {
+        if cell.modifier.contains(modifier) {
+            codes.push(code.to_owned());
+        }
+    }
+    codes.extend(color_code(cell.fg, false));
+    codes.extend(color_code(cell.bg, true));
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+/// Get the SGR parameters setting a foreground (or background) color, or None for the
+/// terminal's own
+fn color_code(color: Color, background: bool) -> Option<String> {
+    let base = match background {
+        true => 10,
+        false => 0,
+    };
+    let basic = |code: u8| Some((code + base).to_string());
+    match color {
+        Color::Reset => None,
+        Color::Black => basic(30),
+        Color::Red => basic(31),
+        Color::Green => basic(32),
+        Color::Yellow => basic(33),
+        Color::Blue => basic(34),
+        Color::Magenta => basic(35),
+        Color::Cyan => basic(36),
+        Color::Gray => basic(37),
+        Color::DarkGray => basic(90),
+        Color::LightRed => basic(91),
+        Color::LightGreen => basic(92),
+        Color::LightYellow => basic(93),
+        Color::LightBlue => basic(94),
+        Color::LightMagenta => basic(95),
+        Color::LightCyan => basic(96),
+        Color::White => basic(97),
+        Color::Indexed(i) => Some(format!("{};5;{}", 38 + base, i)),
+        Color::Rgb(r, g, b) => Some(format!("{};2;{};{};{}", 38 + base, r, g, b)),
+    }
+}
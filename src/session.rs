@@ -0,0 +1,71 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::FatColumns;
+use crate::pennfat::PennFat;
+use crate::snapshot;
+
+/// What pfview remembers about an image between runs, kept as JSON in
+/// `<image name>.pfview.json` next to the image
+#[derive(Default, Serialize, Deserialize)]
+pub struct Session {
+    /// Free-text notes on blocks, by block number
+    #[serde(default)]
+    pub notes: BTreeMap<u16, String>,
+    /// The columns the FAT list shows
+    #[serde(default)]
+    pub fat_columns: FatColumns,
+}
+
+impl Session {
+    /// Get the path of the session file of an image
+    pub fn path(image: &Path) -> PathBuf {
+        let stem = image
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "image".to_owned());
+        snapshot::snapshot_dir(image, None).join(format!("{}.pfview.json", stem))
+    }
+
+    /// Load the session of an image. An image without a session file starts a new one, as
+    /// does an image loaded from memory, which has nowhere to keep one
+    pub fn load(image: &PennFat) -> io::Result<Self> {
+        if image.in_memory() {
+            return Ok(Session::default());
+        }
+        match fs::read_to_string(Self::path(image.path())) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Session::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the session of an image to its session file. The session of an image loaded from
+    /// memory is only kept for as long as it's viewed
+    pub fn save(&self, image: &PennFat) -> io::Result<()> {
+        if image.in_memory() {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(Self::path(image.path()), json + "\n")
+    }
+
+    /// Get the note on a block, if any
+    pub fn note(&self, block: u16) -> Option<&str> {
+        self.notes.get(&block).map(String::as_str)
+    }
+
+    /// Set the note on a block. Blank text deletes the note
+    pub fn set_note(&mut self, block: u16, text: &str) {
+        match text.trim() {
+            "" => self.notes.remove(&block),
+            text => self.notes.insert(block, text.to_owned()),
+        };
+    }
+}
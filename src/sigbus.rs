@@ -0,0 +1,196 @@
+//! Surviving a writer truncating a mapped image under a read. Touching a page of a map past the
+//! end of its file raises SIGBUS, which kills the process. On Unix, a handler for it puts a page
+//! of zeros where the missing one was, so the read carries on, and marks the map faulted so the
+//! image isn't read from it again (see `PennFat::contents`). Faults outside a registered map
+//! are passed on to whatever handled SIGBUS before
+
+use std::ops::Deref;
+
+use memmap2::Mmap;
+
+/// A map of an image, registered with the SIGBUS handler for as long as it's kept
+pub(crate) struct Mapped {
+    // dropped before the map, so no fault is put down to a map that has gone
+    guard: Option<imp::Guard>,
+    map: Mmap,
+}
+
+impl Mapped {
+    pub(crate) fn new(map: Mmap) -> Self {
+        Mapped {
+            guard: imp::Guard::register(&map),
+            map,
+        }
+    }
+
+    /// Whether a page of the map was found missing, and replaced with zeros
+    pub(crate) fn faulted(&self) -> bool {
+        self.guard.as_ref().is_some_and(imp::Guard::faulted)
+    }
+}
+
+impl Deref for Mapped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        mem, ptr,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Once, OnceLock,
+        },
+    };
+
+    use libc::{c_int, c_void, siginfo_t};
+
+    /// The most maps that can be registered at once. Past that, a map goes unprotected
+    const SLOTS: usize = 64;
+
+    /// A registered map: where it starts (0 for a free slot), how long it is, and whether a
+    /// page of it was replaced. Atomics, as the handler reads them without taking a lock
+    struct Slot {
+        start: AtomicUsize,
+        len: AtomicUsize,
+        faulted: AtomicBool,
+    }
+
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: Slot = Slot {
+        start: AtomicUsize::new(0),
+        len: AtomicUsize::new(0),
+        faulted: AtomicBool::new(false),
+    };
+    static MAPS: [Slot; SLOTS] = [FREE; SLOTS];
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+    /// How SIGBUS was handled before, to pass on the faults that aren't ours
+    static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+    static INSTALL: Once = Once::new();
+
+    pub(super) struct Guard(usize);
+
+    impl Guard {
+        pub(super) fn register(map: &[u8]) -> Option<Guard> {
+            if map.is_empty() {
+                return None;
+            }
+            INSTALL.call_once(install);
+            let start = map.as_ptr() as usize;
+            let index = MAPS.iter().position(|slot| {
+                slot.start
+                    .compare_exchange(0, start, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })?;
+            MAPS[index].faulted.store(false, Ordering::SeqCst);
+            MAPS[index].len.store(map.len(), Ordering::SeqCst);
+            Some(Guard(index))
+        }
+
+        pub(super) fn faulted(&self) -> bool {
+            MAPS[self.0].faulted.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            MAPS[self.0].len.store(0, Ordering::SeqCst);
+            MAPS[self.0].start.store(0, Ordering::SeqCst);
+        }
+    }
+
+    fn install() {
+        // SAFETY: sysconf and sigaction are given valid arguments, and the handler only does
+        // what's safe in a signal handler: atomics, mmap, and calling the previous handler
+        unsafe {
+            PAGE_SIZE.store(libc::sysconf(libc::_SC_PAGESIZE) as usize, Ordering::SeqCst);
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_sigbus as *const () as usize;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = mem::zeroed();
+            if libc::sigaction(libc::SIGBUS, &action, &mut previous) == 0 {
+                let _ = PREVIOUS.set(previous);
+            }
+        }
+    }
+
+    extern "C" fn on_sigbus(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+        // SAFETY: the kernel passes a valid siginfo to an SA_SIGINFO handler
+        let address = unsafe { (*info).si_addr() } as usize;
+        let page_size = PAGE_SIZE.load(Ordering::SeqCst);
+        for slot in &MAPS {
+            let start = slot.start.load(Ordering::SeqCst);
+            let len = slot.len.load(Ordering::SeqCst);
+            if start == 0 || !(start..start + len).contains(&address) {
+                continue;
+            }
+            let page = address & !(page_size - 1);
+            // SAFETY: the page is part of a live map of ours, so replacing it only changes
+            // what reads of the map see, and the map is unmapped whole when it's dropped
+            let replaced = unsafe {
+                libc::mmap(
+                    page as *mut c_void,
+                    page_size,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                    -1,
+                    0,
+                )
+            };
+            if replaced != libc::MAP_FAILED {
+                slot.faulted.store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+        pass_on(signal, info, context);
+    }
+
+    /// Hand a fault that isn't ours to the previous handler, or, if there was none, go back to
+    /// the default action, which the fault happening again on return then takes
+    fn pass_on(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+        let previous = PREVIOUS
+            .get()
+            .map_or(libc::SIG_DFL, |action| action.sa_sigaction);
+        // SAFETY: the previous handler is called as it was installed to be called, and
+        // resetting to the default action is always allowed
+        unsafe {
+            match (previous, PREVIOUS.get()) {
+                (libc::SIG_DFL | libc::SIG_IGN, _) | (_, None) => {
+                    let mut action: libc::sigaction = mem::zeroed();
+                    action.sa_sigaction = libc::SIG_DFL;
+                    libc::sigaction(signal, &action, ptr::null_mut());
+                }
+                (handler, Some(action)) if action.sa_flags & libc::SA_SIGINFO != 0 => {
+                    let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) =
+                        mem::transmute(handler);
+                    handler(signal, info, context);
+                }
+                (handler, Some(_)) => {
+                    let handler: extern "C" fn(c_int) = mem::transmute(handler);
+                    handler(signal);
+                }
+            }
+        }
+    }
+}
+
+/// Elsewhere, reading past the end of a file through a map doesn't raise a signal to handle
+#[cfg(not(unix))]
+mod imp {
+    pub(super) struct Guard;
+
+    impl Guard {
+        pub(super) fn register(_: &[u8]) -> Option<Guard> {
+            None
+        }
+
+        pub(super) fn faulted(&self) -> bool {
+            false
+        }
+    }
+}
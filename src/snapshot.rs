@@ -0,0 +1,217 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+
+use crate::pennfat::{PennFat, PfError};
+
+/// Marker between the image name and the timestamp in snapshot file names
+const SNAPSHOT_MARKER: &str = ".snapshot-";
+/// Suffix of snapshots taken automatically, which are the only ones ever pruned
+const AUTO_SUFFIX: &str = ".auto.img";
+/// Suffix of snapshots taken on demand
+const MANUAL_SUFFIX: &str = ".img";
+
+/// Get the directory snapshots of an image go in: the given one, or next to the image
+pub fn snapshot_dir(image: &Path, dir: Option<&Path>) -> PathBuf {
+    match dir {
+        Some(dir) => dir.to_owned(),
+        None => image
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+            .to_owned(),
+    }
+}
+
+/// Get the prefix shared by the file names of every snapshot of an image
+fn snapshot_prefix(image: &Path) -> String {
+    let stem = image
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image".to_owned());
+    format!("{}{}", stem, SNAPSHOT_MARKER)
+}
+
+/// Write a snapshot of the image's current contents, as
+/// `<image name>.snapshot-<timestamp>.img` in `dir` (or next to the image).
+/// The snapshot is written to a temporary file first and renamed into place,
+/// so a partially written snapshot never shows up under a snapshot name.
+pub fn take(fs: &PennFat, dir: Option<&Path>, auto: bool) -> io::Result<PathBuf> {
+    let dir = snapshot_dir(fs.path(), dir);
+    let name = format!(
+        "{}{}{}",
+        snapshot_prefix(fs.path()),
+        // sortable, and fine-grained enough that snapshots on consecutive ticks don't collide
+        Utc::now().format("%Y%m%d-%H%M%S%.3f"),
+        if auto { AUTO_SUFFIX } else { MANUAL_SUFFIX }
+    );
+    let path = dir.join(&name);
+    let tmp_path = dir.join(format!(".{}.tmp", name));
+
+    let write = || -> io::Result<()> {
+        let mut tmp = fs::File::create(&tmp_path)?;
+        tmp.write_all(fs.bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    };
+    if let Err(e) = write() {
+        // don't leave the partial copy behind
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    Ok(path)
+}
+
+/// List the snapshots of an image in `dir` (or next to the image), oldest first
+pub fn list(image: &Path, dir: Option<&Path>) -> io::Result<Vec<PathBuf>> {
+    let prefix = snapshot_prefix(image);
+    let mut snapshots = fs::read_dir(snapshot_dir(image, dir))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(MANUAL_SUFFIX))
+        })
+        .collect::<Vec<_>>();
+    // the timestamp follows the common prefix, so names sort chronologically
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Delete all but the newest `keep` automatic snapshots of an image.
+/// Snapshots taken on demand are never deleted
+pub fn prune(image: &Path, dir: Option<&Path>, keep: usize) -> io::Result<()> {
+    let auto = list(image, dir)?
+        .into_iter()
+        .filter(|path| path.to_string_lossy().ends_with(AUTO_SUFFIX))
+        .collect::<Vec<_>>();
+    for path in &auto[..auto.len().saturating_sub(keep)] {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Navigation back and forth between the live image and its snapshots.
+/// Only the snapshot being viewed and the one before it (to diff against) are kept loaded
+#[derive(Default)]
+pub struct Timeline {
+    /// The snapshot being viewed, or None when viewing the live image
+    viewing: Option<(PathBuf, PennFat)>,
+    /// The snapshot taken just before the one being viewed, if any
+    previous: Option<PennFat>,
+    /// Position of the snapshot being viewed among all snapshots (1-based), and their count
+    position: (usize, usize),
+}
+
+impl Timeline {
+    /// Get the snapshot being viewed, or None when viewing the live image
+    pub fn current(&self) -> Option<&PennFat> {
+        self.viewing.as_ref().map(|(_, fs)| fs)
+    }
+
+    /// Get the snapshot taken just before the one being viewed, if any
+    pub fn previous(&self) -> Option<&PennFat> {
+        self.previous.as_ref()
+    }
+
+    /// Describe the point in time being viewed, or None when viewing the live image
+    pub fn describe(&self) -> Option<String> {
+        self.viewing.as_ref().map(|(path, _)| {
+            format!(
+                "snapshot {} of {} ({})",
+                self.position.0,
+                self.position.1,
+                path.file_name().unwrap_or_default().to_string_lossy()
+            )
+        })
+    }
+
+    /// Show block numbers in decimal (or back in hex) in the loaded snapshots too
+    pub fn set_decimal_blocks(&mut self, decimal: bool) {
+        if let Some((_, fs)) = &mut self.viewing {
+            fs.set_decimal_blocks(decimal);
+        }
+        if let Some(fs) = &mut self.previous {
+            fs.set_decimal_blocks(decimal);
+        }
+    }
+
+    /// Step back to the snapshot before the one being viewed (or the newest one, when viewing
+    /// the live image). Returns false if there is no older snapshot
+    pub fn older(&mut self, live: &PennFat, dir: Option<&Path>) -> Result<bool, PfError> {
+        let snapshots = list(live.path(), dir)?;
+        // the live image comes after every snapshot. A snapshot that was pruned while being
+        // viewed still has its place in the (sorted) list
+        let index = match &self.viewing {
+            None => snapshots.len(),
+            Some((path, _)) => snapshots.binary_search(path).unwrap_or_else(|i| i),
+        };
+        if index == 0 {
+            return Ok(false);
+        }
+        self.view(live, &snapshots, index - 1)?;
+        Ok(true)
+    }
+
+    /// Step forward to the snapshot after the one being viewed, or to the live image after the
+    /// newest snapshot. Returns false if already viewing the live image
+    pub fn newer(&mut self, live: &PennFat, dir: Option<&Path>) -> Result<bool, PfError> {
+        let Some((path, _)) = &self.viewing else {
+            return Ok(false);
+        };
+        let snapshots = list(live.path(), dir)?;
+        let index = match snapshots.binary_search(path) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        if index >= snapshots.len() {
+            // back to the live image; drop the snapshots
+            *self = Self::default();
+        } else {
+            self.view(live, &snapshots, index)?;
+        }
+        Ok(true)
+    }
+
+    /// Load the snapshot at the given index (and the one before it), read the same way as the
+    /// live image
+    fn view(&mut self, live: &PennFat, snapshots: &[PathBuf], index: usize) -> Result<(), PfError> {
+        let load = |path: &Path| {
+            PennFat::load_snapshot(path).map(|mut fs| {
+                fs.set_dentry_layout(*live.dentry_layout());
+                fs.set_root_block(live.root_block());
+                fs.set_profile(live.profile());
+                fs.set_max_chain_len(live.max_chain_len());
+                fs.set_decimal_blocks(live.decimal_blocks());
+                fs
+            })
+        };
+        let fs = load(&snapshots[index])?;
+        self.previous = match index {
+            0 => None,
+            // the previous snapshot is only for diffing, so not being able to read it is fine
+            _ => load(&snapshots[index - 1]).ok(),
+        };
+        self.viewing = Some((snapshots[index].clone(), fs));
+        self.position = (index + 1, snapshots.len());
+        Ok(())
+    }
+}
+
+/// Get the blocks whose FAT entries differ between two FAT tables (as returned by
+/// [`PennFat::get_fat_table`]), among the blocks allocated in `current`
+pub fn fat_diff(current: &[(u16, u16)], previous: &[(u16, u16)]) -> HashSet<u16> {
+    let previous: HashMap<u16, u16> = previous.iter().copied().collect();
+    current
+        .iter()
+        .filter(|(block, next)| previous.get(block) != Some(next))
+        .map(|(block, _)| *block)
+        .collect()
+}
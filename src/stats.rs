@@ -0,0 +1,247 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::pennfat::PennFat;
+use crate::walk;
+
+/// A file standing out in some way, and by how much
+#[derive(Serialize)]
+pub struct Notable {
+    /// The absolute path of the file
+    pub path: String,
+    /// What makes the file stand out: its size, depth or number of extents
+    pub value: usize,
+}
+
+/// Aggregate statistics of a filesystem
+#[derive(Serialize)]
+pub struct Stats {
+    pub files: usize,
+    pub directories: usize,
+    pub symlinks: usize,
+    /// Deleted dentries left in the directories
+    pub deleted_dentries: usize,
+    /// Bytes of file (and symbolic link) contents
+    pub content_bytes: usize,
+    /// Bytes allocated to files but past the end of their contents
+    pub slack_bytes: usize,
+    /// Slack bytes that aren't zero, which may be data leaking from elsewhere
+    pub nonzero_slack_bytes: usize,
+    /// Bytes of the blocks holding directories
+    pub directory_bytes: usize,
+    /// The path with the most components
+    pub deepest_path: Option<Notable>,
+    pub largest_file: Option<Notable>,
+    /// The file whose chain breaks into the most runs of consecutive blocks
+    pub most_fragmented_file: Option<Notable>,
+    /// Allocated blocks not in the chain of any file
+    pub orphaned_blocks: usize,
+}
+
+/// What the viewer's overview says about an image: its configuration, size and how full it is.
+/// Only the FAT is read, so this is quick however large the image is
+#[derive(Serialize)]
+pub struct Overview {
+    pub block_size: u16,
+    pub fat_blocks: u32,
+    /// Size of the FAT in bytes
+    pub fat_size: u32,
+    /// Entries in the FAT, including entry 0
+    pub fat_entries: u32,
+    pub data_blocks: u16,
+    /// Bytes of FAT past the entry of the last data block, which can never be used
+    pub wasted_fat_bytes: u32,
+    /// Size of the image file in bytes
+    pub file_size: u64,
+    /// Bytes of the file past the last data block, which no block number can address
+    pub unaddressed_bytes: usize,
+    /// When the image file was last modified, in UTC
+    pub last_modified: String,
+    /// Data blocks whose FAT entry isn't free
+    pub used_blocks: usize,
+    pub free_blocks: usize,
+}
+
+impl Overview {
+    pub fn collect(fs: &PennFat) -> Self {
+        let used_blocks = fs.get_fat_table().len();
+        let last_modified: DateTime<Utc> = fs.last_update_time().into();
+        Overview {
+            block_size: fs.block_size(),
+            fat_blocks: fs.fat_size() / fs.block_size() as u32,
+            fat_size: fs.fat_size(),
+            fat_entries: fs.num_fat_entries(),
+            data_blocks: fs.data_block_count(),
+            wasted_fat_bytes: fs.wasted_fat_bytes(),
+            file_size: fs.bytes().len() as u64,
+            unaddressed_bytes: fs.unaddressed().len(),
+            last_modified: last_modified.format("%Y-%m-%d %H:%M:%S").to_string(),
+            used_blocks,
+            free_blocks: (fs.data_block_count() as usize).saturating_sub(used_blocks),
+        }
+    }
+
+    /// The overview as key/value pairs, for printing
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("block size", self.block_size.to_string()),
+            ("fat blocks", self.fat_blocks.to_string()),
+            ("fat size", self.fat_size.to_string()),
+            ("fat entries", self.fat_entries.to_string()),
+            ("data blocks", self.data_blocks.to_string()),
+            ("wasted fat", self.wasted_fat_bytes.to_string()),
+            ("file size", self.file_size.to_string()),
+            ("unaddressed", self.unaddressed_bytes.to_string()),
+            ("last modified", self.last_modified.clone()),
+            ("used blocks", self.used_blocks.to_string()),
+            ("free blocks", self.free_blocks.to_string()),
+        ]
+    }
+}
+
+/// Count the runs of consecutive blocks in a chain
+pub fn extents(blocks: &[u16]) -> usize {
+    match blocks {
+        [] => 0,
+        _ => 1 + blocks.windows(2).filter(|w| w[1] != w[0] + 1).count(),
+    }
+}
+
+/// Count the bytes of a file's slack (past `size`, in the blocks of its chain) that aren't zero
+fn nonzero_slack(fs: &PennFat, blocks: &[u16], size: usize) -> usize {
+    let block_size = fs.block_size() as usize;
+    blocks
+        .iter()
+        .enumerate()
+        .skip(size / block_size)
+        .filter_map(|(i, block)| {
+            let bytes = fs.block_bytes(*block).ok()?;
+            let start = size.saturating_sub(i * block_size).min(bytes.len());
+            Some(bytes[start..].iter().filter(|b| **b != 0).count())
+        })
+        .sum()
+}
+
+/// Keep whichever of `best` and the new candidate has the larger value (the first one on ties)
+fn keep_max(best: &mut Option<Notable>, path: &str, value: usize) {
+    if best.as_ref().is_none_or(|best| value > best.value) {
+        *best = Some(Notable {
+            path: path.to_owned(),
+            value,
+        });
+    }
+}
+
+impl Stats {
+    /// Gather the statistics of a filesystem, walking its directory tree once and scanning its FAT
+    pub fn collect(fs: &PennFat) -> Self {
+        let block_size = fs.block_size() as usize;
+        let mut stats = Stats {
+            files: 0,
+            directories: 0,
+            symlinks: 0,
+            deleted_dentries: 0,
+            content_bytes: 0,
+            slack_bytes: 0,
+            nonzero_slack_bytes: 0,
+            directory_bytes: 0,
+            deepest_path: None,
+            largest_file: None,
+            most_fragmented_file: None,
+            orphaned_blocks: 0,
+        };
+        let mut owned = HashSet::new();
+
+        // the root has no dentry, so it isn't part of the walk
+        let mut directories = vec![fs.chain(fs.root_block()).blocks];
+        for entry in walk::walk(fs) {
+            let blocks = fs.chain(entry.dentry.first_block).blocks;
+            let depth = entry.path.matches('/').count();
+            keep_max(&mut stats.deepest_path, &entry.path, depth);
+            if entry.dentry.is_dir() {
+                stats.directories += 1;
+                directories.push(blocks);
+                continue;
+            }
+            if entry.dentry.is_symlink() {
+                stats.symlinks += 1;
+            } else {
+                stats.files += 1;
+                keep_max(
+                    &mut stats.largest_file,
+                    &entry.path,
+                    entry.dentry.size as usize,
+                );
+                keep_max(
+                    &mut stats.most_fragmented_file,
+                    &entry.path,
+                    extents(&blocks),
+                );
+            }
+            let size = entry.dentry.size as usize;
+            let allocated = blocks.len() * block_size;
+            stats.content_bytes += size.min(allocated);
+            stats.slack_bytes += allocated.saturating_sub(size);
+            stats.nonzero_slack_bytes += nonzero_slack(fs, &blocks, size);
+            owned.extend(blocks);
+        }
+
+        // a directory linked into the tree more than once is only counted once
+        let mut seen = HashSet::new();
+        for blocks in directories {
+            for block in blocks {
+                if !seen.insert(block) {
+                    continue;
+                }
+                stats.directory_bytes += block_size;
+                owned.insert(block);
+                let Ok(block) = fs.get_block(block) else {
+                    continue;
+                };
+                stats.deleted_dentries += block.dentry_usage(fs.dentry_layout()).deleted;
+            }
+        }
+
+        stats.orphaned_blocks = fs
+            .get_fat_table()
+            .iter()
+            .filter(|(block, _)| !owned.contains(block))
+            .count();
+        stats
+    }
+
+    /// The statistics as rows of a key/value table
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let notable = |notable: &Option<Notable>, unit: &str| match notable {
+            Some(notable) => format!("{} ({} {})", notable.path, notable.value, unit),
+            None => "-".to_owned(),
+        };
+        vec![
+            ("files", self.files.to_string()),
+            ("directories", self.directories.to_string()),
+            ("symlinks", self.symlinks.to_string()),
+            ("deleted dentries", self.deleted_dentries.to_string()),
+            ("file contents", format!("{} bytes", self.content_bytes)),
+            (
+                "slack",
+                match self.nonzero_slack_bytes {
+                    0 => format!("{} bytes (all zeros)", self.slack_bytes),
+                    n => format!("{} bytes ({} not zero)", self.slack_bytes, n),
+                },
+            ),
+            (
+                "directory metadata",
+                format!("{} bytes", self.directory_bytes),
+            ),
+            ("deepest path", notable(&self.deepest_path, "levels")),
+            ("largest file", notable(&self.largest_file, "bytes")),
+            (
+                "most fragmented file",
+                notable(&self.most_fragmented_file, "extents"),
+            ),
+            ("orphaned blocks", self.orphaned_blocks.to_string()),
+        ]
+    }
+}
@@ -0,0 +1,46 @@
+//! Images to try the library out on, in examples and tests
+
+use std::io::Write;
+
+use tempfile::{NamedTempFile, TempPath};
+
+use crate::fuzz;
+use crate::pennfat::{Mode, PennFat, Window};
+
+/// Build a small sample image in memory, the same every time, to open with
+/// [`PennFat::from_bytes`](crate::pennfat::PennFat::from_bytes). It's the image `pfview demo`
+/// opens: 256-byte blocks, with
+///
+/// - `/README`, a one-line file in block 0x02
+/// - `/notes.txt`, 940 bytes scattered over blocks 0x04, 0x0c, 0x05 and 0x14
+/// - `/latest`, a symbolic link to `docs/guide.txt`
+/// - `/docs/guide.txt` and `/docs/src/main.c`, in the subdirectories `/docs` and `/docs/src`
+/// - a deleted `old.log` in the root directory's second block, 0x09
+/// - an orphan chain, 0x1e to 0x20, that no directory entry leads to
+///
+/// ```
+/// use pfview::pennfat::PennFat;
+///
+/// let fs = PennFat::from_bytes(pfview::testing::sample_image()).unwrap();
+/// assert_eq!(fs.block_size(), 256);
+/// assert_eq!(fs.data_block_count(), 127);
+/// ```
+pub fn sample_image() -> Vec<u8> {
+    fuzz::sample()
+}
+
+/// Write an image to a temporary file and load it writable, to try out changes to it. The
+/// file is deleted when the returned path is dropped
+///
+/// ```
+/// let (_path, fs) = pfview::testing::writable_image(&pfview::testing::sample_image());
+/// fs.set_fat_entry(0x02, 0x03).unwrap();
+/// assert_eq!(fs.fat_entry(0x02), Some(0x03));
+/// ```
+pub fn writable_image(bytes: &[u8]) -> (TempPath, PennFat) {
+    let mut file = NamedTempFile::new().expect("can create a temporary file");
+    file.write_all(bytes).expect("can write a temporary file");
+    let path = file.into_temp_path();
+    let fs = PennFat::load(&path, Mode::Writable, Window::default()).expect("can load the image");
+    (path, fs)
+}
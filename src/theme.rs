@@ -0,0 +1,359 @@
+use std::env;
+
+use tui::{
+    backend::Backend,
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier},
+    widgets::Widget,
+    Frame,
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// The glyphs pfview draws with. Terminals without Unicode (e.g. a serial console) get ASCII
+/// stand-ins for all of them, borders included
+pub struct Theme {
+    /// Draw borders with ASCII characters
+    pub ascii: bool,
+    /// Draw without colors, for terminals that have few or none
+    pub monochrome: bool,
+    /// Between the blocks of a chain
+    pub arrow: &'static str,
+    /// Standing in for the blocks of a chain, or the middle of a name, that don't fit
+    pub ellipsis: &'static str,
+    /// Between a directory and the usage of its block
+    pub dash: &'static str,
+    /// Before a warning about a block
+    pub warning: &'static str,
+    /// Before the read-only tag of the overview
+    pub lock: &'static str,
+    /// In the gutter of a marked block
+    pub mark: &'static str,
+    /// In the gutter of a block with a note
+    pub note: &'static str,
+    /// The up arrow key
+    pub up: &'static str,
+    /// The down arrow key
+    pub down: &'static str,
+    /// The part of the FAT list's minimap that's in view
+    pub thumb: &'static str,
+    /// Before a directory of the tree view whose entries are hidden
+    pub collapsed: &'static str,
+    /// Before a directory of the tree view whose entries are shown under it
+    pub expanded: &'static str,
+}
+
+impl Theme {
+    const UNICODE: Theme = Theme {
+        ascii: false,
+        monochrome: false,
+        arrow: "→",
+        ellipsis: "…",
+        dash: "—",
+        warning: "⚠",
+        lock: "🔒 ",
+        mark: "●",
+        note: "✎",
+        up: "↑",
+        down: "↓",
+        thumb: "█",
+        collapsed: "▸",
+        expanded: "▾",
+    };
+
+    const ASCII: Theme = Theme {
+        ascii: true,
+        monochrome: false,
+        arrow: "->",
+        ellipsis: "...",
+        dash: "-",
+        warning: "*",
+        lock: "",
+        mark: "*",
+        note: "+",
+        up: "up",
+        down: "down",
+        thumb: "#",
+        collapsed: "+",
+        expanded: "-",
+    };
+
+    /// The theme to draw with, in ASCII or Unicode, and in color or not
+    pub fn new(ascii: bool, monochrome: bool) -> Self {
+        let theme = match ascii {
+            true => Self::ASCII,
+            false => Self::UNICODE,
+        };
+        Theme {
+            monochrome,
+            ..theme
+        }
+    }
+
+    /// Guess whether the terminal can't show Unicode: when there is no locale, or it isn't a
+    /// UTF-8 one, or there's no (or a dumb) terminal
+    pub fn detect_ascii() -> bool {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty());
+        let utf8 = locale.is_some_and(|locale| {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        });
+        let dumb = env::var("TERM").map_or(true, |term| term.is_empty() || term == "dumb");
+        !utf8 || dumb
+    }
+
+    /// Guess whether the terminal can't show the colors pfview draws with, from the environment
+    pub fn detect_monochrome() -> bool {
+        let var = |name| env::var(name).ok().filter(|value| !value.is_empty());
+        monochrome(
+            var("TERM").as_deref(),
+            var("COLORTERM").is_some(),
+            var("NO_COLOR").is_some(),
+        )
+    }
+
+    /// The label of a key binding, with the arrow keys spelled the way this theme does
+    pub fn key_label(&self, label: &str) -> String {
+        label.replace('↑', self.up).replace('↓', self.down)
+    }
+
+    /// Shorten text to at most `width` columns by cutting out its middle, keeping a file
+    /// extension whole where there's room for it. Characters are never split, and wide ones
+    /// (such as CJK) count as the two columns they take
+    pub fn truncate_middle(&self, text: &str, width: usize) -> String {
+        if text.width() <= width {
+            return text.to_owned();
+        }
+        let room = width.saturating_sub(self.ellipsis.width());
+        if room == 0 {
+            return take_width(self.ellipsis.chars(), width).collect();
+        }
+        let extension = text
+            .rfind('.')
+            .map(|i| &text[i..])
+            .filter(|ext| ext.len() > 1 && !ext.contains(' ') && ext.width() < room)
+            .map_or(0, UnicodeWidthStr::width);
+        let tail: Vec<char> = take_width(text.chars().rev(), (room / 2).max(extension)).collect();
+        let tail_width: usize = tail.iter().map(|c| c.width().unwrap_or(0)).sum();
+        let head: String = take_width(text.chars(), room - tail_width).collect();
+        format!(
+            "{}{}{}",
+            head,
+            self.ellipsis,
+            tail.into_iter().rev().collect::<String>()
+        )
+    }
+
+    /// Finish drawing a frame, replacing the box drawing characters of the borders with ASCII
+    /// ones if need be. Call it after rendering everything else
+    pub fn finish<B: Backend>(&self, frame: &mut Frame<B>) {
+        if self.ascii {
+            frame.render_widget(AsciiBorders, frame.size());
+        }
+        if self.monochrome {
+            frame.render_widget(Monochrome, frame.size());
+        }
+    }
+}
+
+/// Take characters for as long as they fit in `width` columns
+fn take_width(chars: impl Iterator<Item = char>, width: usize) -> impl Iterator<Item = char> {
+    let mut used = 0;
+    chars.take_while(move |c| {
+        used += c.width().unwrap_or(0);
+        used <= width
+    })
+}
+
+/// Decide whether a terminal has too few colors (8 or fewer) for pfview's, going by its
+/// `$TERM`, whether `$COLORTERM` is set, and whether the user asked for no colors (`$NO_COLOR`)
+pub fn monochrome(term: Option<&str>, colorterm: bool, no_color: bool) -> bool {
+    if no_color {
+        return true;
+    }
+    if colorterm {
+        return false;
+    }
+    let Some(term) = term.filter(|term| *term != "dumb") else {
+        return true;
+    };
+    let many_colors = ["256color", "16color", "truecolor", "direct"];
+    let color_terminals = [
+        "xterm",
+        "screen",
+        "tmux",
+        "rxvt",
+        "alacritty",
+        "kitty",
+        "foot",
+        "wezterm",
+        "konsole",
+        "gnome",
+        "putty",
+        "cygwin",
+        "ms-terminal",
+    ];
+    // the Linux console, vt100s and the like have 8 colors at most
+    !(many_colors.iter().any(|suffix| term.contains(suffix))
+        || color_terminals
+            .iter()
+            .any(|prefix| term.starts_with(prefix)))
+}
+
+/// Takes the colors out of what's already drawn. Highlights drawn with a background color
+/// are drawn reversed instead, so the selection stays visible
+struct Monochrome;
+
+impl Widget for Monochrome {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                if cell.bg != Color::Reset {
+                    cell.modifier.insert(Modifier::REVERSED);
+                }
+                cell.fg = Color::Reset;
+                cell.bg = Color::Reset;
+            }
+        }
+    }
+}
+
+/// Replaces the box drawing characters already drawn in an area with ASCII ones. The widgets
+/// can't draw ASCII borders themselves, so this goes over them afterwards
+struct AsciiBorders;
+
+impl Widget for AsciiBorders {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for y in area.top()..area.bottom() {
+            for x in area.left()..area.right() {
+                let cell = buf.get_mut(x, y);
+                let ascii = match cell.symbol.as_str() {
+                    "│" | "┃" | "║" => "|",
+                    "─" | "━" | "═" => "-",
+                    "┌" | "┐" | "└" | "┘" | "╭" | "╮" | "╰" | "╯" | "├" | "┤" | "┬" | "┴" | "┼" => {
+                        "+"
+                    }
+                    _ => continue,
+                };
+                cell.set_symbol(ascii);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{ColorChoice, Options};
+
+    #[test]
+    fn few_colors_mean_monochrome() {
+        for term in [
+            None,
+            Some("dumb"),
+            Some("linux"),
+            Some("vt100"),
+            Some("ansi"),
+        ] {
+            assert!(monochrome(term, false, false), "{:?} has colors", term);
+        }
+        // $COLORTERM is only set by terminals with many colors, whatever $TERM says
+        assert!(!monochrome(Some("vt100"), true, false));
+    }
+
+    #[test]
+    fn color_terminals_get_colors() {
+        for term in [
+            "xterm",
+            "xterm-256color",
+            "screen-256color",
+            "tmux-256color",
+            "alacritty",
+            "st-truecolor",
+        ] {
+            assert!(
+                !monochrome(Some(term), false, false),
+                "{} has no colors",
+                term
+            );
+        }
+    }
+
+    #[test]
+    fn no_color_wins() {
+        assert!(monochrome(Some("xterm-256color"), true, true));
+    }
+
+    #[test]
+    fn the_color_flag_overrides_detection() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            Options::parse(&args).map(|options| options.color)
+        };
+        assert!(parse(&["a.img"]) == Ok(ColorChoice::Auto));
+        assert!(parse(&["--color=never", "a.img"]) == Ok(ColorChoice::Never));
+        assert!(parse(&["--color", "always", "a.img"]) == Ok(ColorChoice::Always));
+        assert!(parse(&["--color=sometimes", "a.img"]).is_err());
+    }
+
+    #[test]
+    fn monochrome_drawing_keeps_highlights_visible() {
+        let area = Rect::new(0, 0, 2, 1);
+        let mut buf = Buffer::empty(area);
+        buf.get_mut(0, 0).set_fg(Color::Red);
+        buf.get_mut(1, 0).set_fg(Color::Black).set_bg(Color::Cyan);
+        Monochrome.render(area, &mut buf);
+        for x in 0..2 {
+            assert_eq!(buf.get(x, 0).fg, Color::Reset);
+            assert_eq!(buf.get(x, 0).bg, Color::Reset);
+        }
+        assert!(!buf.get(0, 0).modifier.contains(Modifier::REVERSED));
+        assert!(buf.get(1, 0).modifier.contains(Modifier::REVERSED));
+    }
+    #[test]
+    fn short_text_is_left_alone() {
+        let theme = Theme::new(false, false);
+        assert_eq!(theme.truncate_middle("notes.txt", 9), "notes.txt");
+        assert_eq!(theme.truncate_middle("", 0), "");
+    }
+
+    #[test]
+    fn truncation_keeps_the_extension() {
+        let theme = Theme::new(false, false);
+        let name = "a_very_long_file_name_indeed.txt";
+        let short = theme.truncate_middle(name, 16);
+        assert_eq!(short.width(), 16);
+        assert!(short.starts_with("a_very"), "{}", short);
+        assert!(short.ends_with(".txt"), "{}", short);
+        assert!(short.contains('…'));
+        let ascii = Theme::new(true, false).truncate_middle(name, 16);
+        assert_eq!(ascii.width(), 16);
+        assert!(ascii.contains("..."), "{}", ascii);
+    }
+
+    #[test]
+    fn truncation_never_splits_wide_characters() {
+        let theme = Theme::new(false, false);
+        // each character takes two columns and three bytes
+        let name = "\u{65e5}\u{672c}\u{8a9e}\u{306e}\u{9577}\u{3044}\u{540d}\u{524d}.txt";
+        for width in 0..name.width() {
+            let short = theme.truncate_middle(name, width);
+            assert!(short.width() <= width, "{} is wider than {}", short, width);
+            assert!(short.chars().all(|c| name.contains(c) || c == '…'));
+        }
+        assert_eq!(
+            theme.truncate_middle(name, 11),
+            "\u{65e5}\u{672c}\u{8a9e}….txt"
+        );
+    }
+
+    #[test]
+    fn too_narrow_for_anything_but_the_ellipsis() {
+        assert_eq!(Theme::new(false, false).truncate_middle("abcdef", 1), "…");
+        assert_eq!(Theme::new(true, false).truncate_middle("abcdef", 2), "..");
+    }
+}
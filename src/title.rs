@@ -0,0 +1,75 @@
+use unicode_width::UnicodeWidthStr;
+
+use crate::theme::Theme;
+
+/// A pane's title, built from the pane's state: a head that's always shown (such as the block
+/// number), then parts that say more about it, each ranked by how much it matters. When the
+/// pane is too narrow for all of them, the parts that matter least are dropped first
+pub struct Title {
+    head: String,
+    parts: Vec<Part>,
+}
+
+/// A part of a title, and what goes between it and what's before it
+struct Part {
+    separator: &'static str,
+    text: String,
+    rank: u8,
+}
+
+impl Title {
+    pub fn new(head: impl Into<String>) -> Self {
+        Title {
+            head: head.into(),
+            parts: Vec::new(),
+        }
+    }
+
+    /// Add a part after the ones so far, separated from them by " - ". The higher its `rank`,
+    /// the longer it's kept in a narrow pane. An empty part is left out
+    pub fn part(self, rank: u8, text: impl Into<String>) -> Self {
+        self.joined(" - ", rank, text)
+    }
+
+    /// Add a part after the ones so far, with `separator` in between
+    pub fn joined(mut self, separator: &'static str, rank: u8, text: impl Into<String>) -> Self {
+        let text = text.into();
+        if !text.is_empty() {
+            self.parts.push(Part {
+                separator,
+                text,
+                rank,
+            });
+        }
+        self
+    }
+
+    /// Get the title as it fits in `width` columns: with the parts that matter least dropped
+    /// (the later of two that matter as much), until the rest fit. A head too wide on its own
+    /// is cut short in the middle
+    pub fn fit(&self, theme: &Theme, width: usize) -> String {
+        let mut kept: Vec<&Part> = self.parts.iter().collect();
+        loop {
+            let title = self.join(&kept);
+            if title.width() <= width || kept.is_empty() {
+                return theme.truncate_middle(&title, width);
+            }
+            let least = kept
+                .iter()
+                .enumerate()
+                .rev()
+                .min_by_key(|(_, part)| part.rank)
+                .map_or(0, |(i, _)| i);
+            kept.remove(least);
+        }
+    }
+
+    fn join(&self, parts: &[&Part]) -> String {
+        let mut title = self.head.clone();
+        for part in parts {
+            title.push_str(part.separator);
+            title.push_str(&part.text);
+        }
+        title
+    }
+}
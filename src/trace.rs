@@ -0,0 +1,81 @@
+use std::fmt;
+
+use crate::pennfat::{format_offset, BlockFormat, ShowBlocks};
+
+/// A step taken while reading the image: what was read, where, and what it was read as
+pub struct Step {
+    /// How many operations the step is nested in
+    pub depth: usize,
+    pub operation: &'static str,
+    pub block: Option<u16>,
+    /// Where the bytes read are in the image file
+    pub offset: Option<u64>,
+    pub value: String,
+}
+
+/// The steps an operation on the image took, in the order it took them. Operations made of
+/// other operations (a lookup searching directories, say) nest their steps under their own
+#[derive(Default)]
+pub struct Trace {
+    pub steps: Vec<Step>,
+    depth: usize,
+}
+
+impl Trace {
+    /// Record a step of the current operation
+    pub fn step(
+        &mut self,
+        operation: &'static str,
+        block: Option<u16>,
+        offset: Option<u64>,
+        value: String,
+    ) {
+        self.steps.push(Step {
+            depth: self.depth,
+            operation,
+            block,
+            offset,
+            value,
+        });
+    }
+
+    /// Record the start of an operation, nesting the steps after it under it until it's left
+    pub fn enter(
+        &mut self,
+        operation: &'static str,
+        block: Option<u16>,
+        offset: Option<u64>,
+        value: String,
+    ) {
+        self.step(operation, block, offset, value);
+        self.depth += 1;
+    }
+
+    /// Record the end of the current operation
+    pub fn leave(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
+
+impl ShowBlocks for Trace {
+    /// A step per line, indented by how deeply it's nested
+    fn fmt_blocks(&self, f: &mut fmt::Formatter<'_>, blocks: BlockFormat) -> fmt::Result {
+        for step in &self.steps {
+            write!(
+                f,
+                "{:indent$}{}",
+                "",
+                step.operation,
+                indent = step.depth * 2
+            )?;
+            if let Some(block) = step.block {
+                write!(f, " block {}", blocks.block(block))?;
+            }
+            if let Some(offset) = step.offset {
+                write!(f, " at {}", format_offset(offset))?;
+            }
+            writeln!(f, ": {}", step.value)?;
+        }
+        Ok(())
+    }
+}
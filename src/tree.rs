@@ -0,0 +1,422 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use tui::widgets::TableState;
+
+use crate::actions::Action;
+use crate::app::CacheKey;
+use crate::pane;
+use crate::pennfat::{Dentry, PennFat, PfError};
+use crate::walk;
+
+/// How many entries of a directory the tree view lists at first, and how many more each load
+/// adds, so a directory whose chain spans hundreds of blocks isn't made into thousands of rows
+pub const PAGE: usize = 500;
+
+/// The tree view: the root's entries, with each directory expanded in place to show its own,
+/// indented under it. Which directories are expanded is kept while the classic view is up
+#[derive(Default)]
+pub struct Tree {
+    /// The paths of the directories expanded
+    expanded: HashSet<String>,
+    /// How many entries are listed of the directories that had more loaded, by path (the
+    /// root's is empty). The rest list a page
+    limits: HashMap<String, usize>,
+    /// How many entries the directories listed so far have, by first block. Counting reads a
+    /// directory's whole chain, so each is only counted once for what `counted` says
+    counts: RefCell<HashMap<u16, usize>>,
+    /// The reload generation (and snapshot) the counts are of
+    counted: Option<CacheKey>,
+    /// The selected row
+    pub state: TableState,
+}
+
+/// A row of the tree view, `depth` directories below the root
+pub struct Row {
+    pub depth: usize,
+    pub kind: RowKind,
+}
+
+pub enum RowKind {
+    /// An entry, by its path. A directory says what's in it too
+    Entry {
+        path: String,
+        dentry: Dentry,
+        dir: Option<DirRow>,
+    },
+    /// A block of the directory above that couldn't be read
+    Error(PfError),
+    /// The directory at `dir_path` has more entries than the `shown` listed above
+    More { dir_path: String, shown: usize },
+}
+
+/// What a row of the tree view says about a directory
+pub struct DirRow {
+    /// How many entries in use the directory has
+    pub entries: usize,
+    /// Whether the entries are shown under it
+    pub expanded: bool,
+    /// Whether the directory is one of the directories it's in, so expanding it would go
+    /// round in circles
+    pub looped: bool,
+}
+
+impl Tree {
+    /// Forget the entry counts if they're of another reload generation or snapshot than `key`
+    pub fn refresh(&mut self, key: &CacheKey) {
+        if self.counted.as_ref() != Some(key) {
+            self.counted = Some(key.clone());
+            self.counts.get_mut().clear();
+        }
+    }
+
+    /// Count the entries of the directory starting at `first_block`, unless it's been counted
+    fn count(&self, fs: &PennFat, first_block: u16) -> usize {
+        *self
+            .counts
+            .borrow_mut()
+            .entry(first_block)
+            .or_insert_with(|| walk::count_children(fs, first_block))
+    }
+
+    /// List the rows of the tree as it's expanded, from the root's entries down
+    pub fn rows(&self, fs: &PennFat) -> Vec<Row> {
+        let mut rows = Vec::new();
+        self.push_children(fs, "", &mut vec![fs.root_block()], &mut rows);
+        rows
+    }
+
+    /// List the entries of the directory at `dir_path`, the last of `ancestors`, each followed
+    /// by its own if it's an expanded directory
+    fn push_children(
+        &self,
+        fs: &PennFat,
+        dir_path: &str,
+        ancestors: &mut Vec<u16>,
+        rows: &mut Vec<Row>,
+    ) {
+        let depth = ancestors.len() - 1;
+        let first_block = *ancestors.last().expect("the root is always an ancestor");
+        let limit = self.limits.get(dir_path).copied().unwrap_or(PAGE);
+        let mut children = walk::children(fs, first_block);
+        for child in children.by_ref().take(limit) {
+            let dentry = match child {
+                Ok(dentry) => dentry,
+                Err(e) => {
+                    rows.push(Row {
+                        depth,
+                        kind: RowKind::Error(e),
+                    });
+                    continue;
+                }
+            };
+            let path = format!("{}/{}", dir_path, dentry.display_name());
+            let dir = dentry.is_dir().then(|| DirRow {
+                entries: self.count(fs, dentry.first_block),
+                expanded: self.expanded.contains(&path),
+                looped: ancestors.contains(&dentry.first_block),
+            });
+            let descend = dir.as_ref().is_some_and(|dir| dir.expanded && !dir.looped);
+            let first_block = dentry.first_block;
+            rows.push(Row {
+                depth,
+                kind: RowKind::Entry {
+                    path: path.clone(),
+                    dentry,
+                    dir,
+                },
+            });
+            if descend {
+                ancestors.push(first_block);
+                self.push_children(fs, &path, ancestors, rows);
+                ancestors.pop();
+            }
+        }
+        // only as many blocks of the chain are read as it takes to tell there's more
+        if children.next().is_some() {
+            rows.push(Row {
+                depth,
+                kind: RowKind::More {
+                    dir_path: dir_path.to_owned(),
+                    shown: limit,
+                },
+            });
+        }
+    }
+
+    /// Get the selected row, if there's one
+    fn selected_row(&self, fs: &PennFat) -> Option<Row> {
+        let selected = self.state.selected().unwrap_or(0);
+        self.rows(fs).into_iter().nth(selected)
+    }
+
+    /// Get the path of the selected entry, if it could be read
+    pub fn selected_path(&self, fs: &PennFat) -> Option<String> {
+        match self.selected_row(fs)?.kind {
+            RowKind::Entry { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Get the selected entry, if it could be read
+    pub fn selected(&self, fs: &PennFat) -> Option<Dentry> {
+        match self.selected_row(fs)?.kind {
+            RowKind::Entry { dentry, .. } => Some(dentry),
+            _ => None,
+        }
+    }
+
+    /// Move the selection by a navigation action
+    pub fn step(&mut self, fs: &PennFat, action: Action) {
+        let len = self.rows(fs).len();
+        let selected = self.state.selected().unwrap_or(0);
+        if let Some(selected) = pane::step(selected, len, action) {
+            self.state.select(Some(selected));
+        }
+    }
+
+    /// Expand the selected directory, or collapse it if it's expanded. On the row saying a
+    /// directory has more entries, load them. Anything else is an error to show
+    pub fn toggle(&mut self, fs: &PennFat) -> Result<(), String> {
+        let (path, dentry, dir) = match self.selected_row(fs).map(|row| row.kind) {
+            Some(RowKind::Entry { path, dentry, dir }) => (path, dentry, dir),
+            Some(RowKind::More { .. }) => return self.load_more(fs).map(|_| ()),
+            _ => return Err("nothing to expand".to_owned()),
+        };
+        match dir {
+            Some(dir) if dir.looped => Err(format!(
+                "{} is one of the directories it's in",
+                dentry.display_name()
+            )),
+            Some(_) => {
+                if !self.expanded.remove(&path) {
+                    self.expanded.insert(path);
+                }
+                Ok(())
+            }
+            None => Err(format!("{} isn't a directory", dentry.display_name())),
+        }
+    }
+
+    /// List another page of the entries of the directory the selected row is in, or whose
+    /// remaining entries it stands for. Returns how many are listed now, or an error to show if
+    /// they all were already
+    pub fn load_more(&mut self, fs: &PennFat) -> Result<usize, String> {
+        let rows = self.rows(fs);
+        let selected = self.state.selected().unwrap_or(0);
+        let dir_path = match rows.get(selected).map(|row| &row.kind) {
+            Some(RowKind::More { dir_path, .. }) => dir_path.clone(),
+            Some(RowKind::Entry { path, .. }) => {
+                path.rsplit_once('/').map_or("", |(dir, _)| dir).to_owned()
+            }
+            _ => return Err("nothing to load more of".to_owned()),
+        };
+        let shown = rows.iter().find_map(|row| match &row.kind {
+            RowKind::More {
+                dir_path: more,
+                shown,
+            } if *more == dir_path => Some(*shown),
+            _ => None,
+        });
+        let name = match dir_path.as_str() {
+            "" => "/",
+            path => path,
+        };
+        let Some(shown) = shown else {
+            return Err(format!("all of {} is listed", name));
+        };
+        self.limits.insert(dir_path, shown + PAGE);
+        Ok(shown + PAGE)
+    }
+
+    /// Collapse the directory the selected row is in, selecting it. Returns whether there was
+    /// one to collapse, rather than the root
+    pub fn collapse_parent(&mut self, fs: &PennFat) -> bool {
+        let rows = self.rows(fs);
+        let selected = self
+            .state
+            .selected()
+            .unwrap_or(0)
+            .min(rows.len().saturating_sub(1));
+        let Some(depth) = rows
+            .get(selected)
+            .map(|row| row.depth)
+            .filter(|depth| *depth > 0)
+        else {
+            return false;
+        };
+        // the parent is the nearest row above that's a level up
+        let Some((parent, path)) = rows[..selected]
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, row)| row.depth < depth)
+            .and_then(|(i, row)| match &row.kind {
+                RowKind::Entry { path, .. } => Some((i, path)),
+                _ => None,
+            })
+        else {
+            return false;
+        };
+        self.expanded.remove(path);
+        self.state.select(Some(parent));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pfview::testing;
+
+    fn names(tree: &Tree, fs: &PennFat) -> Vec<String> {
+        tree.rows(fs)
+            .into_iter()
+            .map(|row| match row.kind {
+                RowKind::Entry { path, dir, .. } => format!(
+                    "{}{}{}",
+                    "  ".repeat(row.depth),
+                    path,
+                    dir.map_or(String::new(), |dir| format!(" ({})", dir.entries))
+                ),
+                RowKind::Error(e) => format!("error: {}", e),
+                RowKind::More { dir_path, shown } => {
+                    format!("{}{}/ past {}", "  ".repeat(row.depth), dir_path, shown)
+                }
+            })
+            .collect()
+    }
+
+    fn select(tree: &mut Tree, fs: &PennFat, path: &str) {
+        let i = tree
+            .rows(fs)
+            .iter()
+            .position(|row| matches!(&row.kind, RowKind::Entry { path: p, .. } if p == path))
+            .unwrap();
+        tree.state.select(Some(i));
+    }
+
+    #[test]
+    fn directories_expand_in_place_with_their_entry_counts() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        let mut tree = Tree::default();
+        let top = names(&tree, &fs);
+        assert_eq!(top[..2], ["/README", "/docs (2)"]);
+
+        select(&mut tree, &fs, "/docs");
+        tree.toggle(&fs).unwrap();
+        let expanded = names(&tree, &fs);
+        assert_eq!(expanded.len(), top.len() + 2);
+        assert_eq!(expanded[2], "  /docs/guide.txt");
+        assert_eq!(expanded[3], "  /docs/src (1)");
+
+        // backspace from inside goes back to the directory, folded up
+        select(&mut tree, &fs, "/docs/guide.txt");
+        assert!(tree.collapse_parent(&fs));
+        assert_eq!(tree.state.selected(), Some(1));
+        assert_eq!(names(&tree, &fs), top);
+        assert!(!tree.collapse_parent(&fs));
+
+        select(&mut tree, &fs, "/README");
+        assert!(tree.toggle(&fs).is_err());
+    }
+
+    #[test]
+    fn each_directory_is_counted_once_until_the_image_changes() {
+        let fs = PennFat::from_bytes(testing::sample_image()).unwrap();
+        let walk::Lookup::Found(docs) = walk::lookup(&fs, "/docs", false) else {
+            panic!("no /docs");
+        };
+        let reads_of_docs = |tree: &Tree| {
+            fs.start_trace();
+            tree.rows(&fs);
+            let trace = fs.finish_trace().unwrap();
+            trace
+                .steps
+                .iter()
+                .filter(|step| step.block == Some(docs.dentry.first_block))
+                .count()
+        };
+        let mut tree = Tree::default();
+        tree.refresh(&(0, None));
+        assert!(reads_of_docs(&tree) > 0);
+        assert_eq!(reads_of_docs(&tree), 0);
+        tree.refresh(&(0, None));
+        assert_eq!(reads_of_docs(&tree), 0);
+        tree.refresh(&(1, None));
+        assert!(reads_of_docs(&tree) > 0);
+    }
+
+    #[test]
+    fn a_directory_inside_itself_isnt_expanded() {
+        use pfview::fuzz::{DentrySpec, ImageBuilder};
+
+        let mut image = ImageBuilder::new(0, 1);
+        let dir = image.allocate_lowest(1).unwrap()[0];
+        let spec = |name: &str, first_block| DentrySpec {
+            name: name.as_bytes().to_vec(),
+            size: 0,
+            first_block,
+            type_: 1,
+            perm: 7,
+            mtime: 0,
+        };
+        image.write_dentry(1, 0, &spec("d", dir));
+        image.write_dentry(dir, 0, &spec("self", dir));
+        let fs = PennFat::from_bytes(image.bytes()).unwrap();
+
+        let mut tree = Tree::default();
+        tree.toggle(&fs).unwrap();
+        select(&mut tree, &fs, "/d/self");
+        assert!(tree.toggle(&fs).is_err());
+        assert_eq!(names(&tree, &fs), ["/d (1)", "  /d/self (1)"]);
+    }
+
+    #[test]
+    fn a_huge_directory_is_listed_a_page_at_a_time() {
+        use pfview::fuzz::{DentrySpec, ImageBuilder};
+
+        // a directory of 1200 empty files, 16 to a block, in a chain of 75 blocks
+        let mut image = ImageBuilder::new(2, 4);
+        let dir = image.allocate_lowest(75).unwrap();
+        let spec = |name: String, type_| DentrySpec {
+            name: name.into_bytes(),
+            size: 0,
+            first_block: 0,
+            type_,
+            perm: 6,
+            mtime: 0,
+        };
+        image.write_dentry(
+            1,
+            0,
+            &DentrySpec {
+                first_block: dir[0],
+                ..spec("big".to_owned(), 1)
+            },
+        );
+        for i in 0..1200 {
+            image.write_dentry(dir[i / 16], i % 16, &spec(format!("f{}", i), 0));
+        }
+        let fs = PennFat::from_bytes(image.bytes()).unwrap();
+
+        let mut tree = Tree::default();
+        tree.toggle(&fs).unwrap();
+        let rows = names(&tree, &fs);
+        assert_eq!(rows.len(), 1 + PAGE + 1);
+        assert_eq!(rows[0], "/big (1200)");
+        assert_eq!(rows[PAGE + 1], "  /big/ past 500");
+
+        // from any row of the directory, or its last
+        select(&mut tree, &fs, "/big/f3");
+        assert_eq!(tree.load_more(&fs), Ok(1000));
+        tree.state.select(Some(1 + 1000));
+        assert_eq!(tree.toggle(&fs), Ok(()));
+        let rows = names(&tree, &fs);
+        assert_eq!(rows.len(), 1 + 1200);
+        assert_eq!(rows[1200], "  /big/f1199");
+        assert!(tree.load_more(&fs).is_err());
+    }
+}
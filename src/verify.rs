@@ -0,0 +1,291 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt, fs,
+    io::{self, Read},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::extract;
+use crate::pennfat::{Dentry, PennFat};
+use crate::walk;
+
+/// How far a host file's mtime may be from its dentry's. Host filesystems round timestamps
+/// differently (FAT to 2 seconds), so an exact match is too much to ask
+const MTIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// How a path differs between the image and the host directory
+pub enum Difference {
+    /// In the image, but not on the host
+    Missing,
+    /// On the host, but not in the image
+    Extra,
+    /// A file on one side is a directory (or a link) on the other
+    Kind {
+        image: &'static str,
+        host: &'static str,
+    },
+    Size {
+        image: u32,
+        host: u64,
+    },
+    /// The contents differ, first at this byte
+    Content(u64),
+    Mtime {
+        image: SystemTime,
+        host: SystemTime,
+    },
+    /// The symbolic links point to different targets
+    Target {
+        image: String,
+        host: String,
+    },
+    /// Either side couldn't be read
+    Unreadable(String),
+}
+
+/// A difference found at a path, named as in the image
+pub struct Finding {
+    pub path: String,
+    pub difference: Difference,
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.difference {
+            Difference::Missing => write!(f, "missing: {}", self.path),
+            Difference::Extra => write!(f, "extra: {}", self.path),
+            Difference::Kind { image, host } => {
+                write!(
+                    f,
+                    "mismatch: {} is a {} but a {} on the host",
+                    self.path, image, host
+                )
+            }
+            Difference::Size { image, host } => {
+                write!(
+                    f,
+                    "mismatch: {} is {} bytes but {} on the host",
+                    self.path, image, host
+                )
+            }
+            Difference::Content(offset) => {
+                write!(f, "mismatch: {} differs from byte {}", self.path, offset)
+            }
+            Difference::Mtime { image, host } => write!(
+                f,
+                "mtime: {} was modified {} but {} on the host",
+                self.path,
+                format_time(*image),
+                format_time(*host)
+            ),
+            Difference::Target { image, host } => write!(
+                f,
+                "mismatch: {} links to {} but {} on the host",
+                self.path, image, host
+            ),
+            Difference::Unreadable(e) => write!(f, "unreadable: {}: {}", self.path, e),
+        }
+    }
+}
+
+/// The outcome of comparing an image's files against a copy of them on the host
+#[derive(Default)]
+pub struct Verification {
+    /// The number of files, directories and links of the image compared
+    pub checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl Verification {
+    fn count(&self, matches: fn(&Difference) -> bool) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| matches(&finding.difference))
+            .count()
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} checked: {} missing, {} extra, {} mismatched",
+            self.checked,
+            self.count(|d| matches!(d, Difference::Missing)),
+            self.count(|d| matches!(d, Difference::Extra)),
+            self.count(|d| !matches!(d, Difference::Missing | Difference::Extra)),
+        )
+    }
+}
+
+/// What kind of file a dentry is, in the words of the findings
+fn image_kind(dentry: &Dentry) -> &'static str {
+    match dentry {
+        _ if dentry.is_dir() => "directory",
+        _ if dentry.is_symlink() => "symlink",
+        _ => "file",
+    }
+}
+
+/// What kind of file a host path is, in the words of the findings
+fn host_kind(metadata: &fs::Metadata) -> &'static str {
+    match metadata.file_type() {
+        t if t.is_dir() => "directory",
+        t if t.is_symlink() => "symlink",
+        _ => "file",
+    }
+}
+
+/// Compare every file of the image with its copy under `dir`, named by [`walk::host_names`].
+/// Files are compared a block at a time, so neither side is read in whole. With `check_mtime`,
+/// modification times must match too, within a tolerance
+pub fn verify(fs: &PennFat, dir: &Path, check_mtime: bool) -> Verification {
+    let mut verification = Verification::default();
+    let mut visited = HashSet::from([fs.root_block()]);
+    // the root's path is empty so that its children get paths like "/name"
+    let mut directories = VecDeque::from([(String::new(), dir.to_owned(), fs.root_block())]);
+
+    while let Some((dir_path, host_dir, first_block)) = directories.pop_front() {
+        let mut dentries = Vec::new();
+        for dentry in walk::children(fs, first_block) {
+            match dentry {
+                Ok(dentry) => dentries.push(dentry),
+                Err(e) => verification.findings.push(Finding {
+                    path: format!("{}/", dir_path),
+                    difference: Difference::Unreadable(e.to_string()),
+                }),
+            }
+        }
+        // named as extract-all names them, with the manifest it writes at the top
+        let taken: &[&str] = match dir_path.is_empty() {
+            true => &[extract::MANIFEST],
+            false => &[],
+        };
+        let names: Vec<String> = dentries.iter().map(Dentry::name).collect();
+        let host_names = walk::host_names(names.iter().map(String::as_str), taken);
+        let mut names: HashSet<String> = taken.iter().map(|name| name.to_string()).collect();
+        for (dentry, host_name) in dentries.into_iter().zip(host_names) {
+            let path = format!("{}/{}", dir_path, dentry.display_name());
+            let host_path = host_dir.join(&host_name);
+            names.insert(host_name);
+            verification.checked += 1;
+            if let Some(difference) = compare(fs, &dentry, &host_path, check_mtime) {
+                verification.findings.push(Finding { path, difference });
+                continue;
+            }
+            if dentry.is_dir() && visited.insert(dentry.first_block) {
+                directories.push_back((path, host_path, dentry.first_block));
+            }
+        }
+        // whatever else is on the host wasn't copied from the image
+        let Ok(listing) = fs::read_dir(&host_dir) else {
+            continue;
+        };
+        let mut extra: Vec<String> = listing
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| !names.contains(name))
+            .collect();
+        extra.sort();
+        for name in extra {
+            verification.findings.push(Finding {
+                path: format!("{}/{}", dir_path, name),
+                difference: Difference::Extra,
+            });
+        }
+    }
+    verification
+}
+
+/// Compare a file of the image with its copy on the host, returning the first difference
+fn compare(fs: &PennFat, dentry: &Dentry, host: &Path, check_mtime: bool) -> Option<Difference> {
+    let metadata = match fs::symlink_metadata(host) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Some(Difference::Missing),
+        Err(e) => return Some(Difference::Unreadable(e.to_string())),
+    };
+    let (image, host_kind) = (image_kind(dentry), host_kind(&metadata));
+    if image != host_kind {
+        return Some(Difference::Kind {
+            image,
+            host: host_kind,
+        });
+    }
+    if dentry.is_dir() {
+        // directories' own sizes and times aren't kept when copying
+        return None;
+    }
+    if dentry.is_symlink() {
+        let image = walk::read_link(fs, dentry).unwrap_or_default();
+        let host = fs::read_link(host)
+            .map(|target| target.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        return (image != host).then_some(Difference::Target { image, host });
+    }
+    if metadata.len() != dentry.size as u64 {
+        return Some(Difference::Size {
+            image: dentry.size,
+            host: metadata.len(),
+        });
+    }
+    if let Some(difference) = compare_contents(fs, dentry, host) {
+        return Some(difference);
+    }
+    let image_mtime = SystemTime::UNIX_EPOCH + Duration::from_millis(dentry.mtime);
+    match metadata.modified() {
+        Ok(host_mtime) if check_mtime => {
+            let apart = host_mtime
+                .duration_since(image_mtime)
+                .or_else(|_| image_mtime.duration_since(host_mtime))
+                .unwrap_or_default();
+            (apart > MTIME_TOLERANCE).then_some(Difference::Mtime {
+                image: image_mtime,
+                host: host_mtime,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Compare a file's contents in the image with a host file of the same size, a block at a time
+fn compare_contents(fs: &PennFat, dentry: &Dentry, host: &Path) -> Option<Difference> {
+    let mut file = match fs::File::open(host) {
+        Ok(file) => file,
+        Err(e) => return Some(Difference::Unreadable(e.to_string())),
+    };
+    let mut host_block = vec![0; fs.block_size() as usize];
+    let mut offset = 0u64;
+    for block in fs.chain(dentry.first_block).blocks {
+        let remaining = (dentry.size as u64).saturating_sub(offset);
+        if remaining == 0 {
+            break;
+        }
+        let image_block = match fs.block_bytes(block) {
+            Ok(bytes) => &bytes[..bytes.len().min(remaining as usize)],
+            Err(e) => return Some(Difference::Unreadable(e.to_string())),
+        };
+        let host_block = &mut host_block[..image_block.len()];
+        if let Err(e) = file.read_exact(host_block) {
+            return Some(Difference::Unreadable(e.to_string()));
+        }
+        if let Some(i) = image_block
+            .iter()
+            .zip(host_block.iter())
+            .position(|(a, b)| a != b)
+        {
+            return Some(Difference::Content(offset + i as u64));
+        }
+        offset += image_block.len() as u64;
+    }
+    // a chain too short for the size is cut off early
+    (offset < dentry.size as u64).then_some(Difference::Content(offset))
+}
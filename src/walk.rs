@@ -0,0 +1,566 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::history;
+use crate::pennfat::{Dentry, PennFat, Result};
+
+/// How many of the first data blocks are considered when looking for the root directory
+const ROOT_CANDIDATES: u16 = 8;
+
+/// The longest host file name [`host_name`] makes, in bytes. Host filesystems allow 255, and
+/// this leaves room for what's put around it, such as a temporary file's prefix
+const MAX_HOST_NAME: usize = 200;
+
+/// A file (or directory) found walking the directory tree
+pub struct Entry {
+    /// The absolute path of the file
+    pub path: String,
+    /// The file's directory entry
+    pub dentry: Dentry,
+}
+
+/// Stream the dentries in use of the directory starting at `first_block`. The blocks of its chain
+/// are read one at a time, as the dentries are asked for, so a huge directory is never read in
+/// whole just to find or show a few entries. A block that can't be read (in a truncated image)
+/// yields its error in place of its dentries, and a chain cut short by the limit on chains'
+/// length yields its error after the dentries of the blocks within it
+pub fn children(fs: &PennFat, first_block: u16) -> impl Iterator<Item = Result<Dentry>> + '_ {
+    let chain = fs.chain(first_block);
+    let too_long = chain.check_len().err();
+    chain
+        .blocks
+        .into_iter()
+        .flat_map(move |block| match fs.get_block(block) {
+            Ok(block) => block
+                .as_dentries(fs.dentry_layout())
+                .into_iter()
+                .filter(Dentry::is_in_use)
+                .map(Ok)
+                .collect(),
+            Err(e) => vec![Err(e)],
+        })
+        .chain(too_long.map(Err))
+}
+
+/// Count the entries in use of the directory starting at `first_block`. Blocks that can't be
+/// read count for nothing
+pub fn count_children(fs: &PennFat, first_block: u16) -> usize {
+    children(fs, first_block).filter(Result::is_ok).count()
+}
+
+/// Walk the directory tree breadth-first from the root directory, returning every dentry in use.
+/// Each directory is only visited once, so directories linked into the tree more than once
+/// (or into themselves) can't make the walk loop
+///
+/// ```
+/// use pfview::pennfat::PennFat;
+///
+/// let fs = PennFat::from_bytes(pfview::testing::sample_image()).unwrap();
+/// let paths: Vec<String> = pfview::walk::walk(&fs)
+///     .into_iter()
+///     .map(|entry| entry.path)
+///     .collect();
+/// // the root's entries come first, then each directory's in turn
+/// assert_eq!(paths[..4], ["/README", "/docs", "/notes.txt", "/latest"]);
+/// assert!(paths.contains(&"/docs/src/main.c".to_owned()));
+/// ```
+pub fn walk(fs: &PennFat) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut visited = HashSet::from([fs.root_block()]);
+    // the root's path is empty so that its children get paths like "/name"
+    let mut directories = VecDeque::from([(String::new(), fs.root_block())]);
+
+    while let Some((dir_path, first_block)) = directories.pop_front() {
+        // unreadable blocks are skipped
+        for dentry in children(fs, first_block).flatten() {
+            let path = format!("{}/{}", dir_path, dentry.display_name());
+            if dentry.is_dir() && visited.insert(dentry.first_block) {
+                directories.push_back((path.clone(), dentry.first_block));
+            }
+            entries.push(Entry { path, dentry });
+        }
+    }
+
+    entries
+}
+
+/// How many symbolic links a lookup follows before deciding they loop
+pub const MAX_SYMLINK_HOPS: usize = 16;
+
+/// The outcome of looking up a path
+pub enum Lookup {
+    /// The path names this file
+    Found(Entry),
+    /// Nothing is at the path
+    Missing,
+    /// Resolving the path followed too many symbolic links, which most likely loop
+    Loop,
+}
+
+impl Lookup {
+    /// Describe what the lookup found, as a symbolic link's target
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Lookup::Found(entry) if entry.dentry.is_dir() => "directory",
+            Lookup::Found(entry) if entry.dentry.is_symlink() => "symlink",
+            Lookup::Found(_) => "file",
+            Lookup::Missing => "dangling",
+            Lookup::Loop => "loop",
+        }
+    }
+}
+
+/// The root directory, which has no dentry of its own
+fn root(fs: &PennFat) -> Entry {
+    Entry {
+        path: "/".to_owned(),
+        dentry: Dentry {
+            name: Vec::new(),
+            size: 0,
+            first_block: fs.root_block(),
+            type_: 1,
+            perm: 0,
+            mtime: 0,
+            _reserved: Vec::new(),
+        },
+    }
+}
+
+/// Find the dentry in use named `name` in the directory starting at `first_block`. While
+/// tracing, each dentry compared is recorded, with where it is in the image
+fn find(fs: &PennFat, first_block: u16, name: &str) -> Option<Dentry> {
+    fs.trace_enter("search directory", Some(first_block), || {
+        format!("for '{}'", name)
+    });
+    let layout = fs.dentry_layout();
+    let mut found = None;
+    'blocks: for block in fs.chain(first_block).blocks {
+        // unreadable blocks are skipped, as when listing the directory
+        let (Ok(bytes), Ok(offset)) = (fs.block_bytes(block), fs.block_offset(block)) else {
+            continue;
+        };
+        for (slot, entry) in bytes.chunks_exact(layout.size).enumerate() {
+            let Some(dentry) = Dentry::parse(entry, layout).filter(Dentry::is_in_use) else {
+                continue;
+            };
+            let offset = (offset + slot * layout.size) as u64;
+            fs.trace("dentry", Some(block), Some(offset), || {
+                let kind = match (dentry.is_dir(), dentry.is_symlink()) {
+                    (true, _) => "directory",
+                    (_, true) => "symlink",
+                    _ => "file",
+                };
+                format!(
+                    "'{}' ({}, first block {}, {} bytes)",
+                    dentry.name(),
+                    kind,
+                    fs.format_block(dentry.first_block),
+                    dentry.size
+                )
+            });
+            if dentry.name() == name {
+                found = Some(dentry);
+                break 'blocks;
+            }
+        }
+    }
+    fs.trace(
+        "search directory",
+        Some(first_block),
+        None,
+        || match found {
+            Some(_) => format!("found '{}'", name),
+            None => format!("no '{}'", name),
+        },
+    );
+    fs.trace_leave();
+    found
+}
+
+/// Read the target of a symbolic link, up to its first NUL
+pub fn read_link(fs: &PennFat, dentry: &Dentry) -> Result<String> {
+    let target = fs.read_file(dentry)?;
+    let len = target.iter().position(|b| *b == 0).unwrap_or(target.len());
+    Ok(String::from_utf8_lossy(&target[..len]).into_owned())
+}
+
+/// Look up an absolute path, following symbolic links on the way. With `follow`, a symbolic link
+/// at the end of the path is followed too; otherwise the link itself is returned
+///
+/// ```
+/// use pfview::pennfat::PennFat;
+/// use pfview::walk::{lookup, Lookup};
+///
+/// let fs = PennFat::from_bytes(pfview::testing::sample_image()).unwrap();
+/// // /latest is a symbolic link to docs/guide.txt
+/// let Lookup::Found(link) = lookup(&fs, "/latest", false) else {
+///     panic!("no /latest");
+/// };
+/// assert!(link.dentry.is_symlink());
+/// let Lookup::Found(guide) = lookup(&fs, "/latest", true) else {
+///     panic!("/latest leads nowhere");
+/// };
+/// assert_eq!(guide.path, "/docs/guide.txt");
+/// assert!(matches!(lookup(&fs, "/missing", true), Lookup::Missing));
+/// ```
+pub fn lookup(fs: &PennFat, path: &str, follow: bool) -> Lookup {
+    fs.trace_enter("lookup", None, || format!("'{}'", path));
+    let lookup = resolve(fs, path, follow);
+    fs.trace("lookup", None, None, || match &lookup {
+        Lookup::Found(entry) => format!("found {}", entry.path),
+        Lookup::Missing => "no such file or directory".to_owned(),
+        Lookup::Loop => "too many levels of symbolic links".to_owned(),
+    });
+    fs.trace_leave();
+    lookup
+}
+
+/// Resolve a path for [`lookup`]
+fn resolve(fs: &PennFat, path: &str, follow: bool) -> Lookup {
+    let mut components: VecDeque<String> = path.split('/').map(str::to_owned).collect();
+    // the directories leading to the current one, below the root
+    let mut dirs: Vec<Entry> = Vec::new();
+    let mut hops = 0;
+
+    while let Some(component) = components.pop_front() {
+        match component.as_str() {
+            "" | "." => continue,
+            ".." => {
+                dirs.pop();
+                continue;
+            }
+            _ => {}
+        }
+        let dir_block = dirs
+            .last()
+            .map_or(fs.root_block(), |dir| dir.dentry.first_block);
+        let Some(dentry) = find(fs, dir_block, &component) else {
+            return Lookup::Missing;
+        };
+        let last = components.iter().all(|c| c.is_empty() || c == ".");
+        if dentry.is_symlink() && (follow || !last) {
+            hops += 1;
+            if hops > MAX_SYMLINK_HOPS {
+                return Lookup::Loop;
+            }
+            let Ok(target) = read_link(fs, &dentry) else {
+                return Lookup::Missing;
+            };
+            fs.trace("follow symlink", Some(dentry.first_block), None, || {
+                format!("'{}' -> '{}'", component, target)
+            });
+            if target.starts_with('/') {
+                dirs.clear();
+            }
+            for component in target.split('/').rev() {
+                components.push_front(component.to_owned());
+            }
+            continue;
+        }
+        let dir_path = dirs.last().map_or("", |dir| dir.path.as_str());
+        let entry = Entry {
+            path: format!("{}/{}", dir_path, component),
+            dentry,
+        };
+        if last {
+            return Lookup::Found(entry);
+        }
+        if !entry.dentry.is_dir() {
+            return Lookup::Missing;
+        }
+        dirs.push(entry);
+    }
+
+    Lookup::Found(dirs.pop().unwrap_or_else(|| root(fs)))
+}
+
+/// Turn the name of a file in the image into one that's safe on the host: anything that isn't
+/// (a slash or backslash, a colon, a control character such as NUL, or the names "." and "..")
+/// is escaped as %XX, and so is '%' itself, so different names never end up the same. A name
+/// that comes out longer than [`MAX_HOST_NAME`] is cut short and ends in a checksum of the whole
+/// name instead. Every host file named after a file in the image is named this way, so none
+/// lands outside the directory it's written to
+pub fn host_name(name: &str) -> String {
+    match name {
+        "." => return "%2E".to_owned(),
+        ".." => return "%2E%2E".to_owned(),
+        _ => {}
+    }
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '/' | '\\' | ':' | '%' => escaped += &format!("%{:02X}", c as u32),
+            c if c.is_control() => escaped += &format!("%{:02X}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    if escaped.len() > MAX_HOST_NAME {
+        let mut end = MAX_HOST_NAME - 9;
+        while !escaped.is_char_boundary(end) {
+            end -= 1;
+        }
+        escaped = format!(
+            "{}~{:08x}",
+            &escaped[..end],
+            history::checksum(name.as_bytes())
+        );
+    }
+    escaped
+}
+
+/// Name the files of a directory on the host, as [`host_name`] does, but so that no two get
+/// names differing only in case, which case-insensitive filesystems (macOS's and Windows'
+/// usually) would take as the same file. Later names that clash with an earlier one, or with
+/// one of `taken`, get a `~2`, `~3`... on the end
+pub fn host_names<'a>(names: impl IntoIterator<Item = &'a str>, taken: &[&str]) -> Vec<String> {
+    let mut used: HashSet<String> = taken.iter().map(|name| name.to_lowercase()).collect();
+    names
+        .into_iter()
+        .map(|name| {
+            let base = host_name(name);
+            let mut host = base.clone();
+            let mut n = 1;
+            while !used.insert(host.to_lowercase()) {
+                n += 1;
+                host = format!("{}~{}", base, n);
+            }
+            host
+        })
+        .collect()
+}
+
+/// Resolve the symbolic link `dentry`, found in the directory at `dir_path`, to its target and
+/// what the target names
+pub fn resolve_link(fs: &PennFat, dir_path: &str, dentry: &Dentry) -> (String, Lookup) {
+    let Ok(target) = read_link(fs, dentry) else {
+        return (String::new(), Lookup::Missing);
+    };
+    // relative targets are relative to the directory holding the link
+    let path = match target.starts_with('/') {
+        true => target.clone(),
+        false => format!("{}/{}", dir_path.trim_end_matches('/'), target),
+    };
+    let lookup = lookup(fs, &path, true);
+    (target, lookup)
+}
+
+/// Whether a dentry in use looks like a real one: a printable name, a known type and a first
+/// block in range
+fn is_plausible(fs: &PennFat, dentry: &Dentry) -> bool {
+    let name = dentry.name();
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+        && dentry.type_ <= 2
+        && dentry.first_block <= fs.data_block_count()
+}
+
+/// Whether a block looks like a directory block: most of its dentry slots are free, deleted or
+/// hold a plausible dentry. If it does, returns how many plausible dentries in use it holds
+fn directory_likeness(fs: &PennFat, block: u16) -> Option<usize> {
+    let block = fs.get_block(block).ok()?;
+    let dentries = block.as_dentries(fs.dentry_layout());
+    let in_use = dentries
+        .iter()
+        .filter(|d| d.is_in_use() && is_plausible(fs, d))
+        .count();
+    let unused = dentries.iter().filter(|d| !d.is_in_use()).count();
+    match (in_use + unused) * 2 > dentries.len() {
+        true => Some(in_use),
+        false => None,
+    }
+}
+
+/// Find the root directory and set it on `fs`: the `given` block, or else block 1 if it looks
+/// like a directory, or else whichever of the first few blocks looks the most like one.
+/// Returns a warning to show when the root had to be guessed
+pub fn find_root(fs: &mut PennFat, given: Option<u16>) -> Option<String> {
+    if let Some(block) = given {
+        fs.set_root_block(block);
+        return None;
+    }
+    fs.set_root_block(1);
+    if directory_likeness(fs, 1).is_some() {
+        return None;
+    }
+    // an empty block elsewhere is more likely unused than an empty root
+    let best = (2..=ROOT_CANDIDATES.min(fs.data_block_count()))
+        .filter_map(|block| Some((directory_likeness(fs, block)?, block)))
+        .filter(|(in_use, _)| *in_use > 0)
+        .max_by_key(|(in_use, block)| (*in_use, std::cmp::Reverse(*block)));
+    match best {
+        Some((_, block)) => {
+            fs.set_root_block(block);
+            Some(format!(
+                "root directory guessed at block {}",
+                fs.format_block(block)
+            ))
+        }
+        None => Some("block 1 doesn't look like a root directory".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Component, Path};
+
+    use super::*;
+    use crate::fuzz::{DentrySpec, ImageBuilder};
+    use crate::pennfat::ShowBlocks;
+    use crate::testing::sample_image;
+
+    /// An image whose root holds files named "../escape", "a/b" and "..", next to a directory
+    /// "a" holding a real "b"
+    fn image_with_unsafe_names() -> PennFat {
+        let mut image = ImageBuilder::new(0, 1);
+        let dentry = |image: &mut ImageBuilder, dir, slot, name: &[u8], type_| {
+            let block = image.allocate_lowest(1).unwrap()[0];
+            image.write_dentry(
+                dir,
+                slot,
+                &DentrySpec {
+                    name: name.to_vec(),
+                    size: 0,
+                    first_block: block,
+                    type_,
+                    perm: 6,
+                    mtime: 0,
+                },
+            );
+            block
+        };
+        dentry(&mut image, 1, 0, b"../escape", 0);
+        dentry(&mut image, 1, 1, b"a/b", 0);
+        dentry(&mut image, 1, 2, b"..", 0);
+        let a = dentry(&mut image, 1, 3, b"a", 1);
+        dentry(&mut image, a, 0, b"b", 0);
+        PennFat::from_bytes(image.bytes()).unwrap()
+    }
+
+    #[test]
+    fn names_with_slashes_and_dot_names_stay_one_component() {
+        let fs = image_with_unsafe_names();
+        let paths: Vec<String> = walk(&fs).into_iter().map(|entry| entry.path).collect();
+        assert_eq!(
+            paths,
+            ["/..\\x2fescape", "/a\\x2fb", "/\\x2e\\x2e", "/a", "/a/b"]
+        );
+    }
+
+    #[test]
+    fn lookup_never_matches_a_name_against_several_components() {
+        let fs = image_with_unsafe_names();
+        // the real a/b, in the directory a, not the file named "a/b" in the root
+        let Lookup::Found(b) = lookup(&fs, "/a/b", true) else {
+            panic!("no /a/b");
+        };
+        assert_eq!(b.path, "/a/b");
+        assert_eq!(b.dentry.first_block, 6);
+        assert!(matches!(lookup(&fs, "/../escape", true), Lookup::Missing));
+        // ".." goes up (from the root, to the root), never to the file named ".."
+        let Lookup::Found(up) = lookup(&fs, "/a/..", true) else {
+            panic!("no /a/..");
+        };
+        assert_eq!(up.dentry.first_block, fs.root_block());
+    }
+
+    #[test]
+    fn host_names_never_leave_the_directory() {
+        let dest = Path::new("/tmp/dest");
+        for name in [
+            "../escape",
+            "..",
+            ".",
+            "a/b",
+            "/etc/passwd",
+            "nul\0mid",
+            "..\\..\\up",
+        ] {
+            let host = host_name(name);
+            assert!(
+                matches!(
+                    Path::new(&host).components().collect::<Vec<_>>()[..],
+                    [Component::Normal(_)]
+                ),
+                "{:?} became {:?}",
+                name,
+                host
+            );
+            assert_eq!(dest.join(&host).parent(), Some(dest));
+        }
+        assert_eq!(host_name("../escape"), "..%2Fescape");
+        assert_eq!(host_name("C:\\Windows"), "C%3A%5CWindows");
+    }
+
+    #[test]
+    fn names_differing_in_case_get_different_host_names() {
+        assert_eq!(
+            host_names(["README", "readme", "ReadMe", "readme~2", "other"], &[]),
+            ["README", "readme~2", "ReadMe~3", "readme~2~2", "other"]
+        );
+        assert_eq!(
+            host_names(["Manifest.json"], &["manifest.json"]),
+            ["Manifest.json~2"]
+        );
+    }
+
+    #[test]
+    fn tracing_a_two_level_lookup_shows_each_directory_searched() {
+        let fs = PennFat::from_bytes(sample_image()).unwrap();
+        fs.start_trace();
+        let Lookup::Found(entry) = lookup(&fs, "/docs/guide.txt", true) else {
+            panic!("no /docs/guide.txt");
+        };
+        assert_eq!(entry.dentry.first_block, 0xa);
+        let trace = fs.finish_trace().unwrap();
+        let steps: Vec<(&str, Option<u16>)> = trace
+            .steps
+            .iter()
+            .map(|step| (step.operation, step.block))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("lookup", None),
+                ("search directory", Some(1)),
+                ("fat entry", Some(1)),
+                ("fat entry", Some(9)),
+                ("read", Some(1)),
+                ("dentry", Some(1)),
+                ("dentry", Some(1)),
+                ("search directory", Some(1)),
+                ("search directory", Some(3)),
+                ("fat entry", Some(3)),
+                ("read", Some(3)),
+                ("dentry", Some(3)),
+                ("search directory", Some(3)),
+                ("lookup", None),
+            ]
+        );
+        assert_eq!(
+            trace.show(fs.block_format()).to_string(),
+            "lookup: '/docs/guide.txt'\n\
+             \x20 search directory block 0x01: for 'docs'\n\
+             \x20   fat entry block 0x01 at 0x0000_0002: next 0x09\n\
+             \x20   fat entry block 0x09 at 0x0000_0012: EOF\n\
+             \x20   read block 0x01 at 0x0000_0100: 256 bytes\n\
+             \x20   dentry block 0x01 at 0x0000_0100: 'README' (file, first block 0x02, 44 bytes)\n\
+             \x20   dentry block 0x01 at 0x0000_0140: 'docs' (directory, first block 0x03, 0 bytes)\n\
+             \x20   search directory block 0x01: found 'docs'\n\
+             \x20 search directory block 0x03: for 'guide.txt'\n\
+             \x20   fat entry block 0x03 at 0x0000_0006: EOF\n\
+             \x20   read block 0x03 at 0x0000_0300: 256 bytes\n\
+             \x20   dentry block 0x03 at 0x0000_0300: 'guide.txt' (file, first block 0x0a, 400 bytes)\n\
+             \x20   search directory block 0x03: found 'guide.txt'\n\
+             \x20 lookup: found /docs/guide.txt\n"
+        );
+    }
+
+    #[test]
+    fn reads_arent_recorded_unless_tracing() {
+        let fs = PennFat::from_bytes(sample_image()).unwrap();
+        assert!(matches!(
+            lookup(&fs, "/docs/guide.txt", true),
+            Lookup::Found(_)
+        ));
+        assert!(fs.finish_trace().is_none());
+    }
+}
@@ -0,0 +1,228 @@
+//! Each subcommand's exit status, and what it writes where: what a script reads goes to stdout,
+//! and errors to stderr, with the exit status saying which kind of failure it was
+
+use std::{fs, path::Path, process::Output};
+
+use assert_cmd::Command;
+use tempfile::TempDir;
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+const PROBLEMS: i32 = 2;
+const USAGE: i32 = 64;
+
+/// A directory holding `good.img`, the sample image, and `bad.img`, the same but with the
+/// chain of /README looping back on itself
+fn images() -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let good = pfview::testing::sample_image();
+    let mut bad = good.clone();
+    // /README is in block 0x02 alone
+    bad[2 * 2..2 * 2 + 2].copy_from_slice(&2u16.to_le_bytes());
+    fs::write(dir.path().join("good.img"), good).unwrap();
+    fs::write(dir.path().join("bad.img"), bad).unwrap();
+    dir
+}
+
+/// Run pfview in `dir`, with no terminal to draw on
+fn pfview(dir: &Path, args: &[&str]) -> Output {
+    Command::cargo_bin("pfview")
+        .unwrap()
+        .current_dir(dir)
+        .args(args)
+        .env_remove("PFVIEW_LOG")
+        .output()
+        .unwrap()
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Run pfview, checking that it exits with `code` and writes its error to stderr alone. Returns
+/// what it wrote to stderr
+fn fails(dir: &Path, args: &[&str], code: i32) -> String {
+    let output = pfview(dir, args);
+    let stderr = text(&output.stderr);
+    assert_eq!(output.status.code(), Some(code), "{:?}: {}", args, stderr);
+    assert!(
+        output.stdout.is_empty(),
+        "{:?}: {}",
+        args,
+        text(&output.stdout)
+    );
+    assert!(!stderr.is_empty(), "{:?}", args);
+    stderr
+}
+
+#[test]
+fn every_subcommand_succeeds_on_stdout_alone() {
+    let dir = images();
+    fs::copy(dir.path().join("good.img"), dir.path().join("copy.img")).unwrap();
+    // in order, as some use what ones before them wrote
+    let runs: &[&[&str]] = &[
+        &["ls", "good.img"],
+        &["ls", "--numeric", "good.img", "/docs"],
+        &["cat", "good.img", "/README"],
+        &["extract", "good.img", "/notes.txt", "notes.txt"],
+        &["extract-all", "good.img", "tree"],
+        &["stat", "good.img", "/latest"],
+        &["lookup", "good.img", "/latest"],
+        &["graph", "good.img", "/notes.txt"],
+        &["stats", "good.img"],
+        &["stats", "--json", "good.img"],
+        &["info", "--json", "good.img"],
+        &["report", "--format", "json", "good.img"],
+        &["locate", "good.img", "0x300"],
+        &["notes", "good.img"],
+        &["verify", "good.img", "tree"],
+        &["doctor", "good.img"],
+        &["fuzz-gen", "--count", "2", "--seed", "1", "fuzzed"],
+        &["fat-export", "good.img", "patch.json"],
+        &["fat-apply", "--dry-run", "copy.img", "patch.json"],
+        &["fat-apply", "copy.img", "patch.json"],
+        &["cp", "good.img:/README", "copy.img:/README2"],
+        &["fit", "good.img"],
+        &["repack", "good.img", "repacked.img"],
+        &["repair", "--dry-run", "good.img"],
+        &["explain"],
+        &["explain", "PF007"],
+        &["--help"],
+    ];
+    for args in runs {
+        let output = pfview(dir.path(), args);
+        assert_eq!(
+            output.status.code(),
+            Some(SUCCESS),
+            "{:?}: {}",
+            args,
+            text(&output.stderr)
+        );
+        assert!(
+            output.stderr.is_empty(),
+            "{:?}: {}",
+            args,
+            text(&output.stderr)
+        );
+    }
+    assert_eq!(
+        fs::read(dir.path().join("notes.txt")).unwrap().len(),
+        940,
+        "extract writes the file, not stdout"
+    );
+}
+
+#[test]
+fn ls_says_how_many_entries_each_directory_has() {
+    let dir = images();
+    let listing = text(&pfview(dir.path(), &["ls", "good.img"]).stdout);
+    assert!(listing.contains(" docs (2 entries)\n"), "{}", listing);
+    let listing = text(&pfview(dir.path(), &["ls", "good.img", "/docs"]).stdout);
+    assert!(listing.contains(" src (1 entry)\n"), "{}", listing);
+    assert!(listing.ends_with("2 entries\n"), "{}", listing);
+}
+
+#[test]
+fn traces_go_to_stderr_alongside_the_output() {
+    let dir = images();
+    let output = pfview(dir.path(), &["cat", "--trace", "good.img", "/README"]);
+    assert_eq!(output.status.code(), Some(SUCCESS));
+    let plain = pfview(dir.path(), &["cat", "good.img", "/README"]);
+    assert_eq!(output.stdout, plain.stdout);
+    assert!(text(&output.stderr).contains("lookup: found /README"));
+}
+
+#[test]
+fn problems_found_exit_with_2() {
+    let dir = images();
+    let report = pfview(dir.path(), &["doctor", "bad.img"]);
+    assert_eq!(report.status.code(), Some(PROBLEMS));
+    // the report is the output, and the verdict the error
+    assert!(text(&report.stdout).contains("cycle"));
+    assert_eq!(text(&report.stderr), "Error: bad.img has problems\n");
+    // an image that doesn't load is diagnosed too, as long as there's a file to read
+    fs::write(dir.path().join("short.img"), [0]).unwrap();
+    let report = pfview(dir.path(), &["doctor", "short.img"]);
+    assert_eq!(report.status.code(), Some(PROBLEMS));
+    assert!(text(&report.stdout).contains("FAIL  load"));
+
+    fs::create_dir(dir.path().join("tree")).unwrap();
+    fs::write(dir.path().join("tree/extra"), "not in the image").unwrap();
+    let differences = pfview(dir.path(), &["verify", "good.img", "tree"]);
+    assert_eq!(differences.status.code(), Some(PROBLEMS));
+    assert!(!differences.stdout.is_empty());
+
+    // a patch that makes the image worse is written, and then reported
+    let exported = pfview(dir.path(), &["fat-export", "bad.img", "bad.json"]);
+    assert_eq!(exported.status.code(), Some(SUCCESS));
+    let worse = pfview(dir.path(), &["fat-apply", "good.img", "bad.json"]);
+    assert_eq!(worse.status.code(), Some(PROBLEMS));
+    assert_eq!(
+        text(&worse.stderr),
+        "Error: the patch made good.img worse\n"
+    );
+}
+
+#[test]
+fn an_image_that_cant_be_read_exits_with_1_and_nothing_on_stdout() {
+    let dir = images();
+    let runs: &[&[&str]] = &[
+        &["missing.img"],
+        &["ls", "missing.img"],
+        &["cat", "missing.img", "/README"],
+        &["extract", "missing.img", "/README", "out"],
+        &["extract-all", "missing.img", "tree"],
+        &["stat", "missing.img", "/README"],
+        &["lookup", "missing.img", "/README"],
+        &["graph", "missing.img"],
+        &["stats", "missing.img"],
+        &["info", "missing.img"],
+        &["report", "missing.img"],
+        &["locate", "missing.img", "0"],
+        &["notes", "missing.img"],
+        &["verify", "missing.img", "."],
+        &["doctor", "missing.img"],
+        &["fat-export", "missing.img", "patch.json"],
+        &["fit", "missing.img"],
+        &["repack", "missing.img", "repacked.img"],
+        &["repair", "--dry-run", "missing.img"],
+        &["cp", "missing.img:/a", "good.img:/a"],
+        // and errors past loading it
+        &["cat", "good.img", "/docs"],
+        &["ls", "good.img", "/nope"],
+        &["explain", "PF999"],
+    ];
+    for args in runs {
+        fails(dir.path(), args, FAILURE);
+    }
+}
+
+#[test]
+fn json_output_reports_errors_as_json_too() {
+    let dir = images();
+    let output = pfview(dir.path(), &["info", "--json", "missing.img"]);
+    assert_eq!(output.status.code(), Some(FAILURE));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["error"]["code"], "PF001");
+    assert!(text(&output.stderr).starts_with("Error: PF001"));
+}
+
+#[test]
+fn usage_errors_exit_with_64_and_the_usage_on_stderr() {
+    let dir = images();
+    let runs: &[&[&str]] = &[
+        &[],
+        &["--bogus", "good.img"],
+        &["ls"],
+        &["stat", "good.img"],
+        &["explain", "PF001", "PF002"],
+        &["fuzz-gen", "--count", "many", "fuzzed"],
+    ];
+    for args in runs {
+        let stderr = fails(dir.path(), args, USAGE);
+        assert!(stderr.contains("Usage:"), "{:?}", args);
+    }
+    // asked for, the usage is the output
+    let help = pfview(dir.path(), &["--help"]);
+    assert!(text(&help.stdout).contains("Usage:"));
+}
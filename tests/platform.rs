@@ -0,0 +1,98 @@
+//! Loading and reloading images the same way on every platform: whatever kind of path names
+//! the image, with the host's own separators, and however coarsely the host keeps modification
+//! times
+
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf, MAIN_SEPARATOR},
+};
+
+use pfview::pennfat::{FormatError, Mode, PennFat, PfError, Reload, Window};
+use pfview::testing;
+
+/// A directory holding the sample image at `sub/sample.img`
+fn sample_in_a_subdirectory() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub/sample.img"), testing::sample_image()).unwrap();
+    dir
+}
+
+/// Read the image the way the viewer first does: its FAT, then a block of it
+fn read(fs: &PennFat) {
+    let fat = fs.get_fat_table();
+    assert_eq!(fat.first(), Some(&(0x01, 0x09)));
+    let readme = fs.get_block(0x02).unwrap();
+    assert!(readme.data.starts_with(b"a sample PennFAT image"));
+}
+
+#[test]
+fn images_load_from_any_kind_of_path() {
+    let dir = sample_in_a_subdirectory();
+    // joined with the host's separator: a backslash on Windows
+    let text = format!(
+        "{}{}sub{}sample.img",
+        dir.path().display(),
+        MAIN_SEPARATOR,
+        MAIN_SEPARATOR
+    );
+    let path = PathBuf::from(&text);
+    let loads = [
+        PennFat::load(&text, Mode::ReadOnly, Window::default()),
+        PennFat::load(text.as_str(), Mode::ReadOnly, Window::default()),
+        PennFat::load(&path, Mode::ReadOnly, Window::default()),
+        PennFat::load(path.as_path(), Mode::ReadOnly, Window::default()),
+        PennFat::load(
+            path.clone().into_os_string(),
+            Mode::ReadOnly,
+            Window::default(),
+        ),
+    ];
+    for fs in loads {
+        let fs = fs.unwrap();
+        assert_eq!(fs.path(), Path::new(&text));
+        read(&fs);
+    }
+}
+
+#[cfg(windows)]
+#[test]
+fn windows_paths_with_either_separator_load() {
+    let dir = sample_in_a_subdirectory();
+    let base = dir.path().display().to_string();
+    for path in [
+        format!("{}\\sub\\sample.img", base),
+        format!("{}/sub/sample.img", base),
+        format!("{}\\sub/sample.img", base),
+    ] {
+        read(&PennFat::load(&path, Mode::ReadOnly, Window::default()).unwrap());
+    }
+}
+
+#[test]
+fn a_change_in_length_is_reloaded_even_with_the_same_mtime() {
+    let dir = sample_in_a_subdirectory();
+    let path = dir.path().join("sub").join("sample.img");
+    let mut fs = PennFat::load(&path, Mode::ReadOnly, Window::default()).unwrap();
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+    // as a platform keeping times coarsely (or lazily) shows a write: a new length, the same
+    // modification time. A block too many for the FAT, so the reload says what it found
+    let mut grown = testing::sample_image();
+    grown.extend([0; 256]);
+    fs::write(&path, &grown).unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+    assert!(matches!(
+        fs.reload(),
+        Err(PfError::Format(FormatError::FileSize { actual, .. })) if actual == grown.len() as u64
+    ));
+    // and with nothing changed since, nothing is reloaded
+    assert!(matches!(fs.reload(), Ok(Reload::Unchanged)));
+}